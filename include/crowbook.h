@@ -0,0 +1,41 @@
+/* C interface to Crowbook, available when it is built with the capi feature:
+ *
+ *     cargo rustc --release --lib --no-default-features --features capi --crate-type cdylib
+ *
+ * Functions that fail return NULL or a non-zero value; crowbook_last_error() then
+ * returns the error message. */
+
+#ifndef CROWBOOK_H
+#define CROWBOOK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct CrowbookBook CrowbookBook;
+
+/* Message of the last error in this thread, or NULL; valid until the next failing call */
+const char *crowbook_last_error(void);
+
+/* Creates a book from the content of a configuration file; free it with crowbook_book_free */
+CrowbookBook *crowbook_book_new(const char *config);
+
+void crowbook_book_free(CrowbookBook *book);
+
+/* Adds a numbered chapter written in Markdown; returns 0 on success */
+int crowbook_book_add_chapter(CrowbookBook *book, const char *content);
+
+/* Renders a format (e.g. "html" or "epub") in memory, setting len to the size of the
+ * returned buffer; free it with crowbook_buffer_free */
+uint8_t *crowbook_book_render(const CrowbookBook *book, const char *format, size_t *len);
+
+void crowbook_buffer_free(uint8_t *buffer, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,274 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Daemon mode (`--daemon`), keeping books in memory and answering JSON-RPC 2.0 requests,
+//! one per line, on stdin/stdout or on a unix socket, so editors and GUIs don't have to run
+//! Crowbook for each request.
+
+use crowbook::{Book, Stats};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rust_i18n::t;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Loading or rendering a book failed
+const CROWBOOK_ERROR: i64 = -32000;
+
+/// Logger keeping the warnings and errors, so they can be attached to the book of the
+/// request that emitted them
+struct Collector {
+    records: Mutex<Vec<Value>>,
+}
+
+static COLLECTOR: Collector = Collector {
+    records: Mutex::new(vec![]),
+};
+
+impl Log for Collector {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let level = if record.level() == Level::Error {
+                "error"
+            } else {
+                "warning"
+            };
+            self.records
+                .lock()
+                .unwrap()
+                .push(json!({"level": level, "message": record.args().to_string()}));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// A loaded book, with the diagnostics emitted since it was loaded
+struct Loaded {
+    book: Book<'static>,
+    diagnostics: Vec<Value>,
+}
+
+/// Books loaded by the daemon, by path
+#[derive(Default)]
+struct Daemon {
+    books: HashMap<String, Loaded>,
+}
+
+/// Error of a request, sent back to the client
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new<S: Into<String>>(code: i64, message: S) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<crowbook::Error> for RpcError {
+    fn from(err: crowbook::Error) -> RpcError {
+        RpcError::new(CROWBOOK_ERROR, err.to_string())
+    }
+}
+
+/// Returns a string parameter of a request
+fn param<'p>(params: &'p Value, name: &str) -> Result<&'p str, RpcError> {
+    params[name]
+        .as_str()
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, t!("daemon.missing_param", name = name)))
+}
+
+impl Daemon {
+    /// Returns the book a request is about
+    fn book(&mut self, params: &Value) -> Result<&mut Loaded, RpcError> {
+        let path = param(params, "book")?;
+        self.books
+            .get_mut(path)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, t!("daemon.not_loaded", book = path)))
+    }
+
+    /// Runs a method, attaching the warnings and errors it logged to its book
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        COLLECTOR.records.lock().unwrap().clear();
+        let result = self.dispatch(method, params);
+        let records: Vec<Value> = COLLECTOR.records.lock().unwrap().drain(..).collect();
+        let path = if method == "load" {
+            params["path"].as_str()
+        } else {
+            params["book"].as_str()
+        };
+        if let Some(loaded) = path.and_then(|path| self.books.get_mut(path)) {
+            loaded.diagnostics.extend(records);
+        }
+        result
+    }
+
+    fn dispatch(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "load" => {
+                let path = param(params, "path")?;
+                let mut book = Book::new();
+                book.load_file(path)?;
+                let chapters = book.chapters.len();
+                self.books.insert(
+                    path.to_owned(),
+                    Loaded {
+                        book,
+                        diagnostics: vec![],
+                    },
+                );
+                Ok(json!({"book": path, "chapters": chapters}))
+            }
+            "set_option" => {
+                let key = param(params, "key")?;
+                // Non-string values (e.g. `true` or `2`) are set as they would be written in
+                // a book configuration file
+                let value = match &params["value"] {
+                    Value::String(value) => value.clone(),
+                    Value::Null => {
+                        return Err(RpcError::new(
+                            INVALID_PARAMS,
+                            t!("daemon.missing_param", name = "value"),
+                        ))
+                    }
+                    value => value.to_string(),
+                };
+                self.book(params)?.book.options.set(key, &value)?;
+                Ok(Value::Null)
+            }
+            "render" => {
+                let format = param(params, "format")?;
+                let book = &self.book(params)?.book;
+                let output = params["output"]
+                    .as_str()
+                    .map(String::from)
+                    .or_else(|| book.options.get_path(&format!("output.{format}")).ok());
+                match output {
+                    Some(path) => {
+                        book.render_format_to_file(format, &path)?;
+                        Ok(json!({ "path": path }))
+                    }
+                    None => Ok(json!({"content": book.render_format_to_string(format)?})),
+                }
+            }
+            "stats" => {
+                let advanced = params["advanced"].as_bool().unwrap_or(false);
+                let stats = Stats::new(&self.book(params)?.book, advanced);
+                Ok(json!({"text": stats.to_string()}))
+            }
+            "diagnostics" => Ok(json!({ "diagnostics": self.book(params)?.diagnostics })),
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                t!("daemon.unknown_method", method = method),
+            )),
+        }
+    }
+}
+
+/// Answers a line containing a request, returning None for a notification (which has no id)
+fn answer(daemon: &Mutex<Daemon>, line: &str) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => {
+            return Some(json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {"code": PARSE_ERROR, "message": err.to_string()},
+            }))
+        }
+    };
+    let id = request.get("id").cloned();
+    let result = match request["method"].as_str() {
+        Some(method) => daemon.lock().unwrap().call(method, &request["params"]),
+        None => Err(RpcError::new(INVALID_REQUEST, t!("daemon.no_method"))),
+    };
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": err.code, "message": err.message},
+        }),
+    })
+}
+
+/// Answers the requests read from `input` until its end
+fn serve<R: BufRead, W: Write>(daemon: &Mutex<Daemon>, input: R, mut output: W) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = answer(daemon, &line) {
+            writeln!(output, "{response}")?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the daemon, on a unix socket at `socket` if it is set, or else on stdin/stdout
+pub fn run(socket: Option<&str>) -> io::Result<()> {
+    let _ = log::set_logger(&COLLECTOR);
+    log::set_max_level(LevelFilter::Warn);
+    let daemon = Arc::new(Mutex::new(Daemon::default()));
+    match socket {
+        None => serve(&daemon, io::stdin().lock(), io::stdout().lock()),
+        #[cfg(unix)]
+        Some(path) => {
+            use std::os::unix::fs::FileTypeExt;
+            use std::os::unix::net::UnixListener;
+
+            // A socket left by a previous daemon would prevent binding it again
+            if std::fs::metadata(path).map_or(false, |m| m.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)?;
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let daemon = Arc::clone(&daemon);
+                std::thread::spawn(move || {
+                    let input = io::BufReader::new(stream.try_clone()?);
+                    serve(&daemon, input, stream)
+                });
+            }
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            t!("daemon.no_socket"),
+        )),
+    }
+}
@@ -15,9 +15,11 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-use clap::{Arg, ArgAction, ArgMatches, Command};
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap_complete::Shell;
 use console::style;
-use crowbook::Book;
+use crowbook::{Book, BookOptions};
 use rust_i18n::t;
 
 use std::env;
@@ -163,6 +165,16 @@ pub fn create_matches() -> ArgMatches {
     app().get_matches()
 }
 
+/// Writes the completion script of a shell, where the keys of the book options are
+/// proposed after `--set`
+pub fn write_completions(shell: Shell, out: &mut dyn Write) {
+    let keys = BookOptions::keys()
+        .into_iter()
+        .map(|(key, description)| PossibleValue::new(key).help(description));
+    let mut app = app().mut_arg("set", |arg| arg.value_parser(PossibleValuesParser::new(keys)));
+    clap_complete::generate(shell, &mut app, "crowbook", out);
+}
+
 // in its own function for testing purpose
 fn app() -> clap::Command {
     lazy_static! {
@@ -174,15 +186,44 @@ fn app() -> clap::Command {
         static ref CREATE: String = t!("cmd.create");
         static ref AUTOGRAPH: String = t!("cmd.autograph");
         static ref OUTPUT: String = t!("cmd.output");
+        static ref OUTPUT_DIR: String = t!("cmd.output_dir");
         static ref LANG: String = t!("cmd.lang");
         static ref TO: String = t!("cmd.to");
+        static ref FROM: String = t!("cmd.from");
         static ref SET: String = t!("cmd.set");
         static ref NO_FANCY: String = t!("cmd.no_fancy");
         static ref LIST_OPTIONS: String = t!("cmd.list_options");
         static ref LIST_OPTIONS_MD: String = t!("cmd.list_options_md");
+        static ref MD: String = t!("cmd.md");
+        static ref COMPLETIONS: String = t!("cmd.completions");
         static ref PRINT_TEMPLATE: String = t!("cmd.template");
+        static ref LIST_TEMPLATE_VARS: String = t!("cmd.template_vars");
         static ref BOOK: String = t!("cmd.book");
         static ref STATS: String = t!("cmd.stats");
+        static ref SANDBOX: String = t!("cmd.sandbox");
+        static ref STRICT: String = t!("cmd.strict");
+        static ref KEEP_TEMP: String = t!("cmd.keep_temp");
+        static ref TIMINGS: String = t!("cmd.timings");
+        static ref DRY_RUN: String = t!("cmd.dry_run");
+        static ref PROFILE: String = t!("cmd.profile");
+        static ref ONLY: String = t!("cmd.only");
+        static ref EXCLUDE: String = t!("cmd.exclude");
+        static ref SAMPLE: String = t!("cmd.sample");
+        static ref IMPORT: String = t!("cmd.import");
+        static ref IMPORT_EPUB: String = t!("cmd.import_epub");
+        static ref IMPORT_OUTPUT: String = t!("cmd.import_output");
+        static ref DAEMON: String = t!("cmd.daemon");
+        static ref SOCKET: String = t!("cmd.socket");
+        static ref CONFIG: String = t!("cmd.config");
+        static ref CONFIG_BOOK: String = t!("cmd.config_book");
+        static ref CONFIG_INTERACTIVE: String = t!("cmd.config_interactive");
+        static ref MIGRATE: String = t!("cmd.migrate");
+        static ref MIGRATE_BOOK: String = t!("cmd.migrate_book");
+        static ref DIFF: String = t!("cmd.diff");
+        static ref DIFF_OLD: String = t!("cmd.diff_old");
+        static ref DIFF_NEW: String = t!("cmd.diff_new");
+        static ref DIFF_REV: String = t!("cmd.diff_rev");
+        static ref DIFF_OUTPUT: String = t!("cmd.diff_output");
         static ref TEMPLATE: String = t!("clap.template");
     }
 
@@ -248,9 +289,18 @@ fn app() -> clap::Command {
                 .long("output")
                 .action(ArgAction::Set)
                 .num_args(1)
+                .value_hint(ValueHint::FilePath)
                 .help(OUTPUT.as_str())
                 .requires("to"),
         )
+        .arg(
+            Arg::new("output-dir")
+                .long("output-dir")
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_hint(ValueHint::DirPath)
+                .help(OUTPUT_DIR.as_str()),
+        )
         .arg(
             Arg::new("to")
                 .short('t')
@@ -263,9 +313,23 @@ fn app() -> clap::Command {
                     "tex",
                     "odt",
                     "html.dir",
+                    "pandoc-json",
+                    "opf",
+                    "azw3",
+                    "lit",
+                    "opds",
+                    "onix",
+                    "site",
                 ])
                 .help(TO.as_str()),
         )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .action(ArgAction::Set)
+                .value_parser(["markdown", "org", "pandoc-json"])
+                .help(FROM.as_str()),
+        )
         .arg(
             Arg::new("set")
                 .long("set")
@@ -287,6 +351,22 @@ fn app() -> clap::Command {
                 .help(LIST_OPTIONS_MD.as_str())
                 .hide(true),
         )
+        .arg(
+            Arg::new("md")
+                .long("md")
+                .action(ArgAction::SetTrue)
+                .requires("list-options")
+                .help(MD.as_str()),
+        )
+        .arg(
+            Arg::new("completions")
+                .long("completions")
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_name("SHELL")
+                .value_parser(clap::value_parser!(Shell))
+                .help(COMPLETIONS.as_str()),
+        )
         .arg(
             Arg::new("lang")
                 .short('L')
@@ -302,6 +382,14 @@ fn app() -> clap::Command {
                 .num_args(1)
                 .help(PRINT_TEMPLATE.as_str()),
         )
+        .arg(
+            Arg::new("list-template-vars")
+                .long("list-template-vars")
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_name("FORMAT")
+                .help(LIST_TEMPLATE_VARS.as_str()),
+        )
         .arg(
             Arg::new("stats")
                 .short('S')
@@ -309,12 +397,178 @@ fn app() -> clap::Command {
                 .action(ArgAction::SetTrue)
                 .help(STATS.as_str()),
         )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .help(DRY_RUN.as_str()),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .action(ArgAction::Set)
+                .num_args(1)
+                .help(PROFILE.as_str()),
+        )
+        .arg(
+            Arg::new("only")
+                .long("only")
+                .action(ArgAction::Append)
+                .num_args(1)
+                .help(ONLY.as_str()),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .action(ArgAction::Append)
+                .num_args(1)
+                .help(EXCLUDE.as_str()),
+        )
+        .arg(
+            Arg::new("sample")
+                .long("sample")
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32))
+                .help(SAMPLE.as_str()),
+        )
+        .arg(
+            Arg::new("timings")
+                .long("timings")
+                .action(ArgAction::Set)
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("text")
+                .value_parser(["text", "json"])
+                .help(TIMINGS.as_str()),
+        )
+        .arg(
+            Arg::new("sandbox")
+                .long("sandbox")
+                .action(ArgAction::SetTrue)
+                .help(SANDBOX.as_str()),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .help(STRICT.as_str()),
+        )
+        .arg(
+            Arg::new("keep-temp")
+                .long("keep-temp")
+                .action(ArgAction::SetTrue)
+                .help(KEEP_TEMP.as_str()),
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .action(ArgAction::SetTrue)
+                .help(DAEMON.as_str()),
+        )
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_name("PATH")
+                .value_hint(ValueHint::FilePath)
+                .requires("daemon")
+                .help(SOCKET.as_str()),
+        )
         .arg(
             Arg::new("BOOK")
                 .index(1)
                 .action(ArgAction::Set)
+                .value_hint(ValueHint::FilePath)
                 .help(BOOK.as_str()),
         )
+        .subcommand(
+            Command::new("import")
+                .about(IMPORT.as_str())
+                .arg(
+                    Arg::new("EPUB")
+                        .index(1)
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .help(IMPORT_EPUB.as_str()),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .help(IMPORT_OUTPUT.as_str()),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about(CONFIG.as_str())
+                .arg(
+                    Arg::new("BOOK")
+                        .index(1)
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .value_hint(ValueHint::FilePath)
+                        .help(CONFIG_BOOK.as_str()),
+                )
+                .arg(
+                    Arg::new("interactive")
+                        .short('i')
+                        .long("interactive")
+                        .required(true)
+                        .action(ArgAction::SetTrue)
+                        .help(CONFIG_INTERACTIVE.as_str()),
+                ),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about(MIGRATE.as_str())
+                .arg(
+                    Arg::new("BOOK")
+                        .index(1)
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .value_hint(ValueHint::FilePath)
+                        .help(MIGRATE_BOOK.as_str()),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about(DIFF.as_str())
+                .arg(
+                    Arg::new("OLD")
+                        .index(1)
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .help(DIFF_OLD.as_str()),
+                )
+                .arg(
+                    Arg::new("NEW")
+                        .index(2)
+                        .required_unless_present("rev")
+                        .conflicts_with("rev")
+                        .action(ArgAction::Set)
+                        .help(DIFF_NEW.as_str()),
+                )
+                .arg(
+                    Arg::new("rev")
+                        .long("rev")
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .help(DIFF_REV.as_str()),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .help(DIFF_OUTPUT.as_str()),
+                ),
+        )
         .help_template(TEMPLATE.as_str());
 
     app
@@ -322,10 +576,20 @@ fn app() -> clap::Command {
 
 #[cfg(test)]
 mod tests {
-    use super::app;
+    use super::{app, write_completions};
+    use clap_complete::Shell;
 
     #[test]
     fn verify_app() {
         app().debug_assert();
     }
+
+    #[test]
+    fn completions() {
+        let mut script = vec![];
+        write_completions(Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("--set"));
+        assert!(script.contains("tex.chapter.style"));
+    }
 }
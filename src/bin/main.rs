@@ -1,9 +1,12 @@
+#[cfg(feature = "binary")]
+mod daemon;
 #[macro_use]
 #[cfg(feature = "binary")]
 mod helpers;
 #[cfg(feature = "binary")]
 mod real_main;
 #[cfg(feature = "binary")]
+mod wizard;
 #[cfg(feature = "binary")]
 #[macro_use]
 extern crate lazy_static;
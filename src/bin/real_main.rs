@@ -15,24 +15,28 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::daemon;
 use crate::helpers::*;
+use crate::wizard;
 
 use crowbook::Stats;
-use crowbook::{Book, BookOptions, Result};
+use crowbook::{Book, BookOptions, ConfigFile, Error, Result, Source};
 
 use clap::ArgMatches;
+use clap_complete::Shell;
 use simplelog::{ConfigBuilder, LevelFilter, SimpleLogger, TermLogger, WriteLogger};
 use std::env;
 use std::fs::File;
 use std::io;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use yaml_rust::Yaml;
 use rust_i18n::t;
 
 
 /// Render a book to specific format
-fn render_format(book: &mut Book, emoji: bool, matches: &ArgMatches, format: &str) {
+fn render_format(book: &Book, emoji: bool, matches: &ArgMatches, format: &str) {
     let mut key = String::from("output.");
     key.push_str(format);
 
@@ -60,6 +64,98 @@ fn render_format(book: &mut Book, emoji: bool, matches: &ArgMatches, format: &st
     }
 }
 
+/// Checks that the book can be rendered and displays the files that would be generated
+fn dry_run(book: &Book, emoji: bool, matches: &ArgMatches) {
+    let results = if let Some(format) = matches.get_one::<String>("to") {
+        let path = matches
+            .get_one::<String>("output")
+            .cloned()
+            .or_else(|| book.options.get_path(&format!("output.{format}")).ok())
+            .unwrap_or_else(|| String::from("-"));
+        vec![(format.as_str(), book.dry_run_format(format, path))]
+    } else {
+        book.dry_run_all()
+    };
+    if results.is_empty() {
+        print_warning(&t!("msg.dry_run_nothing"), emoji);
+    }
+    for (format, result) in results {
+        match result {
+            Ok(path) => println!(
+                "{}",
+                t!("msg.dry_run", format = format, path = path.display())
+            ),
+            Err(err) => print_error(&format!("{format}: {err}"), emoji),
+        }
+    }
+}
+
+/// Converts an I/O error
+fn io_error(err: io::Error) -> Error {
+    Error::default(Source::empty(), err.to_string())
+}
+
+/// Returns the book configuration file of a version of a book, which can be
+/// a directory containing a single `.book` file
+fn find_book(path: &str) -> Result<PathBuf> {
+    let path = Path::new(path);
+    if !path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    let books: Vec<PathBuf> = std::fs::read_dir(path).map_err(io_error)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.extension().map_or(false, |ext| ext == "book"))
+        .collect();
+    if books.len() == 1 {
+        Ok(books.into_iter().next().unwrap())
+    } else {
+        Err(Error::default(
+            Source::empty(),
+            t!("error.diff_book", dir = path.display()),
+        ))
+    }
+}
+
+/// Renders the changes between two versions of a book, or between a book and
+/// a git revision of it
+fn diff(matches: &ArgMatches) -> Result<String> {
+    // ok to unwrap since clap checks it's there
+    let old = matches.get_one::<String>("OLD").unwrap();
+    let tmp_dir = tempfile::tempdir().map_err(io_error)?;
+    let (old_path, new_path) = match matches.get_one::<String>("rev") {
+        Some(rev) => {
+            let new_path = find_book(old)?;
+            let old_path = crowbook::checkout_revision(&new_path, rev, tmp_dir.path())?;
+            (old_path, new_path)
+        }
+        None => (find_book(old)?, find_book(matches.get_one::<String>("NEW").unwrap())?),
+    };
+    let mut old_book = Book::new();
+    old_book.load_file(old_path)?;
+    let mut new_book = Book::new();
+    new_book.load_file(new_path)?;
+
+    let output = matches
+        .get_one::<String>("output")
+        .map_or("diff.html", |s| s.as_str());
+    std::fs::write(output, crowbook::diff_books(&old_book, &new_book)).map_err(io_error)?;
+    Ok(output.to_owned())
+}
+
+/// Translates a book configuration file to the current version of the format and sets its
+/// `crowbook.version`, returning the changes, or None if it was already up to date
+fn migrate(book: &Path) -> Result<Option<Vec<String>>> {
+    let mut config = ConfigFile::load(book)?;
+    let changes = crowbook::migrate(&mut config)?;
+    let version = Yaml::Integer(crowbook::CONFIG_VERSION);
+    if changes.is_empty() && config.get("crowbook.version") == Some(version.clone()) {
+        return Ok(None);
+    }
+    config.set("crowbook.version", version);
+    config.save(book)?;
+    Ok(Some(changes))
+}
+
 pub fn try_main() -> Result<()> {
     let lang = get_lang().or_else(|| match env::var("LANG") {
         Ok(val) => Some(val),
@@ -86,8 +182,13 @@ pub fn try_main() -> Result<()> {
         display_header(emoji);
     }
 
+    if let Some(shell) = matches.get_one::<Shell>("completions") {
+        write_completions(*shell, &mut io::stdout());
+        exit(0);
+    }
+
     if matches.get_flag("list-options") {
-        println!("{}", BookOptions::description(false));
+        println!("{}", BookOptions::description(matches.get_flag("md")));
         exit(0);
     }
 
@@ -104,6 +205,10 @@ pub fn try_main() -> Result<()> {
     if let Some(template) = matches.get_one::<String>("print-template") {
         let mut book = Book::new();
         set_book_options(&mut book, &matches);
+        if matches.get_flag("strict") {
+            // Set after loading, so the book's configuration can't turn it off
+            book.options.set("rendering.strict", "true").unwrap();
+        }
         let result = book.get_template(template.as_ref());
         match result {
             Ok(s) => {
@@ -117,6 +222,85 @@ pub fn try_main() -> Result<()> {
         }
     }
 
+    if let Some(format) = matches.get_one::<String>("list-template-vars") {
+        let mut book = Book::new();
+        set_book_options(&mut book, &matches);
+        // A chapter is needed for chapter templates to be rendered
+        let result = book
+            .add_chapter_from_source(crowbook::Number::Default, "# Chapter\n\nText\n".as_bytes(), false)
+            .and_then(|book| book.template_variables(format));
+        match result {
+            Ok(vars) => {
+                for var in vars {
+                    println!("{var}");
+                }
+                exit(0);
+            }
+            Err(err) => print_error_and_exit(&format!("{err}"), emoji),
+        }
+    }
+
+    if let Some(import) = matches.subcommand_matches("import") {
+        // ok to unwrap since clap checks it's there
+        let epub = import.get_one::<String>("EPUB").unwrap();
+        let dest = match import.get_one::<String>("output") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(Path::new(epub).file_stem().unwrap_or_default()),
+        };
+        match crowbook::import_epub(epub, &dest) {
+            Ok(file) => {
+                println!("{}", t!("msg.imported", file = file));
+                exit(0);
+            }
+            Err(err) => print_error_and_exit(&format!("{err}"), emoji),
+        }
+    }
+
+    if let Some(config) = matches.subcommand_matches("config") {
+        // ok to unwrap since clap checks it's there
+        let book = Path::new(config.get_one::<String>("BOOK").unwrap());
+        match wizard::run(book, io::stdin().lock(), io::stdout()) {
+            Ok(()) => exit(0),
+            Err(err) => print_error_and_exit(&format!("{err}"), emoji),
+        }
+    }
+
+    if let Some(migrate_matches) = matches.subcommand_matches("migrate") {
+        // ok to unwrap since clap checks it's there
+        let book = Path::new(migrate_matches.get_one::<String>("BOOK").unwrap());
+        match migrate(book) {
+            Ok(None) => {
+                println!("{}", t!("msg.up_to_date", file = book.display()));
+                exit(0);
+            }
+            Ok(Some(changes)) => {
+                for change in changes {
+                    println!("  - {change}");
+                }
+                println!("{}", t!("msg.migrated", file = book.display()));
+                exit(0);
+            }
+            Err(err) => print_error_and_exit(&format!("{err}"), emoji),
+        }
+    }
+
+    if let Some(diff_matches) = matches.subcommand_matches("diff") {
+        match diff(diff_matches) {
+            Ok(file) => {
+                println!("{}", t!("msg.diff", file = file));
+                exit(0);
+            }
+            Err(err) => print_error_and_exit(&format!("{err}"), emoji),
+        }
+    }
+
+    if matches.get_flag("daemon") {
+        match daemon::run(matches.get_one::<String>("socket").map(|s| s.as_str())) {
+            Ok(()) => exit(0),
+            Err(err) => print_error_and_exit(&t!("daemon.error", error = err), emoji),
+        }
+    }
+
     if matches.get_many::<String>("files").is_some() {
         create_book(&matches);
     }
@@ -189,10 +373,27 @@ pub fn try_main() -> Result<()> {
         if fancy_ui {
             book.add_progress_bar(emoji);
         }
+        if matches.get_flag("sandbox") {
+            // Must be set first, so it applies to all other options
+            book.options.set("crowbook.sandbox", "true").unwrap();
+        }
+        if let Some(profile) = matches.get_one::<String>("profile") {
+            book.set_profile(profile.as_str());
+        }
+        if matches.get_flag("keep-temp") {
+            book.options.set("crowbook.temp_dir.keep", "true").unwrap();
+        }
+        if let Some(n) = matches.get_one::<u32>("sample") {
+            book.options.set("sample.chapters", &n.to_string()).unwrap();
+        }
+        if let Some(format) = matches.get_one::<String>("from") {
+            book.options.set("input.format", format).unwrap();
+        }
         book.set_options(&get_book_options(&matches));
 
         {
-            let res = if matches.get_flag("single") {
+            let single = matches.get_flag("single") || matches.get_one::<String>("from").is_some();
+            let res = if single {
                 if s != "-" {
                     book.load_markdown_file(s)
                 } else {
@@ -215,6 +416,38 @@ pub fn try_main() -> Result<()> {
         }
 
         set_book_options(&mut book, &matches);
+        if let Some(dir) = matches.get_one::<String>("output-dir") {
+            // Relative to the current directory, like --output, not to the book's one
+            let dir = env::current_dir()
+                .map(|current| current.join(dir))
+                .unwrap_or_else(|_| PathBuf::from(dir));
+            let res = book.options.set_yaml(
+                Yaml::String(String::from("output.base_path")),
+                Yaml::String(dir.to_string_lossy().into_owned()),
+            );
+            if let Err(err) = res {
+                print_error_and_exit(
+                    &t!("error.set_key", key = "output.base_path", error = err),
+                    emoji,
+                );
+            }
+        }
+
+        // Options set by arguments can change what other options reference
+        if let Err(err) = book.options.evaluate_references() {
+            print_error_and_exit(&format!("{err}"), emoji);
+        }
+
+        let tags = |name: &str| -> Vec<&str> {
+            matches
+                .get_many::<String>(name)
+                .map(|tags| tags.map(|tag| tag.as_str()).collect())
+                .unwrap_or_default()
+        };
+        let (only, exclude) = (tags("only"), tags("exclude"));
+        if !only.is_empty() || !exclude.is_empty() {
+            book.select_chapters(&only, &exclude);
+        }
 
         if matches.get_flag("stats") {
             let stats = Stats::new(&book, matches.get_flag("verbose"));
@@ -222,11 +455,19 @@ pub fn try_main() -> Result<()> {
             exit(0);
         }
 
-        if let Some(format) = matches.get_one::<String>("to") {
-            render_format(&mut book, emoji, &matches, format);
+        if matches.get_flag("dry-run") {
+            dry_run(&book, emoji, &matches);
+        } else if let Some(format) = matches.get_one::<String>("to") {
+            render_format(&book, emoji, &matches, format);
         } else {
             book.render_all();
         }
+
+        match matches.get_one::<String>("timings").map(|s| s.as_str()) {
+            Some("json") => eprintln!("{}", book.timings().to_json()),
+            Some(_) => eprint!("{}", book.timings()),
+            None => {}
+        }
     }
     if fancy_ui {
         let mut errors = String::new();
@@ -0,0 +1,171 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Configuration wizard (`crowbook config --interactive`), asking for the most important
+//! options and writing them to a book configuration file, which keeps its comments.
+
+use crowbook::{BookOptions, ConfigFile, Error, Result, Source};
+
+use rust_i18n::t;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use yaml_rust::Yaml;
+
+/// How an answer is converted to the value of an option
+enum Kind {
+    Str,
+    Lang,
+    List,
+    Int,
+}
+
+/// Options asked by the wizard, with the key of their question
+const QUESTIONS: &[(&str, &str, Kind)] = &[
+    ("title", "wizard.title", Kind::Str),
+    ("author", "wizard.author", Kind::Str),
+    ("lang", "wizard.lang", Kind::Lang),
+    ("output", "wizard.output", Kind::List),
+    ("rendering.num_depth", "wizard.num_depth", Kind::Int),
+];
+
+/// Converts an I/O error
+fn io_error(err: std::io::Error) -> Error {
+    Error::default(Source::empty(), err.to_string())
+}
+
+/// Displays the value of an option as it can be typed as an answer
+fn display(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(s) | Yaml::Real(s) => Some(s.clone()),
+        Yaml::Integer(n) => Some(n.to_string()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        Yaml::Array(values) => Some(
+            values
+                .iter()
+                .filter_map(display)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        _ => None,
+    }
+}
+
+/// Returns true if `lang` looks like a language code, e.g. `fr` or `en-GB`
+fn is_lang(lang: &str) -> bool {
+    let mut parts = lang.split(|c| c == '-' || c == '_');
+    let language = parts.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Converts an answer to the value of an option, checking that it is valid
+fn parse(key: &str, kind: &Kind, answer: &str) -> std::result::Result<Yaml, String> {
+    let value = match kind {
+        Kind::Str => Yaml::String(answer.to_owned()),
+        Kind::Lang if is_lang(answer) => Yaml::String(answer.to_owned()),
+        Kind::Lang => return Err(t!("wizard.invalid_lang", lang = answer)),
+        Kind::List => Yaml::Array(
+            answer
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| Yaml::String(s.to_owned()))
+                .collect(),
+        ),
+        Kind::Int => match answer.parse() {
+            Ok(n) => Yaml::Integer(n),
+            Err(_) => return Err(t!("wizard.invalid_int", value = answer)),
+        },
+    };
+    BookOptions::new()
+        .set_yaml(Yaml::String(key.to_owned()), value.clone())
+        .map_err(|err| err.to_string())?;
+    Ok(value)
+}
+
+/// Asks for the options of the book configuration file at `path`, which is created if it
+/// doesn't exist, reading the answers from `input`
+///
+/// An empty answer keeps the current value.
+pub fn run<R: BufRead, W: Write>(path: &Path, mut input: R, mut output: W) -> Result<()> {
+    let mut config = if path.exists() {
+        ConfigFile::load(path)?
+    } else {
+        ConfigFile::parse(&t!("msg.chapter_list"))
+    };
+    let defaults = BookOptions::new();
+    let mut eof = false;
+    for (key, question, kind) in QUESTIONS {
+        let current = config
+            .get(key)
+            .as_ref()
+            .and_then(display)
+            .or_else(|| match kind {
+                Kind::Int => defaults.get_i32(key).ok().map(|n| n.to_string()),
+                _ => defaults.get_str(key).ok().map(String::from),
+            })
+            .filter(|current| !current.is_empty());
+        while !eof {
+            match current {
+                Some(ref current) => write!(output, "{} [{current}]: ", t!(question)),
+                None => write!(output, "{}: ", t!(question)),
+            }
+            .and_then(|_| output.flush())
+            .map_err(io_error)?;
+            let mut answer = String::new();
+            eof = input.read_line(&mut answer).map_err(io_error)? == 0;
+            let answer = answer.trim();
+            if answer.is_empty() {
+                break;
+            }
+            match parse(key, kind, answer) {
+                Ok(value) => {
+                    config.set(key, value);
+                    break;
+                }
+                Err(err) => writeln!(output, "{err}").map_err(io_error)?,
+            }
+        }
+    }
+    config.save(path)?;
+    writeln!(output, "{}", t!("msg.config_written", file = path.display())).map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+
+    #[test]
+    fn wizard() {
+        let path = std::env::temp_dir().join(format!("crowbook-wizard-{}.book", std::process::id()));
+        std::fs::write(&path, "# My book\ntitle: Old\n\n+ chapter.md\n").unwrap();
+        // Invalid answers are asked again, empty ones keep the current value
+        let answers = "New\n\nxx1\nfr\nepub, foo\nepub\n";
+        let mut output = vec![];
+        run(&path, answers.as_bytes(), &mut output).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            content,
+            "# My book\ntitle: New\nlang: fr\noutput:\n  - epub\n\n+ chapter.md\n"
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("'xx1'"));
+        assert!(output.contains("foo"));
+    }
+}
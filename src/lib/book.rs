@@ -16,37 +16,66 @@
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::book_bars::Bars;
+use crate::hooks;
+use crate::timings::Timings;
 use crate::book_renderer::BookRenderer;
+use crate::cancellation::{CancellationToken, CommandLimits};
+use crate::capabilities::{self, Downgrade};
 use crate::bookoptions::BookOptions;
+use crate::calibre::{EbookConvert, Opf};
 use crate::chapter::Chapter;
+use crate::code_block::CodeInfo;
+use crate::config_file::ConfigFile;
+use crate::date::Date;
 use crate::cleaner::{Cleaner, CleanerParams, Default, French, Off};
+use crate::encoding;
 use crate::epub::Epub;
-use crate::error::{Error, Result, Source};
+use crate::error::{self, Error, Result, Source};
+use crate::filter::{self, ExternalFilter, Filter};
+use crate::git::GitInfo;
 use crate::html_dir::HtmlDir;
 use crate::html_if::HtmlIf;
 use crate::html_single::HtmlSingle;
+use crate::imprint;
 use crate::lang;
+use crate::link_refs::LinkRefs;
+use crate::manifest;
+use crate::migrate;
 use crate::latex::{Latex, Pdf};
 use crate::misc;
 use crate::number::Number;
+use crate::onix::Onix;
+use crate::opds::Opds;
+use crate::pandoc::PandocJson;
 use crate::parser::Features;
 use crate::parser::Parser;
+use crate::publish;
 use crate::resource_handler::ResourceHandler;
-use crate::templates::{epub, epub3, highlight, html, html_dir, html_if, html_single, latex};
+use crate::site::Site;
+use crate::shortcodes::Shortcodes;
+use crate::slug::Slugifier;
+use crate::temp_dir::TempDir;
+use crate::template_blocks;
+use crate::template_vars::{TemplateVar, TemplateVars};
+use crate::templates::{epub, epub3, highlight, html, html_dir, html_if, html_single, latex, sample, site};
 use crate::text_view::view_as_text;
 use crate::token::Token;
+use crate::zipper::Zipper;
 
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::{HashMap, BTreeMap};
+use std::env;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::iter::IntoIterator;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use numerals::roman::Roman;
 use rayon::prelude::*;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
 use yaml_rust::{Yaml, YamlLoader};
 use rust_i18n::t;
 
@@ -88,6 +117,33 @@ pub enum CrowbarState {
     Error,
 }
 
+/// Progress of the generation of a book, reported to the callback set with
+/// `Book::set_progress_callback`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Progress<'s> {
+    /// Loading a book configuration file
+    Loading(&'s str),
+    /// Parsing the chapter number `i` (starting at 1) of the `n` chapters listed in the
+    /// configuration file
+    Parsing(usize, usize),
+    /// Starting to render a format
+    Rendering(&'s str),
+    /// Running step `i` (starting at 1) of `n` of a long rendering, e.g. a LaTeX pass
+    /// when generating a PDF file
+    Step(&'s str, usize, usize),
+    /// Finished rendering a format
+    Rendered(&'s str),
+}
+
+/// Callback receiving the progress of the generation of a book
+pub type ProgressCallback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Function called before rendering a book, see `Book::add_pre_render_hook`
+pub type PreRenderHook = Box<dyn Fn(&Book) -> Result<()> + Send + Sync>;
+
+/// Function called after rendering a format to a file, see `Book::add_post_render_hook`
+pub type PostRenderHook = Box<dyn Fn(&Book, &str, &Path) -> Result<()> + Send + Sync>;
+
 impl fmt::Display for HeaderData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.text)
@@ -104,6 +160,10 @@ impl fmt::Display for HeaderData {
 /// (`HtmlRendrer`, `LatexRenderer`, `EpubRenderer` and/or `OdtRenderer`)
 /// to convert the AST into documents.
 ///
+/// Once loaded, a book isn't modified by rendering: rendering methods only take `&self`
+/// and `Book` is `Send` and `Sync`, so a single book can be rendered to several formats
+/// at the same time, e.g. by a server handling multiple requests.
+///
 /// # Examples
 ///
 /// ```
@@ -146,6 +206,24 @@ pub struct Book<'a> {
     #[doc(hidden)]
     pub bars: Bars,
 
+    progress: Option<ProgressCallback>,
+    /// Shared with the variants of the book, so their renderings are measured too
+    timings: Arc<Timings>,
+    cancellation: CancellationToken,
+    /// Number of images in the chapters, for `limits.images`
+    image_count: usize,
+    pre_render_hooks: Vec<PreRenderHook>,
+    post_render_hooks: Vec<PostRenderHook>,
+    filters: Vec<Box<dyn Filter>>,
+    profile: Option<String>,
+    git: Option<GitInfo>,
+    /// Link definitions shared by chapters, loaded with the first chapter
+    pub(crate) link_refs: Option<LinkRefs>,
+    /// Variables passed to templates, recorded by `template_variables`
+    pub(crate) template_vars: TemplateVars,
+    /// Formats rendered to files and their paths, listed in the manifest
+    rendered: Mutex<Vec<(String, PathBuf)>>,
+
     /// Store the templates registry
     pub registry: upon::Engine<'a>,
 }
@@ -162,6 +240,18 @@ impl<'a> Book<'a> {
             formats: HashMap::new(),
             features: Features::new(),
             bars: Bars::new(),
+            progress: None,
+            timings: Arc::new(Timings::new()),
+            cancellation: CancellationToken::new(),
+            image_count: 0,
+            pre_render_hooks: vec![],
+            post_render_hooks: vec![],
+            filters: vec![],
+            profile: None,
+            git: None,
+            link_refs: None,
+            template_vars: TemplateVars::default(),
+            rendered: Mutex::new(vec![]),
             registry: upon::Engine::new(),
         };
 
@@ -186,7 +276,18 @@ impl<'a> Book<'a> {
             "html.if",
             t!("html_if"),
             Box::new(HtmlIf {}),
-        );
+        )
+        .add_format(
+            "pandoc-json",
+            t!("format.pandoc_json"),
+            Box::new(PandocJson {}),
+        )
+        .add_format("opf", t!("format.opf"), Box::new(Opf {}))
+        .add_format("azw3", t!("format.azw3"), Box::new(EbookConvert { format: "azw3" }))
+        .add_format("lit", t!("format.lit"), Box::new(EbookConvert { format: "lit" }))
+        .add_format("opds", t!("format.opds"), Box::new(Opds {}))
+        .add_format("onix", t!("format.onix"), Box::new(Onix {}))
+        .add_format("site", t!("format.site"), Box::new(Site {}));
         book
     }
 
@@ -202,6 +303,468 @@ impl<'a> Book<'a> {
         self.private_add_progress_bar(emoji);
     }
 
+    /// Sets a function that is called to report the progress of the generation of the book
+    ///
+    /// This allows to display progress when using Crowbook as a library, e.g. in a GUI.
+    /// Since formats are rendered in parallel by `render_all`, the callback can be called
+    /// from different threads.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use crowbook::{Book, Progress};
+    /// let mut book = Book::new();
+    /// book.set_progress_callback(|progress| {
+    ///     if let Progress::Rendered(format) = progress {
+    ///         println!("{format} is done");
+    ///     }
+    /// });
+    /// ```
+    pub fn set_progress_callback<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Returns the token cancelling the renderings of the book, which can be cancelled from
+    /// another thread
+    ///
+    /// Renderings stop between chapters and phases, killing the external commands they
+    /// run, and return an error whose kind is `ErrorKind::Cancelled`. A cancelled token
+    /// stays cancelled: `set_cancellation_token` sets a new one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use crowbook::Book;
+    /// let book = Book::new();
+    /// book.cancellation_token().cancel();
+    /// assert!(book.render_format_to_string("html").unwrap_err().is_cancelled());
+    /// ```
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Sets the token cancelling the renderings of the book, e.g. to share it with other
+    /// books
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Returns an error if the renderings of the book have been cancelled
+    #[doc(hidden)]
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.cancellation.is_cancelled() {
+            Err(Error::cancelled())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the limits on the external commands run while rendering the book
+    pub(crate) fn command_limits(&self) -> CommandLimits {
+        CommandLimits {
+            timeout: self.options.get_duration("crowbook.command.timeout").ok(),
+            cancellation: self.cancellation.clone(),
+        }
+    }
+
+    /// Reports progress to the callback and to the progress bars, if they are set
+    #[doc(hidden)]
+    pub fn report_progress(&self, progress: Progress) {
+        if let Progress::Step(format, i, n) = progress {
+            self.bar_set_steps(format, i, n);
+        }
+        if let Some(ref callback) = self.progress {
+            callback(progress);
+        }
+    }
+
+    /// Adds a function that is called before rendering the book, after the `hooks.pre_render`
+    /// command (if it is set). If it returns an error, nothing is rendered.
+    pub fn add_pre_render_hook<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&Book) -> Result<()> + Send + Sync + 'static,
+    {
+        self.pre_render_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Adds a function that is called after a format has been rendered to a file, with the
+    /// name of the format and the path of the file, after the `hooks.post_render.{format}`
+    /// command (if it is set). If it returns an error, rendering this format fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use crowbook::Book;
+    /// let mut book = Book::new();
+    /// book.add_post_render_hook(|_, format, path| {
+    ///     println!("{format} was rendered to {}", path.display());
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn add_post_render_hook<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&Book, &str, &Path) -> Result<()> + Send + Sync + 'static,
+    {
+        self.post_render_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Sets the build profile, whose `profiles.{name}.{key}` options are applied by
+    /// `read_config` after the other options of the book configuration file.
+    ///
+    /// The `draft` and `release` profiles are always defined, and the latter excludes
+    /// draft chapters unless `profiles.release.input.drafts` is set.
+    pub fn set_profile<S: Into<String>>(&mut self, profile: S) -> &mut Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Returns the build profile, if it is set
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Keeps only the chapters that have one of the `only` tags (unless it is empty), and
+    /// that don't have any of the `exclude` ones
+    ///
+    /// Tags of a chapter are set by `chapter.tags` in its YAML block, or by `tags.{tag}` options
+    /// listing the chapters. Unless `input.tags.renumber` is set, the remaining chapters keep
+    /// the numbers they have in the full book.
+    pub fn select_chapters(&mut self, only: &[&str], exclude: &[&str]) -> &mut Self {
+        for tag in only.iter().chain(exclude) {
+            let used = self.chapters.iter().any(|c| c.tags.iter().any(|t| t == tag))
+                || self.options.get_tags().iter().any(|(t, _)| t == tag);
+            if !used {
+                warn!("{}", t!("warn.unknown_tag", tag = tag));
+            }
+        }
+
+        let numbers = self.chapter_numbers();
+        let renumber = self.options.get_bool("input.tags.renumber").unwrap();
+        let tags = self.options.get_tags();
+        let has_tag = |chapter: &Chapter, tag: &str| {
+            let file = chapter.filename.trim_start_matches("./");
+            chapter.tags.iter().any(|t| t == tag)
+                || tags.iter().any(|(t, files)| {
+                    t == tag && files.iter().any(|f| f.trim_start_matches("./") == file)
+                })
+        };
+        let mut keep = vec![];
+        for chapter in &self.chapters {
+            keep.push(
+                (only.is_empty() || only.iter().any(|tag| has_tag(chapter, tag)))
+                    && !exclude.iter().any(|tag| has_tag(chapter, tag)),
+            );
+        }
+
+        let chapters = std::mem::take(&mut self.chapters);
+        for ((mut chapter, keep), number) in chapters.into_iter().zip(keep).zip(numbers) {
+            if !keep {
+                continue;
+            }
+            if !renumber {
+                chapter.number = number;
+            }
+            self.chapters.push(chapter);
+        }
+        self
+    }
+
+    /// Reads the version metadata of the book's git repository if `git.metadata` is set,
+    /// and checks that its working tree is clean if `git.require_clean` is set.
+    ///
+    /// This is called by `read_config`.
+    pub fn read_git_info(&mut self) -> Result<&mut Self> {
+        let metadata = self.options.get_bool("git.metadata").unwrap();
+        let require_clean = self.options.get_bool("git.require_clean").unwrap();
+        if !metadata && !require_clean {
+            return Ok(self);
+        }
+        let info = match GitInfo::read(&self.root) {
+            Ok(info) => info,
+            Err(err) if !require_clean => {
+                warn!("{}", err);
+                return Ok(self);
+            }
+            Err(err) => return Err(err),
+        };
+        if require_clean && info.dirty {
+            return Err(Error::default(&self.source, t!("git.dirty")));
+        }
+        if metadata {
+            if self.options.get_str("version").is_err() {
+                // Set as a string, since an abbreviated hash may look like a number
+                self.options.set_yaml(
+                    Yaml::String(String::from("version")),
+                    Yaml::String(info.version.clone()),
+                )?;
+            }
+            self.git = Some(info);
+        }
+        Ok(self)
+    }
+
+    /// Returns the URL where an audio or video file can be played, for the placeholders
+    /// displayed by formats that can't embed it, i.e. the URL itself if it is remote, or
+    /// its path appended to `media.base_url` if this option is set
+    #[doc(hidden)]
+    pub fn media_url(&self, url: &str) -> Option<String> {
+        if !ResourceHandler::is_local(url) {
+            Some(url.to_owned())
+        } else {
+            self.options.get_str("media.base_url").ok().map(|base| {
+                format!("{}/{}", base.trim_end_matches('/'), misc::clean_path(url))
+            })
+        }
+    }
+
+    /// Returns the version metadata read from the book's git repository, if `git.metadata` is set
+    pub fn git_info(&self) -> Option<&GitInfo> {
+        self.git.as_ref()
+    }
+
+    /// Makes a sample of the book, keeping only its first `n` chapters (not counting parts),
+    /// followed by the page rendered from `sample.template`, which invites to buy the full
+    /// book at `sample.url`.
+    ///
+    /// This is called by `read_config` if `sample.chapters` is set.
+    pub fn make_sample(&mut self, n: usize) -> Result<&mut Self> {
+        let mut count = 0;
+        let len = self
+            .chapters
+            .iter()
+            .position(|chapter| {
+                if !chapter.number.is_part() {
+                    count += 1;
+                }
+                count > n
+            })
+            .unwrap_or(self.chapters.len());
+        self.chapters.truncate(len);
+
+        let mut data = self.get_metadata(|s| Ok(s.to_owned()))?;
+        let url = self.options.get_str("sample.url").ok();
+        data.insert("sample_url".into(), url.unwrap_or("").into());
+        data.insert("has_sample_url".into(), url.is_some().into());
+        let template_src = self.get_template("sample.template")?;
+        let page = self
+            .compile_str(template_src.as_ref(), &self.source, "sample.template")?
+            .render(&data)
+            .to_string()?;
+        self.add_chapter_from_named_source(Number::Unnumbered, "", page.as_bytes(), false)?;
+        Ok(self)
+    }
+
+    /// Returns the number of each chapter, replacing the default numbering by the numbers
+    /// it results in, so they don't change if some chapters are removed
+    fn chapter_numbers(&self) -> Vec<Number> {
+        let reset_counter = self.options.get_bool("rendering.part.reset_counter").unwrap();
+        let mut part = 0;
+        let mut chapter = 0;
+        let mut res = vec![];
+        for c in &self.chapters {
+            match c.number {
+                Number::Specified(n) => chapter = n - 1,
+                Number::SpecifiedPart(n) => part = n - 1,
+                _ => (),
+            }
+            res.push(match c.number {
+                Number::Default | Number::Specified(_) => Number::Specified(chapter + 1),
+                Number::DefaultPart | Number::SpecifiedPart(_) => Number::SpecifiedPart(part + 1),
+                n => n,
+            });
+            if !c.number.is_numbered() {
+                continue;
+            }
+            for token in &c.content {
+                if let Token::Header(1, _) = *token {
+                    if c.number.is_part() {
+                        part += 1;
+                        if reset_counter {
+                            chapter = 0;
+                        }
+                    } else {
+                        chapter += 1;
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Adds a filter, transforming the tokens of each chapter added to the book after
+    /// this call, after the commands set by `input.filters`.
+    pub fn add_filter<F: Filter + 'static>(&mut self, filter: F) -> &mut Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Runs the commands set by `input.filters` and the filters on a chapter
+    fn run_filters(&self, file: &str, mut tokens: Vec<Token>) -> Result<Vec<Token>> {
+        if let Ok(commands) = self.options.get_str_vec("input.filters") {
+            for command in commands {
+                tokens = ExternalFilter::new(command.as_str()).filter(self, file, tokens)?;
+            }
+        }
+        for filter in &self.filters {
+            tokens = filter.filter(self, file, tokens)?;
+        }
+        Ok(tokens)
+    }
+
+    /// Checks the images of a chapter according to `check.images`
+    fn check_images(&self, file: &str, tokens: &[Token]) -> Result<()> {
+        match self.options.get_str("check.images") {
+            Err(_) => return Ok(()),
+            Ok("require_alt") => (),
+            Ok(value) => {
+                return Err(Error::book_option(
+                    &self.source,
+                    t!("error.check_images", value = value),
+                ))
+            }
+        }
+        let mut urls = vec![];
+        images_without_alt(tokens, &mut urls);
+        for url in urls {
+            let err = Error::default(Source::new(file), t!("check.missing_alt", url = url));
+            if self.options.get_bool("check.fail").unwrap() {
+                return Err(err);
+            }
+            warn!("{}", err);
+        }
+        Ok(())
+    }
+
+    /// Checks the images of a chapter against `limits.images` and `limits.image_size`
+    fn check_image_limits(&mut self, file: &str, tokens: &[Token]) -> Result<()> {
+        let mut urls = vec![];
+        images(tokens, &mut urls);
+        self.image_count += urls.len();
+        if let Ok(limit) = self.options.get_i32("limits.images") {
+            if self.image_count > usize::try_from(limit).unwrap_or(0) {
+                return Err(Error::default(
+                    Source::new(file),
+                    t!("limits.images", limit = limit),
+                ));
+            }
+        }
+        if let Ok(max_size) = self.options.get_size("limits.image_size") {
+            for url in urls.into_iter().filter(|url| ResourceHandler::is_local(url)) {
+                // Missing images are reported when they are rendered
+                let size = fs::metadata(self.root.join(url)).map_or(0, |metadata| metadata.len());
+                if size > max_size {
+                    let limit = self.options.get_str("limits.image_size").unwrap();
+                    return Err(Error::default(
+                        Source::new(file),
+                        t!("limits.image_size", file = url, limit = limit),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the code blocks of a chapter with the `check.code.{language}` commands, if
+    /// `check.code` is set
+    ///
+    /// The code is written to a temporary file, whose path is given to the command by the
+    /// `CROWBOOK_CODE_FILE` environment variable.
+    fn check_code(&self, file: &str, blocks: &[(u32, String, String)]) -> Result<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let mut zipper = Zipper::new(self.temp_dir()?, self.command_limits());
+        for (line, info, code) in blocks {
+            // e.g. `rust,ignore`
+            let info = CodeInfo::new(info);
+            let mut flags = info.language.split(',').map(str::trim);
+            let language = flags.next().unwrap_or_default();
+            if language.is_empty() || flags.any(|flag| flag == "ignore") {
+                continue;
+            }
+            let command = match self.options.get_str(&format!("check.code.{language}")) {
+                Ok(command) => command,
+                Err(_) => continue,
+            };
+            let name = format!("code-{line}.{language}");
+            zipper.write(&name, code.as_bytes(), false)?;
+            let mut check = hooks::shell_command(self, command);
+            check.env("CROWBOOK_CODE_FILE", hooks::absolute(&zipper.path().join(&name)));
+            let output = self.command_limits().output(&mut check, command)?;
+            let error = match output {
+                Ok(ref output) if output.status.success() => continue,
+                Ok(output) => {
+                    let mut error = String::from_utf8_lossy(&output.stderr).trim_end().to_owned();
+                    if error.is_empty() {
+                        error = String::from_utf8_lossy(&output.stdout).trim_end().to_owned();
+                    }
+                    format!("{error} ({})", output.status)
+                }
+                Err(err) => err.to_string(),
+            };
+            let mut source = Source::new(file);
+            source.set_line(*line);
+            let err = Error::default(
+                source,
+                t!("check.code_failed", language = language, error = error),
+            );
+            if self.options.get_bool("check.fail").unwrap() {
+                return Err(err);
+            }
+            warn!("{}", err);
+        }
+        Ok(())
+    }
+
+    /// Runs the `hooks.pre_render` command and pre-render hooks
+    fn run_pre_render_hooks(&self) -> Result<()> {
+        hooks::run(self, "hooks.pre_render", None)?;
+        for hook in &self.pre_render_hooks {
+            hook(self)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the `hooks.post_render.{format}` command, `publish.*` actions and post-render hooks
+    fn run_post_render_hooks(&self, format: &str, path: &Path) -> Result<()> {
+        hooks::run(self, &format!("hooks.post_render.{format}"), Some((format, path)))?;
+        publish::kindle(self, format, path)?;
+        for hook in &self.post_render_hooks {
+            hook(self, format, path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the time spent in each phase of the generation of the book so far
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    /// Returns the variables that the templates of a format receive, with their types,
+    /// by rendering the book to this format and recording them
+    ///
+    /// PDF uses the same template as LaTeX, so `pdf` is rendered as `tex`, without running
+    /// a LaTeX command. Multiple files HTML is rendered in a temporary directory.
+    pub fn template_variables(&self, format: &str) -> Result<Vec<TemplateVar>> {
+        let format = if format == "pdf" { "tex" } else { format };
+        self.template_vars.start();
+        let result = if format == "html.dir" {
+            self.temp_dir()
+                .and_then(|dir| self.render_format_to_file(format, dir.path().join("html")))
+        } else {
+            self.render_format_to(format, &mut std::io::sink())
+        };
+        let vars = self.template_vars.stop();
+        result.map(|_| vars)
+    }
+
     /// Register a format that can be rendered.
     ///
     /// The renderer for this format must implement the `BookRenderer` trait.
@@ -290,6 +853,7 @@ impl<'a> Book<'a> {
         self.source = Source::new(filename.as_str());
         self.options.source = Source::new(filename.as_str());
 
+        self.report_progress(Progress::Loading(&filename));
         let f = File::open(path.as_ref()).map_err(|_| {
             Error::file_not_found(Source::empty(), t!("format.book"), filename.clone())
         })?;
@@ -302,7 +866,7 @@ impl<'a> Book<'a> {
         match self.read_config(&f) {
             Ok(_) => Ok(()),
             Err(err) => {
-                if err.is_config_parser() && path.as_ref().ends_with(".md") {
+                if err.is_config_parser() && path.as_ref().extension().map_or(false, |e| e == "md") {
                     let err = Error::default(
                         Source::empty(),
                         t!("error.parse_book",
@@ -382,6 +946,7 @@ impl<'a> Book<'a> {
 
         // Update grammar checker according to options
         self.add_chapter_from_source(Number::Hidden, source, false)?;
+        self.options.evaluate_references()?;
 
         Ok(())
     }
@@ -474,6 +1039,20 @@ impl<'a> Book<'a> {
             )
         })?;
 
+        // Translate the options of files written for older versions
+        let mut config = ConfigFile::parse(&s);
+        let changes = migrate::migrate(&mut config)?;
+        if !changes.is_empty() {
+            if let Some(ref file) = self.source.file {
+                warn!("{}", t!("migrate.loaded", file = file));
+            }
+            // One warning per change, since the binary only displays the first line of each
+            for change in &changes {
+                warn!("  - {change}");
+            }
+            s = config.to_string();
+        }
+
         // Parse the YAML block, that is, until first chapter
         let mut yaml = String::new();
         let mut lines = s.lines().peekable();
@@ -484,7 +1063,7 @@ impl<'a> Book<'a> {
 
         while let Some(next_line) = lines.peek() {
             if next_line.starts_with(|c| match c {
-                '-' | '+' | '!' | '@' => true,
+                '-' | '+' | '!' | '@' | '~' => true,
                 _ => c.is_ascii_digit(),
             }) {
                 break;
@@ -507,11 +1086,7 @@ impl<'a> Book<'a> {
             if let Some(next_line) = lines.peek() {
                 let doc = YamlLoader::load_from_str(next_line);
                 if let Ok(doc) = doc {
-                    if !doc.is_empty() && doc[0].as_hash().is_some() {
-                        is_next_line_ok = true;
-                    } else {
-                        is_next_line_ok = false;
-                    }
+                    is_next_line_ok = !doc.is_empty() && doc[0].as_hash().is_some();
                 } else {
                     is_next_line_ok = false;
                 }
@@ -540,6 +1115,10 @@ impl<'a> Book<'a> {
             }
         }
         self.set_options_from_yaml(&yaml)?;
+        if let Some(profile) = self.profile.clone() {
+            self.options.apply_profile(&profile)?;
+        }
+        self.options.evaluate_references()?;
 
         // Update cleaner according to options (autoclean/lang)
         self.update_cleaner();
@@ -548,6 +1127,12 @@ impl<'a> Book<'a> {
 
         // Parse chapters
         let lines: Vec<_> = lines.collect();
+        let n_chapters = lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .count();
+        let mut i_chapter = 0;
         self.add_second_bar(&t!("ui.processing"), lines.len() as u64);
         for line in lines {
             self.inc_second_bar();
@@ -557,6 +1142,12 @@ impl<'a> Book<'a> {
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
+            i_chapter += 1;
+            self.report_progress(Progress::Parsing(i_chapter, n_chapters));
+            // A `~` before the usual syntax leaves the chapter out of the table of contents
+            let first_chapter = self.chapters.len();
+            let unlisted = line.starts_with('~');
+            let line = line.trim_start_matches('~').trim_start();
             if line.starts_with("--") {
                 // Subchapter
                 let mut level = 0;
@@ -649,12 +1240,32 @@ impl<'a> Book<'a> {
                     t!("error.chapter_definition"),
                 ));
             }
+            if unlisted {
+                for chapter in &mut self.chapters[first_chapter..] {
+                    chapter.unlisted = true;
+                }
+            }
         }
 
         self.bar_finish(Crowbar::Second, CrowbarState::Success, "");
 
         self.source.unset_line();
+        if let Some(ref link_refs) = self.link_refs {
+            for label in link_refs.unused() {
+                warn!("{}", t!("warn.unused_link", label = label));
+            }
+        }
         self.set_chapter_template()?;
+        self.read_git_info()?;
+        if let Ok(n) = self.options.get_i32("sample.chapters") {
+            if n < 0 {
+                return Err(Error::book_option(
+                    &self.source,
+                    t!("error.sample_chapters", n = n),
+                ));
+            }
+            self.make_sample(n as usize)?;
+        }
         Ok(())
     }
 
@@ -680,17 +1291,23 @@ impl<'a> Book<'a> {
     ///       .unwrap()
     ///       .render_all(); // renders foo.tex in /tmp
     /// ```
-    pub fn render_all(&mut self) {
-        let mut keys: Vec<_> = self
+    pub fn render_all(&self) {
+        let mut keys: Vec<(String, Option<String>)> = self
             .formats
             .keys()
             .filter(|fmt| {
                 self.options.get_path(&format!("output.{fmt}")).is_ok()
             })
-            .map(|s| s.to_string())
+            .map(|s| (s.to_string(), None))
             .collect();
+        // Variants set by output.{format}.{variant}
+        for (format, variant, _) in self.options.get_variants() {
+            if self.formats.contains_key(format) {
+                keys.push((format.to_owned(), Some(variant.to_owned())));
+            }
+        }
         // Make sure that PDF comes first since running latex takes lots of time
-        keys.sort_by(|fmt1, fmt2| {
+        keys.sort_by(|(fmt1, _), (fmt2, _)| {
             if fmt1.contains("pdf") {
                 Ordering::Less
             } else if fmt2.contains("pdf") {
@@ -700,13 +1317,28 @@ impl<'a> Book<'a> {
             }
         });
 
-        for key in &keys {
-            self.add_spinner_to_multibar(key);
+        if let Err(err) = self.run_pre_render_hooks() {
+            self.set_error(&format!("{err}"));
+            error!("{err}");
+            return;
+        }
+
+        for (format, variant) in &keys {
+            match variant {
+                Some(variant) => self.add_spinner_to_multibar(&format!("{format}.{variant}")),
+                None => self.add_spinner_to_multibar(format),
+            };
         }
 
-        keys.par_iter().enumerate().for_each(|(i, fmt)| {
-            self.render_format_with_bar(fmt, i);
+        keys.par_iter().enumerate().for_each(|(i, (fmt, variant))| match variant {
+            Some(variant) => self.render_variant_with_bar(fmt, variant, i),
+            None => self.render_format_with_bar(fmt, i),
         });
+        if let Err(err) = self.write_manifest() {
+            self.set_error(&format!("{err}"));
+            error!("{err}");
+            return;
+        }
 
         self.bar_finish(Crowbar::Main, CrowbarState::Success, &t!("ui.finished"));
 
@@ -724,22 +1356,88 @@ impl<'a> Book<'a> {
             self.bar_set_message(Crowbar::Spinner(bar), &t!("ui.rendering_format"));
             let result = self.render_format_to_file_with_bar(format, path, bar);
             if let Err(err) = result {
+                self.report_rendering_error(format, bar, &err);
+            }
+        }
+    }
+
+    /// Reports an error that occurred while rendering a format to its progress bar and log
+    fn report_rendering_error(&self, name: &str, bar: usize, err: &Error) {
+        self.bar_finish(
+            Crowbar::Spinner(bar),
+            CrowbarState::Error,
+            &format!("{err}"),
+        );
+        error!(
+            "{}",
+            t!("error.rendering",
+                name = name,
+                error = err
+            )
+        );
+    }
+
+    /// Renders a variant of a format, set by `output.{format}.{variant}`, with the options of
+    /// the build profile of the same name, and reports to progress bar if set
+    pub fn render_variant_with_bar(&self, format: &str, variant: &str, bar: usize) {
+        let path = match self.options.get_path(&format!("output.{format}.{variant}")) {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => return,
+        };
+        let name = format!("{format}.{variant}");
+        self.bar_set_message(Crowbar::Spinner(bar), &t!("ui.rendering_format"));
+        let result = self.variant(variant).and_then(|book| {
+            // With `auto`, the variant is added to the file name so it doesn't overwrite the format's one
+            let path = match (path.ends_with("auto"), self.file_stem()) {
+                (true, Some(stem)) => {
+                    let (_, renderer) = self.renderer(format)?;
+                    path.with_file_name(renderer.auto_path(&format!("{stem}.{variant}"))?)
+                }
+                _ => path,
+            };
+            book.render_format_to_file_with_bar(format, path, bar)?;
+            let rendered = std::mem::take(&mut *book.rendered.lock().unwrap());
+            for (_, path) in rendered {
+                for hook in &self.post_render_hooks {
+                    hook(self, format, &path)?;
+                }
                 self.bar_finish(
                     Crowbar::Spinner(bar),
-                    CrowbarState::Error,
-                    &format!("{err}"),
-                );
-                error!(
-                    "{}",
-                    t!("error.rendering",
-                        name = format,
-                        error = err
-                    )
+                    CrowbarState::Success,
+                    &t!("ui.generated", path = misc::normalize(&path)),
                 );
+                self.rendered.lock().unwrap().push((name.clone(), path));
             }
+            Ok(())
+        });
+        if let Err(err) = result {
+            self.report_rendering_error(&name, bar, &err);
         }
     }
 
+    /// Returns a copy of the book whose options are overridden by the build profile `name`,
+    /// which is used to render a variant of a format
+    pub(crate) fn variant(&self, name: &str) -> Result<Book<'a>> {
+        let mut book = Book::new();
+        book.source = self.source.clone();
+        book.root = self.root.clone();
+        book.options = self.options.clone();
+        book.options.apply_profile(name)?;
+        book.options.evaluate_references()?;
+        book.chapters = self.chapters.clone();
+        book.features = self.features;
+        book.profile = self.profile.clone();
+        book.git = self.git.clone();
+        book.link_refs = self.link_refs.clone();
+        // The variant is rendered as part of the book, so it is cancelled, reports its
+        // progress and is measured with it
+        book.cancellation = self.cancellation.clone();
+        book.progress = self.progress.clone();
+        book.timings = Arc::clone(&self.timings);
+        book.update_cleaner();
+        Ok(book)
+    }
+
     pub fn render_format_to_file_with_bar<P: Into<PathBuf>>(
         &self,
         format: &str,
@@ -753,31 +1451,17 @@ impl<'a> Book<'a> {
         let path = path.into();
         match self.formats.get(format) {
             Some((description, renderer)) => {
-                let path = if path.ends_with("auto") {
-                    let file = if let Some(s) = self
-                        .source
-                        .file
-                        .as_ref()
-                        .and_then(|f| Path::new(f).file_stem())
-                    {
-                        s.to_string_lossy().into_owned()
-                    } else {
-                        return Err(Error::default(&self.source, t!("error.infer",
-                                                                     format = description)));
-                    };
-                    let file = renderer.auto_path(&file).map_err(|_| {
-                        Error::default(
-                            &self.source,
-                            t!("error.support",
-                                format = description
-                            ),
-                        )
-                    })?;
-                    path.with_file_name(file)
-                } else {
-                    path
-                };
-                renderer.render_to_file(self, &path)?;
+                self.check_cancelled()?;
+                self.check_format(format)?;
+                self.warn_downgrades(format)?;
+                self.report_progress(Progress::Rendering(format));
+                let path = self.output_path(description, renderer.as_ref(), path)?;
+                self.create_output_dir(&path)?;
+                self.timings
+                    .time(&format!("render: {format}"), || renderer.render_to_file(self, &path))?;
+                self.run_post_render_hooks(format, &path)?;
+                self.rendered.lock().unwrap().push((format.to_owned(), path.clone()));
+                self.report_progress(Progress::Rendered(format));
                 let path = misc::normalize(path);
                 let msg = t!(
                     "msg.generated",
@@ -799,8 +1483,326 @@ impl<'a> Book<'a> {
         }
     }
 
-    /// Render book to specified format according to book options, and write the results
-    /// in the `Write` object.
+    /// Returns `output.name`, or else the name of the book's file without its extension (or
+    /// the slug of the title, depending on `output.filenames`), which output files are named
+    /// after when their path is `auto`
+    pub(crate) fn file_stem(&self) -> Option<String> {
+        if let Ok(name) = self.options.get("output.name").and_then(|name| name.as_path()) {
+            return Some(name.to_owned());
+        }
+        match self.options.get_str("output.filenames").unwrap() {
+            "file" => (),
+            "slug" => {
+                let slug = Slugifier::new(&self.options).slug(self.options.get_str("title").unwrap());
+                return Some(slug).filter(|slug| !slug.is_empty());
+            }
+            value => error!(
+                "{}",
+                t!(
+                    "error.naming",
+                    key = "output.filenames",
+                    value = value,
+                    values = "file, slug"
+                )
+            ),
+        }
+        self.source
+            .file
+            .as_ref()
+            .and_then(|f| Path::new(f).file_stem())
+            .map(|s| s.to_string_lossy().into_owned())
+    }
+
+    /// Returns a UUID identifying the book, which only depends on its title, author,
+    /// language and version, and on `salt`
+    pub(crate) fn stable_uuid(&self, salt: &str) -> uuid::Uuid {
+        let mut identity = String::new();
+        for key in ["title", "author", "lang", "version"] {
+            identity.push_str(self.options.get_str(key).unwrap_or(""));
+            identity.push('\n');
+        }
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&misc::stable_hash(identity.as_bytes()).to_be_bytes());
+        identity.push_str(salt);
+        bytes[8..].copy_from_slice(&misc::stable_hash(identity.as_bytes()).to_be_bytes());
+        uuid::Builder::from_random_bytes(bytes).into_uuid()
+    }
+
+    /// Returns the UUID of the book, i.e. `uuid` if it is set, or a stable one
+    pub(crate) fn uuid(&self) -> uuid::Uuid {
+        self.options
+            .get_str("uuid")
+            .ok()
+            .and_then(|uuid| uuid::Uuid::parse_str(uuid).ok())
+            .unwrap_or_else(|| self.stable_uuid("crowbook"))
+    }
+
+    /// Returns the description and the renderer of a format
+    pub(crate) fn renderer(&self, format: &str) -> Result<(&str, &dyn BookRenderer)> {
+        match self.formats.get(format) {
+            Some((description, renderer)) => Ok((description.as_str(), renderer.as_ref())),
+            None => Err(Error::default(
+                Source::empty(),
+                t!("error.unknown", format = format),
+            )),
+        }
+    }
+
+    /// Creates a new temporary directory in `crowbook.temp_dir`, which is deleted when it is
+    /// dropped unless `crowbook.temp_dir.keep` is set
+    ///
+    /// Fails if `crowbook.in_memory` is set, so nothing is written to the filesystem.
+    pub(crate) fn temp_dir(&self) -> Result<TempDir> {
+        if self.options.get_bool("crowbook.in_memory").unwrap() {
+            return Err(Error::default(Source::empty(), t!("error.in_memory")));
+        }
+        TempDir::new(
+            self.options.get_path("crowbook.temp_dir").unwrap(),
+            self.options.get_bool("crowbook.temp_dir.keep").unwrap(),
+        )
+    }
+
+    /// Returns the timestamp that must be used instead of the current time so builds are
+    /// reproducible, i.e. `SOURCE_DATE_EPOCH` if this environment variable is set, or, if
+    /// `crowbook.reproducible` is set, the date of the commit read with `git.metadata` or
+    /// 1980-01-01 (the earliest date a ZIP file can contain).
+    #[doc(hidden)]
+    pub fn reproducible_timestamp(&self) -> Option<i64> {
+        match env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.trim().parse().ok()) {
+            Some(timestamp) => Some(timestamp),
+            None if self.options.get_bool("crowbook.reproducible").unwrap() => {
+                Some(self.git.as_ref().map_or(315_532_800, |git| git.timestamp))
+            }
+            None => None,
+        }
+    }
+
+    /// Returns the timestamp at which the book is considered built: the reproducible one if
+    /// it is set, the date of the commit read with `git.metadata`, or the current time
+    pub(crate) fn build_timestamp(&self) -> i64 {
+        self.reproducible_timestamp()
+            .or_else(|| self.git.as_ref().map(|git| git.timestamp))
+            .unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs() as i64)
+            })
+    }
+
+    /// Checks that a format can be rendered with the current options, i.e. that the external
+    /// programs it requires (such as LaTeX for PDF) are installed.
+    ///
+    /// This is called before rendering a format, so it fails early with an explanation instead
+    /// of failing midway.
+    pub fn check_format(&self, format: &str) -> Result<()> {
+        match self.formats.get(format) {
+            Some((description, renderer)) => {
+                for command in renderer.required_commands(self)? {
+                    if !misc::command_exists(&command) {
+                        return Err(Error::render(
+                            Source::empty(),
+                            t!(
+                                "error.missing_command",
+                                format = description,
+                                command = command
+                            ),
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            None => Err(Error::default(
+                Source::empty(),
+                t!("error.unknown", format = format),
+            )),
+        }
+    }
+
+    /// Returns the features used by the book that a format can't render as they are, with
+    /// the chapters using them and what is rendered instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use crowbook::{Book, Feature, Fallback, Number};
+    /// let mut book = Book::new();
+    /// book.add_chapter_from_source(Number::Default, "![A video](clip.mp4)".as_bytes(), false)
+    ///     .unwrap();
+    /// let downgrades = book.downgrades("tex").unwrap();
+    /// assert_eq!(downgrades[0].feature, Feature::Video);
+    /// assert_eq!(downgrades[0].fallback, Fallback::Link);
+    /// ```
+    pub fn downgrades(&self, format: &str) -> Result<Vec<Downgrade>> {
+        let (_, renderer) = self.renderer(format)?;
+        let unsupported = renderer.unsupported(self);
+        let mut downgrades = vec![];
+        if unsupported.is_empty() {
+            return Ok(downgrades);
+        }
+        for (i, chapter) in self.chapters.iter().enumerate() {
+            let mut features = vec![];
+            capabilities::features(&chapter.content, &mut features);
+            features.sort_unstable();
+            for feature in features {
+                if let Some(&(_, fallback)) = unsupported.iter().find(|(f, _)| *f == feature) {
+                    let chapter = if chapter.filename.is_empty() {
+                        t!("capabilities.chapter", n = i + 1)
+                    } else {
+                        chapter.filename.clone()
+                    };
+                    downgrades.push(Downgrade {
+                        format: format.to_owned(),
+                        feature,
+                        chapter,
+                        fallback,
+                    });
+                }
+            }
+        }
+        Ok(downgrades)
+    }
+
+    /// Warns about the features used by the book that a format can't render as they are,
+    /// or fails on the first one if `rendering.strict` is set
+    fn warn_downgrades(&self, format: &str) -> Result<()> {
+        let strict = self.options.get_bool("rendering.strict").unwrap();
+        for downgrade in self.downgrades(format)? {
+            error::lossy(strict, Source::empty(), downgrade.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the formats that can be rendered with the current options, i.e. for which
+    /// all required external programs are installed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use crowbook::Book;
+    /// let book = Book::new();
+    /// // HTML doesn't require any external program
+    /// assert!(book.available_renderers().contains(&"html"));
+    /// ```
+    pub fn available_renderers(&self) -> Vec<&'static str> {
+        let mut formats: Vec<_> = self
+            .formats
+            .keys()
+            .copied()
+            .filter(|format| self.check_format(format).is_ok())
+            .collect();
+        formats.sort_unstable();
+        formats
+    }
+
+    /// Returns the path of the file that is generated when rendering to `path`,
+    /// which can end with `auto` to infer the file name from the book's
+    fn output_path(
+        &self,
+        description: &str,
+        renderer: &dyn BookRenderer,
+        path: PathBuf,
+    ) -> Result<PathBuf> {
+        if path.ends_with("auto") {
+            let file = if let Some(s) = self.file_stem() {
+                s
+            } else {
+                return Err(Error::default(&self.source, t!("error.infer",
+                                                             format = description)));
+            };
+            let file = renderer.auto_path(&file).map_err(|_| {
+                Error::default(
+                    &self.source,
+                    t!("error.support",
+                        format = description
+                    ),
+                )
+            })?;
+            Ok(path.with_file_name(file))
+        } else {
+            Ok(path)
+        }
+    }
+
+    /// Creates the directory of an output file if it is inside `output.base_path`, so
+    /// files can be rendered to a directory that doesn't exist yet
+    fn create_output_dir(&self, path: &Path) -> Result<()> {
+        let base = PathBuf::from(self.options.get_path("output.base_path")?);
+        let dir = match path.parent() {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        if base.as_os_str().is_empty() || !path.starts_with(&base) || dir.is_dir() {
+            return Ok(());
+        }
+        fs::create_dir_all(dir).map_err(|err| {
+            Error::default(
+                &self.source,
+                t!("error.output_dir", path = dir.display(), error = err),
+            )
+        })
+    }
+
+    /// Checks that the book can be rendered to a format, without writing any file nor
+    /// running external programs, and returns the path of the file that would be generated.
+    ///
+    /// Warnings (e.g. about missing resources) are logged as during a normal rendering.
+    pub fn dry_run_format<P: Into<PathBuf>>(&self, format: &str, path: P) -> Result<PathBuf> {
+        self.check_format(format)?;
+        self.warn_downgrades(format)?;
+        let (description, renderer) = &self.formats[format];
+        let path = self.output_path(description, renderer.as_ref(), path.into())?;
+        renderer.dry_run(self)?;
+        Ok(path)
+    }
+
+    /// Performs a dry run (see `dry_run_format`) of all the formats whose output is set in
+    /// the book's options, returning the result for each of them.
+    pub fn dry_run_all(&self) -> Vec<(&'static str, Result<PathBuf>)> {
+        let mut formats: Vec<_> = self.formats.keys().copied().collect();
+        formats.sort_unstable();
+        formats
+            .into_iter()
+            .filter_map(|format| {
+                let path = self.options.get_path(&format!("output.{format}")).ok()?;
+                Some((format, self.dry_run_format(format, path)))
+            })
+            .collect()
+    }
+
+    /// Renders the book to a format in memory, and returns the content of the file
+    ///
+    /// With `crowbook.in_memory` set, nothing is written to the filesystem, e.g. for a
+    /// serverless deployment with a read-only filesystem; formats that need temporary files
+    /// (such as PDF, which runs LaTeX) then fail.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use crowbook::Book;
+    /// let mut book = Book::new();
+    /// book.set_options(&[("crowbook.in_memory", "true")]);
+    /// book.add_chapter_from_source(crowbook::Number::Default, "# Chapter\n\nText".as_bytes(), false).unwrap();
+    /// let epub = book.render_format_to_vec("epub").unwrap();
+    /// assert!(epub.starts_with(b"PK"));
+    /// ```
+    pub fn render_format_to_vec(&self, format: &str) -> Result<Vec<u8>> {
+        let mut content = vec![];
+        self.render_format_to(format, &mut content)?;
+        Ok(content)
+    }
+
+    /// Renders the book to a text format (e.g. `tex` or `html`) in memory, and returns it
+    ///
+    /// Fails if the output of the format isn't UTF-8 text, e.g. for EPUB (see
+    /// `render_format_to_vec`).
+    pub fn render_format_to_string(&self, format: &str) -> Result<String> {
+        String::from_utf8(self.render_format_to_vec(format)?).map_err(|_| {
+            Error::default(Source::empty(), t!("error.not_utf8", format = format))
+        })
+    }
+
+    /// Render book to specified format according to book options, and write the results
+    /// in the `Write` object.
     ///
     /// This method will fail if the format is not handled by the book, or if there is a
     /// problem during rendering, or if the renderer can't render to a byte stream (e.g.
@@ -810,37 +1812,50 @@ impl<'a> Book<'a> {
     /// * `render_format_to_file`, which creates a new file (that *can* be a directory).
     /// * `render_format`, which won't do anything if `output.{format}` isn't specified
     ///   in the book configuration file.
-    pub fn render_format_to<T: Write>(&mut self, format: &str, f: &mut T) -> Result<()> {
+    pub fn render_format_to<T: Write>(&self, format: &str, f: &mut T) -> Result<()> {
         debug!(
             "{}",
             t!("msg.attempting", format = format)
         );
         let bar = self.add_spinner_to_multibar(format);
         match self.formats.get(format) {
-            Some((description, renderer)) => match renderer.render(self, f) {
-                Ok(_) => {
-                    self.bar_finish(
-                        Crowbar::Spinner(bar),
-                        CrowbarState::Success,
-                        &t!("ui.generated", path = format),
-                    );
-                    self.bar_finish(Crowbar::Main, CrowbarState::Success, &t!("ui.finished"));
-                    info!(
-                        "{}",
-                        t!("msg.generated_short", format = description)
-                    );
-                    Ok(())
-                }
-                Err(e) => {
-                    self.bar_finish(
-                        Crowbar::Spinner(bar),
-                        CrowbarState::Error,
-                        &format!("{error}", error = e),
-                    );
-                    self.bar_finish(Crowbar::Main, CrowbarState::Error, &t!("ui.error"));
-                    Err(e)
+            Some((description, renderer)) => {
+                self.report_progress(Progress::Rendering(format));
+                let result = self
+                    .check_cancelled()
+                    .and_then(|_| self.check_format(format))
+                    .and_then(|_| self.warn_downgrades(format))
+                    .and_then(|_| self.run_pre_render_hooks())
+                    .and_then(|_| {
+                        self.timings
+                            .time(&format!("render: {format}"), || renderer.render(self, f))
+                    });
+                match result {
+                    Ok(_) => {
+                        self.report_progress(Progress::Rendered(format));
+                        self.bar_finish(
+                            Crowbar::Spinner(bar),
+                            CrowbarState::Success,
+                            &t!("ui.generated", path = format),
+                        );
+                        self.bar_finish(Crowbar::Main, CrowbarState::Success, &t!("ui.finished"));
+                        info!(
+                            "{}",
+                            t!("msg.generated_short", format = description)
+                        );
+                        Ok(())
+                    }
+                    Err(e) => {
+                        self.bar_finish(
+                            Crowbar::Spinner(bar),
+                            CrowbarState::Error,
+                            &format!("{error}", error = e),
+                        );
+                        self.bar_finish(Crowbar::Main, CrowbarState::Error, &t!("ui.error"));
+                        Err(e)
+                    }
                 }
-            },
+            }
             None => {
                 self.bar_finish(
                     Crowbar::Spinner(bar),
@@ -872,49 +1887,163 @@ impl<'a> Book<'a> {
     /// * `render_format`, which won't do anything if `output.{format}` isn't specified
     ///   in the book configuration file.
 
-    pub fn render_format_to_file<P: Into<PathBuf>>(&mut self, format: &str, path: P) -> Result<()> {
+    pub fn render_format_to_file<P: Into<PathBuf>>(&self, format: &str, path: P) -> Result<()> {
+        self.run_pre_render_hooks()?;
         let bar = self.add_spinner_to_multibar(format);
         self.render_format_to_file_with_bar(format, path, bar)?;
+        self.write_manifest()?;
         self.bar_finish(Crowbar::Main, CrowbarState::Success, &t!("ui.finished"));
         Ok(())
     }
 
+    /// Writes the manifest of the files rendered since the last one, if `manifest.file` is set
+    fn write_manifest(&self) -> Result<()> {
+        let rendered = std::mem::take(&mut *self.rendered.lock().unwrap());
+        self.timings.time("manifest", || manifest::write(self, &rendered))
+    }
+
     /// Adds a chapter to the book.
     ///
     /// This method is the backend used both by `add_chapter` and `add_chapter_from_source`.
+    ///
+    /// `file` doesn't need to be an existing file: it is only used to resolve relative
+    /// links and images, to guess the input format from its extension, and in error
+    /// messages, where it is displayed as given.
     pub fn add_chapter_from_named_source<R: Read>(
         &mut self,
         number: Number,
         file: &str,
-        mut source: R,
+        source: R,
         mut add_title_if_empty: bool,
     ) -> Result<&mut Self> {
+        self.check_cancelled()?;
         self.bar_set_message(
             Crowbar::Main,
             &t!("ui.processing_file", file = file),
         );
-        let mut content = String::new();
-        source.read_to_string(&mut content).map_err(|_| {
-            Error::parser(
-                &self.source,
-                t!(
-                    "error.utf8",
-                    file = misc::normalize(file)
-                ),
-            )
-        })?;
+        let error_source = if file.is_empty() {
+            self.source.clone()
+        } else {
+            Source::new(file)
+        };
+        let mut bytes = vec![];
+        // Larger chapters aren't read to their end, so they can't fill the memory
+        let max_size = self.options.get_size("limits.chapter_size").ok();
+        let read_limit = max_size.map_or(u64::MAX, |max| max.saturating_add(1));
+        source
+            .take(read_limit)
+            .read_to_end(&mut bytes)
+            .map_err(|err| {
+                Error::default(&error_source, t!("error.read_file", file = file)).with_cause(err)
+            })?;
+        if max_size.map_or(false, |max| bytes.len() as u64 > max) {
+            let limit = self.options.get_str("limits.chapter_size").unwrap();
+            return Err(Error::default(
+                &error_source,
+                t!("limits.chapter_size", limit = limit),
+            ));
+        }
+        let mut content = encoding::decode(
+            &error_source,
+            &bytes,
+            self.options.get_str("input.encoding").unwrap(),
+        )?;
+        if self.options.get_bool("input.normalize").unwrap() && !is_nfc(&content) {
+            content = content.nfc().collect();
+        }
 
         // parse the file
         self.bar_set_message(Crowbar::Second, &t!("ui.parsing..."));
 
+        let dialect = self.options.get_str("input.markdown").unwrap();
+        if dialect != "commonmark" && dialect != "extended" {
+            return Err(Error::book_option(
+                &self.source,
+                t!("error.input_markdown", value = dialect),
+            ));
+        }
+        if self.link_refs.is_none() {
+            self.link_refs = Some(LinkRefs::from(&self.options)?);
+        }
         let mut parser = Parser::from(self);
         parser.set_source_file(file);
+        // Front matter is always parsed for chapter metadata
+        parser.frontmatter(true);
         let mut yaml_block = String::from("");
-        let mut tokens = parser.parse(&content, Option::Some(&mut yaml_block))?;
+        let phase = if file.is_empty() {
+            String::from("parse")
+        } else {
+            format!("parse: {file}")
+        };
+        let mut tokens = self.timings.time(&phase, || {
+            match self.input_format(file) {
+                "pandoc-json" => parser.parse_pandoc_json(&content, Option::Some(&mut yaml_block)),
+                "org" => parser.parse_org(&content, Option::Some(&mut yaml_block)),
+                _ => parser.parse(&content, Option::Some(&mut yaml_block)),
+            }
+        })?;
 
         // Parse YAML block
-        self.parse_yaml(&yaml_block);
+        let chapter_metadata = self.parse_yaml(&yaml_block);
+
+        // Shortcodes are expanded once options set by the YAML block are known
+        let shortcodes = Shortcodes::from(&self.options);
+        if !shortcodes.is_empty() {
+            shortcodes.expand(&Source::new(file), &mut parser, &mut tokens)?;
+        }
         self.features = self.features | parser.features();
+        if let Some(ref mut link_refs) = self.link_refs {
+            for label in parser.used_link_refs() {
+                link_refs.mark_used(label);
+            }
+        }
+
+        let mut chapter = Chapter::new(number, file, vec![]);
+        for (key, value) in chapter_metadata {
+            match (key.as_str(), value) {
+                ("chapter.date", Yaml::String(date)) if Date::parse(&date).is_some() => {
+                    chapter.date = Some(date)
+                }
+                ("chapter.title", Yaml::String(title)) => chapter.title = Some(title),
+                ("chapter.status", Yaml::String(status)) => chapter.status = Some(status),
+                ("chapter.synopsis", Yaml::String(synopsis)) => chapter.synopsis = Some(synopsis),
+                ("chapter.layout", Yaml::String(layout))
+                    if layout == "landscape" || layout == "two_columns" =>
+                {
+                    chapter.layout = Some(layout)
+                }
+                ("chapter.tags", Yaml::String(tags)) => {
+                    chapter.tags = tags.split(',').map(|tag| tag.trim().to_owned()).collect()
+                }
+                ("chapter.tags", Yaml::Array(ref tags)) if tags.iter().all(|tag| tag.as_str().is_some()) => {
+                    chapter.tags = tags.iter().map(|tag| tag.as_str().unwrap().to_owned()).collect()
+                }
+                (key, value) => error!(
+                    "{}",
+                    t!(
+                        "error.yaml_set",
+                        key = key,
+                        value = format!("{:?}", value),
+                        err = t!("error.chapter_metadata")
+                    )
+                ),
+            }
+        }
+
+        if chapter.status.as_deref() == Some("draft")
+            && !self.options.get_bool("input.drafts").unwrap()
+        {
+            debug!("{}", t!("debug.skip_draft", file = file));
+            self.bar_set_message(Crowbar::Second, "");
+            return Ok(self);
+        }
+
+        if !self.filters.is_empty() || self.options.get_str_vec("input.filters").is_ok() {
+            tokens = self.run_filters(file, tokens)?;
+            self.features = self.features | filter::features(&tokens);
+        }
+        self.check_images(file, &tokens)?;
+        self.check_code(file, parser.code_blocks())?;
 
         // transform the AST to make local links and images relative to `book` directory
         let offset = if let Some(f) = Path::new(file).parent() {
@@ -925,10 +2054,7 @@ impl<'a> Book<'a> {
         if offset.starts_with("..") {
             debug!(
                 "{}",
-                t!(
-                    "warn.above",
-                    file = misc::normalize(file)
-                )
+                t!("warn.above", file = file)
             );
         }
 
@@ -953,12 +2079,30 @@ impl<'a> Book<'a> {
         }
         // add offset
         ResourceHandler::add_offset(link_offset, image_offset, &mut tokens);
+        self.check_image_limits(file, &tokens)?;
 
         // If files_mean_chapters is set, override the default setting
         if let Ok(x) = self.options.get_bool("crowbook.files_mean_chapters") {
             add_title_if_empty = x;
         }
 
+        // Replace the title of the chapter if it is set by its metadata
+        if let Some(ref title) = chapter.title {
+            let title = Parser::from(self).parse_inline(title)?;
+            match tokens.iter_mut().find(|token| matches!(**token, Token::Header(1, _))) {
+                Some(Token::Header(_, ref mut inner)) => {
+                    // Attributes of the header are kept
+                    let attributes = match inner.first() {
+                        Some(Token::HeaderAttributes(..)) => Some(inner.remove(0)),
+                        _ => None,
+                    };
+                    *inner = title;
+                    inner.splice(0..0, attributes);
+                }
+                _ => tokens.insert(0, Token::Header(1, title)),
+            }
+        }
+
         // Add a title if there is none in the chapter (unless this is subchapter)
         if add_title_if_empty {
             misc::insert_title(&mut tokens);
@@ -966,11 +2110,23 @@ impl<'a> Book<'a> {
 
         self.bar_set_message(Crowbar::Second, "");
 
-        self.chapters.push(Chapter::new(number, file, tokens));
+        chapter.content = tokens;
+        self.chapters.push(chapter);
 
         Ok(self)
     }
 
+    /// Returns the input format of a chapter file, according to its
+    /// extension or, if it isn't recognized, to the `input.format` option
+    fn input_format(&self, file: &str) -> &str {
+        match Path::new(file).extension().and_then(|e| e.to_str()) {
+            Some("md") | Some("markdown") => "markdown",
+            Some("json") => "pandoc-json",
+            Some("org") => "org",
+            _ => self.options.get_str("input.format").unwrap(),
+        }
+    }
+
     /// Adds a chapter, as a file name, to the book
     pub fn add_subchapter(&mut self, level: i32, file: &str) -> Result<&mut Self> {
         let number = {
@@ -1032,6 +2188,14 @@ impl<'a> Book<'a> {
                 format!("{}", path.display()),
             )
         })?;
+        if self.options.is_sandboxed()
+            && !(misc::is_contained(file) && misc::is_inside(&path, &self.root))
+        {
+            return Err(Error::default(
+                &self.source,
+                t!("error.sandbox", file = misc::normalize(file)),
+            ));
+        }
 
         self.add_chapter_from_named_source(number, file, f, add_title_if_empty)
     }
@@ -1047,6 +2211,9 @@ impl<'a> Book<'a> {
     /// * `content`: the content of the chapter.
     ///
     /// **Returns** an error if there was some error parsing `content`.
+    ///
+    /// # See also
+    /// * `add_chapter_from_reader`, which also sets the name of the chapter.
     pub fn add_chapter_from_source<R: Read>(
         &mut self,
         number: Number,
@@ -1056,11 +2223,47 @@ impl<'a> Book<'a> {
         self.add_chapter_from_named_source(number, "", source, add_title_if_empty)
     }
 
+    /// Adds a chapter to the book from a source, with a logical name
+    ///
+    /// This is useful to feed content that doesn't come from a file (e.g. in a web
+    /// application), while still having meaningful error messages.
+    ///
+    /// # Arguments
+    /// * `number`: specifies if the chapter must be numbered, not numbered, or if its title
+    ///   must be hidden. See `Number`.
+    /// * `name`: the name of the chapter, used in error messages and to resolve relative
+    ///   links and images, e.g. `chapter_1.md`;
+    /// * `source`: the content of the chapter.
+    ///
+    /// **Returns** an error if `source` could not be read or if there was some error
+    /// parsing it.
+    pub fn add_chapter_from_reader<R: Read>(
+        &mut self,
+        number: Number,
+        name: &str,
+        source: R,
+    ) -> Result<&mut Self> {
+        let add_title_if_empty = !matches!(number, Number::Unnumbered | Number::Hidden);
+        self.add_chapter_from_named_source(number, name, source, add_title_if_empty)
+    }
+
+    /// Adds a chapter to the book from a string, with a logical name
+    ///
+    /// See `add_chapter_from_reader`.
+    pub fn add_chapter_from_str(
+        &mut self,
+        number: Number,
+        name: &str,
+        content: &str,
+    ) -> Result<&mut Self> {
+        self.add_chapter_from_reader(number, name, content.as_bytes())
+    }
+
     /// Either clean a string or does nothing,
     /// according to book `lang` and `autoclean` options
     #[doc(hidden)]
     pub fn clean<'s, S: Into<Cow<'s, str>>>(&self, text: S) -> Cow<'s, str> {
-        self.cleaner.clean(text.into())
+        self.timings.time("clean", || self.cleaner.clean(text.into()))
     }
 
     /// Returns a template
@@ -1093,6 +2296,8 @@ impl<'a> Book<'a> {
             "html.css.print" => html::PRINT_CSS,
             "html.standalone.template" => html_single::HTML,
             "html.standalone.js" => html_single::JS,
+            "html.interactive.css" => html_single::INTERACTIVE_CSS,
+            "html.interactive.js" => html_single::INTERACTIVE_JS,
             "html.js" => html::JS,
             "html.dir.template" => html_dir::TEMPLATE,
             "html.highlight.js" => highlight::JS,
@@ -1100,6 +2305,12 @@ impl<'a> Book<'a> {
             "html.if.js" => html_if::JS,
             "html.if.new_game" => html_if::NEW_GAME,
             "tex.template" => latex::TEMPLATE,
+            "tex.template.preamble" => latex::PREAMBLE,
+            "tex.template.titlepage" => latex::TITLEPAGE,
+            "tex.template.headers" => latex::HEADERS,
+            "tex.template.chapter_heading" => latex::CHAPTER_HEADING,
+            "sample.template" => sample::PAGE,
+            "site.template" => site::TEMPLATE,
             _ => {
                 return Err(Error::config_parser(
                     &self.source,
@@ -1118,19 +2329,50 @@ impl<'a> Book<'a> {
                     t!("error.read_file", file = s),
                 )
             })?;
+            if res.trim_start().starts_with(template_blocks::EXTENDS) {
+                // Only override some blocks of the default template
+                res = template_blocks::extend(fallback, &res).map_err(|err| {
+                    Error::template(
+                        &self.source,
+                        t!("template.extend", template = template, error = err),
+                    )
+                })?;
+            }
             Ok(Cow::Owned(res))
         } else {
             Ok(Cow::Borrowed(fallback))
         }
     }
 
-    /// Sets the chapter_template once and for all (also sets part template)
-    fn set_chapter_template(&mut self) -> Result<()> {
+    /// Sets the chapter_template once and for all (also sets part and section templates)
+    pub(crate) fn set_chapter_template(&mut self) -> Result<()> {
         self.register_template("rendering.chapter.template")?;
         self.register_template("rendering.part.template")?;
+        self.register_template("rendering.section.template")?;
+        self.register_template("rendering.subsection.template")?;
+        self.register_template("rendering.subsubsection.template")?;
         Ok(())
     }
 
+    /// Renders one of the templates registered by `set_chapter_template`
+    fn render_header_template(
+        &self,
+        key: &str,
+        data: &BTreeMap<String, upon::Value>,
+    ) -> Result<String> {
+        self.template_vars.record(key, data);
+        match self.registry.get_template(key) {
+            Some(template) => Ok(template.render(data).to_string()?),
+            // The template is registered when the book is loaded, but chapters can also be
+            // added to a new book
+            None => Ok(self
+                .registry
+                .compile(self.options.get_str(key).unwrap())?
+                .render(data)
+                .to_string()?),
+        }
+    }
+
     
     fn register_template(&mut self, tpl: &'static str) -> Result<()> {
         self.registry.add_template(tpl,
@@ -1183,11 +2425,35 @@ impl<'a> Book<'a> {
         header: Header,
         n: i32,
         title: String,
+        f: F,
+    ) -> Result<HeaderData>
+    where
+        F: FnMut(&str) -> Result<String>,
+    {
+        let numbers = match header {
+            Header::Part => [n, 0],
+            Header::Chapter => [0, n],
+        };
+        self.get_header_numbers(header, &numbers, title, f)
+    }
+
+    /// Same as `get_header`, except the numbers of all levels (part, chapter, section, ...)
+    /// are given, so templates can use them
+    #[doc(hidden)]
+    pub fn get_header_numbers<F>(
+        &self,
+        header: Header,
+        numbers: &[i32],
+        title: String,
         mut f: F,
     ) -> Result<HeaderData>
     where
         F: FnMut(&str) -> Result<String>,
     {
+        let n = match header {
+            Header::Part => numbers[0],
+            Header::Chapter => numbers[1],
+        };
         let header_type = match header {
             Header::Part => "part",
             Header::Chapter => "chapter",
@@ -1206,11 +2472,9 @@ impl<'a> Book<'a> {
         data.insert(format!("{header_type}_title"), title.clone().into());
         data.insert(header_type.into(), header_name.clone().into());
         data.insert("number".into(), number.clone().into());
+        insert_numbers(&mut data, numbers);
 
-        let res = self.registry.get_template(&format!("rendering.{header_type}.template"))
-            .expect(&format!("Error accessing template rendering.{header_type}.template"))
-            .render(&data)
-            .to_string()?;
+        let res = self.render_header_template(&format!("rendering.{header_type}.template"), &data)?;
         Ok(HeaderData {
             text: res,
             number,
@@ -1219,6 +2483,36 @@ impl<'a> Book<'a> {
         })
     }
 
+    /// Returns the string corresponding to the numbers and title of a section (`level` being
+    /// 2 for a section, 3 for a subsection, and so on), using the `rendering.section.template`,
+    /// `rendering.subsection.template` or `rendering.subsubsection.template` template
+    ///
+    /// `number` is the already formatted number, e.g. "1.2.".
+    #[doc(hidden)]
+    pub fn get_section_header(
+        &self,
+        level: i32,
+        numbers: &[i32],
+        number: String,
+        title: String,
+    ) -> Result<HeaderData> {
+        let key = match level {
+            ..=2 => "rendering.section.template",
+            3 => "rendering.subsection.template",
+            _ => "rendering.subsubsection.template",
+        };
+        let mut data = BTreeMap::new();
+        data.insert("number".to_owned(), number.clone().into());
+        data.insert("title".to_owned(), title.clone().into());
+        insert_numbers(&mut data, numbers);
+        Ok(HeaderData {
+            text: self.render_header_template(key, &data)?,
+            number,
+            header: String::new(),
+            title,
+        })
+    }
+
     /// Returns the string corresponding to a number, title, and the numbering template for chapter
     #[doc(hidden)]
     pub fn get_chapter_header<F>(&self, n: i32, title: String, f: F) -> Result<HeaderData>
@@ -1237,6 +2531,44 @@ impl<'a> Book<'a> {
         self.get_header(Header::Part, n, title, f)
     }
 
+    /// Adds the metadata of a chapter to the data of a template, i.e. `chapter_date`,
+    /// `chapter_status` and `chapter_synopsis` (with `has_chapter_{key}` booleans and
+    /// `chapter_synopsis_raw`), using `f` to render the synopsis.
+    pub fn add_chapter_metadata<F>(
+        &self,
+        chapter: &Chapter,
+        data: &mut BTreeMap<String, upon::Value>,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&str) -> Result<String>,
+    {
+        let lang = self.options.get_str("lang").unwrap();
+        let date = chapter.date.as_deref().and_then(Date::parse);
+        data.insert(
+            "chapter_date".into(),
+            date.map(|date| date.format(lang)).unwrap_or_default().into(),
+        );
+        data.insert(
+            "chapter_date_iso".into(),
+            date.map(|date| date.to_string()).unwrap_or_default().into(),
+        );
+        data.insert("has_chapter_date".into(), date.is_some().into());
+        data.insert("chapter_status".into(), chapter.status.clone().unwrap_or_default().into());
+        data.insert("has_chapter_status".into(), chapter.status.is_some().into());
+        let (synopsis, synopsis_raw) = match chapter.synopsis {
+            Some(ref synopsis) => (
+                f(synopsis)?,
+                view_as_text(&Parser::from(self).parse_inline(synopsis)?),
+            ),
+            None => (String::new(), String::new()),
+        };
+        data.insert("chapter_synopsis".into(), synopsis.into());
+        data.insert("chapter_synopsis_raw".into(), synopsis_raw.into());
+        data.insert("has_chapter_synopsis".into(), chapter.synopsis.is_some().into());
+        Ok(())
+    }
+
     /// Returns a `Map of Key/Value` (used by `Upon` for templating), to be used (and completed)
     /// by renderers. It fills it with the metadata options.
     ///
@@ -1252,11 +2584,33 @@ impl<'a> Book<'a> {
     {
         let mut m: BTreeMap<String, upon::Value> = BTreeMap::new();
         m.insert("crowbook_version".into(), env!("CARGO_PKG_VERSION").into());
-        m.insert(format!("lang_{}", self.options.get_str("lang").unwrap()), true.into());
+        let lang = self.options.get_str("lang").unwrap();
+        m.insert(format!("lang_{lang}"), true.into());
+        if let Some(ref profile) = self.profile {
+            m.insert(format!("profile_{profile}"), true.into());
+        }
+        if let Some(ref git) = self.git {
+            git.add_metadata(&mut m);
+        }
 
         // Add metadata to map
         for key in self.options.get_metadata() {
-            if let Ok(s) = self.options.get_str(key) {
+            // Dates written in free text by older versions are displayed as they are
+            let date = match self.options.is_date(key) {
+                true => {
+                    m.insert(format!("{}_iso", key.replace('.', "_")), "".into());
+                    self.options.get_date(key).ok()
+                }
+                false => None,
+            };
+            if let Some(date) = date {
+                // Dates are written in the language of the book, and in ISO 8601 for machines
+                let key = key.replace('.', "_");
+                m.insert(key.clone(), date.format(lang).into());
+                m.insert(format!("{key}_raw"), date.format(lang).into());
+                m.insert(format!("{key}_iso"), date.to_string().into());
+                m.insert(format!("has_{key}"), true.into());
+            } else if let Ok(s) = self.options.get_str(key) {
                 let key = key.replace('.', "_");
 
                 // Don't render lang as markdown
@@ -1295,6 +2649,24 @@ impl<'a> Book<'a> {
             }
         }
 
+        // Add the revision history, whose dates and changes can contain Markdown too
+        let mut revisions = vec![];
+        if let Ok(list) = self.options.get_str_vec("revisions") {
+            for (date, changes) in list.iter().filter_map(|entry| imprint::parse_revision(entry)) {
+                let mut revision = BTreeMap::new();
+                revision.insert(String::from("date"), upon::Value::from(f(date)?));
+                revision.insert(String::from("changes"), upon::Value::from(f(changes)?));
+                revisions.push(upon::Value::Map(revision));
+            }
+        }
+        let has_revisions = !revisions.is_empty();
+        m.insert("has_revisions".into(), has_revisions.into());
+        m.insert(
+            "revisions_table".into(),
+            (has_revisions && self.options.get_bool("revisions.table").unwrap()).into(),
+        );
+        m.insert("revisions".into(), upon::Value::List(revisions));
+
         // Add localization strings
         let hash = lang::get_hash(self.options.get_str("lang").unwrap());
         for (key, value) in hash {
@@ -1333,7 +2705,12 @@ impl<'a> Book<'a> {
     /// ---
     /// or
     /// ...
-    fn parse_yaml(&mut self, yaml_block: &String) {
+    ///
+    /// Keys starting with `chapter.` don't set book options but metadata of the current
+    /// chapter, which are returned. Other keys are ignored unless `input.yaml_blocks` is set.
+    fn parse_yaml(&mut self, yaml_block: &String) -> Vec<(String, Yaml)> {
+        let mut chapter_metadata = vec![];
+        let yaml_blocks = self.options.get_bool("input.yaml_blocks").unwrap();
         // Checks that this is valid YAML
         match YamlLoader::load_from_str(yaml_block) {
             Ok(docs) => {
@@ -1342,6 +2719,17 @@ impl<'a> Book<'a> {
                 if !docs.is_empty() && docs[0].as_hash().is_some() {
                     let hash = docs[0].as_hash().unwrap();
                     for (key, value) in hash {
+                        if let Some(key) = key.as_str().filter(|key| key.starts_with("chapter.")) {
+                            chapter_metadata.push((key.to_owned(), value.clone()));
+                            continue;
+                        }
+                        if !yaml_blocks {
+                            debug!(
+                                "{}",
+                                t!("debug.yaml_ignore_key", key = format!("{:?}", key))
+                            );
+                            continue;
+                        }
                         match self
                             .options
                             //todo: remove clone
@@ -1407,10 +2795,24 @@ impl<'a> Book<'a> {
                 );
             }
         }
+        chapter_metadata
     }
 
     // Update the cleaner according to autoclean and lang options
-    fn update_cleaner(&mut self) {
+    pub(crate) fn update_cleaner(&mut self) {
+        let lang = self.options.get_str("lang").unwrap().to_lowercase();
+        let french = lang.starts_with("fr");
+        let mut symbols = vec![];
+        if let Ok(list) = self.options.get_str_vec("input.clean.symbols") {
+            for symbol in list {
+                match symbol.rsplit_once('=') {
+                    Some((from, to)) if !from.is_empty() => {
+                        symbols.push((from.to_owned(), to.to_owned()))
+                    }
+                    _ => warn!("{}", t!("warn.invalid_symbol", symbol = symbol)),
+                }
+            }
+        }
         let params = CleanerParams {
             smart_quotes: self.options.get_bool("input.clean.smart_quotes").unwrap(),
             ligature_dashes: self
@@ -1421,10 +2823,20 @@ impl<'a> Book<'a> {
                 .options
                 .get_bool("input.clean.ligature.guillemets")
                 .unwrap(),
+            ellipsis: self.options.get_bool("input.clean.ellipsis").unwrap_or(french),
+            // Only French and English ordinals are supported
+            ordinals: self.options.get_bool("input.clean.ordinals").unwrap()
+                && (french || lang.starts_with("en")),
+            fractions: self.options.get_bool("input.clean.fractions").unwrap(),
+            symbols,
+            nb_space: self.options.get_char("input.clean.french.nb_space").unwrap(),
+            nb_space_narrow: self
+                .options
+                .get_char("input.clean.french.nb_space_narrow")
+                .unwrap(),
         };
         if self.options.get_bool("input.clean").unwrap() {
-            let lang = self.options.get_str("lang").unwrap().to_lowercase();
-            let cleaner: Box<dyn Cleaner> = if lang.starts_with("fr") {
+            let cleaner: Box<dyn Cleaner> = if french {
                 Box::new(French::new(params))
             } else {
                 Box::new(Default::new(params))
@@ -1442,3 +2854,60 @@ impl std::default::Default for Book<'_> {
     }
 }
 
+/// Adds the URLs of images to `urls`
+fn images<'t>(tokens: &'t [Token], urls: &mut Vec<&'t str>) {
+    for token in tokens {
+        match *token {
+            Token::Image(ref url, ..) | Token::StandaloneImage(ref url, ..) => urls.push(url),
+            _ => {
+                if let Some(inner) = token.inner() {
+                    images(inner, urls);
+                }
+            }
+        }
+    }
+}
+
+/// Adds the URLs of images without alternative text to `urls`
+fn images_without_alt<'t>(tokens: &'t [Token], urls: &mut Vec<&'t str>) {
+    for token in tokens {
+        match *token {
+            Token::Image(ref url, _, ref alt) | Token::StandaloneImage(ref url, _, ref alt)
+                if view_as_text(alt).trim().is_empty() =>
+            {
+                urls.push(url)
+            }
+            _ => {
+                if let Some(inner) = token.inner() {
+                    images_without_alt(inner, urls);
+                }
+            }
+        }
+    }
+}
+
+/// Adds the numbers of each level to the data of a numbering template, e.g.
+/// `chapter_number` ("7"), `chapter_padded` ("07") and `chapter_roman` ("VII")
+///
+/// Roman numerals are empty for levels whose number is zero.
+fn insert_numbers(data: &mut BTreeMap<String, upon::Value>, numbers: &[i32]) {
+    const LEVELS: [&str; 7] = [
+        "part",
+        "chapter",
+        "section",
+        "subsection",
+        "subsubsection",
+        "paragraph",
+        "subparagraph",
+    ];
+    for (name, n) in LEVELS.iter().zip(numbers) {
+        let roman = if *n >= 1 {
+            format!("{:X}", Roman::from(*n as i16))
+        } else {
+            String::new()
+        };
+        data.insert(format!("{name}_number"), n.to_string().into());
+        data.insert(format!("{name}_padded"), format!("{n:02}").into());
+        data.insert(format!("{name}_roman"), roman.into());
+    }
+}
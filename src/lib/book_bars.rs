@@ -20,10 +20,11 @@
 
 use crate::book::{Book, Crowbar, CrowbarState};
 
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressState, ProgressStyle};
 use rust_i18n::t;
 
-use std::sync::Arc;
+use std::fmt;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 /// Store the progress bars needed for the book
@@ -38,8 +39,8 @@ pub struct Bars {
     pub secondbar: Option<ProgressBar>,
     // /// Guard for thread
     // pub guard: Option<thread::JoinHandle<()>>,
-    /// Spinners for each renderier
-    pub spinners: Vec<ProgressBar>,
+    /// Spinners for each renderer
+    pub spinners: Mutex<Vec<ProgressBar>>,
 }
 
 impl Bars {
@@ -51,7 +52,7 @@ impl Bars {
             mainbar: None,
             secondbar: None,
             // guard: None,
-            spinners: vec![],
+            spinners: Mutex::new(vec![]),
         }
     }
 }
@@ -86,28 +87,9 @@ impl Book<'_> {
     /// Sets a finished message to the progress bar, if it is set
     pub fn bar_finish(&self, bar: Crowbar, state: CrowbarState, msg: &str) {
         self.bar_set_style(bar, state);
-        let pb = match bar {
-            Crowbar::Main => {
-                if let Some(ref bar) = self.bars.mainbar {
-                    bar
-                } else {
-                    return;
-                }
-            }
-            Crowbar::Second => {
-                if let Some(ref bar) = self.bars.secondbar {
-                    bar
-                } else {
-                    return;
-                }
-            }
-            Crowbar::Spinner(i) => {
-                if i < self.bars.spinners.len() {
-                    &self.bars.spinners[i]
-                } else {
-                    return;
-                }
-            }
+        let pb = match self.get_bar(bar) {
+            Some(bar) => bar,
+            None => return,
         };
 
         match bar {
@@ -133,8 +115,17 @@ impl Book<'_> {
         }
     }
 
+    /// Returns a progress bar, if it exists
+    fn get_bar(&self, bar: Crowbar) -> Option<ProgressBar> {
+        match bar {
+            Crowbar::Main => self.bars.mainbar.clone(),
+            Crowbar::Second => self.bars.secondbar.clone(),
+            Crowbar::Spinner(i) => self.bars.spinners.lock().unwrap().get(i).cloned(),
+        }
+    }
+
     /// Adds a spinner labeled key to the multibar, and set mainbar to "rendering"
-    pub fn add_spinner_to_multibar(&mut self, key: &str) -> usize {
+    pub fn add_spinner_to_multibar(&self, key: &str) -> usize {
         if let Some(ref multibar) = self.bars.multibar {
             if let Some(ref mainbar) = self.bars.mainbar {
                 mainbar.set_message(t!("ui.rendering"));
@@ -144,8 +135,11 @@ impl Book<'_> {
             bar.enable_steady_tick(Duration::from_millis(200));
             bar.set_message(t!("ui.waiting"));
             bar.set_prefix(format!("{key}:"));
-            let i = self.bars.spinners.len();
-            self.bars.spinners.push(bar);
+            let i = {
+                let mut spinners = self.bars.spinners.lock().unwrap();
+                spinners.push(bar);
+                spinners.len() - 1
+            };
             self.bar_set_style(Crowbar::Spinner(i), CrowbarState::Running);
 
             i
@@ -155,56 +149,40 @@ impl Book<'_> {
     }
 
     pub fn bar_set_message(&self, bar: Crowbar, msg: &str) {
-        let bar = match bar {
-            Crowbar::Main => {
-                if let Some(ref bar) = self.bars.mainbar {
-                    bar
-                } else {
-                    return;
-                }
-            }
-            Crowbar::Second => {
-                if let Some(ref bar) = self.bars.secondbar {
-                    bar
-                } else {
-                    return;
-                }
-            }
-            Crowbar::Spinner(i) => {
-                if i < self.bars.spinners.len() {
-                    &self.bars.spinners[i]
-                } else {
-                    return;
-                }
-            }
+        let bar = match self.get_bar(bar) {
+            Some(bar) => bar,
+            None => return,
         };
         bar.set_message(msg.to_owned());
     }
 
+    /// Turns the spinner of a format into a bar showing the steps of its rendering, with an ETA
+    pub fn bar_set_steps(&self, format: &str, i: usize, n: usize) {
+        let prefix = format!("{format}:");
+        let spinners = self.bars.spinners.lock().unwrap();
+        if let Some(bar) = spinners.iter().find(|bar| bar.prefix() == prefix) {
+            let style = ProgressStyle::default_bar()
+                .template("{prefix} {bar:20.cyan/blue} {msg} {eta}")
+                .expect("Error in steps progress bar style")
+                .progress_chars("##-")
+                .with_key("eta", |state: &ProgressState, w: &mut dyn fmt::Write| {
+                    if state.pos() > 0 {
+                        let eta = HumanDuration(state.eta()).to_string();
+                        let _ = write!(w, "{}", t!("ui.eta", duration = eta));
+                    }
+                });
+            bar.set_style(style);
+            bar.set_length(n as u64);
+            bar.set_position(i as u64 - 1);
+            bar.set_message(t!("ui.step", step = i, steps = n));
+        }
+    }
+
     /// Sets the style of a  bar
     fn bar_set_style(&self, bar: Crowbar, state: CrowbarState) {
-        let pb = match bar {
-            Crowbar::Main => {
-                if let Some(ref bar) = self.bars.mainbar {
-                    bar
-                } else {
-                    return;
-                }
-            }
-            Crowbar::Second => {
-                if let Some(ref bar) = self.bars.secondbar {
-                    bar
-                } else {
-                    return;
-                }
-            }
-            Crowbar::Spinner(i) => {
-                if i < self.bars.spinners.len() {
-                    &self.bars.spinners[i]
-                } else {
-                    return;
-                }
-            }
+        let pb = match self.get_bar(bar) {
+            Some(bar) => bar,
+            None => return,
         };
         let emoji = self.bars.emoji;
         let mut style = match bar {
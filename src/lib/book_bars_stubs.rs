@@ -41,9 +41,12 @@ impl Book<'_> {
     pub fn inc_second_bar(&self) {}
 
     /// Adds a spinner labeled key to the multibar, and set mainbar to "rendering"
-    pub fn add_spinner_to_multibar(&mut self, _: &str) -> usize {
+    pub fn add_spinner_to_multibar(&self, _: &str) -> usize {
         0
     }
 
     pub fn bar_set_message(&self, _: Crowbar, _: &str) {}
+
+    /// Turns the spinner of a format into a bar showing the steps of its rendering
+    pub fn bar_set_steps(&self, _: &str, _: usize, _: usize) {}
 }
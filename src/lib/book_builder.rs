@@ -0,0 +1,148 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Caribon is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Construction of books in memory, without any configuration file.
+
+use crate::book::Book;
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::number::Number;
+
+use yaml_rust::Yaml;
+
+/// Builds a book in memory, without any configuration file nor chapter files
+///
+/// Errors (e.g. an invalid option or a chapter that can't be parsed) are reported
+/// by `build`.
+///
+/// # Example
+///
+/// ```
+/// use crowbook::BookBuilder;
+/// let book = BookBuilder::new()
+///     .title("A book")
+///     .author("Joan Doe")
+///     .lang("en")
+///     .option("rendering.num_depth", "2")
+///     .add_chapter_from_str("# The beginning\n\nBla, bla, bla")
+///     .build()
+///     .unwrap();
+/// let mut html = vec![];
+/// book.render_format_to("html", &mut html).unwrap();
+/// ```
+pub struct BookBuilder<'a> {
+    book: Book<'a>,
+    chapters: Vec<(Number, String)>,
+    error: Option<Error>,
+}
+
+impl<'a> BookBuilder<'a> {
+    /// Creates a new builder, for a book with default options and no chapters
+    pub fn new() -> BookBuilder<'a> {
+        BookBuilder {
+            book: Book::new(),
+            chapters: vec![],
+            error: None,
+        }
+    }
+
+    /// Sets the title of the book
+    pub fn title<S: Into<String>>(self, title: S) -> Self {
+        self.set_str("title", title.into())
+    }
+
+    /// Sets the subtitle of the book
+    pub fn subtitle<S: Into<String>>(self, subtitle: S) -> Self {
+        self.set_str("subtitle", subtitle.into())
+    }
+
+    /// Sets the author of the book
+    pub fn author<S: Into<String>>(self, author: S) -> Self {
+        self.set_str("author", author.into())
+    }
+
+    /// Sets the language of the book, e.g. `en` or `fr`
+    pub fn lang<S: Into<String>>(self, lang: S) -> Self {
+        self.set_str("lang", lang.into())
+    }
+
+    /// Sets an option, whose value is parsed as YAML like in a configuration file,
+    /// e.g. `option("rendering.num_depth", "2")`
+    pub fn option(mut self, key: &str, value: &str) -> Self {
+        if self.error.is_none() {
+            if let Err(err) = self.book.options.set(key, value) {
+                self.error = Some(err);
+            }
+        }
+        self
+    }
+
+    /// Adds a numbered chapter, written in Markdown
+    pub fn add_chapter_from_str<S: Into<String>>(self, content: S) -> Self {
+        self.add_numbered_chapter_from_str(Number::Default, content)
+    }
+
+    /// Adds a chapter written in Markdown, specifying how it is numbered
+    /// (e.g. `Number::Unnumbered`)
+    pub fn add_numbered_chapter_from_str<S: Into<String>>(mut self, number: Number, content: S) -> Self {
+        self.chapters.push((number, content.into()));
+        self
+    }
+
+    /// Adds a filter, transforming the tokens of each chapter after it has been parsed
+    pub fn filter<F: Filter + 'static>(mut self, filter: F) -> Self {
+        self.book.add_filter(filter);
+        self
+    }
+
+    /// Builds the book, parsing its chapters
+    ///
+    /// **Returns** the first error that occurred while setting options or parsing chapters.
+    pub fn build(self) -> Result<Book<'a>> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let mut book = self.book;
+        // Chapters are only parsed now, so they are affected by all options
+        book.update_cleaner();
+        for (number, content) in self.chapters {
+            book.add_chapter_from_source(number, content.as_bytes(), true)?;
+        }
+        book.set_chapter_template()?;
+        Ok(book)
+    }
+
+    /// Sets a string option, without parsing its value as YAML
+    fn set_str(mut self, key: &str, value: String) -> Self {
+        if self.error.is_none() {
+            if let Err(err) = self
+                .book
+                .options
+                .set_yaml(Yaml::String(key.to_owned()), Yaml::String(value))
+            {
+                self.error = Some(err);
+            }
+        }
+        self
+    }
+}
+
+impl Default for BookBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
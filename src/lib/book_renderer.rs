@@ -16,16 +16,17 @@
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::book::Book;
+use crate::capabilities::{Fallback, Feature};
 use crate::error::{Error, Result, Source};
 
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
 use std::path::Path;
 use rust_i18n::t;
 
 /// Trait that must be implemented by the various renderers to render a whole book.
 
-pub trait BookRenderer: Sync {
+pub trait BookRenderer: Send + Sync {
     /// Path destination when output is set to auto
     fn auto_path(&self, _book_file: &str) -> Result<String> {
         Err(Error::default(
@@ -34,18 +35,45 @@ pub trait BookRenderer: Sync {
         ))
     }
 
+    /// Returns the external programs that are needed to render the book with its
+    /// current options, so it is possible to check they are installed before starting
+    /// to render it.
+    ///
+    /// The default implementation doesn't require any.
+    fn required_commands(&self, _book: &Book) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// Returns the features that can't be rendered as they are with the book's current
+    /// options, with what is rendered instead, so the book's author can be warned if the
+    /// book uses them.
+    ///
+    /// The default implementation supports all features.
+    fn unsupported(&self, _book: &Book) -> Vec<(Feature, Fallback)> {
+        vec![]
+    }
+
     /// Render the book and write the result to the specified writer
     fn render(&self, book: &Book, to: &mut dyn Write) -> Result<()>;
 
+    /// Checks that the book can be rendered, without writing any file nor running
+    /// external programs.
+    ///
+    /// The default implementation renders the book and discards the result, so renderers
+    /// that write files or run commands must override it.
+    fn dry_run(&self, book: &Book) -> Result<()> {
+        self.render(book, &mut io::sink())
+    }
+
     /// Render the book to a given file.
     ///
-    /// The default implementation creates a file and calls `render` to write to it,
-    /// but in some cases it might be useful to override it.
+    /// The default implementation calls `render` to write to a temporary file next to
+    /// `path`, which is renamed once the book is rendered, so the output is streamed
+    /// without leaving an empty or truncated file if it fails. In some cases it might
+    /// be useful to override it.
     fn render_to_file(&self, book: &Book, path: &Path) -> Result<()> {
-        // Not optimal but avoid creating an empty file if it fails
-        let mut content = vec![];
-        self.render(book, &mut content)?;
-        let mut file = File::create(path).map_err(|err| {
+        let temp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4().as_simple()));
+        let file = File::create(&temp).map_err(|err| {
             Error::default(
                 Source::empty(),
                 t!(
@@ -55,7 +83,8 @@ pub trait BookRenderer: Sync {
                 ),
             )
         })?;
-        file.write_all(&content).map_err(|err| {
+        let mut file = BufWriter::new(file);
+        let write_error = |err| {
             Error::default(
                 Source::empty(),
                 t!(
@@ -64,7 +93,16 @@ pub trait BookRenderer: Sync {
                     err = err
                 ),
             )
-        })?;
-        Ok(())
+        };
+        let result = self
+            .render(book, &mut file)
+            .and_then(|_| file.flush().map_err(write_error));
+        // The file must be closed before it is renamed on Windows
+        drop(file);
+        let result = result.and_then(|_| fs::rename(&temp, path).map_err(write_error));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
     }
 }
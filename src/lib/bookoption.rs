@@ -2,6 +2,8 @@ use rust_i18n::t;
 
 use crate::error::{Error, Result, Source};
 
+use yaml_rust::Yaml;
+
 /// Structure for storing a book option
 ///
 /// This Enum might grow additional variants, so library users should
@@ -122,4 +124,16 @@ impl BookOption {
             )),
         }
     }
+
+    /// Returns the BookOption as it is written in a book configuration file
+    pub(crate) fn to_yaml(&self) -> Yaml {
+        match self {
+            BookOption::String(s) | BookOption::Path(s) => Yaml::String(s.clone()),
+            BookOption::Bool(b) => Yaml::Boolean(*b),
+            BookOption::Char(c) => Yaml::String(c.to_string()),
+            BookOption::Int(i) => Yaml::Integer(i64::from(*i)),
+            BookOption::Float(f) => Yaml::Real(f.to_string()),
+            BookOption::StringVec(v) => Yaml::Array(v.iter().cloned().map(Yaml::String).collect()),
+        }
+    }
 }
@@ -1,12 +1,23 @@
 use crate::book::Book;
 use crate::bookoption::BookOption;
+use crate::config_file::ConfigFile;
+use crate::date::Date;
 use crate::error::{Error, Result, Source};
+use crate::misc;
+use crate::references;
+use crate::slug::{self, Slugifier};
 use crate::style;
+use crate::identifier;
+use crate::imprint;
+use crate::subject;
+use crate::units;
 
 use std::collections::HashMap;
 use std::env;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use yaml_rust::{Yaml, YamlLoader};
+use unicode_normalization::UnicodeNormalization;
 use rust_i18n::t;
 
 lazy_static! {
@@ -23,17 +34,40 @@ cover:path                          # {cover}
 subtitle:meta                       # {subtitle}
 license:meta                        # {license}
 version:meta                        # {version}
-date:meta                           # {date}
+date:date                           # {date}
+publication_date:date               # {publication_date}
 autograph:meta                      # {autograph}
+edition:meta                        # {edition}
+printing:meta                       # {printing}
+imprint:meta                        # {imprint}
+revisions:strvec                    # {revisions}
+revisions.table:bool:false          # {revisions_table}
+subject.keywords:strvec             # {subject_keywords}
+subject.bisac:strvec                # {subject_bisac}
+subject.thema:strvec                # {subject_thema}
+isbn:str                            # {isbn}
+isbn.epub:str                       # {isbn_epub}
+isbn.print:str                      # {isbn_print}
+doi:str                             # {doi}
+uuid:str                            # {uuid}
 
 # {output_opt}
 output:strvec                       # {output}
+output.name:path                    # {output_name}
+output.filenames:str:file           # {output_filenames}
 output.epub:path                    # {output_epub}
 output.html:path                    # {output_html}
 output.html.dir:path                # {output_html_dir}
 output.tex:path                     # {output_tex}
 output.pdf:path                     # {output_pdf}
 output.html.if:path                 # {output_if}
+output.pandoc-json:path             # {output_pandoc_json}
+output.opf:path                     # {output_opf}
+output.azw3:path                    # {output_azw3}
+output.lit:path                     # {output_lit}
+output.site:path                    # {output_site}
+output.opds:path                    # {output_opds}
+output.onix:path                    # {output_onix}
 output.base_path:path:\"\"            # {output_base_path}
 
 # {render_opt}
@@ -51,6 +85,15 @@ rendering.part.reset_counter:bool:true                                      # {r
 rendering.chapter.template:str:\"{{{{number}}}}. {{{{chapter_title}}}}\" # {chapter_template}
 
 rendering.part.template:str:\"{{{{number}}}}. {{{{part_title}}}}\" # {part_template}
+rendering.section.template:str:\"{{{{number}}}} {{{{title}}}}\" # {section_template}
+rendering.subsection.template:str:\"{{{{number}}}} {{{{title}}}}\" # {subsection_template}
+rendering.subsubsection.template:str:\"{{{{number}}}} {{{{title}}}}\" # {subsubsection_template}
+rendering.code.line_numbers:bool:false                               # {rendering_code_line_numbers}
+rendering.verse.line_numbers:int:0                                   # {rendering_verse_line_numbers}
+rendering.notes.numbering:str:arabic                                 # {notes_numbering}
+rendering.widows:int                                                 # {rendering_widows}
+rendering.orphans:int                                                # {rendering_orphans}
+rendering.strict:bool:false                                          # {rendering_strict}
 
 
 
@@ -64,25 +107,43 @@ html.highlight.theme:str            # {html_theme}
 html.header:str                     # {html_header}
 html.footer:str                     # {html_footer}
 html.css:tpl                        # {html_css}
-html.css.add:str                    # {html_css_add}
+html.css.add:strvec                 # {html_css_add}
 html.css.colors:tpl                # {css_colors}
+html.css.accent:str                 # {css_accent}
+html.css.font:str                   # {css_font}
+html.css.measure:str                # {css_measure}
+html.css.line_height:str            # {css_line_height}
 html.js:tpl                         # {html_js}
+html.js.add:strvec                  # {html_js_add}
 html.css.print:tpl                  # {css_print}
 html.highlight.js:tpl               # {highlight_js}
 html.highlight.css:tpl              # {highlight_css}
 html.side_notes:bool:false          # {side_notes}
 html.escape_nb_spaces:bool:true     # {nb_spaces}
-html.chapter.template:str:\"<h1 id = 'link-{{{{link}}}}'>{{% if has_number %}}<span class = 'chapter-header'>{{{{header}}}} {{{{number}}}}</span>{{% if has_title %}}<br />{{% endif %}}{{% endif %}}{{{{title}}}}</h1>\" # {html_chapter_template}
-html.part.template:str:\"<h2 class = 'part'>{{{{header}}}} {{{{number}}}}</h2> <h1 id = 'link-{{{{link}}}}' class = 'part'>{{{{title}}}}</h1>\" # {html_part_template}
+html.sanitize:bool:false            # {html_sanitize}
+html.base_url:str                   # {html_base_url}
+html.anchors:str:number             # {html_anchors}
+html.chapter.template:str:\"<h1 id = '{{{{anchor}}}}'{{% if has_classes %}} class = '{{{{classes}}}}'{{% endif %}}>{{% if has_number %}}<span class = 'chapter-header'>{{{{header}}}} {{{{number}}}}</span>{{% if has_title %}}<br />{{% endif %}}{{% endif %}}{{{{title}}}}</h1>\" # {html_chapter_template}
+html.part.template:str:\"<h2 class = 'part'>{{{{header}}}} {{{{number}}}}</h2> <h1 id = '{{{{anchor}}}}' class = 'part{{% if has_classes %}} {{{{classes}}}}{{% endif %}}'>{{{{title}}}}</h1>\" # {html_part_template}
 
 # {html_single_opt}
+html.standalone:bool:false                  # {html_standalone}
 html.standalone.template:tpl                # {single_html}
 html.standalone.one_chapter:bool:false  # {one_chapter}
 html.standalone.js:tpl                  # {single_js}
+html.interactive:bool:false             # {html_interactive}
+html.interactive.css:tpl                # {html_interactive_css}
+html.interactive.js:tpl                 # {html_interactive_js}
 
 
 # {html_dir_opt}
 html.dir.template:tpl               # {html_dir_template}
+html.dir.search:bool:false          # {html_dir_search}
+html.dir.cache:bool:false           # {html_dir_cache}
+html.dir.filenames:str:number       # {html_dir_filenames}
+html.feed:bool:false                # {html_feed}
+html.sitemap:bool:true              # {html_sitemap}
+html.chapter_footer_snippet:str     # {html_chapter_footer}
 
 # {html_if_opt}
 html.if.js:tpl                      # {if_js}
@@ -90,23 +151,72 @@ html.if.new_turn:str               # {if_new_turn}
 html.if.end_turn:str                # {if_end_turn}
 html.if.new_game:tpl                # {if_new_game}
 
+# {site_opt}
+site.template:tpl                   # {site_template}
+site.downloads:strvec               # {site_downloads}
+site.reader:str:read                # {site_reader}
+site.blurb:str                      # {site_blurb}
+site.nojekyll:bool:true             # {site_nojekyll}
+
 # {epub_opt}
 epub.version:int:2                  # {epub_ver}
 epub.highlight.theme:str            # {epub_theme}
 epub.css:tpl                        # {epub_css}
-epub.css.add:str                    # {epub_css_add}
+epub.css.add:strvec                 # {epub_css_add}
+epub.css.font:str                   # {css_font}
+epub.css.line_height:str            # {css_line_height}
 epub.chapter.xhtml:tpl              # {chapter_xhtml}
 epub.titlepage.xhtml:tpl            # {titlepage_xhtml}
 epub.toc.extras:bool:true           # {epub_toc}
+epub.pages:strvec                   # {epub_pages}
 epub.escape_nb_spaces:bool:true     # {nb_spaces}
 
+# {pdf_opt}
+pdf.backend:str:latex               # {pdf_backend}
+pdf.command:str                     # {pdf_command}
+pdf.bleed:str                       # {pdf_bleed}
+pdf.crop_marks:bool:false           # {pdf_crop_marks}
+pdf.grayscale:bool:false            # {pdf_grayscale}
+pdf.icc_profile:path                # {pdf_icc_profile}
+pdf.pdfx:bool:false                 # {pdf_pdfx}
+pdf.pdfa:bool:false                 # {pdf_pdfa}
+pdf.link_colors:bool:true           # {pdf_link_colors}
+
+# {calibre_opt}
+calibre.command:str:ebook-convert   # {calibre_command}
+calibre.azw3.args:str               # {calibre_args}
+calibre.lit.args:str                # {calibre_args}
+
+# {opds_opt}
+opds.formats:strvec                 # {opds_formats}
+opds.base_url:str                   # {opds_base_url}
+
+# {onix_opt}
+onix.isbn:str                       # {onix_isbn}
+onix.publisher:str                  # {onix_publisher}
+onix.contributors:strvec            # {onix_contributors}
+onix.product_form:str:EA            # {onix_product_form}
+onix.prices:strvec                  # {onix_prices}
+onix.price_type:str:\"02\"          # {onix_price_type}
+
+# {manifest_opt}
+manifest.file:path                  # {manifest_file}
+manifest.sign.key:str               # {manifest_sign_key}
+manifest.sign.command:str:gpg       # {manifest_sign_command}
+
 # {tex_opt}
 tex.cover:bool:false                # {tex_cover}
 tex.highlight.theme:str             # {tex_theme}
 tex.links_as_footnotes:bool:true    # {tex_links}
+tex.side_notes:bool:false           # {tex_side_notes}
 tex.command:str:xelatex             # {tex_command}
+tex.runs:int:3                      # {tex_runs}
 tex.escape_nb_spaces:bool:true      # {nb_spaces_tex}
 tex.template:tpl                    # {tex_tmpl}
+tex.template.preamble:tpl           # {tex_tmpl_preamble}
+tex.template.titlepage:tpl          # {tex_tmpl_titlepage}
+tex.template.headers:tpl            # {tex_tmpl_headers}
+tex.template.chapter_heading:tpl    # {tex_tmpl_chapter_heading}
 tex.template.add:str                # {tex_tmpl_add}
 tex.class:str:book                  # {tex_class}
 tex.paper.size:str:a5paper          # {tex_paper_size}
@@ -118,6 +228,17 @@ tex.title:bool:true                 # {tex_title}
 tex.font.size:int                   # {tex_font_size}
 tex.hyperref:bool:true              # {tex_hyperref}
 tex.stdpage:bool:false              # {tex_stdpage}
+tex.figure_float:bool:false         # {tex_figure_float}
+tex.figure_placement:str:htbp       # {tex_figure_placement}
+tex.figure_caption:bool:false       # {tex_figure_caption}
+tex.image_max_width:float:0.8       # {tex_image_max_width}
+tex.landscape_tables:int            # {tex_landscape_tables}
+tex.microtype:bool:false            # {tex_microtype}
+tex.chapter.recto:bool:true         # {tex_chapter_recto}
+tex.chapter.drop:str                # {tex_chapter_drop}
+tex.chapter.style:str:default       # {tex_chapter_style}
+tex.line_breaking:str:normal        # {tex_line_breaking}
+tex.hyphenation.exceptions:strvec   # {tex_hyphenation_exceptions}
 
 
 # {rs_opt}
@@ -129,13 +250,31 @@ resources.base_path.images:path:.    # {rs_img}
 resources.base_path.files:path:.     # {rs_base_files}
 resources.base_path.templates:path:. # {rs_tmpl}
 
+# {media_opt}
+media.poster:path                    # {media_poster}
+media.base_url:str                   # {media_base_url}
+
 # {input_opt}    #[serde(flatten)]
 
 input.clean:bool:true               # {autoclean}
 input.clean.smart_quotes:bool:true  # {smart_quotes}
 input.clean.ligature.dashes:bool:false # {ligature_dashes}
 input.clean.ligature.guillemets:bool:false # {ligature_guillemets}
+input.clean.ellipsis:bool           # {clean_ellipsis}
+input.clean.ordinals:bool:false     # {clean_ordinals}
+input.clean.fractions:bool:false    # {clean_fractions}
+input.clean.symbols:strvec          # {clean_symbols}
+input.clean.french.nb_space:char:nbsp # {french_nb_space}
+input.clean.french.nb_space_narrow:char:narrow-nbsp # {french_nb_space_narrow}
 input.yaml_blocks:bool:false        # {yaml}
+input.format:str:markdown           # {input_format}
+input.markdown:str:extended         # {input_markdown}
+input.raw:bool:false                # {input_raw}
+input.filters:strvec                # {input_filters}
+input.drafts:bool:true              # {input_drafts}
+input.tags.renumber:bool:false      # {tags_renumber}
+input.normalize:bool:false          # {input_normalize}
+input.encoding:str:auto             # {input_encoding}
 
 
 # {crowbook_opt}
@@ -143,7 +282,68 @@ crowbook.html_as_text:bool:true     # {html_as_text}
 crowbook.files_mean_chapters:bool   # {files_mean_chapters}
 crowbook.markdown.superscript:bool:false  # {superscript}
 crowbook.temp_dir:path:             # {tmp_dir}
+crowbook.temp_dir.keep:bool:false   # {tmp_dir_keep}
 crowbook.zip.command:str:zip        # {zip}
+crowbook.command.timeout:duration   # {command_timeout}
+crowbook.in_memory:bool:false       # {in_memory}
+crowbook.sandbox:bool:false         # {sandbox}
+crowbook.reproducible:bool:false    # {reproducible}
+crowbook.version:int                # {config_version}
+
+# {hooks_opt}
+hooks.pre_render:str                # {pre_render}
+hooks.post_render.html:str          # {post_render}
+hooks.post_render.html.dir:str      # {post_render}
+hooks.post_render.html.if:str       # {post_render}
+hooks.post_render.epub:str          # {post_render}
+hooks.post_render.pdf:str           # {post_render}
+hooks.post_render.tex:str           # {post_render}
+hooks.post_render.pandoc-json:str   # {post_render}
+hooks.post_render.opf:str           # {post_render}
+hooks.post_render.azw3:str          # {post_render}
+hooks.post_render.lit:str           # {post_render}
+hooks.post_render.site:str          # {post_render}
+hooks.post_render.opds:str          # {post_render}
+hooks.post_render.onix:str          # {post_render}
+
+# {publish_opt}
+publish.kindle:bool:false           # {publish_kindle}
+publish.kindle.formats:strvec       # {publish_kindle_formats}
+publish.kindle.previewer:str        # {publish_kindle_previewer}
+publish.kindle.device:path          # {publish_kindle_device}
+publish.kindle.email:str            # {publish_kindle_email}
+
+# {sample_opt}
+sample.chapters:int                 # {sample_chapters}
+sample.url:str                      # {sample_url}
+sample.template:tpl                 # {sample_template}
+
+# {git_opt}
+git.metadata:bool:false             # {git_metadata}
+git.require_clean:bool:false        # {git_require_clean}
+
+# {check_opt}
+check.images:str                    # {check_images}
+check.fail:bool:false               # {check_fail}
+check.code:bool:false               # {check_code}
+
+# {links_opt}
+links.file:path                     # {links_file}
+links.style.tex:str                 # {links_style_tex}
+links.style.html:str:hyperlink      # {links_style_html}
+links.style.epub:str:hyperlink      # {links_style_epub}
+
+# {slug_opt}
+slug.separator:str:\"-\"              # {slug_separator}
+slug.lowercase:bool:true            # {slug_lowercase}
+slug.transliterate:bool:true        # {slug_transliterate}
+
+# {limits_opt}
+limits.chapter_size:size:16MB       # {limits_chapter_size}
+limits.nesting:int:100              # {limits_nesting}
+limits.images:int                   # {limits_images}
+limits.image_size:size              # {limits_image_size}
+limits.include_depth:int:10         # {limits_include_depth}
 
 # {deprecated_opt}
 html.css.colours:alias:html.css.colors              # {renamed}
@@ -209,13 +409,21 @@ output.odt:path                                     # {removed}
                                          metadata2 = t!("opt.add_metadata"),
                                          output_opt = t!("opt.output_opt"),
                                          output = t!("opt.output"),
+                                         output_name = t!("opt.output_name"),
+                                         output_filenames = t!("opt.output_filenames"),
                                          render_opt = t!("opt.render"),
                                          special_ops = t!("opt.special"),
                                          html_opt = t!("opt.html"),
                                          html_single_opt = t!("opt.html_single"),
                                          html_dir_opt = t!("opt.html_dir"),
                                          html_if_opt = t!("opt.html_if"),
+                                         site_opt = t!("opt.site"),
                                          epub_opt = t!("opt.epub"),
+                                         pdf_opt = t!("opt.pdf"),
+                                         calibre_opt = t!("opt.calibre"),
+                                         opds_opt = t!("opt.opds"),
+                                         onix_opt = t!("opt.onix"),
+                                         manifest_opt = t!("opt.manifest"),
                                          tex_opt = t!("opt.tex"),
                                          rs_opt = t!("opt.resources"),
                                          input_opt = t!("opt.input"),
@@ -233,13 +441,26 @@ output.odt:path                                     # {removed}
                                          license = t!("opt.license"),
                                          version = t!("opt.version"),
                                          date = t!("opt.date"),
+                                         publication_date = t!("opt.publication_date"),
                                          autograph = t!("opt.autograph"),
+                                         edition = t!("opt.edition"),
+                                         printing = t!("opt.printing"),
+                                         imprint = t!("opt.imprint"),
+                                         revisions = t!("opt.revisions"),
+                                         revisions_table = t!("opt.revisions_table"),
 
                                          output_epub = t!("opt.output_epub"),
                                          output_html = t!("opt.output_html"),
                                          output_tex = t!("opt.output_tex"),
                                          output_pdf = t!("opt.output_pdf"),
                                          output_if = t!("opt.output_if"),
+                                         output_pandoc_json = t!("opt.output_pandoc_json"),
+                                         output_opf = t!("opt.output_opf"),
+                                         output_azw3 = t!("opt.output_azw3"),
+                                         output_lit = t!("opt.output_lit"),
+                                         output_site = t!("opt.output_site"),
+                                         output_opds = t!("opt.output_opds"),
+                                         output_onix = t!("opt.output_onix"),
                                          output_html_dir = t!("opt.output_html_dir"),
                                          output_base_path = t!("opt.output_base_path"),
 
@@ -253,6 +474,12 @@ output.odt:path                                     # {removed}
                                          chapter = t!("opt.chapter"),
                                          chapter_template = t!("opt.chapter_template"),
                                          part_template = t!("opt.part_template"),
+                                         rendering_code_line_numbers = t!("opt.rendering_code_line_numbers"),
+                                         rendering_verse_line_numbers = t!("opt.rendering_verse_line_numbers"),
+                                         rendering_strict = t!("opt.rendering_strict"),
+                                         section_template = t!("opt.section_template"),
+                                         subsection_template = t!("opt.subsection_template"),
+                                         subsubsection_template = t!("opt.subsubsection_template"),
                                          roman_numerals_parts = t!("opt.roman_numeral_parts"),
                                          roman_numerals_chapters = t!("opt.roman_numerals_chapters"),
                                          reset_counter = t!("opt.reset_counter"),
@@ -265,11 +492,20 @@ output.odt:path                                     # {removed}
                                          html_css = t!("opt.html_css"),
                                          html_css_add = t!("opt.html_css_add"),
                                          css_colors = t!("opt.css_colors"),
+                                         css_accent = t!("opt.css_accent"),
+                                         css_font = t!("opt.css_font"),
+                                         css_measure = t!("opt.css_measure"),
+                                         css_line_height = t!("opt.css_line_height"),
                                          html_js = t!("opt.html_js"),
+                                         html_js_add = t!("opt.html_js_add"),
                                          css_print = t!("opt.css_print"),
                                          highlight_js = t!("opt.highlight_js"),
                                          highlight_css = t!("opt.highlight_css"),
                                          side_notes = t!("opt.side_notes"),
+                                         notes_numbering = t!("opt.notes_numbering"),
+                                         rendering_widows = t!("opt.rendering_widows"),
+                                         rendering_orphans = t!("opt.rendering_orphans"),
+                                         tex_side_notes = t!("opt.tex_side_notes"),
                                          nb_spaces = t!("opt.nb_spaces"),
                                          nb_spaces_tex = t!("opt.nb_spaces_tex"),
 
@@ -291,11 +527,16 @@ output.odt:path                                     # {removed}
                                          chapter_xhtml = t!("opt.chapter_xhtml"),
                                          titlepage_xhtml = t!("opt.titlepage_xhtml"),
                                          epub_toc = t!("opt.epub_toc"),
+                                         epub_pages = t!("opt.epub_pages"),
 
                                          tex_cover = t!("opt.tex_cover"),
                                          tex_links = t!("opt.tex_links"),
                                          tex_command = t!("opt.tex_command"),
                                          tex_tmpl = t!("opt.tex_tmpl"),
+                                         tex_tmpl_preamble = t!("opt.tex_tmpl_preamble"),
+                                         tex_tmpl_titlepage = t!("opt.tex_tmpl_titlepage"),
+                                         tex_tmpl_headers = t!("opt.tex_tmpl_headers"),
+                                         tex_tmpl_chapter_heading = t!("opt.tex_tmpl_chapter_heading"),
                                          tex_tmpl_add = t!("opt.tex_tmpl_add"),
                                          tex_class = t!("opt.tex_class"),
                                          tex_title = t!("opt.tex_title"),
@@ -307,6 +548,17 @@ output.odt:path                                     # {removed}
                                          tex_font_size = t!("opt.tex_font_size"),
                                          tex_hyperref = t!("opt.tex_hyperref"),
                                          tex_stdpage = t!("opt.tex_stdpage"),
+                                         tex_figure_float = t!("opt.tex_figure_float"),
+                                         tex_figure_placement = t!("opt.tex_figure_placement"),
+                                         tex_figure_caption = t!("opt.tex_figure_caption"),
+                                         tex_image_max_width = t!("opt.tex_image_max_width"),
+                                         tex_landscape_tables = t!("opt.tex_landscape_tables"),
+                                         tex_microtype = t!("opt.tex_microtype"),
+                                         tex_chapter_recto = t!("opt.tex_chapter_recto"),
+                                         tex_chapter_drop = t!("opt.tex_chapter_drop"),
+                                         tex_chapter_style = t!("opt.tex_chapter_style"),
+                                         tex_line_breaking = t!("opt.tex_line_breaking"),
+                                         tex_hyphenation_exceptions = t!("opt.tex_hyphenation_exceptions"),
 
                                          rs_files = t!("opt.rs_files"),
                                          rs_out = t!("opt.rs_out"),
@@ -320,12 +572,119 @@ output.odt:path                                     # {removed}
                                          smart_quotes = t!("opt.smart"),
                                          ligature_dashes = t!("opt.dashes"),
                                          ligature_guillemets = t!("opt.guillemets"),
+                                         clean_ellipsis = t!("opt.clean_ellipsis"),
+                                         clean_ordinals = t!("opt.clean_ordinals"),
+                                         clean_fractions = t!("opt.clean_fractions"),
+                                         clean_symbols = t!("opt.clean_symbols"),
+                                         french_nb_space = t!("opt.french_nb_space"),
+                                         french_nb_space_narrow = t!("opt.french_nb_space_narrow"),
                                          superscript = t!("opt.superscript"),
                                          yaml = t!("opt.yaml"),
+                                         input_format = t!("opt.input_format"),
+                                         input_markdown = t!("opt.input_markdown"),
                                          html_as_text = t!("opt.html_as_text"),
+                                         input_raw = t!("opt.input_raw"),
+                                         input_filters = t!("opt.input_filters"),
+                                         input_drafts = t!("opt.input_drafts"),
+                                         tags_renumber = t!("opt.tags_renumber"),
+                                         input_normalize = t!("opt.input_normalize"),
+                                         input_encoding = t!("opt.input_encoding"),
+                                         html_sanitize = t!("opt.html_sanitize"),
+                                         tex_runs = t!("opt.tex_runs"),
+                                         pdf_backend = t!("opt.pdf_backend"),
+                                         pdf_command = t!("opt.pdf_command"),
+                                         pdf_bleed = t!("opt.pdf_bleed"),
+                                         pdf_crop_marks = t!("opt.pdf_crop_marks"),
+                                         pdf_grayscale = t!("opt.pdf_grayscale"),
+                                         pdf_icc_profile = t!("opt.pdf_icc_profile"),
+                                         pdf_pdfx = t!("opt.pdf_pdfx"),
+                                         pdf_pdfa = t!("opt.pdf_pdfa"),
+                                         pdf_link_colors = t!("opt.pdf_link_colors"),
+                                         calibre_command = t!("opt.calibre_command"),
+                                         calibre_args = t!("opt.calibre_args"),
+                                         opds_formats = t!("opt.opds_formats"),
+                                         opds_base_url = t!("opt.opds_base_url"),
+                                         onix_isbn = t!("opt.onix_isbn"),
+                                         onix_publisher = t!("opt.onix_publisher"),
+                                         onix_contributors = t!("opt.onix_contributors"),
+                                         onix_product_form = t!("opt.onix_product_form"),
+                                         onix_prices = t!("opt.onix_prices"),
+                                         onix_price_type = t!("opt.onix_price_type"),
+                                         manifest_file = t!("opt.manifest_file"),
+                                         manifest_sign_key = t!("opt.manifest_sign_key"),
+                                         manifest_sign_command = t!("opt.manifest_sign_command"),
+                                         subject_bisac = t!("opt.subject_bisac"),
+                                         subject_thema = t!("opt.subject_thema"),
+                                         subject_keywords = t!("opt.subject_keywords"),
+                                         isbn = t!("opt.isbn"),
+                                         isbn_epub = t!("opt.isbn_epub"),
+                                         isbn_print = t!("opt.isbn_print"),
+                                         doi = t!("opt.doi"),
+                                         uuid = t!("opt.uuid"),
+                                         html_interactive = t!("opt.html_interactive"),
+                                         html_interactive_css = t!("opt.html_interactive_css"),
+                                         html_interactive_js = t!("opt.html_interactive_js"),
+                                         html_dir_search = t!("opt.html_dir_search"),
+                                         html_base_url = t!("opt.html_base_url"),
+                                         html_anchors = t!("opt.html_anchors"),
+                                         html_feed = t!("opt.html_feed"),
+                                         html_sitemap = t!("opt.html_sitemap"),
+                                         html_chapter_footer = t!("opt.html_chapter_footer"),
+                                         html_standalone = t!("opt.html_standalone"),
+                                         html_dir_cache = t!("opt.html_dir_cache"),
+                                         html_dir_filenames = t!("opt.html_dir_filenames"),
+                                         reproducible = t!("opt.reproducible"),
+                                         config_version = t!("opt.config_version"),
+                                         hooks_opt = t!("opt.hooks"),
+                                         pre_render = t!("opt.pre_render"),
+                                         post_render = t!("opt.post_render"),
+                                         publish_opt = t!("opt.publish"),
+                                         publish_kindle = t!("opt.publish_kindle"),
+                                         publish_kindle_formats = t!("opt.publish_kindle_formats"),
+                                         publish_kindle_previewer = t!("opt.publish_kindle_previewer"),
+                                         publish_kindle_device = t!("opt.publish_kindle_device"),
+                                         publish_kindle_email = t!("opt.publish_kindle_email"),
+                                         site_template = t!("opt.site_template"),
+                                         site_downloads = t!("opt.site_downloads"),
+                                         site_reader = t!("opt.site_reader"),
+                                         site_blurb = t!("opt.site_blurb"),
+                                         site_nojekyll = t!("opt.site_nojekyll"),
+                                         sample_opt = t!("opt.sample"),
+                                         sample_chapters = t!("opt.sample_chapters"),
+                                         sample_url = t!("opt.sample_url"),
+                                         sample_template = t!("opt.sample_template"),
+                                         media_opt = t!("opt.media"),
+                                         media_poster = t!("opt.media_poster"),
+                                         media_base_url = t!("opt.media_base_url"),
+                                         git_opt = t!("opt.git"),
+                                         git_metadata = t!("opt.git_metadata"),
+                                         git_require_clean = t!("opt.git_require_clean"),
+                                         check_opt = t!("opt.check"),
+                                         check_images = t!("opt.check_images"),
+                                         check_fail = t!("opt.check_fail"),
+                                         check_code = t!("opt.check_code"),
+                                         links_opt = t!("opt.links"),
+                                         links_file = t!("opt.links_file"),
+                                         links_style_tex = t!("opt.links_style_tex"),
+                                         links_style_html = t!("opt.links_style_html"),
+                                         links_style_epub = t!("opt.links_style_epub"),
+                                         slug_opt = t!("opt.slug"),
+                                         slug_separator = t!("opt.slug_separator"),
+                                         slug_lowercase = t!("opt.slug_lowercase"),
+                                         slug_transliterate = t!("opt.slug_transliterate"),
+                                         limits_opt = t!("opt.limits"),
+                                         limits_chapter_size = t!("opt.limits_chapter_size"),
+                                         limits_nesting = t!("opt.limits_nesting"),
+                                         limits_images = t!("opt.limits_images"),
+                                         limits_image_size = t!("opt.limits_image_size"),
+                                         limits_include_depth = t!("opt.limits_include_depth"),
                                          files_mean_chapters = t!("opt.files_mean_chapters"),
                                          tmp_dir = t!("opt.tmp_dir"),
+                                         tmp_dir_keep = t!("opt.tmp_dir_keep"),
                                          zip = t!("opt.zip"),
+                                         command_timeout = t!("opt.command_timeout"),
+                                         in_memory = t!("opt.in_memory"),
+                                         sandbox = t!("opt.sandbox"),
 
                                          tex_theme = t!("opt.tex_theme"),
                                          html_theme = t!("opt.html_theme"),
@@ -336,6 +695,44 @@ output.odt:path                                     # {removed}
     );
 }
 
+/// Characters that `char` options can be set to by name
+const NAMED_CHARS: [(&str, char); 3] = [
+    ("nbsp", '\u{A0}'),
+    ("narrow-nbsp", '\u{202F}'),
+    ("thin-space", '\u{2009}'),
+];
+
+/// Parses the value of a `char` option: a character (which can be quoted, as in `'x'`), a
+/// code point (e.g. `U+00A0`) or the name of one of `NAMED_CHARS`
+///
+/// **Returns** the error message if it isn't valid.
+pub(crate) fn parse_char(value: &str) -> std::result::Result<char, String> {
+    if let Some(&(_, c)) = NAMED_CHARS.iter().find(|(name, _)| *name == value) {
+        return Ok(c);
+    }
+    if let Some(hex) = value.strip_prefix("U+").or_else(|| value.strip_prefix("u+")) {
+        return Some(hex)
+            .filter(|hex| (1..=6).contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| t!("opt.invalid_codepoint", value = value));
+    }
+    let unquoted = value
+        .strip_prefix('\'')
+        .and_then(|v| v.strip_suffix('\''))
+        .filter(|v| !v.is_empty())
+        .unwrap_or(value);
+    // A letter followed by combining marks is composed into a single char if possible
+    let chars: Vec<_> = unquoted.nfc().collect();
+    match chars[..] {
+        [c] => Ok(c),
+        _ => {
+            let names: Vec<_> = NAMED_CHARS.iter().map(|(name, _)| *name).collect();
+            Err(t!("opt.expected_char", value = value, names = names.join(", ")))
+        }
+    }
+}
+
 /// Contains the options of a book.
 ///
 /// This structure offers some facilities to check the content of an option.
@@ -359,20 +756,33 @@ output.odt:path                                     # {removed}
 /// let res = options.set("epub.version", "foo");
 /// assert!(res.is_err());
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BookOptions {
     options: HashMap<String, BookOption>,
     defaults: HashMap<String, BookOption>,
     deprecated: HashMap<String, Option<String>>,
+    /// Options referencing other ones, with their value as it is written and as it was
+    /// last evaluated
+    references: HashMap<String, (BookOption, BookOption)>,
     valid_tpls: Vec<&'static str>,
     valid_bools: Vec<&'static str>,
     valid_chars: Vec<&'static str>,
+    valid_dates: Vec<&'static str>,
+    valid_sizes: Vec<&'static str>,
+    valid_durations: Vec<&'static str>,
     valid_strings: Vec<&'static str>,
     valid_paths: Vec<&'static str>,
     valid_ints: Vec<&'static str>,
     valid_floats: Vec<&'static str>,
     valid_str_vecs: Vec<&'static str>,
     metadata: Vec<String>,
+    shortcodes: Vec<String>,
+    link_refs: Vec<String>,
+    /// Number of configuration files importing this one, for `limits.include_depth`
+    import_depth: i32,
+    profiles: Vec<(String, String, Yaml)>,
+    variants: Vec<String>,
+    tags: Vec<(String, Vec<String>)>,
 
     /// Source for errors (unnecessary copy :/)
     #[doc(hidden)]
@@ -389,9 +799,13 @@ impl BookOptions {
         let mut options = BookOptions {
             options: HashMap::new(),
             deprecated: HashMap::new(),
+            references: HashMap::new(),
             defaults: HashMap::new(),
             valid_bools: vec![],
             valid_chars: vec![],
+            valid_dates: vec![],
+            valid_sizes: vec![],
+            valid_durations: vec![],
             valid_ints: vec![],
             valid_floats: vec![],
             valid_strings: vec![],
@@ -399,6 +813,12 @@ impl BookOptions {
             valid_tpls: vec![],
             valid_str_vecs: vec![],
             metadata: vec![],
+            shortcodes: vec![],
+            link_refs: vec![],
+            import_depth: 0,
+            profiles: vec![],
+            variants: vec![],
+            tags: vec![],
             root: PathBuf::new(),
             source: Source::empty(),
         };
@@ -420,6 +840,12 @@ impl BookOptions {
                 "int" => options.valid_ints.push(key),
                 "float" => options.valid_floats.push(key),
                 "char" => options.valid_chars.push(key),
+                "date" => {
+                    options.metadata.push(key.to_owned());
+                    options.valid_dates.push(key);
+                }
+                "size" => options.valid_sizes.push(key),
+                "duration" => options.valid_durations.push(key),
                 "path" => options.valid_paths.push(key),
                 "tpl" => {
                     options.valid_tpls.push(key);
@@ -480,6 +906,18 @@ impl BookOptions {
             ));
         };
 
+        // A single string is accepted for a list of strings
+        let value = match value {
+            Yaml::String(value) if self.valid_str_vecs.contains(&key.as_ref()) => {
+                Yaml::Array(vec![Yaml::String(value)])
+            }
+            value => value,
+        };
+
+        if self.is_sandboxed() {
+            self.check_sandbox(&key, &value)?;
+        }
+
         if self.valid_str_vecs.contains(&key.as_ref()) {
             // Value is a list of string
             if let Yaml::Array(array) = value {
@@ -517,6 +955,24 @@ impl BookOptions {
                         })?;
                     }
                 }
+                // subject codes are checked early, since they are sent to distributors
+                if let Some((scheme, code)) = subject::invalid_code(&key, &inner) {
+                    return Err(Error::book_option(
+                        &self.source,
+                        t!(
+                            "opt.invalid_subject_code",
+                            key = &key,
+                            scheme = scheme,
+                            code = code
+                        ),
+                    ));
+                }
+                if let Some(entry) = imprint::invalid_revision(&key, &inner) {
+                    return Err(Error::book_option(
+                        &self.source,
+                        t!("opt.invalid_revision", key = &key, entry = entry),
+                    ));
+                }
                 Ok(self.options.insert(key, BookOption::StringVec(inner)))
             } else {
                 Err(Error::book_option(
@@ -531,6 +987,24 @@ impl BookOptions {
         } else if self.valid_strings.contains(&key.as_ref()) {
             // value is a string
             if let Yaml::String(value) = value {
+                // identifiers are checked early, since a wrong checksum is easy to miss
+                if let Some(kind) = identifier::invalid(&key, &value) {
+                    return Err(Error::book_option(
+                        &self.source,
+                        t!(
+                            "opt.invalid_identifier",
+                            key = &key,
+                            kind = kind,
+                            value = &value
+                        ),
+                    ));
+                }
+                if key == "slug.separator" && !slug::is_valid_separator(&value) {
+                    return Err(Error::book_option(
+                        &self.source,
+                        t!("opt.invalid_separator", key = &key, value = &value),
+                    ));
+                }
                 Ok(self.options.insert(key, BookOption::String(value)))
             } else {
                 Err(Error::book_option(
@@ -557,7 +1031,25 @@ impl BookOptions {
                             ),
                         )
                     })?;
+                    let depth = self.import_depth + 1;
                     let mut book = Book::new();
+                    // Imported files get the same limit, set before sandbox mode forbids it
+                    if let Ok(limit) = self.get_i32("limits.include_depth") {
+                        if depth > limit {
+                            return Err(Error::book_option(
+                                &self.source,
+                                t!("limits.include_depth", file = &value, limit = limit),
+                            ));
+                        }
+                        book.options.set_yaml(
+                            Yaml::String(String::from("limits.include_depth")),
+                            Yaml::Integer(i64::from(limit)),
+                        )?;
+                    }
+                    book.options.import_depth = depth;
+                    if self.is_sandboxed() {
+                        book.options.set("crowbook.sandbox", "true")?;
+                    }
                     book.load_file(file)?;
                     self.merge(&book.options)?;
                     Ok(None)
@@ -577,17 +1069,8 @@ impl BookOptions {
         } else if self.valid_chars.contains(&key.as_ref()) {
             // value is a char
             if let Yaml::String(value) = value {
-                let chars: Vec<_> = value.chars().collect();
-                if chars.len() != 1 {
-                    return Err(Error::book_option(
-                        &self.source,
-                        t!(
-                            "opt.expected_char",
-                            value = &value
-                        ),
-                    ));
-                }
-                Ok(self.options.insert(key, BookOption::Char(chars[0])))
+                let c = parse_char(&value).map_err(|msg| Error::book_option(&self.source, msg))?;
+                Ok(self.options.insert(key, BookOption::Char(c)))
             } else {
                 Err(Error::book_option(
                     &self.source,
@@ -598,6 +1081,50 @@ impl BookOptions {
                     ),
                 ))
             }
+        } else if self.valid_dates.contains(&key.as_ref()) {
+            // value is a date, which is kept as it is written once it is checked. Older
+            // versions took any text, which is kept (but not used as a date) with a warning
+            match value {
+                Yaml::String(value) if Date::parse(&value).is_some() => {
+                    Ok(self.options.insert(key, BookOption::String(value)))
+                }
+                Yaml::String(value) if !Date::looks_iso(&value) => {
+                    warn!(
+                        "{}",
+                        t!("opt.legacy_date", key = &key, value = format!("{:?}", &value))
+                    );
+                    Ok(self.options.insert(key, BookOption::String(value)))
+                }
+                _ => Err(Error::book_option(
+                    &self.source,
+                    t!(
+                        "opt.expected_date",
+                        key = &key,
+                        value = format!("{:?}", &value)
+                    ),
+                )),
+            }
+        } else if self.valid_sizes.contains(&key.as_ref())
+            || self.valid_durations.contains(&key.as_ref())
+        {
+            // value is a size or a duration, which is kept as it is written once it is
+            // checked; numbers alone are bytes or seconds
+            let value = match value {
+                Yaml::String(value) => value,
+                Yaml::Integer(n) => n.to_string(),
+                Yaml::Real(f) => f,
+                value => format!("{value:?}"),
+            };
+            let valid = if self.valid_sizes.contains(&key.as_ref()) {
+                units::parse_size(&value).is_some()
+            } else {
+                units::parse_duration(&value).is_some()
+            };
+            if valid {
+                Ok(self.options.insert(key, BookOption::String(value)))
+            } else {
+                Err(self.unit_error(&key, &value))
+            }
         } else if self.valid_bools.contains(&key.as_ref()) {
             // value is a bool
             if let Yaml::Boolean(value) = value {
@@ -684,6 +1211,125 @@ impl BookOptions {
                     ),
                 ))
             }
+        } else if let Some(tag) = key.strip_prefix("tags.") {
+            // key is a tag, whose value is the list of chapters it applies to
+            let debug = format!("{:?}", value);
+            let files = match value {
+                Yaml::String(file) => Some(vec![file]),
+                Yaml::Array(array) => array
+                    .into_iter()
+                    .map(|file| file.into_string())
+                    .collect(),
+                _ => None,
+            };
+            match files {
+                Some(files) if !tag.is_empty() => {
+                    self.tags.retain(|(t, _)| t != tag);
+                    self.tags.push((tag.to_owned(), files));
+                    Ok(None)
+                }
+                _ => Err(Error::book_option(
+                    &self.source,
+                    t!("opt.expected_strings", key = &key, value = debug),
+                )),
+            }
+        } else if let Some(profile) = key.strip_prefix("profiles.") {
+            // key is an option set by a build profile, which is only checked
+            // when the profile is applied
+            match profile.split_once('.') {
+                Some((name, option)) if !name.is_empty() && !option.is_empty() => {
+                    self.profiles
+                        .push((name.to_owned(), option.to_owned(), value));
+                    Ok(None)
+                }
+                _ => Err(Error::book_option(
+                    &self.source,
+                    t!("opt.invalid_profile", key = &key),
+                )),
+            }
+        } else if let Some(language) = key.strip_prefix("check.code.") {
+            // key is the command checking code blocks in a language
+            // value must be a string
+            match value {
+                Yaml::String(value) if !language.is_empty() => {
+                    Ok(self.options.insert(key, BookOption::String(value)))
+                }
+                _ => Err(Error::book_option(
+                    &self.source,
+                    t!(
+                        "opt.expected_string_value",
+                        key = &key,
+                        value = format!("{:?}", &&value)
+                    ),
+                )),
+            }
+        } else if let Some(label) = key.strip_prefix("links.refs.") {
+            // key is a reference-style link definition shared by all chapters
+            // value must be a string
+            match value {
+                Yaml::String(value) if !label.is_empty() => {
+                    if !self.link_refs.contains(&key) {
+                        self.link_refs.push(key.clone());
+                    }
+                    Ok(self.options.insert(key, BookOption::String(value)))
+                }
+                _ => Err(Error::book_option(
+                    &self.source,
+                    t!(
+                        "opt.expected_string_value",
+                        key = &key,
+                        value = format!("{:?}", &&value)
+                    ),
+                )),
+            }
+        } else if let Some(shortcode) = key.strip_prefix("shortcodes.") {
+            // key is the template of a shortcode for a format
+            // value must be a string
+            let valid = shortcode
+                .rsplit_once('.')
+                .map(|(name, format)| {
+                    !name.is_empty() && ["markdown", "html", "tex"].contains(&format)
+                })
+                .unwrap_or(false);
+            if !valid {
+                return Err(Error::book_option(
+                    &self.source,
+                    t!("opt.invalid_shortcode", key = &key),
+                ));
+            }
+            if let Yaml::String(value) = value {
+                if !self.shortcodes.contains(&key) {
+                    self.shortcodes.push(key.clone());
+                }
+                Ok(self.options.insert(key, BookOption::String(value)))
+            } else {
+                Err(Error::book_option(
+                    &self.source,
+                    t!(
+                        "opt.expected_string_value",
+                        key = &key,
+                        value = format!("{:?}", &&value)
+                    ),
+                ))
+            }
+        } else if self.variant_of(&key).is_some() {
+            // key is the output file of a variant of a format, rendered with the options
+            // of the build profile of the same name
+            if let Yaml::String(value) = value {
+                if !self.variants.contains(&key) {
+                    self.variants.push(key.clone());
+                }
+                Ok(self.options.insert(key, BookOption::Path(value)))
+            } else {
+                Err(Error::book_option(
+                    &self.source,
+                    t!(
+                        "opt.expected_string_value",
+                        key = &key,
+                        value = format!("{:?}", &&value)
+                    ),
+                ))
+            }
         } else {
             // key not recognized
             Err(Error::book_option(
@@ -693,6 +1339,112 @@ impl BookOptions {
         }
     }
 
+    /// Returns true if sandbox mode is enabled
+    ///
+    /// In this mode, paths must stay inside the book's directory and
+    /// options that specify shell commands can't be modified.
+    pub fn is_sandboxed(&self) -> bool {
+        self.get_bool("crowbook.sandbox").unwrap_or(false)
+    }
+
+    /// Checks that a `limits.*` option isn't raised, which isn't allowed in sandbox mode
+    ///
+    /// Invalid values are reported when they are set.
+    fn check_limit(&self, key: &str, value: &Yaml) -> Result<()> {
+        let current = if self.valid_sizes.contains(&key) {
+            let new = match *value {
+                Yaml::String(ref s) | Yaml::Real(ref s) => units::parse_size(s),
+                Yaml::Integer(n) => u64::try_from(n).ok(),
+                _ => None,
+            };
+            match (self.get_size(key), new) {
+                (Ok(current), Some(new)) if new > current => {
+                    self.get_str(key).ok().map(String::from)
+                }
+                _ => None,
+            }
+        } else {
+            match (self.get_i32(key), value) {
+                (Ok(current), Yaml::Integer(new)) if *new > i64::from(current) => {
+                    Some(current.to_string())
+                }
+                _ => None,
+            }
+        };
+        match current {
+            Some(current) => Err(Error::book_option(
+                &self.source,
+                t!("opt.sandbox_limit", key = key, current = current),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that setting `key` to `value` is allowed in sandbox mode
+    fn check_sandbox(&self, key: &str, value: &Yaml) -> Result<()> {
+        match key {
+            "crowbook.sandbox" if *value != Yaml::Boolean(true) => Err(Error::book_option(
+                &self.source,
+                t!("opt.sandbox_disable"),
+            )),
+            "tex.command"
+            | "pdf.command"
+            | "crowbook.zip.command"
+            | "input.filters"
+            | "calibre.command"
+            | "manifest.sign.command" => Err(Error::book_option(
+                &self.source,
+                t!("opt.sandbox_command", key = key),
+            )),
+            // Raw content is inserted as is in the output, where it could run scripts or
+            // LaTeX commands reading any file
+            "input.raw"
+            | "html.chapter_footer_snippet"
+            | "html.css.add"
+            | "html.js.add"
+            | "epub.css.add"
+            | "tex.template.add" => Err(Error::book_option(
+                &self.source,
+                t!("opt.sandbox_raw", key = key),
+            )),
+            key if key.starts_with("shortcodes.") && !key.ends_with(".markdown") => Err(
+                Error::book_option(&self.source, t!("opt.sandbox_raw", key = key)),
+            ),
+            key if key.starts_with("limits.") => self.check_limit(key, value),
+            key if key.starts_with("hooks.")
+                || key.starts_with("check.code.")
+                || key.starts_with("calibre.")
+                || key.starts_with("publish.") => Err(Error::book_option(
+                &self.source,
+                t!("opt.sandbox_command", key = key),
+            )),
+            "resources.files" => match *value {
+                Yaml::Array(ref paths) => {
+                    for path in paths {
+                        if let Yaml::String(ref path) = *path {
+                            if !misc::stays_inside(path, &self.root) {
+                                return Err(Error::book_option(
+                                    &self.source,
+                                    t!("opt.sandbox_path", key = key, value = path),
+                                ));
+                            }
+                        }
+                    }
+                    Ok(())
+                }
+                _ => Ok(()),
+            },
+            key if self.valid_paths.contains(&key) || self.variant_of(key).is_some() => match *value {
+                Yaml::String(ref path) if !misc::stays_inside(path, &self.root) => Err(Error::book_option(
+                    &self.source,
+                    t!("opt.sandbox_path", key = key, value = path),
+                )),
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
     /// Sets an option
     ///
     /// # Arguments
@@ -747,12 +1499,91 @@ impl BookOptions {
         }
     }
 
+    /// Returns a string that changes whenever the value of an option changes
+    #[doc(hidden)]
+    pub fn fingerprint(&self) -> String {
+        let mut keys: Vec<_> = self.options.keys().collect();
+        keys.sort();
+        let mut fingerprint = String::new();
+        for key in keys {
+            fingerprint.push_str(&format!("{key}: {:?}\n", self.options[key]));
+        }
+        fingerprint
+    }
+
     /// Return the list of keys that are metadata
     #[doc(hidden)]
     pub fn get_metadata(&self) -> &[String] {
         &self.metadata
     }
 
+    /// Sets the options of a build profile, i.e. the `profiles.{name}.{key}` options
+    ///
+    /// The `release` profile also excludes draft chapters by default.
+    pub fn apply_profile(&mut self, name: &str) -> Result<()> {
+        let options: Vec<_> = self
+            .profiles
+            .iter()
+            .filter(|(profile, _, _)| profile == name)
+            .map(|(_, key, value)| (key.clone(), value.clone()))
+            .collect();
+        if options.is_empty() && name != "draft" && name != "release" {
+            return Err(Error::book_option(
+                &self.source,
+                t!("opt.unknown_profile", profile = name),
+            ));
+        }
+        if name == "release" {
+            self.set("input.drafts", "false")?;
+        }
+        for (key, value) in options {
+            self.set_yaml(Yaml::String(key), value)?;
+        }
+        Ok(())
+    }
+
+    /// Return the tags set by `tags.{tag}` options, with the chapters they apply to
+    #[doc(hidden)]
+    pub fn get_tags(&self) -> &[(String, Vec<String>)] {
+        &self.tags
+    }
+
+    /// Return the list of keys that are shortcode templates, i.e. `shortcodes.{name}.{format}`
+    #[doc(hidden)]
+    pub fn get_shortcodes(&self) -> &[String] {
+        &self.shortcodes
+    }
+
+    /// Returns the format and the variant of an `output.{format}.{variant}` key, if `format`
+    /// is a format that can be rendered to a file
+    fn variant_of<'k>(&self, key: &'k str) -> Option<(&'k str, &'k str)> {
+        let (format, variant) = key.strip_prefix("output.")?.rsplit_once('.')?;
+        let output = &key[..key.len() - variant.len() - 1];
+        if variant.is_empty() || self.valid_paths.contains(&key) || !self.valid_paths.contains(&output) {
+            return None;
+        }
+        Some((format, variant))
+    }
+
+    /// Return the variants set by `output.{format}.{variant}` options, as
+    /// (format, variant, key) tuples
+    #[doc(hidden)]
+    pub fn get_variants(&self) -> Vec<(&str, &str, &str)> {
+        self.variants
+            .iter()
+            .filter_map(|key| {
+                let (format, variant) = self.variant_of(key)?;
+                Some((format, variant, key.as_str()))
+            })
+            .collect()
+    }
+
+    /// Return the list of keys that are link reference definitions, i.e. `links.refs.{label}`
+    #[doc(hidden)]
+    pub fn get_link_refs(&self) -> &[String] {
+        &self.link_refs
+    }
+
     /// Gets an option
     #[doc(hidden)]
     pub fn get(&self, key: &str) -> Result<&BookOption> {
@@ -823,12 +1654,26 @@ impl BookOptions {
             | "output.html.dir"
             | "output.pdf"
             | "output.tex"
-            | "output.html.if" => {
+            | "output.html.if"
+            | "output.pandoc-json"
+            | "output.opf"
+            | "output.azw3"
+            | "output.lit"
+            | "output.site"
+            | "output.opds"
+            | "output.onix"
+            | "manifest.file" => {
                 // Translate according to output.base_path
                 let base = self.get_path("output.base_path").unwrap();
                 Path::new(&base).join(path)
             }
 
+            key if self.variant_of(key).is_some() => {
+                // Translate according to output.base_path, like the format's output file
+                let base = self.get_path("output.base_path").unwrap();
+                Path::new(&base).join(path)
+            }
+
             key if self.valid_tpls.contains(&key) => {
                 // Translate according to resources.base_path.template
                 let base = self.get_path("resources.base_path.templates").unwrap();
@@ -872,6 +1717,44 @@ impl BookOptions {
         self.get(key)?.as_char()
     }
 
+    /// Returns true if an option is a date
+    pub(crate) fn is_date(&self, key: &str) -> bool {
+        self.valid_dates.contains(&key)
+    }
+
+    /// Gets a date option, e.g. `date`
+    pub fn get_date(&self, key: &str) -> Result<Date> {
+        let s = self.get_str(key)?;
+        Date::parse(s).ok_or_else(|| {
+            Error::book_option(
+                &self.source,
+                t!("opt.expected_date", key = key, value = format!("{s:?}")),
+            )
+        })
+    }
+
+    /// Returns the error for an invalid size or duration
+    fn unit_error(&self, key: &str, value: &str) -> Error {
+        let msg = if self.valid_sizes.contains(&key) {
+            "opt.expected_size"
+        } else {
+            "opt.expected_duration"
+        };
+        Error::book_option(&self.source, t!(msg, key = key, value = value))
+    }
+
+    /// Gets a size option, in bytes
+    pub fn get_size(&self, key: &str) -> Result<u64> {
+        let s = self.get_str(key)?;
+        units::parse_size(s).ok_or_else(|| self.unit_error(key, s))
+    }
+
+    /// Gets a duration option
+    pub fn get_duration(&self, key: &str) -> Result<Duration> {
+        let s = self.get_str(key)?;
+        units::parse_duration(s).ok_or_else(|| self.unit_error(key, s))
+    }
+
     /// Gets an int  option
     ///
     /// # Example
@@ -930,7 +1813,7 @@ impl BookOptions {
                     path.to_owned()
                 } else {
                     return Err(Error::book_option(
-                        Source::new(other.root.to_str().unwrap()),
+                        Source::new(other.root.to_string_lossy()),
                         t!(
                             "opt.invalid_utf8",
                             value = key
@@ -942,6 +1825,11 @@ impl BookOptions {
                 self.options.insert(key.clone(), value.clone());
             }
         }
+        for key in &other.variants {
+            if !self.variants.contains(key) {
+                self.variants.push(key.clone());
+            }
+        }
         Ok(())
     }
 
@@ -986,6 +1874,9 @@ impl BookOptions {
                 "path" => t!("ty.path"),
                 "tpl" => t!("ty.tpl"),
                 "meta" => t!("ty.meta"),
+                "date" => t!("ty.date"),
+                "size" => t!("ty.size"),
+                "duration" => t!("ty.duration"),
                 "strvec" => t!("ty.strvec"),
                 "alias" => t!("ty.alias"),
                 _ => unreachable!(),
@@ -1018,6 +1909,178 @@ impl BookOptions {
         out
     }
 
+    /// Writes the options to a book configuration file
+    ///
+    /// If the file already exists, its comments, chapter list and the order of its options
+    /// are kept: options it sets are changed where they are written, and other options are
+    /// added after them. Options set to their default value are only written if the file
+    /// already sets them.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use crowbook::Book;
+    /// let mut book = Book::new();
+    /// book.load_file("foo.book").unwrap();
+    /// book.options.set("title", "A new title").unwrap();
+    /// book.options.save("foo.book").unwrap();
+    /// ```
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut config = if path.exists() {
+            ConfigFile::load(path)?
+        } else {
+            ConfigFile::new()
+        };
+        // New options are added in the order of the list of options
+        let order: HashMap<&str, usize> = Self::options_to_vec()
+            .into_iter()
+            .filter_map(|(_, key, _, _)| key)
+            .enumerate()
+            .map(|(i, key)| (key, i))
+            .collect();
+        let mut keys: Vec<&String> = self.options.keys().collect();
+        keys.sort_by_key(|key| (order.get(key.as_str()).copied().unwrap_or(usize::MAX), *key));
+        let outputs = self.get_str_vec("output").unwrap_or_default();
+        for key in keys {
+            // Options referencing other ones are written as they were
+            let value = match self.references.get(key) {
+                Some((raw, evaluated)) if *evaluated == self.options[key] => raw,
+                _ => &self.options[key],
+            };
+            let yaml = value.to_yaml();
+            match config.get(key) {
+                // Keep the line as it is written, with its comments
+                Some(current) if current == yaml => continue,
+                Some(_) => (),
+                None => {
+                    let is_default = self.defaults.get(key) == Some(value)
+                        || (key == "crowbook.temp_dir"
+                            && value.as_path() == Ok(&env::temp_dir().to_string_lossy()));
+                    // Formats listed in `output` already set `output.{format}` to auto
+                    let in_output = key
+                        .strip_prefix("output.")
+                        .map_or(false, |format| outputs.iter().any(|f| f == format))
+                        && value.as_path() == Ok("auto");
+                    if is_default || in_output {
+                        continue;
+                    }
+                }
+            }
+            config.set(key, yaml);
+        }
+        config.save(path)
+    }
+
+    /// Evaluates the values of path options (except templates) and metadata that reference
+    /// other options, e.g. `output.epub: "{{title|slug}}.epub"`
+    ///
+    /// This is done once the book configuration is read, and must be done again after
+    /// changing options for the references to be up to date.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a value references an option that isn't set, uses an unknown
+    /// filter, or if options reference each other.
+    pub fn evaluate_references(&mut self) -> Result<()> {
+        // Options set since the last evaluation replace their previous value
+        let options = &self.options;
+        self.references
+            .retain(|key, (_, evaluated)| options.get(key) == Some(evaluated));
+        for (key, value) in &self.options {
+            let raw = match value {
+                BookOption::Path(s) if !self.valid_tpls.contains(&key.as_str()) => s,
+                BookOption::String(s) if self.metadata.contains(key) => s,
+                _ => continue,
+            };
+            if references::is_template(raw) {
+                self.references
+                    .insert(key.clone(), (value.clone(), value.clone()));
+            }
+        }
+        let mut keys: Vec<String> = self.references.keys().cloned().collect();
+        keys.sort();
+        let mut done = vec![];
+        for key in keys {
+            self.evaluate_reference(&key, &mut vec![], &mut done)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates an option referencing other ones, after the ones it references
+    ///
+    /// `stack` contains the options whose evaluation needs this one, so a cycle is detected
+    /// when it contains it.
+    fn evaluate_reference(
+        &mut self,
+        key: &str,
+        stack: &mut Vec<String>,
+        done: &mut Vec<String>,
+    ) -> Result<()> {
+        if done.iter().any(|k| k == key) {
+            return Ok(());
+        }
+        if let Some(pos) = stack.iter().position(|k| k == key) {
+            let mut cycle = stack[pos..].to_vec();
+            cycle.push(key.to_owned());
+            return Err(Error::book_option(
+                &self.source,
+                t!("references.cycle", cycle = cycle.join(" -> ")),
+            ));
+        }
+        let raw = self.references[key].0.clone();
+        let value = match raw {
+            BookOption::Path(ref value) | BookOption::String(ref value) => value,
+            _ => unreachable!(),
+        };
+        stack.push(key.to_owned());
+        let referenced = references::references(value).map_err(|err| {
+            Error::book_option(&self.source, t!("references.error", key = key, error = err))
+        })?;
+        for other in referenced {
+            if self.references.contains_key(other) {
+                self.evaluate_reference(other, stack, done)?;
+            }
+        }
+        stack.pop();
+        let slugifier = Slugifier::new(self);
+        let evaluated =
+            references::evaluate(value, &slugifier, |k| self.options.get(k)).map_err(|err| {
+                Error::book_option(&self.source, t!("references.error", key = key, error = err))
+            })?;
+        let evaluated = match raw {
+            BookOption::Path(_) => {
+                // The evaluated path must stay inside the book's directory, like the raw one
+                if self.is_sandboxed() {
+                    self.check_sandbox(key, &Yaml::String(evaluated.clone()))?;
+                }
+                BookOption::Path(evaluated)
+            }
+            _ => BookOption::String(evaluated),
+        };
+        self.options.insert(key.to_owned(), evaluated.clone());
+        self.references.get_mut(key).unwrap().1 = evaluated;
+        done.push(key.to_owned());
+        Ok(())
+    }
+
+    /// Returns the deprecated keys, with the key replacing them if they were renamed
+    pub(crate) fn deprecated(&self) -> &HashMap<String, Option<String>> {
+        &self.deprecated
+    }
+
+    /// Returns the keys of all options valid to pass to a book, except deprecated ones, with
+    /// their descriptions (e.g. for shell completion)
+    pub fn keys() -> Vec<(&'static str, &'static str)> {
+        Self::options_to_vec()
+            .into_iter()
+            .take_while(|(comment, key, _, _)| {
+                key.is_some() || comment.trim() != t!("opt.deprecated")
+            })
+            .filter_map(|(comment, key, _, _)| Some((key?, comment.trim())))
+            .collect()
+    }
+
     /// OPTIONS to a vec of tuples (comment, key, type, default value)
     #[allow(clippy::type_complexity)]
     fn options_to_vec() -> Vec<(
@@ -0,0 +1,158 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Export of the book's metadata as a calibre-compatible `metadata.opf` file, and
+//! conversion of the EPUB output with calibre's `ebook-convert` to formats crowbook
+//! doesn't produce natively.
+
+use crate::book::Book;
+use crate::book_renderer::BookRenderer;
+use crate::epub::Epub;
+use crate::error::{Error, Result};
+use crate::misc;
+use crate::zipper::Zipper;
+
+use std::io::{self, Write};
+
+use crowbook_text_processing::escape;
+use rust_i18n::t;
+
+/// Dublin Core elements of `metadata.opf` and the options they are set from
+const DC_ELEMENTS: &[(&str, &str)] = &[
+    ("title", "title"),
+    ("language", "lang"),
+    ("subject", "subject"),
+    ("description", "description"),
+    ("rights", "license"),
+    ("date", "date"),
+];
+
+/// Returns the book's metadata in the OPF format that calibre reads and writes
+pub fn metadata_opf(book: &Book) -> String {
+    let mut metadata = vec![];
+    for (element, key) in DC_ELEMENTS {
+        if let Ok(value) = book.options.get_str(key) {
+            if !value.is_empty() {
+                metadata.push(format!("<dc:{element}>{}</dc:{element}>", escape::html(value)));
+            }
+        }
+        if *element == "title" {
+            // calibre expects the author right after the title
+            let author = book.options.get_str("author").unwrap();
+            if !author.is_empty() {
+                metadata.push(format!(
+                    "<dc:creator opf:role=\"aut\">{}</dc:creator>",
+                    escape::html(author)
+                ));
+            }
+        }
+    }
+    if let Ok(version) = book.options.get_str("version") {
+        metadata.push(format!(
+            "<meta name=\"crowbook:version\" content=\"{}\"/>",
+            escape::quotes(escape::html(version))
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"2.0\">
+  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:opf=\"http://www.idpf.org/2007/opf\">
+    {}
+    <meta name=\"generator\" content=\"crowbook\"/>
+  </metadata>
+</package>
+",
+        metadata.join("\n    ")
+    )
+}
+
+/// Renders the book's metadata as a calibre-compatible `metadata.opf` file
+pub struct Opf {}
+
+impl BookRenderer for Opf {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.opf"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn Write) -> Result<()> {
+        to.write_all(metadata_opf(book).as_bytes()).map_err(|e| {
+            Error::render(&book.source, t!("calibre.write_error", error = e))
+        })
+    }
+}
+
+/// Renders the book to a format of calibre's `ebook-convert` (e.g. AZW3), by converting
+/// its EPUB output with the metadata of `metadata.opf`
+pub struct EbookConvert {
+    /// Extension of the output format
+    pub format: &'static str,
+}
+
+impl EbookConvert {
+    /// Returns the command line set by `calibre.command` and `calibre.{format}.args`,
+    /// without the input and output files
+    fn command_line(&self, book: &Book) -> Result<(String, Vec<String>)> {
+        let mut command =
+            misc::split_command(book.options.get_str("calibre.command").unwrap()).into_iter();
+        let program = command
+            .next()
+            .ok_or_else(|| Error::render(&book.source, t!("calibre.no_command")))?;
+        let mut args: Vec<String> = command.collect();
+        args.push(String::from("book.epub"));
+        args.push(format!("result.{}", self.format));
+        args.push(String::from("--read-metadata-from-opf"));
+        args.push(String::from("metadata.opf"));
+        if let Ok(extra) = book.options.get_str(&format!("calibre.{}.args", self.format)) {
+            args.extend(misc::split_command(extra));
+        }
+        Ok((program, args))
+    }
+}
+
+impl BookRenderer for EbookConvert {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.{}", self.format))
+    }
+
+    fn required_commands(&self, book: &Book) -> Result<Vec<String>> {
+        Ok(vec![self.command_line(book)?.0])
+    }
+
+    fn dry_run(&self, book: &Book) -> Result<()> {
+        Epub {}.dry_run(book)
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn Write) -> Result<()> {
+        let (program, args) = self.command_line(book)?;
+        let mut zipper = Zipper::new(book.temp_dir()?, book.command_limits());
+        let mut epub = io::BufWriter::new(zipper.create("book.epub")?);
+        Epub {}.render(book, &mut epub)?;
+        epub.flush().map_err(|e| {
+            Error::render(&book.source, t!("epub.write_error", error = e))
+        })?;
+        drop(epub);
+        zipper.write("metadata.opf", metadata_opf(book).as_bytes(), false)?;
+
+        let mut command = misc::command(&program);
+        command.current_dir(zipper.path()).args(args);
+        let result = format!("result.{}", self.format);
+        book.timings().time(&format!("command: {program}"), || {
+            zipper.run_command(command, &program, &result, to)
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cancellation of a book's renderings, and limits (`crowbook.command.timeout` and
+//! cancellation) on the external commands they run, so a hung command can't block them
+//! forever.
+
+use crate::error::{Error, Result, Source};
+
+use std::io::{self, Read};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rust_i18n::t;
+
+/// How often a running command is checked for its end, timeout or cancellation
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A token cancelling the renderings of a book, which can be shared between threads
+///
+/// Renderings check it between chapters and phases, and kill the external commands they
+/// run (e.g. LaTeX) when it is cancelled; they then fail with an error whose kind is
+/// `ErrorKind::Cancelled`.
+///
+/// # Example
+///
+/// ```
+/// use crowbook::Book;
+///
+/// let book = Book::new();
+/// let token = book.cancellation_token();
+/// std::thread::spawn(move || token.cancel());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a new token, which isn't cancelled
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the renderings using this token; it stays cancelled
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true if the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Limits on the external commands run while rendering a book
+#[derive(Debug, Clone, Default)]
+pub struct CommandLimits {
+    /// Set by `crowbook.command.timeout`
+    pub timeout: Option<Duration>,
+    /// Token of the book's renderings
+    pub cancellation: CancellationToken,
+}
+
+/// Reads a pipe of a child process in another thread, so it can't fill up and block it
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut content = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut content);
+        }
+        content
+    })
+}
+
+impl CommandLimits {
+    /// Runs a command and collects its output, like `Command::output`
+    ///
+    /// **Returns** an error if the command is killed because it timed out or the rendering
+    /// was cancelled, or else the result of running it.
+    pub fn output(&self, command: &mut Command, name: &str) -> Result<io::Result<Output>> {
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        match child {
+            Ok(child) => self.wait(child, name),
+            Err(err) => Ok(Err(err)),
+        }
+    }
+
+    /// Waits for a child process whose stdout and stderr are piped and collects its
+    /// output, like `Child::wait_with_output`, killing it if it times out or the rendering
+    /// is cancelled
+    pub fn wait(&self, mut child: Child, name: &str) -> Result<io::Result<Output>> {
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+        let start = Instant::now();
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => (),
+                Err(err) => return Ok(Err(err)),
+            }
+            let timed_out = self.timeout.map_or(false, |timeout| start.elapsed() >= timeout);
+            if timed_out || self.cancellation.is_cancelled() {
+                let _ = child.kill();
+                let _ = child.wait();
+                // The pipes aren't read to their end, since processes started by the command
+                // might still have them open
+                return Err(if timed_out {
+                    Error::render(
+                        Source::empty(),
+                        t!(
+                            "error.timeout",
+                            command = name,
+                            timeout = format!("{:?}", self.timeout.unwrap())
+                        ),
+                    )
+                } else {
+                    Error::cancelled()
+                });
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        Ok(Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        }))
+    }
+}
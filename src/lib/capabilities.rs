@@ -0,0 +1,127 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Features of a book that some formats can't render as they are, each renderer declaring
+//! the ones it doesn't support and what it renders instead, so authors are warned instead
+//! of losing content silently.
+
+use crate::misc;
+use crate::resource_handler::ResourceHandler;
+use crate::token::Token;
+
+use std::fmt;
+
+use rust_i18n::t;
+
+/// A feature of a book that not all formats support
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Feature {
+    /// Audio files, included with the image syntax
+    Audio,
+    /// Video files, included with the image syntax
+    Video,
+    /// Images whose URL isn't a local file
+    RemoteImage,
+    /// Long descriptions of images
+    ImageDescription,
+}
+
+impl Feature {
+    /// Returns the name of the feature, as displayed in warnings
+    pub fn name(self) -> String {
+        match self {
+            Feature::Audio => t!("capabilities.audio"),
+            Feature::Video => t!("capabilities.video"),
+            Feature::RemoteImage => t!("capabilities.remote_image"),
+            Feature::ImageDescription => t!("capabilities.image_description"),
+        }
+    }
+}
+
+/// What a format renders instead of a feature it doesn't support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    /// A link to the file, or its alternative text if it has no URL
+    Link,
+    /// Nothing: the content is left out
+    Omitted,
+}
+
+impl Fallback {
+    /// Returns a description of the fallback, as displayed in warnings
+    pub fn description(self) -> String {
+        match self {
+            Fallback::Link => t!("capabilities.link"),
+            Fallback::Omitted => t!("capabilities.omitted"),
+        }
+    }
+}
+
+/// A feature used by a chapter that a format doesn't support, and what it renders instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Downgrade {
+    /// The format, e.g. `pdf`
+    pub format: String,
+    /// The feature that isn't supported
+    pub feature: Feature,
+    /// The file of the chapter using it (or its number, if it doesn't come from a file)
+    pub chapter: String,
+    /// What is rendered instead
+    pub fallback: Fallback,
+}
+
+impl fmt::Display for Downgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            t!(
+                "capabilities.downgrade",
+                format = self.format,
+                feature = self.feature.name(),
+                chapter = self.chapter,
+                fallback = self.fallback.description()
+            )
+        )
+    }
+}
+
+/// Adds the features used by some tokens to `features`
+pub fn features(tokens: &[Token], features: &mut Vec<Feature>) {
+    for token in tokens {
+        let feature = match *token {
+            Token::Image(ref url, ..) | Token::StandaloneImage(ref url, ..) => {
+                match misc::media_type(url) {
+                    Some("audio") => Some(Feature::Audio),
+                    Some(_) => Some(Feature::Video),
+                    None if !ResourceHandler::is_local(url) => Some(Feature::RemoteImage),
+                    None => None,
+                }
+            }
+            Token::DescribedImage(_) => Some(Feature::ImageDescription),
+            _ => None,
+        };
+        if let Some(feature) = feature {
+            if !features.contains(&feature) {
+                features.push(feature);
+            }
+        }
+        if let Some(inner) = token.inner() {
+            self::features(inner, features);
+        }
+    }
+}
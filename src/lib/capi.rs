@@ -0,0 +1,175 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! C-compatible functions (with the `capi` feature), so bindings for other languages
+//! (e.g. Python or Node) can use Crowbook. They are declared in `include/crowbook.h`.
+//!
+//! Functions that fail return a null pointer or a non-zero value, and the error can then
+//! be read with `crowbook_last_error`.
+
+use crate::book::Book;
+use crate::error::{Error, Result, Source};
+use crate::number::Number;
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use rust_i18n::t;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// Runs `f`, returning `default` and storing the error if it fails or panics, since neither
+/// can cross the C boundary
+fn catch<T, F: FnOnce() -> Result<T>>(default: T, f: F) -> T {
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(Error::default(Source::empty(), t!("capi.panic", error = msg)))
+    });
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            let msg = CString::new(err.to_string().replace('\0', "")).unwrap();
+            LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+            default
+        }
+    }
+}
+
+/// Reads a C string, which must be UTF-8
+///
+/// # Safety
+///
+/// `s` must be null or point to a nul-terminated string.
+unsafe fn to_str<'a>(s: *const c_char, name: &str) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(Error::default(Source::empty(), t!("capi.null", name = name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| Error::default(Source::empty(), t!("capi.utf8", name = name)))
+}
+
+/// Returns the message of the last error that occurred in this thread, or null if there was
+/// none
+///
+/// The string belongs to Crowbook and stays valid until the next call that fails.
+#[no_mangle]
+pub extern "C" fn crowbook_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |msg| msg.as_ptr()))
+}
+
+/// Creates a book from the content of a configuration file, returning null if it is invalid
+///
+/// Chapters listed in the configuration are read relatively to the current directory. The
+/// book must be freed with `crowbook_book_free`.
+///
+/// # Safety
+///
+/// `config` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn crowbook_book_new(config: *const c_char) -> *mut Book<'static> {
+    catch(ptr::null_mut(), || {
+        let config = to_str(config, "config")?;
+        let mut book = Book::new();
+        book.read_config(config.as_bytes())?;
+        Ok(Box::into_raw(Box::new(book)))
+    })
+}
+
+/// Frees a book created by `crowbook_book_new`
+///
+/// # Safety
+///
+/// `book` must be null or a book returned by `crowbook_book_new`, that isn't used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn crowbook_book_free(book: *mut Book<'static>) {
+    if !book.is_null() {
+        drop(Box::from_raw(book));
+    }
+}
+
+/// Adds a numbered chapter, written in Markdown, to a book, returning 0 on success
+///
+/// # Safety
+///
+/// `book` must be a book returned by `crowbook_book_new` and `content` a nul-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn crowbook_book_add_chapter(
+    book: *mut Book<'static>,
+    content: *const c_char,
+) -> c_int {
+    catch(-1, || {
+        let book = book
+            .as_mut()
+            .ok_or_else(|| Error::default(Source::empty(), t!("capi.null", name = "book")))?;
+        let content = to_str(content, "content")?;
+        book.add_chapter_from_source(Number::Default, content.as_bytes(), true)?;
+        Ok(0)
+    })
+}
+
+/// Renders a book to a format (e.g. `html` or `epub`) in memory, returning a buffer with the
+/// content of the file and setting `len` to its length, or null if it fails
+///
+/// The buffer must be freed with `crowbook_buffer_free`.
+///
+/// # Safety
+///
+/// `book` must be a book returned by `crowbook_book_new`, `format` a nul-terminated string
+/// and `len` point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn crowbook_book_render(
+    book: *const Book<'static>,
+    format: *const c_char,
+    len: *mut usize,
+) -> *mut u8 {
+    catch(ptr::null_mut(), || {
+        let book = book
+            .as_ref()
+            .ok_or_else(|| Error::default(Source::empty(), t!("capi.null", name = "book")))?;
+        let format = to_str(format, "format")?;
+        let len = len
+            .as_mut()
+            .ok_or_else(|| Error::default(Source::empty(), t!("capi.null", name = "len")))?;
+        let content = book.render_format_to_vec(format)?.into_boxed_slice();
+        *len = content.len();
+        Ok(Box::into_raw(content) as *mut u8)
+    })
+}
+
+/// Frees a buffer returned by `crowbook_book_render`
+///
+/// # Safety
+///
+/// `buffer` must be null or a buffer returned by `crowbook_book_render`, with the length it
+/// set, that isn't used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn crowbook_buffer_free(buffer: *mut u8, len: usize) {
+    if !buffer.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer, len)));
+    }
+}
@@ -19,7 +19,7 @@ use crate::number::Number;
 use crate::token::Token;
 
 /// Represents the content of a chapter.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chapter {
     /// The numbering scheme of this chapter.
     pub number: Number,
@@ -27,6 +27,23 @@ pub struct Chapter {
     pub filename: String,
     /// The (already parsed) content of this chapter
     pub content: Vec<Token>,
+    /// The publication date of this chapter, set by `chapter.date` in its YAML block
+    pub date: Option<String>,
+    /// The title of this chapter, set by `chapter.title` in its YAML block, which
+    /// replaces the one of its first header
+    pub title: Option<String>,
+    /// The status of this chapter (e.g. `draft`), set by `chapter.status` in its YAML block
+    pub status: Option<String>,
+    /// A short summary of this chapter, set by `chapter.synopsis` in its YAML block
+    pub synopsis: Option<String>,
+    /// The tags of this chapter, set by `chapter.tags` in its YAML block
+    pub tags: Vec<String>,
+    /// The page layout of this chapter in PDF (`landscape` or `two_columns`), set by
+    /// `chapter.layout` in its YAML block
+    pub layout: Option<String>,
+    /// Whether this chapter (and its sections) is left out of the table of contents, set
+    /// by a `~` before its line in the list of files
+    pub unlisted: bool,
 }
 
 impl Chapter {
@@ -41,6 +58,13 @@ impl Chapter {
             number,
             filename: filename.into(),
             content,
+            date: None,
+            title: None,
+            status: None,
+            synopsis: None,
+            tags: vec![],
+            layout: None,
+            unlisted: false,
         }
     }
 }
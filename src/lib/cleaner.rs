@@ -20,19 +20,34 @@
 use crowbook_text_processing::clean;
 use crowbook_text_processing::FrenchFormatter;
 use std::borrow::Cow;
+use unicode_normalization::char::is_combining_mark;
+
+/// Non-breaking spaces inserted by the French formatter
+const NB_SPACE: char = '\u{A0}';
+const NB_SPACE_NARROW: char = '\u{202F}';
+const NB_SPACE_EM: char = '\u{2002}';
 
 /// Contains cleaning parameters
 pub struct CleanerParams {
     pub smart_quotes: bool,
     pub ligature_guillemets: bool,
     pub ligature_dashes: bool,
+    pub ellipsis: bool,
+    pub ordinals: bool,
+    pub fractions: bool,
+    /// Custom substitutions, as `(from, to)`
+    pub symbols: Vec<(String, String)>,
+    /// Non-breaking space inserted by the French cleaner, e.g. before `:`
+    pub nb_space: char,
+    /// Narrow non-breaking space inserted by the French cleaner, e.g. before `?`
+    pub nb_space_narrow: char,
 }
 
 /// Trait for cleaning a string.
 ///
 /// This trait must be called for text that is e.g. in a paragraph, a title,
 /// NOT for code blocks, hyperlinks and so on!
-pub trait Cleaner: Sync {
+pub trait Cleaner: Send + Sync {
     /// Cleans a string. The default implementation is to remove multiple consecutive whitespaces
     ///
     /// # Arguments
@@ -64,7 +79,7 @@ impl Default {
 impl Cleaner for Default {
     /// Remove unnecessary whitespaces
     fn clean<'a>(&self, input: Cow<'a, str>) -> Cow<'a, str> {
-        let mut s = clean::whitespaces(input);
+        let mut s = symbols(clean::whitespaces(input), &self.params.symbols);
         if self.params.smart_quotes {
             s = clean::quotes(s);
         }
@@ -74,6 +89,15 @@ impl Cleaner for Default {
         if self.params.ligature_guillemets {
             s = clean::guillemets(s);
         }
+        if self.params.ellipsis {
+            s = clean::ellipsis(s);
+        }
+        if self.params.ordinals {
+            s = ordinals(s, &ENGLISH_ORDINALS, false);
+        }
+        if self.params.fractions {
+            s = fractions(s);
+        }
         s
     }
 }
@@ -98,13 +122,210 @@ impl French {
         this.formatter.ligature_dashes(this.params.ligature_dashes);
         this.formatter
             .ligature_guillemets(this.params.ligature_guillemets);
+        this.formatter.typographic_ellipsis(this.params.ellipsis);
         this
     }
+
+    /// Replaces the non-breaking spaces of the formatter with the ones set in the parameters
+    fn nb_spaces<'a>(&self, s: Cow<'a, str>) -> Cow<'a, str> {
+        let replaced = |c| match c {
+            NB_SPACE => self.params.nb_space,
+            NB_SPACE_NARROW => self.params.nb_space_narrow,
+            c => c,
+        };
+        if s.chars().any(|c| replaced(c) != c) {
+            Cow::Owned(s.chars().map(replaced).collect())
+        } else {
+            s
+        }
+    }
 }
 
 impl Cleaner for French {
     /// Puts non breaking spaces before/after `:`, `;`, `?`, `!`, `«`, `»`, `—`
     fn clean<'a>(&self, s: Cow<'a, str>) -> Cow<'a, str> {
-        self.formatter.format(s)
+        let s = self.formatter.format(symbols(s, &self.params.symbols));
+        let mut s = self.nb_spaces(s);
+        if self.params.ordinals {
+            s = ordinals(s, &FRENCH_ORDINALS, true);
+        }
+        if self.params.fractions {
+            s = fractions(s);
+        }
+        let nb_chars = [self.params.nb_space, self.params.nb_space_narrow, NB_SPACE_EM];
+        keep_graphemes(s, &nb_chars)
+    }
+}
+
+/// Suffixes of English ordinals, e.g. `1st`
+const ENGLISH_ORDINALS: [&str; 4] = ["st", "nd", "rd", "th"];
+
+/// Suffixes of French ordinals, e.g. `1er` or `XIXe`
+const FRENCH_ORDINALS: [&str; 12] = [
+    "e", "es", "er", "ers", "re", "res", "de", "des", "nd", "nds", "nde", "ndes",
+];
+
+/// Vulgar fractions and the characters replacing them
+const FRACTIONS: [(&str, char); 18] = [
+    ("1/2", '½'),
+    ("1/3", '⅓'),
+    ("2/3", '⅔'),
+    ("1/4", '¼'),
+    ("3/4", '¾'),
+    ("1/5", '⅕'),
+    ("2/5", '⅖'),
+    ("3/5", '⅗'),
+    ("4/5", '⅘'),
+    ("1/6", '⅙'),
+    ("5/6", '⅚'),
+    ("1/7", '⅐'),
+    ("1/8", '⅛'),
+    ("3/8", '⅜'),
+    ("5/8", '⅝'),
+    ("7/8", '⅞'),
+    ("1/9", '⅑'),
+    ("1/10", '⅒'),
+];
+
+/// Returns the superscript version of a letter used in ordinals
+fn superscript(c: char) -> char {
+    match c {
+        'd' => '\u{1D48}',
+        'e' => '\u{1D49}',
+        'h' => '\u{2B0}',
+        'n' => '\u{207F}',
+        'r' => '\u{2B3}',
+        's' => '\u{2E2}',
+        't' => '\u{1D57}',
+        _ => c,
+    }
+}
+
+/// Applies custom substitutions
+fn symbols<'a>(s: Cow<'a, str>, symbols: &[(String, String)]) -> Cow<'a, str> {
+    let mut s = s;
+    for (from, to) in symbols {
+        if s.contains(from.as_str()) {
+            s = Cow::Owned(s.replace(from.as_str(), to));
+        }
+    }
+    s
+}
+
+/// Puts the suffixes of ordinals in superscript, e.g. `1er` to `1ᵉʳ`
+///
+/// If `roman` is set, roman numerals (e.g. `XIXe`) are also considered.
+fn ordinals<'a>(s: Cow<'a, str>, suffixes: &[&str], roman: bool) -> Cow<'a, str> {
+    let is_number = |c: char| c.is_ascii_digit() || (roman && matches!(c, 'I' | 'V' | 'X'));
+    if !s.contains(is_number) {
+        return s;
+    }
+    let mut res = String::with_capacity(s.len());
+    let mut changed = false;
+    let mut rest: &str = &s;
+    let mut previous: Option<char> = None;
+    while let Some(c) = rest.chars().next() {
+        let len = rest.find(|c| !is_number(c)).unwrap_or(rest.len());
+        if len > 0 && !previous.map_or(false, char::is_alphanumeric) {
+            let number = &rest[..len];
+            let after = &rest[len..];
+            let suffix_len = after.find(|c: char| !c.is_alphanumeric()).unwrap_or(after.len());
+            let suffix = &after[..suffix_len];
+            // Roman numerals only take singular suffixes, e.g. `XXIe` or `Ier`, so `Vers` is left alone
+            let valid = if number.starts_with(|c: char| c.is_ascii_digit()) {
+                number.chars().all(|c| c.is_ascii_digit())
+            } else {
+                matches!(suffix, "e" | "er" | "re")
+            };
+            if valid && suffixes.contains(&suffix) {
+                res.push_str(number);
+                res.extend(suffix.chars().map(superscript));
+                changed = true;
+                previous = suffix.chars().last();
+                rest = &after[suffix_len..];
+                continue;
+            }
+        }
+        res.push(c);
+        previous = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if changed {
+        Cow::Owned(res)
+    } else {
+        s
+    }
+}
+
+/// Replaces fractions with the corresponding characters, e.g. `1/2` to `½`
+fn fractions(s: Cow<str>) -> Cow<str> {
+    if !s.contains('/') {
+        return s;
+    }
+    let is_part = |c: char| c.is_alphanumeric() || matches!(c, '/' | '.' | ',');
+    // `1/2.` ends a sentence, but not `1/2.5`
+    let continues = |s: &str| {
+        let mut chars = s.chars();
+        match chars.next() {
+            Some('.') | Some(',') => chars.next().map_or(false, |c| c.is_ascii_digit()),
+            Some(c) => c.is_alphanumeric() || c == '/',
+            None => false,
+        }
+    };
+    let mut res = String::with_capacity(s.len());
+    let mut changed = false;
+    let mut rest: &str = &s;
+    let mut previous: Option<char> = None;
+    'outer: while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() && !previous.map_or(false, is_part) {
+            for (fraction, replacement) in &FRACTIONS {
+                if rest.starts_with(fraction) && !continues(&rest[fraction.len()..]) {
+                    res.push(*replacement);
+                    changed = true;
+                    previous = Some(*replacement);
+                    rest = &rest[fraction.len()..];
+                    continue 'outer;
+                }
+            }
+        }
+        res.push(c);
+        previous = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if changed {
+        Cow::Owned(res)
+    } else {
+        s
+    }
+}
+
+/// Moves the combining marks that follow a non-breaking space before it, so that
+/// inserting a space never splits a letter from its accents
+fn keep_graphemes<'a>(s: Cow<'a, str>, nb_chars: &[char]) -> Cow<'a, str> {
+    let mut chars = s.chars().peekable();
+    let mut split = false;
+    while let Some(c) = chars.next() {
+        if nb_chars.contains(&c) && chars.peek().map_or(false, |&c| is_combining_mark(c)) {
+            split = true;
+            break;
+        }
+    }
+    if !split {
+        return s;
+    }
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if nb_chars.contains(&c) {
+            while let Some(&mark) = chars.peek() {
+                if !is_combining_mark(mark) {
+                    break;
+                }
+                res.push(mark);
+                chars.next();
+            }
+        }
+        res.push(c);
     }
+    Cow::Owned(res)
 }
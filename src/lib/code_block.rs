@@ -0,0 +1,214 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Attributes of fenced code blocks, e.g.
+//! `rust {caption="Hello, world" file=main.rs lines=10-42 highlight=2-3 start=10}`.
+
+/// Where the numbering of the lines of a code block starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Start {
+    /// At the given line number
+    At(u32),
+    /// After the last line of the previous code block
+    Continue,
+}
+
+/// The language and attributes of a code block, parsed from its info string
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CodeInfo {
+    /// Language of the code, e.g. `rust`
+    pub language: String,
+    /// Caption of the code block
+    pub caption: Option<String>,
+    /// Name of the file the code comes from, which is included if the block is empty
+    pub file: Option<String>,
+    /// Range of the lines of `file` to include (the end being `u32::MAX` if not set)
+    pub lines: Option<(u32, u32)>,
+    /// Ranges of highlighted lines, relative to the start of the block
+    pub highlight: Vec<(u32, u32)>,
+    /// Start of the line numbering, which forces line numbers to be displayed
+    pub start: Option<Start>,
+}
+
+impl CodeInfo {
+    /// Parses the info string of a code block
+    ///
+    /// **Returns** the invalid attribute if there is one.
+    pub fn parse(info: &str) -> Result<CodeInfo, String> {
+        let (language, attributes) = match info.find('{') {
+            Some(i) => (&info[..i], &info[i..]),
+            None => (info, ""),
+        };
+        let mut res = CodeInfo {
+            language: language.trim().to_owned(),
+            ..CodeInfo::default()
+        };
+        if attributes.is_empty() {
+            return Ok(res);
+        }
+        let mut rest = attributes
+            .trim_end()
+            .strip_prefix('{')
+            .and_then(|s| s.strip_suffix('}'))
+            .ok_or_else(|| attributes.to_owned())?;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            // Pandoc's way to set the language, e.g. `{.rust}`
+            if let Some(class) = rest[..len].strip_prefix('.') {
+                if res.language.is_empty() {
+                    res.language = class.to_owned();
+                }
+                rest = &rest[len..];
+                continue;
+            }
+            let invalid = || rest[..len].to_owned();
+            let equal = rest.find('=').ok_or_else(invalid)?;
+            let key = &rest[..equal];
+            let value = &rest[equal + 1..];
+            let (value, len) = if let Some(quoted) = value.strip_prefix('"') {
+                let end = quoted.find('"').ok_or_else(invalid)?;
+                (&quoted[..end], end + 2)
+            } else {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                (&value[..end], end)
+            };
+            match key {
+                "caption" => res.caption = Some(value.to_owned()),
+                "file" => res.file = Some(value.to_owned()),
+                "lines" => res.lines = Some(parse_range(value, u32::MAX).ok_or_else(invalid)?),
+                "highlight" => res.highlight = parse_ranges(value).ok_or_else(invalid)?,
+                "start" if value == "continue" => res.start = Some(Start::Continue),
+                "start" => res.start = Some(Start::At(value.parse().map_err(|_| invalid())?)),
+                _ => return Err(invalid()),
+            }
+            rest = &rest[equal + 1 + len..];
+        }
+        Ok(res)
+    }
+
+    /// Parses the info string of a code block, ignoring its attributes if they are invalid
+    pub fn new(info: &str) -> CodeInfo {
+        CodeInfo::parse(info).unwrap_or_else(|_| CodeInfo {
+            language: info[..info.find('{').unwrap_or(info.len())].trim().to_owned(),
+            ..CodeInfo::default()
+        })
+    }
+
+    /// Returns true if the `n`th line of the block (starting from 1) is highlighted
+    pub fn is_highlighted(&self, n: u32) -> bool {
+        self.highlight.iter().any(|&(from, to)| from <= n && n <= to)
+    }
+
+    /// Returns the number of the first line of the block, given the number of the last
+    /// line of the previous one
+    ///
+    /// Unless `start` is set, included lines keep their numbers in the file.
+    pub fn first_line(&self, previous: u32) -> u32 {
+        match self.start {
+            Some(Start::At(n)) => n,
+            Some(Start::Continue) => previous + 1,
+            None => self.lines.map_or(1, |(from, _)| from),
+        }
+    }
+
+    /// Returns the included lines of the content of `file`
+    ///
+    /// **Returns** `None` if the range of lines is outside of the file.
+    pub fn select_lines(&self, content: &str) -> Option<String> {
+        let (from, to) = match self.lines {
+            Some(range) => range,
+            None => return Some(content.to_owned()),
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        if from as usize > lines.len() || (to != u32::MAX && to as usize > lines.len()) {
+            return None;
+        }
+        let to = (to as usize).min(lines.len());
+        let mut res = lines[from as usize - 1..to].join("\n");
+        res.push('\n');
+        Some(res)
+    }
+
+    /// Returns the attributes of the block as `(key, value)` pairs
+    pub fn attributes(&self) -> Vec<(&'static str, String)> {
+        let mut res = vec![];
+        if let Some(ref caption) = self.caption {
+            res.push(("caption", caption.clone()));
+        }
+        if let Some(ref file) = self.file {
+            res.push(("file", file.clone()));
+        }
+        match self.lines {
+            Some((from, u32::MAX)) => res.push(("lines", format!("{from}-"))),
+            Some((from, to)) => res.push(("lines", format!("{from}-{to}"))),
+            None => (),
+        }
+        if !self.highlight.is_empty() {
+            let ranges: Vec<String> = self
+                .highlight
+                .iter()
+                .map(|&(from, to)| if from == to { format!("{from}") } else { format!("{from}-{to}") })
+                .collect();
+            res.push(("highlight", ranges.join(",")));
+        }
+        match self.start {
+            Some(Start::At(n)) => res.push(("start", format!("{n}"))),
+            Some(Start::Continue) => res.push(("start", String::from("continue"))),
+            None => (),
+        }
+        res
+    }
+
+    /// Returns the info string of the block
+    pub fn to_info(&self) -> String {
+        let attributes: Vec<String> = self
+            .attributes()
+            .iter()
+            .map(|(key, value)| format!("{key}=\"{value}\""))
+            .collect();
+        if attributes.is_empty() {
+            self.language.clone()
+        } else {
+            format!("{} {{{}}}", self.language, attributes.join(" "))
+        }
+    }
+}
+
+/// Parses ranges of lines, e.g. `1,3-5`
+fn parse_ranges(s: &str) -> Option<Vec<(u32, u32)>> {
+    s.split(',').map(|range| parse_range(range, 0)).collect()
+}
+
+/// Parses a range of lines, e.g. `3-5` or `3`, the end being `open_end` in `3-` if it isn't 0
+fn parse_range(range: &str, open_end: u32) -> Option<(u32, u32)> {
+    let (from, to) = range.split_once('-').unwrap_or((range, range));
+    let from: u32 = from.trim().parse().ok()?;
+    let to: u32 = if to.trim().is_empty() && open_end > 0 {
+        open_end
+    } else {
+        to.trim().parse().ok()?
+    };
+    if from > 0 && from <= to {
+        Some((from, to))
+    } else {
+        None
+    }
+}
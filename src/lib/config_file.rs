@@ -0,0 +1,230 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Editing of book configuration files that keeps what isn't changed as it is written
+//! (comments, order of the options and chapter list).
+
+use crate::error::{Error, Result, Source};
+use crate::misc;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use rust_i18n::t;
+use yaml_rust::{yaml::Hash, Yaml, YamlEmitter, YamlLoader};
+
+/// A book configuration file, which can be edited without losing its comments, the order
+/// of its options or its chapter list
+///
+/// # Example
+///
+/// ```
+/// use crowbook::ConfigFile;
+/// use yaml_rust::Yaml;
+///
+/// let mut config = ConfigFile::parse("# My book\ntitle: Foo\n\n+ chapter_01.md\n");
+/// config.set("title", Yaml::String(String::from("Bar")));
+/// config.set("lang", Yaml::String(String::from("fr")));
+/// assert_eq!(config.to_string(), "# My book\ntitle: Bar\nlang: fr\n\n+ chapter_01.md\n");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    lines: Vec<String>,
+}
+
+/// Returns true if a line starts the chapter list, as in `Book::read_config`
+fn is_chapter(line: &str) -> bool {
+    line.starts_with(|c: char| matches!(c, '-' | '+' | '!' | '@' | '~') || c.is_ascii_digit())
+}
+
+/// Returns the key set by a line, if it starts a top-level option
+fn key_of(line: &str) -> Option<&str> {
+    if line.starts_with(|c: char| c.is_whitespace() || c == '#') {
+        return None;
+    }
+    let (key, rest) = line.split_once(':')?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(key.trim().trim_matches(|c| c == '"' || c == '\''))
+}
+
+impl ConfigFile {
+    /// Creates an empty configuration file
+    pub fn new() -> ConfigFile {
+        ConfigFile::default()
+    }
+
+    /// Reads a configuration file from its content
+    pub fn parse(content: &str) -> ConfigFile {
+        ConfigFile {
+            lines: content.lines().map(String::from).collect(),
+        }
+    }
+
+    /// Reads a configuration file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ConfigFile> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|_| {
+            Error::file_not_found(Source::empty(), t!("format.book"), misc::normalize(path))
+        })?;
+        Ok(ConfigFile::parse(&content))
+    }
+
+    /// Writes the configuration file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string()).map_err(|err| {
+            Error::default(
+                Source::empty(),
+                t!("config_file.write", file = misc::normalize(path), error = err),
+            )
+        })
+    }
+
+    /// Returns the number of lines before the chapter list
+    fn options_end(&self) -> usize {
+        self.lines
+            .iter()
+            .position(|line| is_chapter(line))
+            .unwrap_or(self.lines.len())
+    }
+
+    /// Returns the range of lines where an option is set: the line with its key, and the
+    /// indented lines continuing its value
+    fn find(&self, key: &str) -> Option<(usize, usize)> {
+        let end = self.options_end();
+        let start = (0..end).find(|&i| key_of(&self.lines[i]) == Some(key))?;
+        let mut last = start + 1;
+        for i in start + 1..end {
+            let line = &self.lines[i];
+            if line.starts_with(char::is_whitespace) && !line.trim().is_empty() {
+                last = i + 1;
+            } else if !line.trim().is_empty() {
+                break;
+            }
+        }
+        Some((start, last))
+    }
+
+    /// Returns the keys of the options, in the order they are set
+    pub fn keys(&self) -> Vec<&str> {
+        self.lines[..self.options_end()]
+            .iter()
+            .filter_map(|line| key_of(line))
+            .collect()
+    }
+
+    /// Returns the value of an option, if it is set
+    pub fn get(&self, key: &str) -> Option<Yaml> {
+        let (start, end) = self.find(key)?;
+        let yaml = self.lines[start..end].join("\n");
+        let mut docs = YamlLoader::load_from_str(&yaml).ok()?;
+        match docs.pop()? {
+            Yaml::Hash(mut hash) => hash.remove(&Yaml::String(key.to_owned())),
+            _ => None,
+        }
+    }
+
+    /// Sets an option, replacing its previous value where it is written, or adding it after
+    /// the last option
+    pub fn set(&mut self, key: &str, value: Yaml) {
+        let mut hash = Hash::new();
+        hash.insert(Yaml::String(key.to_owned()), value);
+        let mut yaml = String::new();
+        // Only emitting aliases can fail
+        YamlEmitter::new(&mut yaml)
+            .dump(&Yaml::Hash(hash))
+            .unwrap();
+        let new_lines = yaml
+            .strip_prefix("---\n")
+            .unwrap_or(&yaml)
+            .lines()
+            .map(String::from);
+        let (start, end) = match self.find(key) {
+            Some(range) => range,
+            None => {
+                let end = self.options_end();
+                let last = self.keys().last().and_then(|last| self.find(last));
+                let at = match last {
+                    Some((_, after)) => after,
+                    // Before the chapter list and the blank lines and comments preceding it
+                    None => (0..end)
+                        .rev()
+                        .take_while(|&i| {
+                            let line = self.lines[i].trim();
+                            line.is_empty() || line.starts_with('#')
+                        })
+                        .last()
+                        .unwrap_or(end),
+                };
+                (at, at)
+            }
+        };
+        self.lines.splice(start..end, new_lines);
+    }
+
+    /// Renames an option, keeping its value and comments as they are written, returning true
+    /// if it was set
+    pub fn rename(&mut self, key: &str, new_key: &str) -> bool {
+        match self.find(key) {
+            Some((start, _)) => {
+                let line = &self.lines[start];
+                // `find` only returns lines with a key followed by ':'
+                let colon = line.find(':').unwrap();
+                self.lines[start] = format!("{new_key}{}", &line[colon..]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Comments out an option, so it is no longer set but can still be read, returning true
+    /// if it was set
+    pub fn comment_out(&mut self, key: &str) -> bool {
+        match self.find(key) {
+            Some((start, end)) => {
+                for line in &mut self.lines[start..end] {
+                    line.insert_str(0, "# ");
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes an option, returning true if it was set
+    pub fn remove(&mut self, key: &str) -> bool {
+        match self.find(key) {
+            Some((start, end)) => {
+                self.lines.drain(start..end);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
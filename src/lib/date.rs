@@ -0,0 +1,111 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Dates of the `date` options and of chapters, written in ISO 8601.
+
+use crate::lang;
+
+use std::fmt;
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+
+/// A date, written in ISO 8601 either as a day (`2024-03-03`) or with a time
+/// (`2024-03-03T10:30:00+01:00`, UTC if the offset is omitted)
+///
+/// # Example
+///
+/// ```
+/// use crowbook::Date;
+///
+/// let date = Date::parse("2024-03-03").unwrap();
+/// assert_eq!(date.format("fr"), "3 mars 2024");
+/// assert_eq!(date.format("en"), "March 3, 2024");
+/// assert_eq!(date.to_string(), "2024-03-03");
+/// assert!(Date::parse("March 2024").is_none());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Date {
+    /// A day, without a time
+    Day(NaiveDate),
+    /// A day and a time
+    Time(DateTime<FixedOffset>),
+}
+
+impl Date {
+    /// Parses a date written in ISO 8601, returning None if it isn't valid
+    pub fn parse(s: &str) -> Option<Date> {
+        let s = s.trim();
+        if let Ok(day) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            Some(Date::Day(day))
+        } else if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            Some(Date::Time(time))
+        } else {
+            ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+                .map(|time| Date::Time(time.and_utc().fixed_offset()))
+        }
+    }
+
+    /// Returns true if a string starts like a date in ISO 8601 (`YYYY-`), in which case
+    /// it is a mistake rather than a date written in free text by older versions
+    pub(crate) fn looks_iso(s: &str) -> bool {
+        let s = s.trim().as_bytes();
+        s.len() > 4 && s[..4].iter().all(u8::is_ascii_digit) && s[4] == b'-'
+    }
+
+    /// Returns the day of the date
+    pub fn day(&self) -> NaiveDate {
+        match self {
+            Date::Day(day) => *day,
+            Date::Time(time) => time.date_naive(),
+        }
+    }
+
+    /// Returns the date in UTC, a day being at midnight
+    pub fn to_utc(&self) -> DateTime<Utc> {
+        match self {
+            Date::Day(day) => day.and_time(Default::default()).and_utc(),
+            Date::Time(time) => time.with_timezone(&Utc),
+        }
+    }
+
+    /// Returns the day of the date as it is written in a language, e.g. `3 mars 2024` in
+    /// French
+    pub fn format(&self, lang: &str) -> String {
+        let day = self.day();
+        let months = lang::get_str(lang, "months");
+        let month = months
+            .split(',')
+            .nth(day.month0() as usize)
+            .unwrap_or_default()
+            .trim();
+        lang::get_str(lang, "date_format")
+            .replace("{day}", &day.day().to_string())
+            .replace("{month}", month)
+            .replace("{year}", &day.year().to_string())
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Date::Day(day) => write!(f, "{}", day.format("%Y-%m-%d")),
+            Date::Time(time) => write!(f, "{}", time.to_rfc3339()),
+        }
+    }
+}
@@ -0,0 +1,489 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Comparison of two versions of a book, rendered as an HTML document where
+//! insertions and deletions are highlighted at the word level.
+
+use crate::book::Book;
+use crate::chapter::Chapter;
+use crate::error::{Error, Result, Source};
+use crate::text_view::view_as_text;
+use crate::token::Token;
+
+use std::fmt::Write;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use html_escape::encode_text;
+use rust_i18n::t;
+
+/// An operation of a diff between two sequences
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    /// Element present in both sequences, at these indices
+    Same(usize, usize),
+    /// Element of the old sequence that was deleted
+    Delete(usize),
+    /// Element of the new sequence that was inserted
+    Insert(usize),
+}
+
+/// Computes the longest common subsequence of two sequences, returned as a list of operations
+fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Op> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (n, m) = (a.len() - prefix - suffix, b.len() - prefix - suffix);
+
+    // lengths[i][j] is the length of the LCS of a[prefix + i..] and b[prefix + j..]
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * (m + 1) + j] = if a[prefix + i] == b[prefix + j] {
+                lengths[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lengths[(i + 1) * (m + 1) + j].max(lengths[i * (m + 1) + j + 1])
+            };
+        }
+    }
+
+    let mut res: Vec<Op> = (0..prefix).map(|i| Op::Same(i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a[prefix + i] == b[prefix + j] {
+            res.push(Op::Same(prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lengths[(i + 1) * (m + 1) + j] >= lengths[i * (m + 1) + j + 1]) {
+            res.push(Op::Delete(prefix + i));
+            i += 1;
+        } else {
+            res.push(Op::Insert(prefix + j));
+            j += 1;
+        }
+    }
+    res.extend((0..suffix).map(|k| Op::Same(a.len() - suffix + k, b.len() - suffix + k)));
+    res
+}
+
+/// A block of text of a chapter, e.g. a paragraph or a header
+#[derive(Debug, PartialEq)]
+struct Block {
+    /// HTML tag used to display it
+    tag: &'static str,
+    text: String,
+}
+
+/// Returns true if a token is a block element
+fn is_block(token: &Token) -> bool {
+    matches!(
+        *token,
+        Token::Paragraph(..)
+            | Token::Header(..)
+            | Token::CodeBlock(..)
+            | Token::BlockQuote(..)
+            | Token::List(..)
+            | Token::OrderedList(..)
+            | Token::DescriptionList(..)
+            | Token::Table(..)
+            | Token::Verse(..)
+            | Token::Letter(..)
+            | Token::Play(..)
+            | Token::Rule
+    )
+}
+
+/// Returns the text of some inline tokens, with normalized whitespace
+fn text(tokens: &[Token]) -> String {
+    view_as_text(tokens)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits the content of a chapter into blocks of text
+fn blocks(tokens: &[Token], res: &mut Vec<Block>) {
+    for token in tokens {
+        let block = match *token {
+            Token::Header(level, ref inner) => {
+                let tags = ["h1", "h2", "h3", "h4", "h5", "h6"];
+                Block {
+                    tag: tags[(level.clamp(1, 6) - 1) as usize],
+                    text: text(inner),
+                }
+            }
+            Token::CodeBlock(_, ref code) => Block {
+                tag: "pre",
+                text: code.trim_end().to_owned(),
+            },
+            Token::Rule | Token::Raw(..) => continue,
+            Token::BlockQuote(ref inner)
+            | Token::DescribedImage(ref inner)
+            | Token::Letter(ref inner)
+            | Token::Play(ref inner)
+            | Token::List(ref inner)
+            | Token::OrderedList(_, ref inner)
+            | Token::DescriptionList(ref inner)
+            | Token::DescriptionItem(ref inner)
+            | Token::FootnoteDefinition(_, ref inner)
+            | Token::Table(_, ref inner)
+            | Token::TableHead(ref inner)
+            | Token::TableRow(ref inner) => {
+                blocks(inner, res);
+                continue;
+            }
+            Token::Item(ref inner)
+            | Token::TaskItem(_, ref inner)
+            | Token::DescriptionTerm(ref inner)
+            | Token::DescriptionDetails(ref inner)
+            | Token::TableCell(ref inner)
+                if inner.iter().any(is_block) =>
+            {
+                blocks(inner, res);
+                continue;
+            }
+            _ => Block {
+                tag: "p",
+                text: text(std::slice::from_ref(token)),
+            },
+        };
+        if !block.text.is_empty() {
+            res.push(block);
+        }
+    }
+}
+
+/// Splits a text into words, each one with the whitespace that follows it
+fn words(s: &str) -> Vec<&str> {
+    let mut res = vec![];
+    let mut start = 0;
+    let mut in_space = false;
+    for (i, c) in s.char_indices() {
+        if c.is_whitespace() {
+            in_space = true;
+        } else if in_space {
+            res.push(&s[start..i]);
+            start = i;
+            in_space = false;
+        }
+    }
+    if start < s.len() {
+        res.push(&s[start..]);
+    }
+    res
+}
+
+/// Number of words inserted and deleted
+#[derive(Debug, Default)]
+struct Count {
+    inserted: usize,
+    deleted: usize,
+}
+
+/// Renders a text entirely deleted or inserted
+fn render_whole(out: &mut String, block: &Block, class: &str, count: &mut Count) {
+    let n = words(&block.text).len();
+    let tag = if class == "ins" {
+        count.inserted += n;
+        "ins"
+    } else {
+        count.deleted += n;
+        "del"
+    };
+    writeln!(
+        out,
+        "<{t} class=\"{class}\"><{tag}>{}</{tag}></{t}>",
+        encode_text(&block.text),
+        t = block.tag
+    )
+    .unwrap();
+}
+
+/// Renders the changes between two versions of a block, at the word level
+///
+/// Returns `None` if they have too little in common to be displayed this way.
+fn render_words(old: &Block, new: &Block, count: &mut Count) -> Option<String> {
+    if old.tag != new.tag {
+        return None;
+    }
+    let (a, b) = (words(&old.text), words(&new.text));
+    let ops = diff(&a, &b);
+    let same: usize = ops
+        .iter()
+        .map(|op| match *op {
+            Op::Same(i, _) => a[i].len(),
+            _ => 0,
+        })
+        .sum();
+    if same * 3 < old.text.len().max(new.text.len()) {
+        return None;
+    }
+
+    let mut res = format!("<{} class=\"changed\">", new.tag);
+    let mut current: Option<&str> = None;
+    for op in ops {
+        let (tag, word) = match op {
+            Op::Same(i, _) => (None, a[i]),
+            Op::Delete(i) => {
+                count.deleted += 1;
+                (Some("del"), a[i])
+            }
+            Op::Insert(j) => {
+                count.inserted += 1;
+                (Some("ins"), b[j])
+            }
+        };
+        if tag != current {
+            if let Some(current) = current {
+                write!(res, "</{current}>").unwrap();
+            }
+            if let Some(tag) = tag {
+                write!(res, "<{tag}>").unwrap();
+            }
+            current = tag;
+        }
+        res.push_str(&encode_text(word));
+    }
+    if let Some(current) = current {
+        write!(res, "</{current}>").unwrap();
+    }
+    writeln!(res, "</{}>", new.tag).unwrap();
+    Some(res)
+}
+
+/// Renders the changes between two versions of a chapter
+///
+/// Returns `None` if it didn't change.
+fn render_chapter(old: &[Block], new: &[Block], count: &mut Count) -> Option<String> {
+    let ops = diff(old, new);
+    if ops.iter().all(|op| matches!(*op, Op::Same(..))) {
+        return None;
+    }
+    let mut res = String::new();
+    let mut i = 0;
+    while i < ops.len() {
+        if let Op::Same(_, j) = ops[i] {
+            writeln!(
+                res,
+                "<{t}>{}</{t}>",
+                encode_text(&new[j].text),
+                t = new[j].tag
+            )
+            .unwrap();
+            i += 1;
+            continue;
+        }
+        // Pair the deleted and inserted blocks of this run of changes
+        let mut deleted = vec![];
+        let mut inserted = vec![];
+        while i < ops.len() {
+            match ops[i] {
+                Op::Delete(k) => deleted.push(&old[k]),
+                Op::Insert(k) => inserted.push(&new[k]),
+                Op::Same(..) => break,
+            }
+            i += 1;
+        }
+        for k in 0..deleted.len().max(inserted.len()) {
+            match (deleted.get(k), inserted.get(k)) {
+                (Some(old), Some(new)) => match render_words(old, new, count) {
+                    Some(s) => res.push_str(&s),
+                    None => {
+                        render_whole(&mut res, old, "del", count);
+                        render_whole(&mut res, new, "ins", count);
+                    }
+                },
+                (Some(old), None) => render_whole(&mut res, old, "del", count),
+                (None, Some(new)) => render_whole(&mut res, new, "ins", count),
+                (None, None) => unreachable!(),
+            }
+        }
+    }
+    Some(res)
+}
+
+/// Returns the title of a chapter, or its file name if it doesn't have one
+fn chapter_title(chapter: &Chapter) -> String {
+    chapter
+        .content
+        .iter()
+        .find_map(|token| match *token {
+            Token::Header(1, ref inner) => Some(text(inner)),
+            _ => None,
+        })
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| chapter.filename.clone())
+}
+
+/// Returns the key used to match the chapters of both versions: their file,
+/// or their title if they don't come from a file
+fn chapter_key(chapter: &Chapter) -> String {
+    if chapter.filename.is_empty() {
+        chapter_title(chapter)
+    } else {
+        chapter.filename.clone()
+    }
+}
+
+/// Renders the changes between two versions of a book as a standalone HTML document
+///
+/// Chapters are matched by file name. Changed paragraphs show the words that were
+/// deleted and inserted, while unchanged chapters are only listed.
+pub fn diff_books(old: &Book, new: &Book) -> String {
+    let old_keys: Vec<String> = old.chapters.iter().map(chapter_key).collect();
+    let new_keys: Vec<String> = new.chapters.iter().map(chapter_key).collect();
+    let mut count = Count::default();
+    let mut content = String::new();
+    let mut unchanged = vec![];
+
+    let chapter_blocks = |chapter: &Chapter| {
+        let mut res = vec![];
+        blocks(&chapter.content, &mut res);
+        res
+    };
+    for op in diff(&old_keys, &new_keys) {
+        let (title, changes) = match op {
+            Op::Same(i, j) => {
+                let title = new_keys[j].clone();
+                let old_blocks = chapter_blocks(&old.chapters[i]);
+                let new_blocks = chapter_blocks(&new.chapters[j]);
+                match render_chapter(&old_blocks, &new_blocks, &mut count) {
+                    Some(changes) => (title, changes),
+                    None => {
+                        unchanged.push(chapter_title(&new.chapters[j]));
+                        continue;
+                    }
+                }
+            }
+            Op::Delete(i) => {
+                let title = t!("diff.deleted", chapter = &old_keys[i]);
+                let changes = render_chapter(&chapter_blocks(&old.chapters[i]), &[], &mut count);
+                (title, changes.unwrap_or_default())
+            }
+            Op::Insert(j) => {
+                let title = t!("diff.inserted", chapter = &new_keys[j]);
+                let changes = render_chapter(&[], &chapter_blocks(&new.chapters[j]), &mut count);
+                (title, changes.unwrap_or_default())
+            }
+        };
+        write!(
+            content,
+            "<section class=\"chapter\">\n<div class=\"file\">{}</div>\n{changes}</section>\n",
+            encode_text(&title)
+        )
+        .unwrap();
+    }
+
+    let title = new.options.get_str("title").unwrap_or("");
+    let mut res = format!(
+        "<!DOCTYPE html>
+<html lang=\"{lang}\">
+<head>
+<meta charset=\"utf-8\" />
+<title>{title}</title>
+<style>
+body {{ max-width: 50em; margin: auto; padding: 1em; font-family: serif; line-height: 1.5; }}
+ins {{ background-color: #c8f0c8; text-decoration: none; }}
+del {{ background-color: #f8c8c8; }}
+.chapter {{ border-top: 1px solid #888; margin-top: 2em; }}
+.file {{ font-family: sans-serif; color: #666; font-size: small; }}
+.changed {{ border-left: 3px solid #f0c040; padding-left: 0.5em; }}
+.del, .ins {{ border-left: 3px solid #888; padding-left: 0.5em; }}
+.summary, .unchanged {{ font-family: sans-serif; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p class=\"summary\">{summary}</p>
+",
+        lang = encode_text(new.options.get_str("lang").unwrap_or("en")),
+        title = encode_text(title),
+        summary = t!(
+            "diff.summary",
+            inserted = count.inserted,
+            deleted = count.deleted
+        )
+    );
+    res.push_str(&content);
+    if !unchanged.is_empty() {
+        writeln!(
+            res,
+            "<p class=\"unchanged\">{}</p>",
+            encode_text(&t!("diff.unchanged", chapters = unchanged.join(", ")))
+        )
+        .unwrap();
+    }
+    res.push_str("</body>\n</html>\n");
+    res
+}
+
+/// Extracts the directory of a book configuration file, as it was at a git revision,
+/// into directory `dest`
+///
+/// Returns the path of the extracted book configuration file.
+pub fn checkout_revision<P: AsRef<Path>, Q: AsRef<Path>>(
+    book: P,
+    revision: &str,
+    dest: Q,
+) -> Result<PathBuf> {
+    let book = book.as_ref();
+    let dest = dest.as_ref();
+    let dir = match book.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let error = |error: String| {
+        Error::default(
+            Source::empty(),
+            t!("diff.git", revision = revision, error = error),
+        )
+    };
+    let git = |dir: &Path, args: &[&str]| {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .output()
+            .map_err(|e| error(e.to_string()))?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(error(
+                String::from_utf8_lossy(&output.stderr).trim_end().to_owned(),
+            ))
+        }
+    };
+
+    let output = git(dir, &["rev-parse", "--show-toplevel", "--show-prefix"])?;
+    let output = String::from_utf8_lossy(&output);
+    let mut lines = output.lines();
+    let top = lines.next().unwrap_or_default();
+    let tree = format!("{revision}:{}", lines.next().unwrap_or_default());
+    // git archive only archives the current directory when run from a subdirectory
+    let archive = git(Path::new(top), &["archive", "--format=zip", &tree])?;
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(archive)).map_err(|e| error(e.to_string()))?;
+    fs::create_dir_all(dest).map_err(|e| error(e.to_string()))?;
+    archive.extract(dest).map_err(|e| error(e.to_string()))?;
+    Ok(dest.join(book.file_name().unwrap_or_default()))
+}
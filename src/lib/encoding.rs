@@ -0,0 +1,88 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of chapter files that are not encoded in UTF-8, set by the `input.encoding` option.
+
+use crate::error::{Error, Result, Source};
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use rust_i18n::t;
+
+/// Decodes the content of a chapter file
+///
+/// `encoding` is either an encoding label (e.g. `latin1` or `utf-16le`) or `auto`, in
+/// which case the encoding is guessed from the byte order mark, or from the content
+/// itself if there is none.
+pub fn decode(source: &Source, bytes: &[u8], encoding: &str) -> Result<String> {
+    let file = source.file.as_deref().unwrap_or_default();
+    if encoding.eq_ignore_ascii_case("auto") {
+        let encoding = detect(bytes);
+        if encoding == WINDOWS_1252 {
+            warn!(
+                "{}",
+                t!("warn.encoding_fallback", file = file, encoding = encoding.name())
+            );
+        }
+        let (content, _) = encoding.decode_with_bom_removal(bytes);
+        return Ok(content.into_owned());
+    }
+
+    let encoding = Encoding::for_label(encoding.trim().as_bytes()).ok_or_else(|| {
+        Error::default(source, t!("error.unknown_encoding", encoding = encoding))
+    })?;
+    let (content, had_errors) = encoding.decode_with_bom_removal(bytes);
+    if had_errors {
+        Err(Error::parser(
+            source,
+            t!("error.encoding", file = file, encoding = encoding.name()),
+        ))
+    } else {
+        Ok(content.into_owned())
+    }
+}
+
+/// Guesses the encoding of some content
+///
+/// Word exports UTF-16 files without byte order mark, so they are detected by the
+/// proportion of null bytes.
+fn detect(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    // Null bytes are valid UTF-8, so this must be checked first
+    if bytes.len() % 2 == 0 {
+        let nulls = |parity: usize| {
+            bytes
+                .iter()
+                .skip(parity)
+                .step_by(2)
+                .filter(|b| **b == 0)
+                .count()
+        };
+        let half = bytes.len() / 2;
+        if nulls(1) > half / 2 && nulls(0) == 0 {
+            return UTF_16LE;
+        } else if nulls(0) > half / 2 && nulls(1) == 0 {
+            return UTF_16BE;
+        }
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        UTF_8
+    } else {
+        WINDOWS_1252
+    }
+}
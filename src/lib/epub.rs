@@ -18,16 +18,23 @@
 use crate::book::Header;
 use crate::book::Book;
 use crate::book_renderer::BookRenderer;
+use crate::capabilities::{Fallback, Feature};
+use crate::chapter::Chapter;
 use crate::error::{Error, Result, Source};
-use crate::html::HtmlRenderer;
+use crate::html::{self, HtmlRenderer};
+use crate::identifier;
 use crate::lang;
-use crate::parser::Parser;
+use crate::link_refs::LinkStyle;
+use crate::misc;
+use crate::parser::{self, Parser};
 use crate::renderer::Renderer;
 use crate::resource_handler;
+use crate::subject;
 use crate::templates::epub::*;
 use crate::templates::epub3;
 use crate::text_view::view_as_text;
 use crate::token::Token;
+use crate::zipper;
 
 use crowbook_text_processing::escape;
 use epub_builder::{
@@ -41,7 +48,7 @@ use std::borrow::Cow;
 use std::convert::{AsMut, AsRef};
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
 
 use std::path::Path;
 
@@ -53,6 +60,7 @@ pub struct EpubRenderer<'a> {
     html: HtmlRenderer<'a>,
     chapter_title: String,
     chapter_title_raw: String,
+    zip_command: bool,
 }
 
 impl<'a> EpubRenderer<'a> {
@@ -66,35 +74,102 @@ impl<'a> EpubRenderer<'a> {
         )?;
         html.handler.set_images_mapping(true);
         html.handler.set_base64(false);
+        html.link_style = LinkStyle::from(&book.options, "epub");
+        html.epub_version = Some(book.options.get_i32("epub.version").unwrap());
         Ok(EpubRenderer {
             html,
             toc: vec![],
             chapter_title: String::new(),
             chapter_title_raw: String::new(),
+            zip_command: true,
         })
     }
 
+    /// Never use `crowbook.zip.command` to create the EPUB file, so no external program is run
+    pub fn without_zip_command(mut self) -> EpubRenderer<'a> {
+        self.zip_command = false;
+        self
+    }
+
+    /// Returns the metadata that epub-builder doesn't support, to add after the UUID that
+    /// identifies the EPUB: the EPUB ISBN and the DOI, the imprint and the edition
+    fn extra_metadata(&self) -> String {
+        let options = &self.html.book.options;
+        let epub3 = options.get_i32("epub.version").unwrap() == 3;
+        let mut metadata = String::new();
+        if let Some(isbn) = identifier::isbn(options, "epub") {
+            let isbn = identifier::normalize_isbn(isbn);
+            if epub3 {
+                metadata.push_str(&format!("\n    <dc:identifier>urn:isbn:{isbn}</dc:identifier>"));
+            } else {
+                metadata.push_str(&format!(
+                    "\n    <dc:identifier opf:scheme=\"ISBN\">{isbn}</dc:identifier>"
+                ));
+            }
+        }
+        if let Ok(doi) = options.get_str("doi") {
+            let doi = escape::html(doi);
+            if epub3 {
+                metadata.push_str(&format!("\n    <dc:identifier>urn:doi:{doi}</dc:identifier>"));
+            } else {
+                metadata.push_str(&format!(
+                    "\n    <dc:identifier opf:scheme=\"DOI\">{doi}</dc:identifier>"
+                ));
+            }
+        }
+        if let Ok(imprint) = options.get_str("imprint") {
+            metadata.push_str(&format!("\n    <dc:publisher>{}</dc:publisher>", escape::html(imprint)));
+        }
+        if let Ok(edition) = options.get_str("edition") {
+            if epub3 {
+                metadata.push_str(&format!(
+                    "\n    <meta property=\"schema:bookEdition\">{}</meta>",
+                    escape::html(edition)
+                ));
+            } else {
+                metadata.push_str(&format!(
+                    "\n    <meta name=\"schema:bookEdition\" content=\"{}\" />",
+                    html_escape::encode_double_quoted_attribute(edition)
+                ));
+            }
+        }
+        metadata
+    }
+
     /// Render a book
     pub fn render_book(&mut self, to: &mut dyn Write) -> Result<String> {
         // Initialize the EPUB builder
-        let mut zip = ZipCommand::new_in(self.html.book.options.get_path("crowbook.temp_dir")?)
-            .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
-        zip.command(
-            self.html
-                .book
-                .options
-                .get_str("crowbook.zip.command")
-                .unwrap(),
-        );
-        let wrapper = if zip.test().is_ok() {
-            ZipCommandOrLibrary::Command(zip)
+        let options = &self.html.book.options;
+        // The zip command needs temporary files, so the EPUB is zipped in memory by the library
+        // with crowbook.in_memory
+        let zip_program = if self.zip_command && !options.get_bool("crowbook.in_memory").unwrap() {
+            // The zip program usually isn't installed on Windows, so the library is silently
+            // used instead
+            misc::find_program(options.get_str("crowbook.zip.command").unwrap())
         } else {
-            warn!(
-                "{}",
-                t!("epub.zip_command")
-            );
-            ZipCommandOrLibrary::Library(ZipLibrary::new()
-                .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?)
+            None
+        };
+        let library = || {
+            ZipLibrary::new()
+                .map(ZipCommandOrLibrary::Library)
+                .map_err(|err| Error::render(Source::empty(), format!("{}", err)))
+        };
+        let wrapper = match zip_program {
+            Some(program) => {
+                let mut zip = ZipCommand::new_in(options.get_path("crowbook.temp_dir")?)
+                    .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
+                zip.command(program.display().to_string());
+                if zip.test().is_ok() {
+                    ZipCommandOrLibrary::Command(zip)
+                } else {
+                    warn!(
+                        "{}",
+                        t!("epub.zip_command")
+                    );
+                    library()?
+                }
+            }
+            None => library()?,
         };
         let mut maker = EpubBuilder::new(wrapper)
             .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
@@ -103,6 +178,32 @@ impl<'a> EpubRenderer<'a> {
             maker.epub_version(EpubVersion::V30);
         }
 
+        let timestamp = self.html.book.reproducible_timestamp();
+        if let Some(timestamp) = timestamp {
+            // Don't use the current date nor a random identifier, so the EPUB file is the same
+            // each time it is generated
+            if let Some(date) = chrono::DateTime::from_timestamp(timestamp, 0) {
+                maker.set_modified_date(date);
+            }
+            maker.set_uuid(self.html.book.uuid());
+        } else if let Some(git) = self.html.book.git_info() {
+            // Use the date of the commit the book is built from
+            if let Some(date) = chrono::DateTime::from_timestamp(git.timestamp, 0) {
+                maker.set_modified_date(date);
+            }
+        }
+        // The date of the book is when it was last revised, which is more accurate than the
+        // one of the build
+        if let Ok(date) = options.get_date("date") {
+            maker.set_modified_date(date.to_utc());
+        }
+        if let Ok(date) = options.get_date("publication_date") {
+            maker.set_publication_date(date.to_utc());
+        }
+        if self.html.book.options.get_str("uuid").is_ok() {
+            maker.set_uuid(self.html.book.uuid());
+        }
+
         let lang = self.html.book.options.get_str("lang").unwrap();
         let toc_extras = self.html.book.options.get_bool("epub.toc.extras").unwrap();
         maker.metadata("lang", lang)
@@ -121,8 +222,14 @@ impl<'a> EpubRenderer<'a> {
             .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
         maker.metadata("toc_name", lang::get_str(lang, "toc"))
             .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
-        if let Ok(subject) = self.html.book.options.get_str("subject") {
-            maker.metadata("subject", subject)
+        let options = &self.html.book.options;
+        let codes = ["subject.bisac", "subject.thema"]
+            .into_iter()
+            .filter_map(|key| options.get_str_vec(key).ok())
+            .flatten()
+            .map(String::as_str);
+        for subject in subject::keywords(options).into_iter().chain(codes) {
+            maker.metadata("subject", escape::html(subject))
                 .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
         }
         if let Ok(description) = self.html.book.options.get_str("description") {
@@ -154,47 +261,37 @@ impl<'a> EpubRenderer<'a> {
         //                                     "toc"));
         // }
 
+        // Links to additional files point to their copies
+        self.html.handler.add_resources_links(self.html.book)?;
+
+        let code_labels = self.html.book.options.get_bool("rendering.code.line_numbers").unwrap();
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
             self.html
                 .handler
                 .add_link(chapter.filename.as_str(), filenamer(i));
-        }
-
-        // Write cover.xhtml (if needs be)
-        if self.html.book.options.get_path("cover").is_ok() {
-            let cover = self.render_cover()?;
-            let mut content =
-                EpubContent::new("cover.xhtml", cover.as_bytes()).reftype(ReferenceType::Cover);
-            if toc_extras {
-                content = content.title(lang::get_str(lang, "cover"));
-            }
-            maker.add_content(content)
-                .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
-        }
-
-        // Write titlepage
-        {
-            let title_page = self.render_titlepage()?;
-            let mut content = EpubContent::new("title_page.xhtml", title_page.as_bytes())
-                .reftype(ReferenceType::TitlePage);
-            if toc_extras {
-                content = content.title(lang::get_str(lang, "title"));
+            // Links to anchors point to the file of their chapter
+            for label in parser::labels(&chapter.content, code_labels) {
+                self.html
+                    .handler
+                    .add_link(format!("#{label}"), format!("{}#{label}", filenamer(i)));
             }
-            maker.add_content(content)
-                .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
         }
 
-        if self
-            .html
-            .book
-            .options
-            .get_bool("rendering.inline_toc")
-            .unwrap()
-        {
-            maker.inline_toc();
-        }
+        // Render cover.xhtml (if needs be) and the title page
+        let pages = self.get_pages()?;
+        let has_page = |page| pages.iter().any(|p| p == page);
+        let cover = if has_page("cover") && self.html.book.options.get_path("cover").is_ok() {
+            Some(self.render_cover()?)
+        } else {
+            None
+        };
+        let title_page = if has_page("titlepage") {
+            Some(self.render_titlepage()?)
+        } else {
+            None
+        };
 
-        // Write chapters
+        // Render chapters
         let template_chapter_src = self.html.book.get_template("epub.chapter.xhtml")?;
         let template_chapter = self.html.book.compile_str(
             template_chapter_src.as_ref(),
@@ -203,35 +300,72 @@ impl<'a> EpubRenderer<'a> {
         )?;
         let mut rendered = vec![];
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+            self.html.book.check_cancelled()?;
             let n = chapter.number;
-            let v = &chapter.content;
             self.html.chapter_config(i, n, filenamer(i));
-            let this_chapter = self.render_chapter(v, &template_chapter)?;
+            let this_chapter = self.render_chapter(chapter, i, &template_chapter)?;
             rendered.push(this_chapter);
         }
 
-        for (i, (rendered_chapter, raw_title)) in rendered.into_iter().enumerate() {
-            let mut content = EpubContent::new(filenamer(i), rendered_chapter.as_bytes());
-            if i == 0 {
-                content = content.reftype(ReferenceType::Text);
-            }
+        // Add generated pages and chapters in the order set by epub.pages, which is also the
+        // order of the landmarks
+        for page in &pages {
+            match page.as_str() {
+                "cover" => {
+                    if let Some(ref cover) = cover {
+                        let mut content = EpubContent::new("cover.xhtml", cover.as_bytes())
+                            .reftype(ReferenceType::Cover);
+                        if toc_extras {
+                            content = content.title(lang::get_str(lang, "cover"));
+                        }
+                        maker.add_content(content)
+                            .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
+                    }
+                }
+                "titlepage" => {
+                    if let Some(ref title_page) = title_page {
+                        let mut content =
+                            EpubContent::new("title_page.xhtml", title_page.as_bytes())
+                                .reftype(ReferenceType::TitlePage);
+                        if toc_extras {
+                            content = content.title(lang::get_str(lang, "title"));
+                        }
+                        maker.add_content(content)
+                            .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
+                    }
+                }
+                "toc" => {
+                    maker.inline_toc();
+                }
+                _ => {
+                    for (i, (rendered_chapter, raw_title)) in
+                        std::mem::take(&mut rendered).into_iter().enumerate()
+                    {
+                        let mut content =
+                            EpubContent::new(filenamer(i), rendered_chapter.as_bytes());
+                        if i == 0 {
+                            content = content.reftype(ReferenceType::Text);
+                        }
 
-            // horrible hack to add subtoc of this chapter to epub's toc
-            // todo: find cleaner way
-            for element in &self.html.toc.elements {
-                if element.url.contains(&filenamer(i)) {
-                    content = content.title(escape::html(&raw_title));
-                    content.toc.children = element.children.clone();
-                    break;
+                        // horrible hack to add subtoc of this chapter to epub's toc
+                        // todo: find cleaner way
+                        for element in &self.html.toc.elements {
+                            if element.url.contains(&filenamer(i)) {
+                                content = content.title(escape::html(&raw_title));
+                                content.toc.children = element.children.clone();
+                                break;
+                            }
+                        }
+                        maker.add_content(content)
+                            .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
+                    }
                 }
             }
-            maker.add_content(content)
-                .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
         }
         self.html.source = Source::empty();
 
         // Render the CSS file and write it
-        let template_css_src = self.html.book.get_template("epub.css").unwrap();
+        let template_css_src = self.html.book.get_template("epub.css")?;
         let template_css = self.html.book.compile_str(
             template_css_src.as_ref(),
             &self.html.book.source,
@@ -242,16 +376,22 @@ impl<'a> EpubRenderer<'a> {
             .book
             .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
         data.insert(self.html.book.options.get_str("lang").unwrap().into(), true.into());
-        let epub_css_add = self.html.book.options.get_str("epub.css.add").unwrap_or("".into()); 
-        data.insert("additional_code".into(), epub_css_add.into());
+        data.insert(
+            "additional_code".into(),
+            self.html.get_additional_code("epub.css.add", ".css")?.into(),
+        );
         
+        data.insert("theme".into(), self.html.get_css_theme("epub").into());
+        self.html.book.template_vars.record("epub.css", &data);
         let css = template_css.render(&data).to_string()?;
         maker.stylesheet(css.as_bytes())
             .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
 
         // Write all images (including cover)
         let cover = self.html.book.options.get_path("cover");
-        for (source, dest) in self.html.handler.images_mapping() {
+        let mut images: Vec<_> = self.html.handler.images_mapping().iter().collect();
+        images.sort();
+        for (source, dest) in images {
             let f = fs::canonicalize(source).and_then(File::open).map_err(|_| {
                 Error::file_not_found(
                     &self.html.source,
@@ -277,7 +417,8 @@ impl<'a> EpubRenderer<'a> {
                 .options
                 .get_path("resources.base_path.files")
                 .unwrap();
-            let list = resource_handler::get_files(list, &base_path_files)?;
+            let mut list = resource_handler::get_files(list, &base_path_files)?;
+            list.sort();
             let data_path = Path::new(
                 self.html
                     .book
@@ -300,8 +441,29 @@ impl<'a> EpubRenderer<'a> {
             }
         }
 
-        maker.generate(to)
-            .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
+        let extra_metadata = self.extra_metadata();
+        if timestamp.is_none() && extra_metadata.is_empty() {
+            maker.generate(to)
+                .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
+        } else {
+            let mut content = vec![];
+            maker.generate(&mut content)
+                .map_err(|err| Error::render(Source::empty(), format!("{}", err)))?;
+            if !extra_metadata.is_empty() {
+                content = zipper::edit_zip_file(&content, "OEBPS/content.opf", |opf| {
+                    let end = "</dc:identifier>";
+                    if let Some(pos) = opf.find(end) {
+                        opf.insert_str(pos + end.len(), &extra_metadata);
+                    }
+                })?;
+            }
+            if let Some(timestamp) = timestamp {
+                content = zipper::normalize_zip(&content, timestamp)?;
+            }
+            to.write_all(&content).map_err(|err| {
+                Error::render(Source::empty(), t!("epub.write_error", error = err))
+            })?;
+        }
 
         Ok(String::new())
     }
@@ -314,10 +476,12 @@ impl<'a> EpubRenderer<'a> {
             &self.html.book.source,
             "epub.titlepage.xhtml",
         )?;
-        let data = self
+        let mut data = self
             .html
             .book
             .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
+        html::escape_raw(&mut data);
+        self.html.book.template_vars.record("epub.titlepage.xhtml", &data);
         Ok(template.render(&data).to_string()?)
     }
 
@@ -348,21 +512,60 @@ impl<'a> EpubRenderer<'a> {
                         .handler
                         .map_image(&self.html.source, Cow::Owned(cover))?
                         .into());
+            html::escape_raw(&mut data);
+            self.html.book.template_vars.record("cover.xhtml", &data);
             Ok(template.render(&data).to_string()?)
         } else {
             unreachable!();
         }
     }
 
+    /// Returns the generated pages and `chapters`, in the order set by `epub.pages`
+    ///
+    /// If it isn't set, the cover, the title page, the table of contents if
+    /// `rendering.inline_toc` is set, then the chapters.
+    fn get_pages(&self) -> Result<Vec<String>> {
+        let options = &self.html.book.options;
+        let pages = match options.get_str_vec("epub.pages") {
+            Ok(pages) => pages.to_vec(),
+            Err(_) => {
+                let mut pages = vec![String::from("cover"), String::from("titlepage")];
+                if options.get_bool("rendering.inline_toc").unwrap() {
+                    pages.push(String::from("toc"));
+                }
+                pages.push(String::from("chapters"));
+                return Ok(pages);
+            }
+        };
+        for (i, page) in pages.iter().enumerate() {
+            if !PAGES.contains(&page.as_str()) || pages[..i].contains(page) {
+                return Err(Error::book_option(
+                    &options.source,
+                    t!("epub.invalid_page", page = page, pages = PAGES.join(", ")),
+                ));
+            }
+        }
+        if !pages.iter().any(|page| page == "chapters") {
+            return Err(Error::book_option(&options.source, t!("epub.missing_chapters")));
+        }
+        Ok(pages)
+    }
+
     /// Render a chapter
     ///
     /// Return chapter content and raw title
-    pub fn render_chapter(&mut self, v: &[Token], template: &Template) -> Result<(String, String)> {
+    pub fn render_chapter(
+        &mut self,
+        chapter: &Chapter,
+        i: usize,
+        template: &Template,
+    ) -> Result<(String, String)> {
+        let v = &chapter.content;
         let mut content = String::new();
 
+        self.html.start_notes(v);
         for token in v {
             content.push_str(&self.render_token(token)?);
-            self.html.render_side_notes(&mut content);
         }
 
         let epub3 = self.html.book.options.get_i32("epub.version").unwrap() == 3;
@@ -407,13 +610,23 @@ impl<'a> EpubRenderer<'a> {
         data.insert("content".into(), content.into());
         data.insert("chapter_title_raw".into(), self.chapter_title_raw.clone(). into());
         data.insert("chapter_title".into(), std::mem::take(&mut self.chapter_title).into());
+        self.html.book.add_chapter_metadata(chapter, &mut data, |s| {
+            self.render_vec(&Parser::new().parse_inline(s)?)
+        })?;
+        data.insert("chapter_index".into(), (i as i64 + 1).into());
+        data.insert("chapters_count".into(), (self.html.book.chapters.len() as i64).into());
+        data.insert("chapter_filename".into(), filenamer(i).into());
+        data.insert("is_part".into(), chapter.number.is_part().into());
+        data.insert("is_numbered".into(), chapter.number.is_numbered().into());
+        html::escape_raw(&mut data);
+        self.html.book.template_vars.record("epub.chapter.xhtml", &data);
         Ok((template.render(&data).to_string()?,
             std::mem::take(&mut self.chapter_title_raw)))
     }
 
     /// Renders the header section of the book, finding the title of the chapter
-    fn find_title(&mut self, vec: &[Token]) -> Result<()> {
-        if self.html.current_hide || self.html.current_numbering == 0 {
+    fn find_title(&mut self, vec: &[Token], unnumbered: bool) -> Result<()> {
+        if self.html.current_hide || self.html.current_numbering == 0 || unnumbered {
             if self.chapter_title.is_empty() {
                 self.chapter_title = self.html.render_vec(vec)?;
                 self.chapter_title_raw = view_as_text(vec);
@@ -427,19 +640,19 @@ impl<'a> EpubRenderer<'a> {
                 );
             }
         } else {
-            let header;
-            let number;
-            if self.html.current_part {
-                header = Header::Part;
-                number = self.html.current_chapter[0] + 1;
+            // The header isn't rendered yet, so its number isn't increased
+            let (header, level) = if self.html.current_part {
+                (Header::Part, 0)
             } else {
-                header = Header::Chapter;
-                number = self.html.current_chapter[1] + 1;
+                (Header::Chapter, 1)
             };
+            let mut numbers = self.html.current_chapter;
+            numbers[level] += 1;
+            numbers[level + 1..].fill(0);
             let res = self
                 .html
                 .book
-                .get_header(header, number, self.html.render_vec(vec)?, |s| {
+                .get_header_numbers(header, &numbers, self.html.render_vec(vec)?, |s| {
                     self.render_vec(&(Parser::new().parse_inline(s)?))
                 });
             let s = res?;
@@ -448,7 +661,7 @@ impl<'a> EpubRenderer<'a> {
                 self.chapter_title_raw = self
                     .html
                     .book
-                    .get_header(header, number, view_as_text(vec), |s| {
+                    .get_header_numbers(header, &numbers, view_as_text(vec), |s| {
                         Ok(view_as_text(&(Parser::new().parse_inline(s)?)))
                     })?
                     .text;
@@ -487,7 +700,7 @@ impl<'a> EpubRenderer<'a> {
                         file = s
                     )
                 );
-                String::from("png")
+                String::from("application/octet-stream")
             }
         }
     }
@@ -550,54 +763,69 @@ impl<'a> EpubRenderer<'a> {
             Token::Header(1, ref vec) => {
                 {
                     let epub: &mut EpubRenderer = this.as_mut();
-                    epub.find_title(vec)?;
+                    epub.find_title(vec, token.has_class("unnumbered"))?;
                 }
                 HtmlRenderer::static_render_token(this, token)
             }
-            Token::FootnoteReference(ref reference) => {
-                let epub3 = (this.as_ref() as &HtmlRenderer)
+            Token::Image(ref url, _, ref alt) | Token::StandaloneImage(ref url, _, ref alt)
+                if misc::media_type(url).is_some()
+                    && (this.as_ref() as &HtmlRenderer)
+                        .book
+                        .options
+                        .get_i32("epub.version")
+                        .unwrap()
+                        != 3 =>
+            {
+                // EPUB 2 can't embed audio or video, so display a placeholder
+                let text = this.render_vec(alt)?;
+                let html: &mut HtmlRenderer = this.as_mut();
+                let link = match html.book.media_url(url) {
+                    Some(url) => format!(
+                        "<a href = \"{}\">{text}</a>",
+                        html_escape::encode_double_quoted_attribute(&url)
+                    ),
+                    None => text,
+                };
+                if token.is_image() {
+                    return Ok(link);
+                }
+                let poster = match html.book.options.get_path("media.poster") {
+                    Ok(poster) => {
+                        let alt = html_escape::encode_double_quoted_attribute(&view_as_text(alt))
+                            .into_owned();
+                        let poster = html.handler.map_image(&html.source, poster)?;
+                        format!(
+                            "  <img src = \"{}\" alt = \"{alt}\" />\n",
+                            html_escape::encode_double_quoted_attribute(poster.as_ref())
+                        )
+                    }
+                    Err(_) => String::new(),
+                };
+                Ok(format!("<div class = \"media\">\n{poster}  <p>{link}</p>\n</div>\n"))
+            }
+            Token::DescribedImage(ref vec)
+                if (this.as_ref() as &HtmlRenderer)
                     .book
                     .options
                     .get_i32("epub.version")
                     .unwrap()
-                    == 3;
-
+                    != 3 =>
+            {
+                // EPUB 2 doesn't support `details`, so display the description after the image
                 Ok(format!(
-                    "<a {} href = \"#note-dest-{reference}\"><sup id = \
-                            \"note-source-{reference}\">[{reference}]</sup></a>",
-                    if epub3 { "epub:type = \"noteref\"" } else { "" },
+                    "{}\n<div class = \"description\">\n{}</div>\n",
+                    this.render_token(&vec[0])?,
+                    this.render_vec(&vec[1..])?
                 ))
             }
-            Token::FootnoteDefinition(ref reference, ref vec) => {
-                let epub3 = (this.as_ref() as &HtmlRenderer)
-                    .book
-                    .options
-                    .get_i32("epub.version")
-                    .unwrap()
-                    == 3;
-                let inner_content = this.render_vec(vec)?;
-                let html: &mut HtmlRenderer = this.as_mut();
-                let note_number = format!(
-                    "<p class = \"note-number\">
-  <a href = \"#note-source-{reference}\">[{reference}]</a>
-</p>\n",
-                );
-                let inner = if epub3 {
-                    format!(
-                        "<aside epub:type = \"footnote\" id = \"note-dest-{reference}\">{inner_content}</aside>"
-                    )
-                } else {
-                    format!("<a id = \"note-dest-{reference}\" />{inner_content}")
-                };
-                html.add_footnote(note_number, inner);
-
-                Ok(String::new())
-            }
             _ => HtmlRenderer::static_render_token(this, token),
         }
     }
 }
 
+/// Generated pages that `epub.pages` can list, `chapters` standing for the chapters of the book
+const PAGES: &[&str] = &["cover", "titlepage", "toc", "chapters"];
+
 /// Generate a file name given an int
 fn filenamer(i: usize) -> String {
     format!("chapter_{i:03}.xhtml")
@@ -612,8 +840,24 @@ impl BookRenderer for Epub {
         Ok(format!("{book_name}.epub"))
     }
 
+    fn unsupported(&self, book: &Book) -> Vec<(Feature, Fallback)> {
+        // EPUB 2 can't embed audio or video
+        if book.options.get_i32("epub.version").unwrap() == 3 {
+            vec![]
+        } else {
+            vec![(Feature::Audio, Fallback::Link), (Feature::Video, Fallback::Link)]
+        }
+    }
+
     fn render(&self, book: &Book, to: &mut dyn Write) -> Result<()> {
         EpubRenderer::new(book)?.render_book(to)?;
         Ok(())
     }
+
+    fn dry_run(&self, book: &Book) -> Result<()> {
+        EpubRenderer::new(book)?
+            .without_zip_command()
+            .render_book(&mut io::sink())?;
+        Ok(())
+    }
 }
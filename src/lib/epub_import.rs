@@ -0,0 +1,724 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conversion of an existing EPUB file to a crowbook project, so
+//! it can be used as a starting point for republishing.
+
+use crate::error::{Error, Result, Source};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rust_i18n::t;
+
+/// Minimal XML tree, enough to walk through OPF and XHTML files
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn new(e: &BytesStart) -> Element {
+        let attrs = e
+            .attributes()
+            .flatten()
+            .map(|a| {
+                (
+                    String::from_utf8_lossy(a.key.local_name().as_ref()).into_owned(),
+                    decode(&a.value),
+                )
+            })
+            .collect();
+        Element {
+            name: String::from_utf8_lossy(e.local_name().as_ref()).to_lowercase(),
+            attrs,
+            children: vec![],
+        }
+    }
+
+    /// Returns the value of an attribute
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Iterates over direct children elements
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(e) => Some(e),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// Collects all elements with this name, in document order
+    fn descendants<'a>(&'a self, name: &str, res: &mut Vec<&'a Element>) {
+        for e in self.elements() {
+            if e.name == name {
+                res.push(e);
+            }
+            e.descendants(name, res);
+        }
+    }
+
+    /// Returns the first element with this name
+    fn find(&self, name: &str) -> Option<&Element> {
+        let mut res = vec![];
+        self.descendants(name, &mut res);
+        res.into_iter().next()
+    }
+
+    /// Returns the concatenated text content of the element
+    fn text(&self) -> String {
+        let mut res = String::new();
+        for node in &self.children {
+            match node {
+                Node::Text(s) => res.push_str(s),
+                Node::Element(e) => res.push_str(&e.text()),
+            }
+        }
+        res
+    }
+}
+
+/// Decodes text or attribute value, accepting HTML entities
+fn decode(bytes: &[u8]) -> String {
+    html_escape::decode_html_entities(&String::from_utf8_lossy(bytes)).into_owned()
+}
+
+/// Parses an XML document, returning a root element containing it
+fn parse_xml(file: &str, s: &str) -> Result<Element> {
+    let mut reader = Reader::from_str(s);
+    reader.check_end_names(false);
+    let mut stack = vec![Element {
+        name: String::new(),
+        attrs: vec![],
+        children: vec![],
+    }];
+    loop {
+        let node = match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                stack.push(Element::new(&e));
+                continue;
+            }
+            Ok(Event::End(_)) => {
+                if stack.len() <= 1 {
+                    continue;
+                }
+                Node::Element(stack.pop().unwrap())
+            }
+            Ok(Event::Empty(e)) => Node::Element(Element::new(&e)),
+            Ok(Event::Text(t)) => Node::Text(decode(&t)),
+            Ok(Event::CData(t)) => Node::Text(String::from_utf8_lossy(&t).into_owned()),
+            Ok(Event::Eof) => break,
+            Ok(_) => continue,
+            Err(err) => {
+                return Err(Error::parser(
+                    Source::new(file),
+                    t!("import.xml", error = err.to_string()),
+                ))
+            }
+        };
+        stack.last_mut().unwrap().children.push(node);
+    }
+    // Close elements that were left open
+    while stack.len() > 1 {
+        let e = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(Node::Element(e));
+    }
+    Ok(stack.pop().unwrap())
+}
+
+/// Returns the directory of a file inside the archive, with a trailing slash
+fn dir_of(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(i) => &path[..=i],
+        None => "",
+    }
+}
+
+/// Decodes the percent-encoded bytes of a link (e.g. `%20`), keeping invalid sequences as they are
+fn percent_decode(href: &str) -> String {
+    let bytes = href.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                res.push(byte);
+                i += 3;
+            }
+            _ => {
+                res.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// Resolves a relative link against a directory inside the archive
+///
+/// Links are percent-decoded, and backslashes (written by some Windows tools) are read as slashes.
+fn resolve(dir: &str, href: &str) -> String {
+    let href = percent_decode(href).replace('\\', "/");
+    let mut parts: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+    for part in href.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Escapes characters that have a meaning in Markdown
+fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// Collapses whitespace the way HTML does
+fn collapse(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut space = false;
+    for c in s.chars() {
+        if c.is_whitespace() {
+            if !space {
+                res.push(' ');
+            }
+            space = true;
+        } else {
+            res.push(c);
+            space = false;
+        }
+    }
+    res
+}
+
+/// Surrounds inline content with a Markdown marker, leaving whitespace outside
+fn wrap(marker: &str, content: String) -> String {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return content;
+    }
+    let before = if content.starts_with(' ') { " " } else { "" };
+    let after = if content.ends_with(' ') { " " } else { "" };
+    format!("{before}{marker}{trimmed}{marker}{after}")
+}
+
+/// Prefixes the first line of a block with `first`, and the other ones with `other`
+fn indent(block: &str, first: &str, other: &str) -> String {
+    let mut res = String::new();
+    for (i, line) in block.lines().enumerate() {
+        if i > 0 {
+            res.push('\n');
+        }
+        let prefix = if i == 0 { first } else { other };
+        if line.is_empty() {
+            res.push_str(prefix.trim_end());
+        } else {
+            res.push_str(prefix);
+            res.push_str(line);
+        }
+    }
+    res
+}
+
+/// Appends a block, separated from previous ones by a blank line
+fn push_block(out: &mut String, block: &str) {
+    let block = block.trim_end();
+    if block.trim().is_empty() {
+        return;
+    }
+    if !out.is_empty() {
+        out.push_str("\n\n");
+    }
+    out.push_str(block);
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div"
+            | "section"
+            | "article"
+            | "header"
+            | "footer"
+            | "aside"
+            | "nav"
+            | "main"
+            | "figure"
+            | "figcaption"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "ul"
+            | "ol"
+            | "dl"
+            | "dt"
+            | "dd"
+            | "blockquote"
+            | "pre"
+            | "hr"
+            | "table"
+            | "head"
+            | "script"
+            | "style"
+    )
+}
+
+/// Converts XHTML content to Markdown
+struct Converter<'a> {
+    /// Directory of the current chapter inside the archive
+    dir: &'a str,
+    /// Maps files of the archive to their path in the new project
+    files: &'a HashMap<String, String>,
+}
+
+impl<'a> Converter<'a> {
+    /// Converts a link of the EPUB to a link in the new project
+    fn link(&self, href: &str) -> String {
+        if href.starts_with('#') || href.contains(':') {
+            return href.to_owned();
+        }
+        let path = href.split('#').next().unwrap();
+        match self.files.get(&resolve(self.dir, path)) {
+            Some(file) => file.clone(),
+            None => href.to_owned(),
+        }
+    }
+
+    fn blocks(&self, nodes: &[Node]) -> String {
+        let mut out = String::new();
+        let mut inline = String::new();
+        for node in nodes {
+            match node {
+                Node::Element(e) if is_block(&e.name) => {
+                    push_block(&mut out, inline.trim());
+                    inline.clear();
+                    push_block(&mut out, &self.block(e));
+                }
+                node => inline.push_str(&self.inline(node)),
+            }
+        }
+        push_block(&mut out, inline.trim());
+        out
+    }
+
+    fn block(&self, e: &Element) -> String {
+        match e.name.as_str() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = e.name[1..].parse::<usize>().unwrap();
+                let title = self.inlines(&e.children).replace("\\\n", " ");
+                format!("{} {}", "#".repeat(level), title.trim())
+            }
+            "p" | "dt" => self.inlines(&e.children).trim().to_owned(),
+            "hr" => String::from("***"),
+            "pre" => {
+                let language = e
+                    .find("code")
+                    .and_then(|code| code.attr("class"))
+                    .and_then(|class| {
+                        class
+                            .split_whitespace()
+                            .find_map(|c| c.strip_prefix("language-"))
+                    })
+                    .unwrap_or("");
+                format!("```{language}\n{}\n```", e.text().trim_end_matches('\n'))
+            }
+            "blockquote" => indent(&self.blocks(&e.children), "> ", "> "),
+            "dd" => indent(&self.blocks(&e.children), "    ", "    "),
+            "ul" | "ol" => self.list(e),
+            "table" => self.table(e),
+            "head" | "script" | "style" => String::new(),
+            _ => self.blocks(&e.children),
+        }
+    }
+
+    fn list(&self, e: &Element) -> String {
+        let start = e
+            .attr("start")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(1);
+        let mut out = String::new();
+        for (i, item) in e.elements().filter(|e| e.name == "li").enumerate() {
+            let marker = if e.name == "ol" {
+                format!("{}. ", start + i)
+            } else {
+                String::from("- ")
+            };
+            let content = self.blocks(&item.children);
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(&indent(&content, &marker, &" ".repeat(marker.len())));
+        }
+        out
+    }
+
+    fn table(&self, e: &Element) -> String {
+        let mut rows = vec![];
+        e.descendants("tr", &mut rows);
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| {
+                row.elements()
+                    .filter(|c| c.name == "td" || c.name == "th")
+                    .map(|c| {
+                        collapse(self.inlines(&c.children).trim())
+                            .replace('|', "\\|")
+                    })
+                    .collect()
+            })
+            .collect();
+        let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        if width == 0 {
+            return String::new();
+        }
+        let line = |cells: &[String]| {
+            let mut res = String::from("|");
+            for i in 0..width {
+                res.push(' ');
+                res.push_str(cells.get(i).map(|s| s.as_str()).unwrap_or(""));
+                res.push_str(" |");
+            }
+            res
+        };
+        let mut out = line(&rows[0]);
+        out.push('\n');
+        out.push_str(&line(&vec![String::from("---"); width]));
+        for row in &rows[1..] {
+            out.push('\n');
+            out.push_str(&line(row));
+        }
+        out
+    }
+
+    fn inlines(&self, nodes: &[Node]) -> String {
+        nodes.iter().map(|node| self.inline(node)).collect()
+    }
+
+    fn inline(&self, node: &Node) -> String {
+        let e = match node {
+            Node::Text(s) => return escape(&collapse(s)),
+            Node::Element(e) => e,
+        };
+        match e.name.as_str() {
+            "em" | "i" | "cite" => wrap("*", self.inlines(&e.children)),
+            "strong" | "b" => wrap("**", self.inlines(&e.children)),
+            "del" | "s" | "strike" => wrap("~~", self.inlines(&e.children)),
+            "sup" => wrap("^", self.inlines(&e.children)),
+            "sub" => wrap("~", self.inlines(&e.children)),
+            "code" | "tt" | "kbd" | "samp" => {
+                let code = collapse(&e.text());
+                if code.contains('`') {
+                    format!("`` {code} ``")
+                } else {
+                    format!("`{code}`")
+                }
+            }
+            "br" => String::from("\\\n"),
+            "img" | "image" => {
+                let src = e.attr("src").or_else(|| e.attr("href")).unwrap_or("");
+                let alt = e.attr("alt").unwrap_or("");
+                format!("![{}]({})", escape(alt), self.link(src))
+            }
+            "a" => {
+                let content = self.inlines(&e.children);
+                match e.attr("href") {
+                    Some(href) if !content.trim().is_empty() => {
+                        format!("[{}]({})", content.trim(), self.link(href))
+                    }
+                    _ => content,
+                }
+            }
+            "script" | "style" => String::new(),
+            _ => self.inlines(&e.children),
+        }
+    }
+}
+
+/// Content of an EPUB file
+struct Epub {
+    archive: zip::ZipArchive<fs::File>,
+    file: String,
+}
+
+impl Epub {
+    fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        let mut entry = self.archive.by_name(path).map_err(|_| {
+            Error::file_not_found(Source::new(self.file.as_str()), t!("import.epub"), path.to_owned())
+        })?;
+        let mut content = vec![];
+        entry.read_to_end(&mut content).map_err(|err| {
+            Error::default(
+                Source::new(self.file.as_str()),
+                t!("import.read", file = path, error = err.to_string()),
+            )
+        })?;
+        Ok(content)
+    }
+
+    fn read_xml(&mut self, path: &str) -> Result<Element> {
+        let content = self.read(path)?;
+        parse_xml(path, &String::from_utf8_lossy(&content))
+    }
+}
+
+fn write<P: AsRef<Path>>(path: P, content: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    fs::write(path, content).map_err(|err| {
+        Error::default(
+            Source::empty(),
+            t!(
+                "import.write",
+                file = path.display().to_string(),
+                error = err.to_string()
+            ),
+        )
+    })
+}
+
+/// Converts an EPUB file to a crowbook project in directory `dest`.
+///
+/// Chapters are converted to Markdown, images are extracted in an
+/// `images` subdirectory, and metadata are used to generate a book
+/// configuration file, named after the EPUB file.
+///
+/// Returns the path of the generated book configuration file.
+pub fn import_epub<P: AsRef<Path>, Q: AsRef<Path>>(epub: P, dest: Q) -> Result<String> {
+    let epub = epub.as_ref();
+    let dest = dest.as_ref();
+    let epub_name = epub.display().to_string();
+    let file = fs::File::open(epub).map_err(|_| {
+        Error::file_not_found(Source::empty(), t!("import.epub"), epub_name.clone())
+    })?;
+    let archive = zip::ZipArchive::new(file).map_err(|err| {
+        Error::default(
+            Source::new(epub_name.as_str()),
+            t!("import.zip", error = err.to_string()),
+        )
+    })?;
+    let mut epub_file = Epub {
+        archive,
+        file: epub_name.clone(),
+    };
+
+    if dest.exists() && fs::read_dir(dest).map_or(true, |mut d| d.next().is_some()) {
+        return Err(Error::default(
+            Source::empty(),
+            t!("import.not_empty", dir = dest.display().to_string()),
+        ));
+    }
+
+    // Find and read the package document
+    let container = epub_file.read_xml("META-INF/container.xml")?;
+    let opf_path = container
+        .find("rootfile")
+        .and_then(|e| e.attr("full-path"))
+        .ok_or_else(|| Error::parser(Source::new(epub_name.as_str()), t!("import.no_opf")))?
+        .to_owned();
+    let opf = epub_file.read_xml(&opf_path)?;
+    let opf_dir = dir_of(&opf_path).to_owned();
+
+    let mut metadata: Vec<(&str, String)> = vec![];
+    let mut cover_id = None;
+    if let Some(meta) = opf.find("metadata") {
+        for (tag, key) in &[
+            ("creator", "author"),
+            ("title", "title"),
+            ("language", "lang"),
+            ("description", "description"),
+            ("subject", "subject"),
+            ("rights", "license"),
+        ] {
+            let mut values = vec![];
+            meta.descendants(tag, &mut values);
+            let values: Vec<String> = values
+                .iter()
+                .map(|e| collapse(e.text().trim()))
+                .filter(|s| !s.is_empty())
+                .collect();
+            if !values.is_empty() {
+                let separator = if *tag == "title" { ": " } else { ", " };
+                metadata.push((key, values.join(separator)));
+            }
+        }
+        cover_id = meta
+            .elements()
+            .find(|e| e.name == "meta" && e.attr("name") == Some("cover"))
+            .and_then(|e| e.attr("content"));
+    }
+
+    // Map files of the archive to their new names
+    let mut items = vec![];
+    if let Some(manifest) = opf.find("manifest") {
+        manifest.descendants("item", &mut items);
+    }
+    let mut files = HashMap::new();
+    let mut images = vec![];
+    let mut cover = None;
+    for item in &items {
+        let (href, media_type) = match (item.attr("href"), item.attr("media-type")) {
+            (Some(href), Some(media_type)) => (href, media_type),
+            _ => continue,
+        };
+        if !media_type.starts_with("image/") {
+            continue;
+        }
+        let path = resolve(&opf_dir, href);
+        // Characters that would break Markdown links are replaced
+        let base: String = Path::new(&path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("image")
+            .chars()
+            .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+            .collect();
+        let mut name = format!("images/{base}");
+        let mut n = 1;
+        while files.values().any(|f| f == &name) {
+            n += 1;
+            name = format!("images/{n}_{base}");
+        }
+        let is_cover = item.attr("id").is_some() && item.attr("id") == cover_id
+            || item
+                .attr("properties")
+                .map_or(false, |p| p.split_whitespace().any(|p| p == "cover-image"));
+        if is_cover {
+            cover = Some(name.clone());
+        }
+        files.insert(path.clone(), name.clone());
+        images.push((path, name));
+    }
+
+    let mut spine = vec![];
+    if let Some(s) = opf.find("spine") {
+        s.descendants("itemref", &mut spine);
+    }
+    let mut chapters = vec![];
+    for itemref in spine {
+        let item = match itemref
+            .attr("idref")
+            .and_then(|id| items.iter().find(|item| item.attr("id") == Some(id)))
+        {
+            Some(item) => item,
+            None => continue,
+        };
+        // Skip the navigation document, crowbook generates its own table of contents
+        if item
+            .attr("properties")
+            .map_or(false, |p| p.split_whitespace().any(|p| p == "nav"))
+        {
+            continue;
+        }
+        let href = match item.attr("href") {
+            Some(href) => href,
+            None => continue,
+        };
+        let path = resolve(&opf_dir, href);
+        let content = epub_file.read_xml(&path)?;
+        let body = match content.find("body") {
+            Some(body) => body,
+            None => &content,
+        };
+        // Skip the cover page, since the image is set as cover
+        let mut pictures = vec![];
+        body.descendants("img", &mut pictures);
+        body.descendants("image", &mut pictures);
+        if cover.is_some() && body.text().trim().is_empty() && pictures.len() <= 1 {
+            continue;
+        }
+        let name = format!("chapter_{:02}.md", chapters.len() + 1);
+        files.insert(path.clone(), name.clone());
+        chapters.push((path, name, content));
+    }
+
+    fs::create_dir_all(dest.join("images")).map_err(|err| {
+        Error::default(
+            Source::empty(),
+            t!(
+                "import.write",
+                file = dest.display().to_string(),
+                error = err.to_string()
+            ),
+        )
+    })?;
+    for (path, name) in &images {
+        let content = epub_file.read(path)?;
+        write(dest.join(name), &content)?;
+    }
+    for (path, name, content) in &chapters {
+        let body = match content.find("body") {
+            Some(body) => body,
+            None => content,
+        };
+        let converter = Converter {
+            dir: dir_of(path),
+            files: &files,
+        };
+        let mut markdown = converter.blocks(&body.children);
+        markdown.push('\n');
+        write(dest.join(name), markdown.as_bytes())?;
+    }
+
+    // Generate the book configuration file
+    let mut config = String::new();
+    for (key, value) in &metadata {
+        // A JSON string is also a valid YAML string
+        config.push_str(&format!("{key}: {}\n", serde_json::Value::from(value.as_str())));
+    }
+    if let Some(cover) = cover {
+        config.push_str(&format!("cover: {}\n", serde_json::Value::from(cover)));
+    }
+    config.push_str(&t!("import.config"));
+    for (_, name, _) in &chapters {
+        config.push_str(&format!("- {name}\n"));
+    }
+    let stem = epub
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("book"));
+    let book_file = dest.join(format!("{stem}.book"));
+    write(&book_file, config.as_bytes())?;
+    Ok(book_file.display().to_string())
+}
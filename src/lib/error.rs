@@ -26,8 +26,8 @@ use rust_i18n::t;
 #[derive(Debug, PartialEq, Clone)]
 /// Source of an error.
 ///
-/// Contains (if it's possible) the file and ideally the line that the user should
-/// look at to correct their error.
+/// Contains (if it's possible) the file and ideally the line (and column) that the user
+/// should look at to correct their error.
 pub struct Source {
     /// File name of the source
     #[doc(hidden)]
@@ -36,14 +36,19 @@ pub struct Source {
     /// Line number of the source
     #[doc(hidden)]
     pub line: Option<u32>,
+
+    /// Column number of the source
+    #[doc(hidden)]
+    pub column: Option<u32>,
 }
 
 impl Source {
-    /// Create an empty source, with both fields set to None
+    /// Create an empty source, with all fields set to None
     pub fn empty() -> Source {
         Source {
             file: None,
             line: None,
+            column: None,
         }
     }
 
@@ -52,6 +57,7 @@ impl Source {
         Source {
             file: Some(s.into()),
             line: None,
+            column: None,
         }
     }
 
@@ -61,12 +67,34 @@ impl Source {
         self
     }
 
-    /// Unsets a line number of a source
+    /// Sets column number of a source.
+    pub fn set_column(&mut self, column: u32) -> &mut Self {
+        self.column = Some(column);
+        self
+    }
+
+    /// Unsets the line and column numbers of a source
     #[doc(hidden)]
     pub fn unset_line(&mut self) -> &mut Self {
         self.line = None;
+        self.column = None;
         self
     }
+
+    /// Returns the file of the source, if it is known
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Returns the line of the source, if it is known
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// Returns the column of the source, if it is known
+    pub fn column(&self) -> Option<u32> {
+        self.column
+    }
 }
 
 impl fmt::Display for Source {
@@ -75,6 +103,9 @@ impl fmt::Display for Source {
             write!(f, "{file}")?;
             if let Some(line) = self.line {
                 write!(f, ":{line}")?;
+                if let Some(column) = self.column {
+                    write!(f, ":{column}")?;
+                }
             }
         } else {
             write!(f, "<UNKNOWN FILE>")?;
@@ -88,55 +119,135 @@ impl<'a> From<&'a Source> for Source {
         s.clone()
     }
 }
-#[derive(Debug, PartialEq)]
+
+/// Kind of a Crowbook error, with its message
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Default variant
+    Default(Cow<'static, str>),
+    /// An error in parsing a markdown file
+    Parser(Cow<'static, str>),
+    /// An error in parsing a book configuration file
+    ConfigParser(Cow<'static, str>),
+    /// An error when a file is not found
+    FileNotFound {
+        /// Description of why the file was needed
+        description: Cow<'static, str>,
+        /// Name of the file that wasn't found
+        file: Cow<'static, str>,
+    },
+    /// An error in a renderer
+    Render(Cow<'static, str>),
+    /// An error during "zipping" processes
+    Zipper(Cow<'static, str>),
+    /// An error relative to BookOption conversion (usually a type error)
+    BookOption(Cow<'static, str>),
+    /// An invalid option
+    InvalidOption(Cow<'static, str>),
+    /// Error when compiling template
+    Template(Cow<'static, str>),
+    /// Error when parsing code syntax
+    Syntect(Cow<'static, str>),
+    /// The rendering was cancelled (see `Book::cancellation_token`)
+    Cancelled(Cow<'static, str>),
+}
+
+impl ErrorKind {
+    /// Returns a short code identifying this kind of error, e.g. `parser`
+    ///
+    /// Contrary to error messages, which are translated, codes are stable and
+    /// can be matched on by programs using Crowbook.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ErrorKind::Default(..) => "default",
+            ErrorKind::Parser(..) => "parser",
+            ErrorKind::ConfigParser(..) => "config",
+            ErrorKind::FileNotFound { .. } => "file-not-found",
+            ErrorKind::Render(..) => "render",
+            ErrorKind::Zipper(..) => "zipper",
+            ErrorKind::BookOption(..) => "book-option",
+            ErrorKind::InvalidOption(..) => "invalid-option",
+            ErrorKind::Template(..) => "template",
+            ErrorKind::Syntect(..) => "syntax",
+            ErrorKind::Cancelled(..) => "cancelled",
+        }
+    }
+
+    /// Returns the message of the error, without its source nor its description
+    pub fn message(&self) -> &str {
+        match *self {
+            ErrorKind::Default(ref s)
+            | ErrorKind::Parser(ref s)
+            | ErrorKind::Zipper(ref s)
+            | ErrorKind::BookOption(ref s)
+            | ErrorKind::ConfigParser(ref s)
+            | ErrorKind::InvalidOption(ref s)
+            | ErrorKind::Render(ref s)
+            | ErrorKind::Template(ref s)
+            | ErrorKind::Syntect(ref s)
+            | ErrorKind::Cancelled(ref s) => s.as_ref(),
+            ErrorKind::FileNotFound { ref file, .. } => file.as_ref(),
+        }
+    }
+}
+
 /// Crowbook Error type.
 ///
 /// This type tries (when it can) to track where the error came from, to
-/// pinpoint the file (at least) and, if possible, the line the user needs
-/// to look at.
+/// pinpoint the file (at least) and, if possible, the line and column the user
+/// needs to look at.
+///
+/// It implements `std::error::Error`, the underlying error (e.g. an I/O error)
+/// being returned by its `source` method when there is one.
+#[derive(Debug)]
 pub struct Error {
     /// Origin (file, line) of the error, if there is one
     source: Source,
-    inner: Inner,
+    kind: ErrorKind,
+    /// Underlying error, if any
+    cause: Option<Box<dyn error::Error + Send + Sync>>,
+}
+
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        self.source == other.source && self.kind == other.kind
+    }
 }
 
 impl Error {
-    /// Creates a new default error.
-    pub fn default<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
+    fn new<O: Into<Source>>(source: O, kind: ErrorKind) -> Error {
         Error {
             source: source.into(),
-            inner: Inner::Default(msg.into()),
+            kind,
+            cause: None,
         }
     }
 
+    /// Creates a new default error.
+    pub fn default<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
+        Error::new(source, ErrorKind::Default(msg.into()))
+    }
+
     /// Creates a new parser error.
     ///
     /// Error when parsing markdown file.
     pub fn parser<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
-        Error {
-            source: source.into(),
-            inner: Inner::Parser(msg.into()),
-        }
+        Error::new(source, ErrorKind::Parser(msg.into()))
     }
 
     /// Creates a new syntect error.
     ///
     /// Error when parsing (and highlighting) syntax code
     pub fn syntect<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
-        Error {
-            source: source.into(),
-            inner: Inner::Syntect(msg.into()),
-        }
+        Error::new(source, ErrorKind::Syntect(msg.into()))
     }
 
     /// Creates a new config parser error.
     ///
     /// Error when parsing the book configuration file.
     pub fn config_parser<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
-        Error {
-            source: source.into(),
-            inner: Inner::ConfigParser(msg.into()),
-        }
+        Error::new(source, ErrorKind::ConfigParser(msg.into()))
     }
 
     /// Creates a new "file not found" error
@@ -154,60 +265,55 @@ impl Error {
         msg: S1,
         file: S2,
     ) -> Error {
-        Error {
-            source: source.into(),
-            inner: Inner::FileNotFound(msg.into(), file.into()),
-        }
+        Error::new(
+            source,
+            ErrorKind::FileNotFound {
+                description: msg.into(),
+                file: file.into(),
+            },
+        )
     }
 
     /// Creates a new render error.
     ///
     /// Error when rendering the book to a given format.
     pub fn render<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
-        Error {
-            source: source.into(),
-            inner: Inner::Render(msg.into()),
-        }
+        Error::new(source, ErrorKind::Render(msg.into()))
     }
 
     /// Creates a new template error.
     ///
     /// Error when compiling a template.
     pub fn template<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
-        Error {
-            source: source.into(),
-            inner: Inner::Template(msg.into()),
-        }
+        Error::new(source, ErrorKind::Template(msg.into()))
     }
 
     /// Creates a new invalid option error.
     ///
     /// Error when trying to set an option.
     pub fn invalid_option<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
-        Error {
-            source: source.into(),
-            inner: Inner::InvalidOption(msg.into()),
-        }
+        Error::new(source, ErrorKind::InvalidOption(msg.into()))
     }
 
     /// Creates a new zipper error.
     ///
     /// Error when moving/copying files to temporary dir, e.g. using `zip` command.
     pub fn zipper<S: Into<Cow<'static, str>>>(msg: S) -> Error {
-        Error {
-            source: Source::empty(),
-            inner: Inner::Zipper(msg.into()),
-        }
+        Error::new(Source::empty(), ErrorKind::Zipper(msg.into()))
+    }
+
+    /// Creates a new cancellation error
+    ///
+    /// Returned when a rendering stops because it was cancelled.
+    pub fn cancelled() -> Error {
+        Error::new(Source::empty(), ErrorKind::Cancelled(t!("error.cancelled").into()))
     }
 
     /// Creates a new book option error
     ///
     /// Used when converting an error to invalid type.
     pub fn book_option<S: Into<Cow<'static, str>>, O: Into<Source>>(source: O, msg: S) -> Error {
-        Error {
-            source: source.into(),
-            inner: Inner::BookOption(msg.into()),
-        }
+        Error::new(source, ErrorKind::BookOption(msg.into()))
     }
 
     /// Change the source of an error.
@@ -216,89 +322,104 @@ impl Error {
         self
     }
 
+    /// Sets the underlying error, returned by `std::error::Error::source`.
+    pub fn with_cause<E: error::Error + Send + Sync + 'static>(mut self, cause: E) -> Error {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    /// Returns the kind of the error
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Returns the code of the error, e.g. `parser`. See `ErrorKind::code`.
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    /// Returns the origin (file, line and column) of the error
+    pub fn location(&self) -> &Source {
+        &self.source
+    }
+
     /// Returns true if self is a default option error, false else.
     pub fn is_default(&self) -> bool {
-        matches!(self.inner, Inner::Default(..))
+        matches!(self.kind, ErrorKind::Default(..))
     }
 
     /// Returns true if self is a parser error, false else.
     pub fn is_parser(&self) -> bool {
-        matches!(self.inner, Inner::Parser(..))
+        matches!(self.kind, ErrorKind::Parser(..))
     }
 
     /// Returns true if self is a config parser error, false else.
     pub fn is_config_parser(&self) -> bool {
-        matches!(self.inner, Inner::ConfigParser(..))
+        matches!(self.kind, ErrorKind::ConfigParser(..))
     }
 
     /// Returns true if self is a file not found error, false else.
     pub fn is_file_not_found(&self) -> bool {
-        matches!(self.inner, Inner::FileNotFound(..))
+        matches!(self.kind, ErrorKind::FileNotFound { .. })
     }
 
     /// Returns true if self is a render error, false else.
     pub fn is_render(&self) -> bool {
-        matches!(self.inner, Inner::Render(..))
+        matches!(self.kind, ErrorKind::Render(..))
     }
 
     /// Returns true if self is a zipper error, false else.
     pub fn is_zipper(&self) -> bool {
-        matches!(self.inner, Inner::Zipper(..))
+        matches!(self.kind, ErrorKind::Zipper(..))
     }
 
     /// Returns true if self is a book option error, false else.
     pub fn is_book_option(&self) -> bool {
-        matches!(self.inner, Inner::BookOption(..))
+        matches!(self.kind, ErrorKind::BookOption(..))
     }
 
     /// Returns true if self is an invalid option error, false else.
     pub fn is_invalid_option(&self) -> bool {
-        matches!(self.inner, Inner::InvalidOption(..))
+        matches!(self.kind, ErrorKind::InvalidOption(..))
+    }
+
+    /// Returns true if the rendering was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.kind, ErrorKind::Cancelled(..))
     }
 }
 
 impl error::Error for Error {
-    fn description(&self) -> &str {
-        match self.inner {
-            Inner::Default(ref s)
-            | Inner::Parser(ref s)
-            | Inner::Zipper(ref s)
-            | Inner::BookOption(ref s)
-            | Inner::ConfigParser(ref s)
-            | Inner::InvalidOption(ref s)
-            | Inner::Render(ref s)
-            | Inner::Template(ref s)
-            | Inner::Syntect(ref s) => s.as_ref(),
-            Inner::FileNotFound(..) => "File not found",
-        }
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.cause
+            .as_ref()
+            .map(|cause| cause.as_ref() as &(dyn error::Error + 'static))
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let source = &self.source;
-        if let Some(ref file) = source.file {
-            write!(f, "{file}")?;
-            if let Some(line) = source.line {
-                write!(f, ":{line}")?;
-            }
-            write!(f, ": ")?;
+        if self.source.file.is_some() {
+            write!(f, "{}: ", self.source)?;
         }
 
-        match self.inner {
-            Inner::Default(ref s) => write!(f, "{s}"),
-            Inner::Parser(ref s) => {
+        match self.kind {
+            ErrorKind::Default(ref s) => write!(f, "{s}"),
+            ErrorKind::Parser(ref s) => {
                 write!(
                     f,
                     "{}",
                     t!("error.markdown", error = s)
                 )
             }
-            Inner::ConfigParser(ref s) => {
+            ErrorKind::ConfigParser(ref s) => {
                 f.write_str(&t!("error.config"))?;
                 f.write_str(s)
             }
-            Inner::FileNotFound(ref description, ref file) => {
+            ErrorKind::FileNotFound {
+                ref description,
+                ref file,
+            } => {
                 write!(
                     f,
                     "{}",
@@ -309,33 +430,34 @@ impl fmt::Display for Error {
                     )
                 )
             }
-            Inner::Template(ref s) => {
+            ErrorKind::Template(ref s) => {
                 write!(
                     f,
                     "{}",
                     t!("error.template", template = s)
                 )
             }
-            Inner::Render(ref s) => {
+            ErrorKind::Render(ref s) => {
                 f.write_str(&t!("error.render_error"))?;
                 f.write_str(s)
             }
-            Inner::Zipper(ref s) => {
+            ErrorKind::Zipper(ref s) => {
                 f.write_str(&t!("error.zipper"))?;
                 f.write_str(s)
             }
-            Inner::BookOption(ref s) => {
+            ErrorKind::BookOption(ref s) => {
                 f.write_str(&t!("error.bookoption"))?;
                 f.write_str(s)
             }
-            Inner::InvalidOption(ref s) => {
+            ErrorKind::InvalidOption(ref s) => {
                 f.write_str(&t!("error.invalid_option"))?;
                 f.write_str(s)
             }
-            Inner::Syntect(ref s) => {
+            ErrorKind::Syntect(ref s) => {
                 f.write_str(&t!("error.syntect"))?;
                 f.write_str(s)
             }
+            ErrorKind::Cancelled(ref s) => f.write_str(s),
         }?;
         Ok(())
     }
@@ -344,10 +466,26 @@ impl fmt::Display for Error {
 /// Crowbook's Result type, used by many methods that can fail
 pub type Result<T> = result::Result<T, Error>;
 
+/// Reports a problem that makes the output lose some content: an error if `strict` is set
+/// (by the `rendering.strict` option), or else a warning
+pub(crate) fn lossy<S: Into<String>, O: Into<Source>>(
+    strict: bool,
+    source: O,
+    problem: S,
+) -> Result<()> {
+    let problem = problem.into();
+    if strict {
+        Err(Error::render(source, t!("error.strict", problem = problem)))
+    } else {
+        warn!("{problem}");
+        Ok(())
+    }
+}
+
 /// Implement our Error from upon::error
 impl From<upon::Error> for Error {
     fn from(err: upon::Error) -> Error {
-        Error::template(Source::empty(), format!("{:#}", err))
+        Error::template(Source::empty(), format!("{:#}", err)).with_cause(err)
     }
 }
 
@@ -358,6 +496,7 @@ impl From<FromUtf8Error> for Error {
             Source::empty(),
             t!("error.utf8_error", error = err),
         )
+        .with_cause(err)
     }
 }
 
@@ -367,6 +506,7 @@ impl From<std::str::Utf8Error> for Error {
             Source::empty(),
             t!("error.utf8_error", error = err),
         )
+        .with_cause(err)
     }
 }
 
@@ -376,9 +516,11 @@ impl From<fmt::Error> for Error {
             Source::empty(),
             t!("error.format", error = err),
         )
+        .with_cause(err)
     }
 }
 
+#[cfg(feature = "syntect")]
 impl From<syntect::Error> for Error {
     fn from(err: syntect::Error) -> Error {
         Error::syntect(
@@ -387,29 +529,6 @@ impl From<syntect::Error> for Error {
                     msg = t!("error.syntect"),
                     error = err),
         )
+        .with_cause(err)
     }
 }
-
-#[derive(Debug, PartialEq)]
-enum Inner {
-    /// Default variant
-    Default(Cow<'static, str>),
-    /// An error in Parsing markdown file
-    Parser(Cow<'static, str>),
-    /// An error in parsing a book configuration file
-    ConfigParser(Cow<'static, str>),
-    /// An error when a file is not found
-    FileNotFound(Cow<'static, str>, Cow<'static, str>), // description, file
-    /// An error in a renderer
-    Render(Cow<'static, str>),
-    /// An error during "zipping" processes
-    Zipper(Cow<'static, str>),
-    /// An error relative to BookOption conversion (usually a type error)
-    BookOption(Cow<'static, str>),
-    /// An invalid option
-    InvalidOption(Cow<'static, str>),
-    /// Error when compiling template
-    Template(Cow<'static, str>),
-    /// Error when parsing code syntax
-    Syntect(Cow<'static, str>),
-}
@@ -0,0 +1,161 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Filters, transforming the tokens of each chapter after it has been parsed.
+
+use crate::book::Book;
+use crate::error::{Error, Result, Source};
+use crate::hooks;
+use crate::pandoc;
+use crate::parser::Features;
+use crate::token::Token;
+
+use std::io::Write;
+use std::process::Stdio;
+use std::thread;
+use rust_i18n::t;
+
+/// A transformation of the tokens of a chapter
+///
+/// Filters are run on each chapter, after it has been parsed and before it is rendered
+/// by any renderer, so they can be used to implement custom transformations (e.g. shortcodes).
+///
+/// Functions taking the book, the name of the chapter's file and its tokens also
+/// implement this trait.
+///
+/// # Example
+///
+/// ```
+/// use crowbook::{Book, Token};
+/// let mut book = Book::new();
+/// book.add_filter(|_: &Book, _: &str, tokens: Vec<Token>| {
+///     Ok(tokens.into_iter().filter(|t| *t != Token::Rule).collect())
+/// });
+/// ```
+pub trait Filter: Send + Sync {
+    /// Transforms the tokens of a chapter
+    ///
+    /// * `book`: the book the chapter belongs to;
+    /// * `file`: the name of the chapter's file (which is empty for chapters that
+    ///   don't come from a file);
+    /// * `tokens`: the tokens of the chapter.
+    fn filter(&self, book: &Book, file: &str, tokens: Vec<Token>) -> Result<Vec<Token>>;
+}
+
+impl<F> Filter for F
+where
+    F: Fn(&Book, &str, Vec<Token>) -> Result<Vec<Token>> + Send + Sync,
+{
+    fn filter(&self, book: &Book, file: &str, tokens: Vec<Token>) -> Result<Vec<Token>> {
+        self(book, file, tokens)
+    }
+}
+
+/// A filter running an external command, set by the `input.filters` option
+///
+/// The chapter is converted to pandoc's JSON format and piped to the command, which must
+/// write the transformed document on its standard output, like pandoc filters do.
+pub struct ExternalFilter {
+    command: String,
+}
+
+impl ExternalFilter {
+    /// Creates a filter running a shell command
+    pub fn new<S: Into<String>>(command: S) -> ExternalFilter {
+        ExternalFilter {
+            command: command.into(),
+        }
+    }
+}
+
+impl Filter for ExternalFilter {
+    fn filter(&self, book: &Book, file: &str, tokens: Vec<Token>) -> Result<Vec<Token>> {
+        let source = Source::new(file);
+        let error = |error: String| {
+            Error::parser(
+                &source,
+                t!("filter.run_error", command = &self.command, error = error),
+            )
+        };
+        let input = pandoc::tokens_to_pandoc(book, &tokens).to_string();
+
+        let mut command = hooks::shell_command(book, &self.command);
+        command
+            .env("CROWBOOK_FILE", file)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn().map_err(|e| error(e.to_string()))?;
+        // Write in another thread so a filter writing before it has read
+        // everything can't block
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let limits = book.command_limits();
+        let output = book
+            .timings()
+            .time(&format!("filter: {}", self.command), || limits.wait(child, &self.command))?
+            .map_err(|e| error(e.to_string()))?;
+        // The filter might not read its input at all, so a broken pipe isn't an error
+        let _ = writer.join();
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let mut msg = output.status.to_string();
+            if !stderr.trim().is_empty() {
+                msg = format!("{msg}\n{}", stderr.trim_end());
+            }
+            return Err(error(msg));
+        }
+        let output = String::from_utf8(output.stdout).map_err(|e| error(e.to_string()))?;
+        let mut features = Features::new();
+        pandoc::from_pandoc_json(&source, &output, None, &mut features)
+    }
+}
+
+/// Returns the features used by some tokens, so they can be updated after filters
+/// modified a chapter
+pub fn features(tokens: &[Token]) -> Features {
+    let mut res = Features::new();
+    add_features(tokens, &mut res);
+    res
+}
+
+fn add_features(tokens: &[Token], features: &mut Features) {
+    for token in tokens {
+        match *token {
+            Token::Image(..) | Token::StandaloneImage(..) => features.image = true,
+            Token::FootnoteReference(..) | Token::FootnoteDefinition(..) => {
+                features.footnote = true
+            }
+            Token::BlockQuote(..) => features.blockquote = true,
+            Token::CodeBlock(..) => features.codeblock = true,
+            Token::OrderedList(..) => features.ordered_list = true,
+            Token::Table(..) => features.table = true,
+            Token::Link(..) => features.url = true,
+            Token::Subscript(..) => features.subscript = true,
+            Token::Superscript(..) => features.superscript = true,
+            Token::Strikethrough(..) => features.strikethrough = true,
+            Token::TaskItem(..) => features.taskitem = true,
+            Token::Letter(..) => features.letter = true,
+            Token::Play(..) => features.play = true,
+            _ => (),
+        }
+        if let Some(inner) = token.inner() {
+            add_features(inner, features);
+        }
+    }
+}
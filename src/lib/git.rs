@@ -0,0 +1,101 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Version metadata read from the git repository of a book.
+
+use crate::error::{Error, Result, Source};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+use rust_i18n::t;
+
+/// Information about the commit a book is built from
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitInfo {
+    /// Hash of the commit
+    pub hash: String,
+    /// Abbreviated hash of the commit
+    pub short_hash: String,
+    /// Tag pointing to the commit, if any
+    pub tag: Option<String>,
+    /// Description of the version, as given by `git describe`, e.g. `v1.2-3-gabc1234`
+    pub version: String,
+    /// Date of the commit, e.g. `2023-08-04`
+    pub date: String,
+    /// Timestamp of the commit
+    pub timestamp: i64,
+    /// True if tracked files were modified since the commit
+    pub dirty: bool,
+}
+
+impl GitInfo {
+    /// Reads the information about the current commit of the repository containing `dir`
+    pub fn read<P: AsRef<Path>>(dir: P) -> Result<GitInfo> {
+        let dir = dir.as_ref();
+        let git = |args: &[&str]| -> Result<Option<String>> {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(if dir == Path::new("") { Path::new(".") } else { dir })
+                .args(args)
+                .output()
+                .map_err(|err| {
+                    Error::default(Source::empty(), t!("git.run_error", error = err.to_string()))
+                })?;
+            Ok(if output.status.success() {
+                Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+            } else {
+                None
+            })
+        };
+
+        let log = git(&["log", "-1", "--format=%H%n%h%n%ct%n%cd", "--date=short"])?
+            .ok_or_else(|| {
+                Error::default(
+                    Source::empty(),
+                    t!("git.no_repository", dir = dir.display().to_string()),
+                )
+            })?;
+        let mut lines = log.lines();
+        let mut next = || lines.next().unwrap_or_default().to_owned();
+        let (hash, short_hash, timestamp, date) = (next(), next(), next(), next());
+        let tag = git(&["describe", "--tags", "--exact-match"])?;
+        let version = git(&["describe", "--tags", "--always"])?.unwrap_or_else(|| short_hash.clone());
+        let dirty = git(&["status", "--porcelain", "--untracked-files=no"])?
+            .map_or(false, |status| !status.is_empty());
+        Ok(GitInfo {
+            hash,
+            short_hash,
+            tag,
+            version,
+            date,
+            timestamp: timestamp.parse().unwrap_or_default(),
+            dirty,
+        })
+    }
+
+    /// Adds the `git_*` template variables
+    pub fn add_metadata(&self, m: &mut BTreeMap<String, upon::Value>) {
+        m.insert("git_hash".into(), self.hash.clone().into());
+        m.insert("git_short_hash".into(), self.short_hash.clone().into());
+        m.insert("git_date".into(), self.date.clone().into());
+        m.insert("git_dirty".into(), self.dirty.into());
+        m.insert("has_git_tag".into(), self.tag.is_some().into());
+        m.insert("git_tag".into(), self.tag.clone().unwrap_or_default().into());
+    }
+}
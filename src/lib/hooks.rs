@@ -0,0 +1,113 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hooks, i.e. shell commands set by `hooks.*` options that are run before
+//! or after rendering.
+
+use crate::book::Book;
+use crate::error::{Error, Result, Source};
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use rust_i18n::t;
+
+/// Returns a command running `command` in a shell, in the book's directory
+///
+/// The `CROWBOOK_BOOK` and `CROWBOOK_TEMP_DIR` (and `TMPDIR`) environment variables are set.
+pub fn shell_command(book: &Book, command: &str) -> Command {
+    #[cfg(windows)]
+    let mut res = {
+        use std::os::windows::process::CommandExt;
+        // cmd doesn't follow the usual quoting rules, so the command is passed as is
+        let mut res = Command::new("cmd");
+        res.arg("/C").raw_arg(command);
+        res
+    };
+    #[cfg(not(windows))]
+    let mut res = {
+        let mut res = Command::new("sh");
+        res.arg("-c").arg(command);
+        res
+    };
+    let temp_dir = absolute(Path::new(
+        &book.options.get_path("crowbook.temp_dir").unwrap(),
+    ));
+    res.current_dir(absolute(&book.root))
+        .env(
+            "CROWBOOK_BOOK",
+            book.source.file.as_ref().map(|f| absolute(Path::new(f))).unwrap_or_default(),
+        )
+        .env("CROWBOOK_TEMP_DIR", &temp_dir)
+        .env("TMPDIR", &temp_dir);
+    res
+}
+
+/// Returns an absolute path, since paths are relative to the current directory, not the book's one
+pub fn absolute(path: &Path) -> PathBuf {
+    env::current_dir()
+        .map(|dir| dir.join(path))
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+/// Runs the shell command set by the `key` option, if it is set
+///
+/// The command is run by `shell_command`, with also `CROWBOOK_FORMAT`
+/// and `CROWBOOK_OUTPUT` set for commands run after rendering a format to a file.
+pub fn run(book: &Book, key: &str, output: Option<(&str, &Path)>) -> Result<()> {
+    let hook = match book.options.get_str(key) {
+        Ok(hook) => hook,
+        Err(_) => return Ok(()),
+    };
+    book.check_cancelled()?;
+    debug!("{}", t!("hooks.running", key = key, command = hook));
+
+    let mut command = shell_command(book, hook);
+    if let Some((format, path)) = output {
+        command
+            .env("CROWBOOK_FORMAT", format)
+            .env("CROWBOOK_OUTPUT", absolute(path));
+    }
+
+    let limits = book.command_limits();
+    let result = book
+        .timings()
+        .time(&format!("hook: {key}"), || limits.output(&mut command, key))?;
+    let output = result.map_err(|err| {
+        Error::render(
+            Source::empty(),
+            t!("hooks.run_error", key = key, error = err),
+        )
+    })?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.trim().is_empty() {
+        info!("{}", t!("hooks.output", key = key, output = stdout.trim_end()));
+    }
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::render(
+            Source::empty(),
+            t!(
+                "hooks.failed",
+                key = key,
+                status = output.status,
+                output = String::from_utf8_lossy(&output.stderr).trim_end()
+            ),
+        ))
+    }
+}
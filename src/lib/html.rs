@@ -18,12 +18,18 @@
 use crate::book::Header;
 use crate::book::HeaderData;
 use crate::book::Book;
+use crate::code_block::CodeInfo;
 use crate::error::{Error, Result, Source};
 use crate::lang;
+use crate::link_refs::LinkStyle;
+use crate::misc;
+use crate::notes::{NoteNumbering, SideNotes};
 use crate::number::Number;
-use crate::parser::Parser;
+use crate::parser::{self, Parser};
 use crate::renderer::Renderer;
+use crate::slug::{Naming, Slugifier};
 use crate::resource_handler::ResourceHandler;
+use crate::subject;
 use crate::syntax::Syntax;
 use crate::token::Data;
 use crate::token::Token;
@@ -32,13 +38,16 @@ use crate::text_view;
 use std::borrow::Cow;
 use std::convert::{AsMut, AsRef};
 use std::fmt::Write;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
 
 use crowbook_text_processing::escape;
 use epub_builder::Toc;
 use epub_builder::TocElement;
 use numerals::roman::Roman;
 use rust_i18n::t;
+use serde_json::{json, Value};
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 /// If/how to highlight code
@@ -55,6 +64,9 @@ pub struct HtmlRenderer<'a> {
     table_head: bool,
     #[doc(hidden)]
     pub verbatim: bool,
+    /// Whether raw HTML and unsafe links must be neutralized
+    #[doc(hidden)]
+    pub sanitize: bool,
     current_par: u32,
     #[doc(hidden)]
     pub first_letter: bool,
@@ -86,6 +98,10 @@ pub struct HtmlRenderer<'a> {
     #[doc(hidden)]
     pub current_hide: bool,
 
+    /// Whether current chapter is left out of the table of contents
+    #[doc(hidden)]
+    pub current_unlisted: bool,
+
     /// Whether current chapter is actually a part
     #[doc(hidden)]
     pub current_part: bool,
@@ -109,6 +125,45 @@ pub struct HtmlRenderer<'a> {
     #[doc(hidden)]
     pub link_number: u32,
 
+    /// Anchor of the current header, set by `html.anchors`
+    #[doc(hidden)]
+    pub current_anchor: String,
+
+    anchors: Naming,
+    slugifier: Slugifier,
+
+    /// Anchors already used, so headers with the same slug get different ones
+    used_anchors: HashSet<String>,
+
+    /// Current long description number
+    #[doc(hidden)]
+    pub description_number: u32,
+
+    /// Number of the last line of the previous code block
+    #[doc(hidden)]
+    pub code_line: u32,
+
+    /// Id of the long description of the image being rendered
+    described_by: Option<String>,
+
+    /// How the URLs of external links are displayed
+    #[doc(hidden)]
+    pub link_style: LinkStyle,
+
+    /// Footnotes of the current chapter, if they are displayed as side notes
+    #[doc(hidden)]
+    pub side_notes: Option<SideNotes>,
+
+    note_numbering: NoteNumbering,
+
+    /// Number of references to each footnote of the current chapter
+    note_references: HashMap<String, u32>,
+
+    /// Version of the EPUB being rendered, if any: EPUB 3 footnotes are marked so reading
+    /// systems can display them as popups, and EPUB 2 (XHTML 1.1) has no `<aside>`
+    #[doc(hidden)]
+    pub epub_version: Option<i32>,
+
     syntax: Option<Syntax>,
 
     part_template_html: upon::Template<'a, 'a>,
@@ -146,15 +201,33 @@ impl<'a> HtmlRenderer<'a> {
             book,
             toc: Toc::new(),
             link_number: 0,
+            current_anchor: String::new(),
+            anchors: Naming::from(&book.options, "html.anchors"),
+            slugifier: Slugifier::new(&book.options),
+            used_anchors: HashSet::new(),
+            description_number: 0,
+            code_line: 0,
+            described_by: None,
+            link_style: LinkStyle::from(&book.options, "html"),
+            side_notes: if book.options.get_bool("html.side_notes").unwrap() {
+                Some(SideNotes::default())
+            } else {
+                None
+            },
+            note_numbering: NoteNumbering::from(&book.options),
+            note_references: HashMap::new(),
+            epub_version: None,
             current_chapter: [0, 0, 0, 0, 0, 0, 0],
             current_numbering: book.options.get_i32("rendering.num_depth").unwrap(),
             current_part: false,
             current_par: 0,
             current_hide: false,
+            current_unlisted: false,
             table_head: false,
             footnotes: vec![],
             footnote_prefix: 0,
             verbatim: false,
+            sanitize: book.options.get_bool("html.sanitize").unwrap(),
             filename: String::new(),
             handler: ResourceHandler::new(),
             source: Source::empty(),
@@ -174,8 +247,20 @@ impl<'a> HtmlRenderer<'a> {
                 "html.chapter.template",
             )?,
         };
+        if html.anchors == Naming::Slug {
+            // Anchors named after headers must not be the same as the ones set by the author
+            let code_labels = book.options.get_bool("rendering.code.line_numbers").unwrap();
+            for chapter in &book.chapters {
+                html.used_anchors.extend(parser::labels(&chapter.content, code_labels));
+            }
+        }
         html.handler.set_images_mapping(true);
         html.handler.set_base64(true);
+        if book.options.is_sandboxed() {
+            html.handler.set_sandbox(Some(book.root.clone()));
+        }
+        html.handler
+            .set_strict(book.options.get_bool("rendering.strict").unwrap());
         Ok(html)
     }
 
@@ -191,6 +276,7 @@ impl<'a> HtmlRenderer<'a> {
         self.source = Source::new(self.book.chapters[i].filename.as_str());
         self.first_paragraph = true;
         self.current_hide = false;
+        self.current_unlisted = self.book.chapters[i].unlisted;
         let book_numbering = self.book.options.get_i32("rendering.num_depth").unwrap();
         match n {
             Number::Unnumbered | Number::UnnumberedPart => self.current_numbering = 0,
@@ -219,9 +305,9 @@ impl<'a> HtmlRenderer<'a> {
         T: AsMut<HtmlRenderer<'a>> + AsRef<HtmlRenderer<'a>> + Renderer,
     {
         let mut res = String::new();
+        this.as_mut().start_notes(tokens);
         for token in tokens {
             res.push_str(&this.render_token(token)?);
-            this.as_mut().render_side_notes(&mut res);
         }
         if render_end_notes {
             this.as_mut().render_end_notes(&mut res, "section", "");
@@ -229,13 +315,68 @@ impl<'a> HtmlRenderer<'a> {
         Ok(res)
     }
 
+    /// Returns the part of the renderer's state that depends on the chapters rendered so far,
+    /// so the rendering of a chapter can be cached
+    #[doc(hidden)]
+    pub fn get_state(&self) -> Value {
+        let mut images: Vec<_> = self.handler.images_mapping().iter().collect();
+        images.sort();
+        let mut used_anchors: Vec<_> = self.used_anchors.iter().collect();
+        used_anchors.sort();
+        json!({
+            "current_par": self.current_par,
+            "first_letter": self.first_letter,
+            "footnote_prefix": self.footnote_prefix,
+            "current_chapter": self.current_chapter,
+            "link_number": self.link_number,
+            "used_anchors": used_anchors,
+            "description_number": self.description_number,
+            "code_line": self.code_line,
+            "toc": self.toc.elements.iter().map(toc_element_to_json).collect::<Vec<_>>(),
+            "images": images,
+        })
+    }
+
+    /// Restores a state returned by `get_state`
+    ///
+    /// **Returns** `None` (leaving the renderer in an unspecified state) if the state is invalid.
+    #[doc(hidden)]
+    pub fn set_state(&mut self, state: &Value) -> Option<()> {
+        self.current_par = state["current_par"].as_u64()? as u32;
+        self.first_letter = state["first_letter"].as_bool()?;
+        self.footnote_prefix = state["footnote_prefix"].as_u64()? as u32;
+        let current_chapter = state["current_chapter"].as_array()?;
+        for (i, n) in self.current_chapter.iter_mut().enumerate() {
+            *n = current_chapter.get(i)?.as_i64()? as i32;
+        }
+        self.link_number = state["link_number"].as_u64()? as u32;
+        self.used_anchors = state["used_anchors"]
+            .as_array()?
+            .iter()
+            .map(|anchor| anchor.as_str().map(String::from))
+            .collect::<Option<_>>()?;
+        self.description_number = state["description_number"].as_u64()? as u32;
+        self.code_line = state["code_line"].as_u64()? as u32;
+        self.toc.elements = state["toc"]
+            .as_array()?
+            .iter()
+            .map(toc_element_from_json)
+            .collect::<Option<_>>()?;
+        for image in state["images"].as_array()? {
+            self.handler.add_image_mapping(
+                image.get(0)?.as_str()?.to_owned(),
+                image.get(1)?.as_str()?.to_owned(),
+            );
+        }
+        Some(())
+    }
+
     /// Renders a title (without `<h1>` tags), increasing header number beforehand
     #[doc(hidden)]
     pub fn render_title(&mut self, n: i32, vec: &[Token]) -> Result<HeaderData> {
         let n = if self.current_part { n - 1 } else { n };
         self.inc_header(n);
 
-        let number = self.current_chapter[n as usize];
         let c_title = self.render_vec(vec)?;
 
         if n <= 1 && self.current_numbering >= 1 {
@@ -244,18 +385,14 @@ impl<'a> HtmlRenderer<'a> {
             } else {
                 Header::Chapter
             };
-            self.book.get_header(header, number, c_title, |s| {
+            let numbers = self.current_chapter;
+            self.book.get_header_numbers(header, &numbers, c_title, |s| {
                 let mut parser = Parser::from(self.book);
                 self.render_vec(&parser.parse_inline(s)?)
             })
         } else if self.current_numbering >= n {
-            let numbers = self.get_numbers();
-            Ok(HeaderData {
-                text: format!("{numbers} {c_title}"),
-                number: numbers,
-                header: String::new(),
-                title: c_title,
-            })
+            self.book
+                .get_section_header(n, &self.current_chapter, self.get_numbers(), c_title)
         } else {
             Ok(HeaderData {
                 text: c_title.clone(),
@@ -268,32 +405,45 @@ impl<'a> HtmlRenderer<'a> {
 
     /// Renders a title, including `<h1>` tags and appropriate links
     #[doc(hidden)]
-    pub fn render_title_full(&mut self, n: i32, data: HeaderData) -> Result<String> {
+    pub fn render_title_full(&mut self, n: i32, data: HeaderData, classes: &[String]) -> Result<String> {
+        let classes = html_escape::encode_double_quoted_attribute(&classes.join(" ")).into_owned();
         if n == 1 {
             if self.current_hide {
-                Ok(format!("<h1 id = \"link-{}\"></h1>", self.link_number))
+                Ok(format!("<h1 id = \"{}\"></h1>", self.current_anchor))
             } else {
-                let template = if self.current_part {
-                    &self.part_template_html
+                let (template, name) = if self.current_part {
+                    (&self.part_template_html, "html.part.template")
                 } else {
-                    &self.chapter_template_html
+                    (&self.chapter_template_html, "html.chapter.template")
                 };
                 let has_number = !data.header.is_empty();
                 let has_title = !data.title.is_empty();
+                let has_classes = !classes.is_empty();
                 let data = upon::value!{
                     has_number: has_number,
                     has_title: has_title,
+                    has_classes: has_classes,
+                    classes: classes,
                     header: data.header,
                     number: data.number,
                     title: data.title,
-                    link: format!("{}", self.link_number)
+                    link: format!("{}", self.link_number),
+                    anchor: self.current_anchor.clone()
                 };
+                if let upon::Value::Map(ref map) = data {
+                    self.book.template_vars.record(name, map);
+                }
                 Ok(template.render(&data).to_string()?)
             }
         } else {
+            let class = if classes.is_empty() {
+                String::new()
+            } else {
+                format!(" class = \"{classes}\"")
+            };
             Ok(format!(
-                "<h{} id = \"link-{}\">{}</h{}>\n",
-                n, self.link_number, data.text, n
+                "<h{} id = \"{}\"{class}>{}</h{}>\n",
+                n, self.current_anchor, data.text, n
             ))
         }
     }
@@ -328,6 +478,17 @@ impl<'a> HtmlRenderer<'a> {
         self.link_number += 1;
     }
 
+    /// Sets the anchor of the header being rendered, after its number or its slug
+    fn set_anchor(&mut self, vec: &[Token]) {
+        let slug = match self.anchors {
+            Naming::Slug => self
+                .slugifier
+                .unique_slug(&text_view::view_as_text(vec), &mut self.used_anchors),
+            Naming::Number => None,
+        };
+        self.current_anchor = slug.unwrap_or_else(|| format!("link-{}", self.link_number));
+    }
+
     /// Returns a "x.y.z" corresponding to current chapter/section/...
     fn get_numbers(&self) -> String {
         let mut output = String::new();
@@ -366,21 +527,19 @@ impl<'a> HtmlRenderer<'a> {
         output
     }
 
-    /// Display side notes if option is to true
+    /// Prepares the rendering of the footnotes of a chapter
     #[doc(hidden)]
-    pub fn render_side_notes(&mut self, res: &mut String) {
-        if self.book.options.get_bool("html.side_notes").unwrap() {
-            for (note_number, footnote) in self.footnotes.drain(..) {
-                write!(
-                    res,
-                    "<div class = \"sidenote\">\n{note_number} {footnote}\n</div>\n",
-                )
-                .unwrap();
-            }
+    pub fn start_notes(&mut self, tokens: &[Token]) {
+        self.note_references.clear();
+        if let Some(ref mut side_notes) = self.side_notes {
+            side_notes.collect(tokens);
         }
     }
 
-    /// Display end notes, if side_notes option is set to false
+    /// Display end notes
+    ///
+    /// When footnotes are displayed as side notes, end notes are only displayed by
+    /// stylesheets on narrow screens.
     ///
     /// * res: string to write to
     /// * section_tag: should be section for HTML5, id for XHTML
@@ -388,9 +547,14 @@ impl<'a> HtmlRenderer<'a> {
     #[doc(hidden)]
     pub fn render_end_notes(&mut self, res: &mut String, section_tag: &str, option: &str) {
         if !self.footnotes.is_empty() {
+            let class = if self.side_notes.is_some() {
+                "notes side-notes-fallback"
+            } else {
+                "notes"
+            };
             write!(
                 res,
-                "<{section_tag} class = \"notes\" {option}>
+                "<{section_tag} class = \"{class}\" {option}>
  <h2 class = \"notes\">{}</h2>\n",
                 lang::get_str(self.book.options.get_str("lang").unwrap(), "notes")
             )
@@ -502,18 +666,29 @@ impl<'a> HtmlRenderer<'a> {
                 Ok(format!("<p id = \"para-{par}\"{class}>{content}</p>\n"))
             }
             Token::Header(n, ref vec) => {
-                let data = this.as_mut().render_title(n, vec)?;
+                let (id, classes) = token.header_attributes();
+                // Headers with the `unnumbered` class are rendered like in unnumbered chapters
+                let numbering = this.as_ref().current_numbering;
+                if token.has_class("unnumbered") {
+                    this.as_mut().current_numbering = 0;
+                }
+                let data = this.as_mut().render_title(n, vec);
+                this.as_mut().current_numbering = numbering;
+                this.as_mut().set_anchor(vec);
+                let mut data = data?;
                 if n <= this
                     .as_ref()
                     .book
                     .options
                     .get_i32("rendering.num_depth")
                     .unwrap()
+                    && !token.has_class("unlisted")
+                    && !this.as_ref().current_unlisted
                 {
                     let url = format!(
-                        "{}#link-{}",
+                        "{}#{}",
                         this.as_ref().filename,
-                        this.as_ref().link_number
+                        this.as_ref().current_anchor
                     );
                     if !this.as_ref().current_part {
                         this.as_mut()
@@ -534,8 +709,18 @@ impl<'a> HtmlRenderer<'a> {
                     
                     }
                 }
-                Ok(this.as_mut().render_title_full(n, data)?)
+                if !id.is_empty() {
+                    let anchor = format!(
+                        "<span id = \"{}\"></span>",
+                        html_escape::encode_double_quoted_attribute(id)
+                    );
+                    data.title.insert_str(0, &anchor);
+                    data.text.insert_str(0, &anchor);
+                }
+                Ok(this.as_mut().render_title_full(n, data, classes)?)
             }
+            // Rendered by the header containing them
+            Token::HeaderAttributes(..) => Ok(String::new()),
             Token::TaskItem(checked, ref vec) => Ok(format!(
                 "<input type = \"checkbox\" disabled = \"\" {}/>{}",
                 if checked { "checked = \"\"" } else { "" },
@@ -545,25 +730,159 @@ impl<'a> HtmlRenderer<'a> {
             Token::Strong(ref vec) => Ok(format!("<b>{}</b>", this.render_vec(vec)?)),
             Token::Strikethrough(ref vec) => Ok(format!("<del>{}</del>", this.render_vec(vec)?)),
             Token::Code(ref s) => Ok(format!("<code>{}</code>", escape::html(s))),
+            Token::Raw(ref format, ref s) => {
+                if format == "html" && this.as_ref().sanitize {
+                    Ok(escape::html(s.as_str()).into_owned())
+                } else if format == "html" {
+                    Ok(s.clone())
+                } else {
+                    Ok(String::new())
+                }
+            }
+            Token::Shortcode(ref raws, ref markdown) => {
+                if raws.iter().any(|raw| matches!(*raw, Token::Raw(ref format, _) if format == "html")) {
+                    this.render_vec(raws)
+                } else {
+                    this.render_vec(markdown)
+                }
+            }
             Token::Subscript(ref vec) => Ok(format!("<sub>{}</sub>", this.render_vec(vec)?)),
             Token::Superscript(ref vec) => Ok(format!("<sup>{}</sup>", this.render_vec(vec)?)),
             Token::BlockQuote(ref vec) => Ok(format!(
                 "<blockquote>{}</blockquote>\n",
                 this.render_vec(vec)?
             )),
-            Token::CodeBlock(ref language, ref s) => {
-                let output = if let Some(ref syntax) = this.as_ref().syntax {
-                    syntax.to_html(s, language)?
-                } else if language.is_empty() {
-                    format!("<pre><code>{s}</code></pre>\n")
+            Token::CodeBlock(ref info, ref s) => {
+                let info = CodeInfo::new(info);
+                let language = info.language.as_str();
+                let numbered = info.start.is_some()
+                    || this.as_ref().book.options.get_bool("rendering.code.line_numbers").unwrap();
+                let (s, labels) = if numbered {
+                    let (code, labels) = parser::code_labels(s);
+                    (Cow::Owned(code), labels)
                 } else {
-                    format!(
-                        "<pre><code class = \"language-{language}\">{}</code></pre>\n",
-                        escape::html(s)
-                    )
+                    (Cow::Borrowed(s.as_str()), vec![])
+                };
+                let count = s.lines().count();
+                let highlight = |i: usize, line: String| {
+                    if i < count && info.is_highlighted(i as u32 + 1) {
+                        format!("<span class = \"highlighted-line\">{line}</span>")
+                    } else {
+                        line
+                    }
                 };
+                let mut output = if let Some(ref syntax) = this.as_ref().syntax {
+                    syntax.to_html(&s, language, &highlight)?
+                } else {
+                    let code: Vec<String> = escape::html(s.as_ref())
+                        .split('\n')
+                        .enumerate()
+                        .map(|(i, line)| highlight(i, line.to_owned()))
+                        .collect();
+                    if language.is_empty() {
+                        format!("<pre><code>{}</code></pre>\n", code.join("\n"))
+                    } else {
+                        format!(
+                            "<pre><code class = \"language-{}\">{}</code></pre>\n",
+                            html_escape::encode_double_quoted_attribute(language),
+                            code.join("\n")
+                        )
+                    }
+                };
+                let first = info.first_line(this.as_ref().code_line);
+                this.as_mut().code_line = (first + count as u32).saturating_sub(1);
+                if numbered {
+                    let numbers = labels
+                        .iter()
+                        .enumerate()
+                        .map(|(i, label)| match *label {
+                            Some(ref label) => format!(
+                                "<span id = \"{}\">{}</span>",
+                                html_escape::encode_double_quoted_attribute(label),
+                                first + i as u32
+                            ),
+                            None => format!("{}", first + i as u32),
+                        })
+                        .collect::<Vec<_>>();
+                    output = format!(
+                        "<div class = \"numbered-code\"><pre class = \"line-numbers\" aria-hidden = \"true\">{}</pre>{output}</div>\n",
+                        numbers.join("\n")
+                    );
+                }
+                if info.caption.is_some() || info.file.is_some() {
+                    let mut caption = String::new();
+                    if let Some(ref file) = info.file {
+                        caption = format!("<span class = \"file-name\">{}</span>", escape::html(file.as_str()));
+                    }
+                    if let Some(ref text) = info.caption {
+                        if !caption.is_empty() {
+                            caption.push(' ');
+                        }
+                        caption.push_str(&escape::html(text.as_str()));
+                    }
+                    output = format!(
+                        "<div class = \"code-block\">\n<p class = \"code-caption\">{caption}</p>\n{output}</div>\n"
+                    );
+                }
                 Ok(output)
             }
+            Token::Verse(ref vec) => {
+                let every = this.as_ref().book.options.get_i32("rendering.verse.line_numbers").unwrap();
+                let mut n = 0;
+                let mut stanzas = vec![vec![]];
+                for line in vec {
+                    match *line {
+                        // An empty line separates stanzas
+                        Token::VerseLine(_, ref v) if v.is_empty() => stanzas.push(vec![]),
+                        _ => {
+                            n += 1;
+                            let mut line = this.render_token(line)?;
+                            if every > 0 && n % every == 0 {
+                                line = format!("<span class = \"verse-number\">{n}</span>{line}");
+                            }
+                            stanzas.last_mut().unwrap().push(line);
+                        }
+                    }
+                }
+                let mut res = String::new();
+                for lines in stanzas.iter().filter(|lines| !lines.is_empty()) {
+                    writeln!(res, "<p class = \"verse\">{}</p>", lines.join("<br />\n"))?;
+                }
+                Ok(res)
+            }
+            Token::Anchor(ref label) => Ok(format!(
+                "<span id = \"{}\"></span>",
+                html_escape::encode_double_quoted_attribute(label.as_str())
+            )),
+            Token::VerseLine(indent, ref vec) => {
+                let style = if indent == 0 {
+                    String::new()
+                } else {
+                    format!(" style = \"margin-left: {}em\"", indent as f32 / 2.0)
+                };
+                Ok(format!("<span class = \"verse-line\"{style}>{}</span>", this.render_vec(vec)?))
+            }
+            Token::Letter(ref vec) => Ok(format!(
+                "<div class = \"letter\">\n{}</div>\n",
+                this.render_vec(vec)?
+            )),
+            Token::LetterField(ref field, ref vec) => Ok(format!(
+                "<p class = \"letter-{field}\">{}</p>\n",
+                this.render_vec(vec)?
+            )),
+            Token::Play(ref vec) => Ok(format!(
+                "<div class = \"play\">\n{}</div>\n",
+                this.render_vec(vec)?
+            )),
+            Token::Speech(ref speaker, ref vec) => Ok(format!(
+                "<p class = \"speech\"><span class = \"speaker\">{}</span> {}</p>\n",
+                escape::html(speaker.as_str()),
+                this.render_vec(vec)?
+            )),
+            Token::StageDirection(ref vec) => Ok(format!(
+                "<p class = \"stage-direction\">{}</p>\n",
+                this.render_vec(vec)?
+            )),
             Token::Rule => Ok(String::from("<p class = \"rule\">***</p>\n")),
             Token::SoftBreak => Ok(String::from(" ")),
             Token::HardBreak => Ok(String::from("<br />\n")),
@@ -588,30 +907,72 @@ impl<'a> HtmlRenderer<'a> {
             Token::DescriptionTerm(ref v) => Ok(format!("<dt>{}</dt>\n", this.render_vec(v)?)),
             Token::DescriptionDetails(ref v) => Ok(format!("<dd>{}</dd>\n", this.render_vec(v)?)),
             Token::Link(ref url, ref title, ref vec) => {
+                if this.as_ref().sanitize && !misc::is_safe_url(url, false) {
+                    return this.render_vec(vec);
+                }
+                let url_text = (this.as_ref().link_style == LinkStyle::Inline
+                    && !url.starts_with('#')
+                    && !ResourceHandler::is_local(url))
+                .then(|| html_escape::encode_text(url.as_str()));
                 let url = html_escape::encode_double_quoted_attribute(url.as_str());
-                let url = if ResourceHandler::is_local(&url) {
-                    Cow::Owned(this.as_ref().handler.get_link(&url).to_owned())
+                // Links to anchors of the current page are kept as is
+                let url = if url.starts_with('#') && !this.as_ref().handler.contains_link(&url) {
+                    url
+                } else if ResourceHandler::is_local(&url) {
+                    let html = this.as_ref();
+                    Cow::Owned(html.handler.map_link(&html.source, &url)?.to_owned())
                 } else {
                     url
                 };
 
-                Ok(format!(
-                    "<a href = \"{url}\"{}>{}</a>",
+                let content = this.render_vec(vec)?;
+                let mut link = format!(
+                    "<a href = \"{url}\"{}>{content}</a>",
                     if title.is_empty() {
                         String::new()
                     } else {
-                        format!(" title = \"{title}\"")
+                        format!(
+                            " title = \"{}\"",
+                            html_escape::encode_double_quoted_attribute(title.as_str())
+                        )
                     },
-                    this.render_vec(vec)?
-                ))
+                );
+                match url_text {
+                    Some(text) if content != text => {
+                        write!(link, " (<span class = \"link-url\">{text}</span>)")?
+                    }
+                    _ => (),
+                }
+                Ok(link)
             }
             Token::Image(ref url, ref title, ref alt)
             | Token::StandaloneImage(ref url, ref title, ref alt) => {
-                let content = this.render_vec(alt)?;
+                let content = html_escape::encode_double_quoted_attribute(&text_view::view_as_text(alt))
+                    .into_owned();
+                let title = html_escape::encode_double_quoted_attribute(title.as_str());
                 let html: &mut HtmlRenderer = this.as_mut();
+                if html.sanitize && !misc::is_safe_url(url, true) {
+                    return Ok(content);
+                }
+                let media = misc::media_type(url);
                 let url = html.handler.map_image(&html.source, url.as_str())?;
+                let url = html_escape::encode_double_quoted_attribute(url.as_ref());
+                let title = match html.described_by.take() {
+                    Some(id) => format!("{title}\" aria-describedby = \"{id}"),
+                    None => title.into_owned(),
+                };
 
-                if token.is_image() {
+                if let Some(media) = media {
+                    // The alternative text is displayed if the browser can't play it
+                    let tag = format!(
+                        "<{media} controls = \"controls\" src = \"{url}\" title = \"{title}\">{content}</{media}>",
+                    );
+                    if token.is_image() {
+                        Ok(tag)
+                    } else {
+                        Ok(format!("<div class = \"{media}\">\n  {tag}\n</div>"))
+                    }
+                } else if token.is_image() {
                     Ok(format!(
                         "<img src = \"{url}\" title = \"{title}\" alt = \"{content}\" />",
                     ))
@@ -624,6 +985,22 @@ impl<'a> HtmlRenderer<'a> {
                     ))
                 }
             }
+            Token::DescribedImage(ref vec) => {
+                let html: &mut HtmlRenderer = this.as_mut();
+                html.description_number += 1;
+                let id = format!("description-{}", html.description_number);
+                html.described_by = Some(id.clone());
+                let summary = lang::get_str(html.book.options.get_str("lang").unwrap(), "description");
+                let image = this.render_token(&vec[0])?;
+                this.as_mut().described_by = None;
+                Ok(format!(
+                    "{image}
+<details class = \"description\" id = \"{id}\">
+  <summary>{summary}</summary>
+{}</details>\n",
+                    this.render_vec(&vec[1..])?
+                ))
+            }
             Token::Table(_, ref vec) => Ok(format!(
                 "<div class = \"table\">
     <table>\n{}
@@ -645,24 +1022,76 @@ impl<'a> HtmlRenderer<'a> {
             Token::FootnoteReference(ref reference) => {
                 // Ensure links are not ambiguous when the same reference is used multiple times
                 // and only one file is generated 
-                let hash = this.as_ref().footnote_prefix;
-                Ok(format!(
-                    "<a class = \"footnote_reference\" href = \"#note-dest-{hash}-{reference}\" id = \
-                     \"note-source-{hash}-{reference}\"><sup>[{reference}]</sup></a>",
-                ))
+                let html = this.as_mut();
+                let hash = html.footnote_prefix;
+                let number = html_escape::encode_text(&html.note_numbering.format(reference)).into_owned();
+                let side_note = html
+                    .side_notes
+                    .as_mut()
+                    .and_then(|side_notes| side_notes.take(reference));
+                let count = html.note_references.entry(reference.clone()).or_insert(0);
+                *count += 1;
+                // Each reference has its own id, so the note can link back to all of them
+                let reference = html_escape::encode_double_quoted_attribute(reference.as_str());
+                let id = if *count == 1 {
+                    format!("note-source-{hash}-{reference}")
+                } else {
+                    format!("note-source-{hash}-{reference}-{count}")
+                };
+                let mut res = format!(
+                    "<a class = \"footnote_reference\"{} href = \"#note-dest-{hash}-{reference}\" \
+                     id = \"{id}\"><sup>[{number}]</sup></a>",
+                    if html.epub_version == Some(3) { " epub:type = \"noteref\"" } else { "" },
+                );
+                if let Some(content) = side_note {
+                    // The note is inside a paragraph, so its own paragraphs are rendered inline
+                    let mut paragraphs = vec![];
+                    for token in &content {
+                        paragraphs.push(match *token {
+                            Token::Paragraph(ref inner) => this.render_vec(inner)?,
+                            _ => this.render_token(token)?,
+                        });
+                    }
+                    write!(
+                        res,
+                        "<span class = \"sidenote\"><span class = \"note-number\">{number}</span> {}</span>",
+                        paragraphs.join("<br />")
+                    )?;
+                }
+                Ok(res)
             },
             Token::FootnoteDefinition(ref reference, ref vec) => {
-                let hash = this.as_ref().footnote_prefix;
+                let html = this.as_ref();
+                let hash = html.footnote_prefix;
+                let number = html_escape::encode_text(&html.note_numbering.format(reference)).into_owned();
+                let count = html.note_references.get(reference).copied().unwrap_or(1);
+                let epub_version = html.epub_version;
+                let reference = html_escape::encode_double_quoted_attribute(reference.as_str());
+                // The number links back to the first reference, and arrows to the other ones
+                let mut backlinks = String::new();
+                for i in 2..=count {
+                    write!(
+                        backlinks,
+                        " <a class = \"backlink\" href = \"#note-source-{hash}-{reference}-{i}\">↩</a>"
+                    )?;
+                }
                 let note_number = format!(
                     "<p class = \"note-number\">
-  <a rel = \"footnote\" href = \"#note-source-{hash}-{reference}\">[{reference}]</a>
+  <a rel = \"footnote\" href = \"#note-source-{hash}-{reference}\">[{number}]</a>{backlinks}
 </p>\n",
                 );
 
-                let inner = format!(
-                    "<aside class = \"footnote\" id = \"note-dest-{hash}-{reference}\">{}</aside>",
-                    this.render_vec(vec)?
-                );
+                let content = this.render_vec(vec)?;
+                let inner = match epub_version {
+                    Some(2) => format!("<a id = \"note-dest-{hash}-{reference}\" />{content}"),
+                    Some(3) => format!(
+                        "<aside class = \"footnote\" epub:type = \"footnote\" \
+                         id = \"note-dest-{hash}-{reference}\">{content}</aside>"
+                    ),
+                    _ => format!(
+                        "<aside class = \"footnote\" id = \"note-dest-{hash}-{reference}\">{content}</aside>"
+                    ),
+                };
                 this.as_mut().footnotes.push((note_number, inner));
                 Ok(String::new())
             }
@@ -691,27 +1120,233 @@ impl<'a> HtmlRenderer<'a> {
             .get_str("rendering.inline_toc.name")
             .unwrap();
         let template = self.book.compile_str(template, &self.book.source, "rendering.inline_toc.name")?;
+        self.book.template_vars.record("rendering.inline_toc.name", &data);
         Ok(template.render(&data).to_string()?)
     }
 
+    /// Returns a metadata option as raw text, if it is set
+    fn get_raw_metadata(&self, key: &str) -> Result<Option<String>> {
+        match self.book.options.get_str(key) {
+            Ok(s) if !s.is_empty() => Ok(Some(text_view::view_as_text(
+                &Parser::from(self.book).parse_inline(s)?,
+            ))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the absolute URL of a page, if `html.base_url` is set
+    ///
+    /// `page` is the path of the page relatively to the base URL (or an empty string
+    /// for the base URL itself). If the base URL points to a file (e.g. a standalone
+    /// HTML document), the path is relative to the directory containing it.
+    #[doc(hidden)]
+    pub fn get_absolute_url(&self, page: &str) -> Option<String> {
+        let base_url = self.book.options.get_str("html.base_url").ok()?;
+        if page.is_empty() {
+            return Some(base_url.to_owned());
+        }
+        let (dir, last) = base_url.rsplit_once('/').unwrap_or((base_url, ""));
+        if last.contains('.') && dir.contains("//") && !dir.ends_with('/') {
+            Some(format!("{dir}/{page}"))
+        } else {
+            Some(format!("{}/{page}", base_url.trim_end_matches('/')))
+        }
+    }
+
+    /// Returns a `:root` rule overriding the theme variables of the default stylesheet
+    /// with the values of the `{format}.css.{accent,font,measure,line_height}` options,
+    /// followed by the widows and orphans of paragraphs, or an empty string if none is set
+    #[doc(hidden)]
+    pub fn get_css_theme(&self, format: &str) -> String {
+        let variables = [
+            ("accent", "--accent-color"),
+            ("font", "--font-body"),
+            ("measure", "--measure"),
+            ("line_height", "--line-height"),
+        ];
+        let mut rule = String::new();
+        for (option, variable) in variables {
+            if let Ok(value) = self.book.options.get_str(&format!("{format}.css.{option}")) {
+                writeln!(rule, "    {variable}: {value};").unwrap();
+            }
+        }
+        let mut css = if rule.is_empty() {
+            rule
+        } else {
+            format!(":root {{\n{rule}}}\n")
+        };
+        // Widows and orphans only apply to paged media (print and e-readers)
+        let mut paragraphs = String::new();
+        for property in ["widows", "orphans"] {
+            if let Ok(lines) = self.book.options.get_i32(&format!("rendering.{property}")) {
+                writeln!(paragraphs, "    {property}: {lines};").unwrap();
+            }
+        }
+        if !paragraphs.is_empty() {
+            write!(css, "p {{\n{paragraphs}}}\n").unwrap();
+        }
+        css
+    }
+
+    /// Returns the code added by an option such as `html.css.add` or `html.js.add`
+    ///
+    /// Items ending with `extension` are paths of files (relative to
+    /// `resources.base_path.templates`) whose content is added; other items are added as is.
+    #[doc(hidden)]
+    pub fn get_additional_code(&self, key: &str, extension: &str) -> Result<String> {
+        let items = match self.book.options.get_str_vec(key) {
+            Ok(items) => items,
+            Err(_) => return Ok(String::new()),
+        };
+        let mut code = Vec::with_capacity(items.len());
+        for item in items {
+            let item = item.trim();
+            if item.contains('\n') || !item.ends_with(extension) {
+                code.push(item.to_owned());
+                continue;
+            }
+            if self.book.options.is_sandboxed() && !misc::is_contained(item) {
+                return Err(Error::book_option(
+                    &self.book.options.source,
+                    t!("opt.sandbox_path", key = key, value = item),
+                ));
+            }
+            let base = self.book.options.get_path("resources.base_path.templates")?;
+            let path = Path::new(&base).join(item);
+            let content = fs::read_to_string(&path).map_err(|err| {
+                Error::file_not_found(
+                    &self.book.source,
+                    t!("format.additional_code"),
+                    misc::normalize(&path),
+                )
+                .with_cause(err)
+            })?;
+            code.push(content);
+        }
+        Ok(code.join("\n"))
+    }
+
+    /// Returns the absolute URL of the cover image, if both `cover` and `html.base_url` are set
+    fn get_cover_url(&mut self) -> Result<Option<String>> {
+        let cover = match self.book.options.get_path("cover") {
+            Ok(cover) => cover,
+            Err(_) => return Ok(None),
+        };
+        if self.book.options.get_str("html.base_url").is_err() {
+            return Ok(None);
+        }
+        let path = if self.handler.is_base64() {
+            // The image is embedded in the document, so it must be published
+            // at the same place relatively to it
+            self.book.options.get_relative_path("cover")?.to_owned()
+        } else {
+            self.handler
+                .map_image(&self.book.source, Cow::Owned(cover))?
+                .into_owned()
+        };
+        Ok(self.get_absolute_url(&path))
+    }
+
     /// Render a section containing schema.org JSON-LD code
     pub fn get_json_ld(&mut self) -> Result<String> {
-        let json = r#"
-<script type = 'application/ld+json'>
-{
-    "@context": "http://schema.org/",
-    "@type": "Book",
-    "author": "{{author}}",
-    "name": "{{title}}",
-    {% if has_version %}"version": "{{version}}",{% endif %}
-    {% if has_subtitle %}"alternateName": "{{subtitle}}",{% endif %}
-    {% if has_subject %}"keywords": "{{subject}}",{% endif %}
-    {% if has_license %}"license": "{{license}}",{% endif %}
-    {% if has_description %}"about": "{{description}}",{% endif %}
-    "inLanguage": "{{lang}}"
-}
-</script>"#;
-        self.templatize(json)
+        let mut json = serde_json::Map::new();
+        json.insert("@context".into(), "http://schema.org/".into());
+        json.insert("@type".into(), "Book".into());
+        for (key, property) in &[
+            ("author", "author"),
+            ("title", "name"),
+            ("version", "version"),
+            ("subtitle", "alternateName"),
+            ("subject", "keywords"),
+            ("license", "license"),
+            ("description", "about"),
+            ("publication_date", "datePublished"),
+            ("date", "dateModified"),
+        ] {
+            if let Some(value) = self.get_raw_metadata(key)? {
+                json.insert((*property).into(), value.into());
+            }
+        }
+        json.insert(
+            "inLanguage".into(),
+            self.book.options.get_str("lang").unwrap().into(),
+        );
+        if let Some(url) = self.get_absolute_url("") {
+            json.insert("url".into(), url.into());
+        }
+        if let Some(image) = self.get_cover_url()? {
+            json.insert("image".into(), image.into());
+        }
+        // Make sure the content can't close the script element
+        let json = serde_json::Value::Object(json).to_string().replace("</", "<\\/");
+        Ok(format!(
+            "<script type = 'application/ld+json'>\n{json}\n</script>"
+        ))
+    }
+
+    /// Returns the `<meta>` and `<link>` tags describing the book for search engines and
+    /// social networks: OpenGraph, Twitter card and canonical URL.
+    ///
+    /// `page` is the path of the current page, relatively to `html.base_url`.
+    pub fn get_meta_tags(&mut self, page: &str) -> Result<String> {
+        let mut tags = vec![];
+        let title = self.get_raw_metadata("title")?.unwrap_or_default();
+        let description = self.get_raw_metadata("description")?;
+        let image = self.get_cover_url()?;
+        let url = self.get_absolute_url(page);
+
+        let mut meta = |attribute: &str, name: &str, content: &str| {
+            tags.push(format!(
+                "<meta {attribute} = \"{name}\" content = \"{}\">",
+                html_escape::encode_double_quoted_attribute(content)
+            ));
+        };
+        if let Some(ref description) = description {
+            meta("name", "description", description);
+        }
+        let keywords = subject::keywords(&self.book.options);
+        if !keywords.is_empty() {
+            meta("name", "keywords", &keywords.join(", "));
+        }
+        meta("property", "og:type", "book");
+        meta("property", "og:title", &title);
+        if let Some(ref description) = description {
+            meta("property", "og:description", description);
+        }
+        if let Some(ref url) = url {
+            meta("property", "og:url", url);
+        }
+        if let Some(ref image) = image {
+            meta("property", "og:image", image);
+        }
+        meta(
+            "property",
+            "og:locale",
+            &self.book.options.get_str("lang").unwrap().replace('-', "_"),
+        );
+        meta(
+            "name",
+            "twitter:card",
+            if image.is_some() {
+                "summary_large_image"
+            } else {
+                "summary"
+            },
+        );
+        meta("name", "twitter:title", &title);
+        if let Some(ref description) = description {
+            meta("name", "twitter:description", description);
+        }
+        if let Some(ref image) = image {
+            meta("name", "twitter:image", image);
+        }
+        if let Some(ref url) = url {
+            tags.push(format!(
+                "<link rel = \"canonical\" href = \"{}\">",
+                html_escape::encode_double_quoted_attribute(url)
+            ));
+        }
+        Ok(tags.join("\n    "))
     }
 
     /// Get metadata useful for many HTML templates.
@@ -724,7 +1359,12 @@ impl<'a> HtmlRenderer<'a> {
 
         // Add stuff used for HTML templates
         data.insert("json_data".into(), self.get_json_ld()?.into());
-        data.insert("script".into(), self.book.get_template("html.js").unwrap().into());
+        data.insert("meta_tags".into(), self.get_meta_tags("")?.into());
+        data.insert("script".into(), self.book.get_template("html.js")?.into());
+        data.insert(
+            "additional_script".into(),
+            self.get_additional_code("html.js.add", ".js")?.into(),
+        );
         data.insert("highlight_code".into(), (self.highlight == Highlight::Js).into());
         data.insert("footer".into(), HtmlRenderer::get_footer(self)?.into());
         data.insert("header".into(), HtmlRenderer::get_header(self)?.into());
@@ -858,3 +1498,39 @@ macro_rules! derive_html {
 
     );
 }
+
+/// Escapes the plain text values of template data (`title_raw`, `chapter_title_raw`...), so
+/// templates can insert them in attributes or in `<title>`
+pub(crate) fn escape_raw(data: &mut BTreeMap<String, upon::Value>) {
+    for (key, value) in data.iter_mut() {
+        if let upon::Value::String(ref mut s) = *value {
+            if key.ends_with("_raw") {
+                *s = html_escape::encode_quoted_attribute(s.as_str()).into_owned();
+            }
+        }
+    }
+}
+
+/// Converts an element of the table of contents to JSON
+fn toc_element_to_json(element: &TocElement) -> Value {
+    json!({
+        "level": element.level,
+        "url": element.url,
+        "title": element.title,
+        "raw_title": element.raw_title,
+        "children": element.children.iter().map(toc_element_to_json).collect::<Vec<_>>(),
+    })
+}
+
+/// Converts JSON returned by `toc_element_to_json` back to an element of the table of contents
+fn toc_element_from_json(json: &Value) -> Option<TocElement> {
+    let mut element = TocElement::new(json["url"].as_str()?, json["title"].as_str()?)
+        .level(json["level"].as_i64()? as i32);
+    element.raw_title = json["raw_title"].as_str().map(|s| s.to_owned());
+    element.children = json["children"]
+        .as_array()?
+        .iter()
+        .map(toc_element_from_json)
+        .collect::<Option<_>>()?;
+    Some(element)
+}
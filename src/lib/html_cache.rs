@@ -0,0 +1,106 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cache of the chapters rendered by the multiple files HTML renderer, so
+//! chapters that didn't change aren't rendered again.
+
+use crate::book::Book;
+
+use serde_json::{json, Map, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use rust_i18n::t;
+
+/// Cache of rendered chapters, stored in `crowbook.temp_dir`
+///
+/// Each entry is keyed on a hash of everything the rendering of a chapter depends on
+/// (its content, the book's options and the state of the renderer before it), and contains
+/// the rendered HTML along with the state of the renderer after it.
+pub struct ChapterCache {
+    path: PathBuf,
+    fingerprint: String,
+    old: Map<String, Value>,
+    new: Map<String, Value>,
+}
+
+impl ChapterCache {
+    /// Loads the cache for a given output directory, or creates an empty one
+    pub fn load(book: &Book, output_dir: &Path) -> ChapterCache {
+        let mut hasher = DefaultHasher::new();
+        fs::canonicalize(output_dir)
+            .unwrap_or_else(|_| output_dir.to_owned())
+            .hash(&mut hasher);
+        let path = Path::new(&book.options.get_path("crowbook.temp_dir").unwrap())
+            .join(format!("crowbook-cache-{:016x}.json", hasher.finish()));
+        let old = fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        ChapterCache {
+            path,
+            fingerprint: format!(
+                "{}\n{:?}\n{}",
+                env!("CARGO_PKG_VERSION"),
+                book.features,
+                book.options.fingerprint()
+            ),
+            old,
+            new: Map::new(),
+        }
+    }
+
+    /// Computes the key of a chapter from the things its rendering depends on
+    pub fn key(&self, parts: &[&str]) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.fingerprint.hash(&mut hasher);
+        parts.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Returns the rendered chapter and the state of the renderer after it, if it is in the cache
+    pub fn get(&mut self, key: &str) -> Option<(String, Value)> {
+        let entry = self.old.remove(key)?;
+        let content = entry["content"].as_str()?.to_owned();
+        let state = entry["state"].clone();
+        self.new.insert(key.to_owned(), entry);
+        Some((content, state))
+    }
+
+    /// Adds a rendered chapter to the cache
+    pub fn insert(&mut self, key: String, content: &str, state: Value) {
+        self.new
+            .insert(key, json!({"content": content, "state": state}));
+    }
+
+    /// Saves the cache, dropping the entries that weren't used by this rendering
+    pub fn save(self) {
+        let content = Value::Object(self.new).to_string();
+        // Write to another file first, so concurrent builds never read a partially written cache
+        let temp = self
+            .path
+            .with_extension(format!("{}.tmp", uuid::Uuid::new_v4().as_simple()));
+        if let Err(err) = fs::write(&temp, content).and_then(|_| fs::rename(&temp, &self.path)) {
+            let _ = fs::remove_file(&temp);
+            warn!(
+                "{}",
+                t!("html.cache_error", file = self.path.display(), error = err)
+            );
+        }
+    }
+}
@@ -17,17 +17,24 @@
 
 use crate::book::Book;
 use crate::book_renderer::BookRenderer;
+use crate::date::Date;
 use crate::error::{Error, Result, Source};
 use crate::html::Highlight;
-use crate::html::HtmlRenderer;
-use crate::parser::Parser;
+use crate::html::{self, HtmlRenderer};
+use crate::html_cache::ChapterCache;
+use crate::html_feed::{self, FeedEntry};
+use crate::html_sitemap::{self, SitemapEntry};
+use crate::parser::{self, Parser};
 use crate::renderer::Renderer;
 use crate::resource_handler;
-use crate::templates::img;
+use crate::search_index::SearchIndex;
+use crate::slug::{Naming, Slugifier};
+use crate::templates::{html_dir, img};
 use crate::text_view::view_as_text;
 use crate::token::Token;
 
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::convert::{AsMut, AsRef};
 use std::fmt::Write;
 use std::fs;
@@ -43,6 +50,11 @@ use rust_i18n::t;
 /// Renders HTML in a given directory.
 pub struct HtmlDirRenderer<'a> {
     html: HtmlRenderer<'a>,
+    dry_run: bool,
+    /// Directory the files are written to
+    dest_path: PathBuf,
+    /// Names of the files of the chapters
+    filenames: Vec<String>,
 }
 
 impl<'a> HtmlDirRenderer<'a> {
@@ -56,19 +68,43 @@ impl<'a> HtmlDirRenderer<'a> {
         )?;
         html.handler.set_images_mapping(true);
         html.handler.set_base64(false);
-        Ok(HtmlDirRenderer { html })
+        Ok(HtmlDirRenderer {
+            html,
+            dry_run: false,
+            dest_path: PathBuf::new(),
+            filenames: filenames(book),
+        })
+    }
+
+    /// Renders the book without writing any file
+    pub fn dry_run(mut self) -> HtmlDirRenderer<'a> {
+        self.dry_run = true;
+        self
     }
 
     /// Render a book
     pub fn render_book(&mut self, dest_path: &Path) -> Result<()> {
+        self.dest_path = dest_path.to_owned();
+        // Links to additional files point to their copies
+        self.html.handler.add_resources_links(self.html.book)?;
+
         // Add internal files to resource handler
+        let code_labels = self.html.book.options.get_bool("rendering.code.line_numbers").unwrap();
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
             self.html
                 .handler
-                .add_link(chapter.filename.as_str(), filenamer(i));
+                .add_link(chapter.filename.as_str(), self.filenames[i].clone());
+            // Links to anchors point to the file of their chapter
+            for label in parser::labels(&chapter.content, code_labels) {
+                self.html
+                    .handler
+                    .add_link(format!("#{label}"), format!("{}#{label}", self.filenames[i]));
+            }
         }
 
-        if let Ok(metadata) = fs::metadata(dest_path) {
+        if self.dry_run {
+            // Don't touch the destination directory
+        } else if let Ok(metadata) = fs::metadata(dest_path) {
             if metadata.is_file() {
                 return Err(Error::render(
                     &self.html.book.source,
@@ -95,18 +131,20 @@ impl<'a> HtmlDirRenderer<'a> {
             }
         }
 
-        fs::DirBuilder::new()
-            .recursive(true)
-            .create(dest_path)
-            .map_err(|e| {
-                Error::render(
-                    &self.html.book.source,
-                    t!("html.create_dir_error",
-                       path = dest_path.display(),
-                       error = e
-                    ),
-                )
-            })?;
+        if !self.dry_run {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .create(dest_path)
+                .map_err(|e| {
+                    Error::render(
+                        &self.html.book.source,
+                        t!("html.create_dir_error",
+                           path = dest_path.display(),
+                           error = e
+                        ),
+                    )
+                })?;
+        }
 
         // Write CSS
         self.write_css()?;
@@ -115,14 +153,17 @@ impl<'a> HtmlDirRenderer<'a> {
             "print.css",
             self.html
                 .book
-                .get_template("html.css.print")
-                .unwrap()
+                .get_template("html.css.print")?
                 .as_bytes(),
         )?;
         // Write index.html and chapter_xxx.html
         self.write_html()?;
         // Write menu.svg
         self.write_file("menu.svg", img::MENU_SVG)?;
+        // Write search script
+        if self.html.book.options.get_bool("html.dir.search").unwrap() {
+            self.write_file("search.js", html_dir::SEARCH_JS.as_bytes())?;
+        }
 
         // Write highlight files if they are needed
         if self.html.highlight == Highlight::Js {
@@ -130,16 +171,14 @@ impl<'a> HtmlDirRenderer<'a> {
                 "highlight.js",
                 self.html
                     .book
-                    .get_template("html.highlight.js")
-                    .unwrap()
+                    .get_template("html.highlight.js")?
                     .as_bytes(),
             )?;
             self.write_file(
                 "highlight.css",
                 self.html
                     .book
-                    .get_template("html.highlight.css")
-                    .unwrap()
+                    .get_template("html.highlight.css")?
                     .as_bytes(),
             )?;
         }
@@ -200,7 +239,7 @@ impl<'a> HtmlDirRenderer<'a> {
                         t!("html.resource_error", error = e),
                     )
                 })?;
-                self.write_file(data_path.join(&path).to_str().unwrap(), &content)?;
+                self.write_file(&data_path.join(&path).to_string_lossy(), &content)?;
             }
         }
 
@@ -213,16 +252,28 @@ impl<'a> HtmlDirRenderer<'a> {
 
         let mut titles = vec![];
         let mut titles_raw = vec![];
+        let search = self.html.book.options.get_bool("html.dir.search").unwrap();
+        let mut search_index = SearchIndex::new();
+        let mut cache = if self.html.book.options.get_bool("html.dir.cache").unwrap() {
+            // The destination may be given on the command line instead of `output.html.dir`
+            Some(ChapterCache::load(self.html.book, &self.dest_path))
+        } else {
+            None
+        };
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+            self.html.book.check_cancelled()?;
             let n = chapter.number;
             let v = &chapter.content;
-            self.html.chapter_config(i, n, filenamer(i));
+            self.html.chapter_config(i, n, self.filenames[i].clone());
             let mut title = String::new();
             let mut title_raw = String::new();
             for token in v {
                 match *token {
                     Token::Header(1, ref vec) => {
-                        if self.html.current_hide || self.html.current_numbering == 0 {
+                        if self.html.current_hide
+                            || self.html.current_numbering == 0
+                            || token.has_class("unnumbered")
+                        {
                             title = self.html.render_vec(vec)?;
                             title_raw = view_as_text(vec);
                         } else {
@@ -252,15 +303,56 @@ impl<'a> HtmlDirRenderer<'a> {
                     }
                 }
             }
+            if search {
+                search_index.add(&self.filenames[i], &title_raw, v);
+            }
             titles.push(title);
             titles_raw.push(title_raw);
 
-            let chapter = HtmlRenderer::render_html(self, v, true);
+            let chapter = match cache {
+                Some(ref mut cache) => {
+                    let key = cache.key(&[
+                        &i.to_string(),
+                        &chapter.filename,
+                        &format!("{n:?}"),
+                        &format!("{v:?}"),
+                        &self.html.get_state().to_string(),
+                    ]);
+                    match cache.get(&key) {
+                        Some((content, ref state)) if self.html.set_state(state).is_some() => {
+                            content
+                        }
+                        _ => {
+                            let content = HtmlRenderer::render_html(self, v, true)?;
+                            cache.insert(key, &content, self.html.get_state());
+                            content
+                        }
+                    }
+                }
+                None => HtmlRenderer::render_html(self, v, true)?,
+            };
             chapters.push(chapter);
         }
+        match cache {
+            Some(cache) if !self.dry_run => cache.save(),
+            _ => {}
+        }
         self.html.source = Source::empty();
         let toc = self.html.toc.render(false, false);
 
+        if search {
+            let index = format!("var crowbookSearchIndex = {};\n", search_index.to_json());
+            self.write_file("search_index.js", index.as_bytes())?;
+        }
+
+        let feed = self.html.book.options.get_bool("html.feed").unwrap();
+        if feed {
+            self.write_feed(&titles_raw, &chapters)?;
+        }
+        if self.html.book.options.get_bool("html.sitemap").unwrap() {
+            self.write_sitemap()?;
+        }
+
         // render all chapters
         let template_src = self.html.book.get_template("html.dir.template")?;
         let template = self.html.book.compile_str(
@@ -268,6 +360,14 @@ impl<'a> HtmlDirRenderer<'a> {
             &self.html.book.source,
             "html.dir.template",
         )?;
+        let footer_snippet = match self.html.book.options.get_str("html.chapter_footer_snippet") {
+            Ok(snippet) => Some(self.html.book.compile_str(
+                snippet,
+                &self.html.book.source,
+                "html.chapter_footer_snippet",
+            )?),
+            Err(_) => None,
+        };
         for (i, content) in chapters.into_iter().enumerate() {
             let prev_chapter = if i > 0 {
                 format!(
@@ -276,7 +376,7 @@ impl<'a> HtmlDirRenderer<'a> {
     « {}
   </a>
 </p>",
-                    filenamer(i - 1),
+                    self.filenames[i - 1],
                     titles[i - 1]
                 )
             } else {
@@ -290,7 +390,7 @@ impl<'a> HtmlDirRenderer<'a> {
     {} »
   </a>
 </p>",
-                    filenamer(i + 1),
+                    self.filenames[i + 1],
                     titles[i + 1]
                 )
             } else {
@@ -301,13 +401,45 @@ impl<'a> HtmlDirRenderer<'a> {
             let mut data = self
                 .html
                 .get_metadata()?;
-            data.insert("content".into(), content?.into());
+            data.insert("content".into(), content.into());
             data.insert("chapter_title".into(), titles[i].clone().into());
             data.insert("chapter_title_raw".into(), titles_raw[i].clone().into());
+            data.insert("meta_tags".into(), self.html.get_meta_tags(&self.filenames[i])?.into());
+            let chapter = &self.html.book.chapters[i];
+            self.html.book.add_chapter_metadata(chapter, &mut data, |s| {
+                self.render_vec(&Parser::new().parse_inline(s)?)
+            })?;
+            if let Some(ref snippet) = footer_snippet {
+                let mut snippet_data = self.html.book.get_metadata(|s| Ok(s.to_owned()))?;
+                snippet_data.insert("chapter_title".into(), titles[i].clone().into());
+                snippet_data.insert("chapter_title_raw".into(), titles_raw[i].clone().into());
+                snippet_data.insert("chapter_number".into(), (i as i64 + 1).into());
+                self.html.book.add_chapter_metadata(chapter, &mut snippet_data, |s| {
+                    self.render_vec(&Parser::new().parse_inline(s)?)
+                })?;
+                snippet_data.insert(
+                    "chapter_url".into(),
+                    self.html
+                        .get_absolute_url(&self.filenames[i])
+                        .unwrap_or_else(|| self.filenames[i].clone())
+                        .into(),
+                );
+                html::escape_raw(&mut snippet_data);
+                self.html.book.template_vars.record("html.chapter_footer_snippet", &snippet_data);
+                let footer = snippet.render(&snippet_data).to_string()?;
+                data.insert(
+                    "chapter_footer".into(),
+                    format!("<div class = \"chapter-footer\">\n{footer}\n</div>").into(),
+                );
+            } else {
+                data.insert("chapter_footer".into(), "".into());
+            }
             data.insert("toc".into(), toc.clone().into());
             data.insert("prev_chapter".into(), prev_chapter.into());
             data.insert("next_chapter".into(), next_chapter.into());
             data.insert("is_chapter".into(), true.into());
+            data.insert("search".into(), search.into());
+            data.insert("feed".into(), feed.into());
             
             if let Ok(favicon) = self.html.book.options.get_path("html.icon") {
                 let favicon = self
@@ -323,8 +455,10 @@ impl<'a> HtmlDirRenderer<'a> {
             }
 
 
+            html::escape_raw(&mut data);
+            self.html.book.template_vars.record("html.dir.template", &data);
             let res = template.render(&data).to_string()?;
-            self.write_file(&filenamer(i), res.as_bytes())?;
+            self.write_file(&self.filenames[i], res.as_bytes())?;
         }
 
         let mut content = if let Ok(cover) = self.html.book.options.get_path("cover") {
@@ -399,7 +533,7 @@ impl<'a> HtmlDirRenderer<'a> {
     {} »
   </a>
 </p>",
-                filenamer(0),
+                self.filenames[0],
                 titles[0]
             )?;
         }
@@ -410,6 +544,8 @@ impl<'a> HtmlDirRenderer<'a> {
         data.insert("content".into(), content.into());
         data.insert("toc".into(), toc.into());
         data.insert("is_chapter".into(), false.into());
+        data.insert("search".into(), search.into());
+        data.insert("feed".into(), feed.into());
         if let Ok(favicon) = self.html.book.options.get_path("html.icon") {
             let favicon = self
                 .html
@@ -419,6 +555,8 @@ impl<'a> HtmlDirRenderer<'a> {
                 "favicon".into(),
                 format!("<link rel = \"icon\" href = \"{favicon}\">").into(),
             );
+        } else {
+            data.insert("favicon".into(), "".into());
         }
         let template_src = self.html.book.get_template("html.dir.template")?;
         let template = self.html.book.compile_str(
@@ -426,12 +564,75 @@ impl<'a> HtmlDirRenderer<'a> {
             &self.html.book.source,
             "html.dir.template",
         )?;
+        html::escape_raw(&mut data);
+        self.html.book.template_vars.record("html.dir.template", &data);
         let res = template.render(&data).to_string()?;
         self.write_file("index.html", res.as_bytes())?;
 
         Ok(())
     }
 
+    // Render the Atom feed of published chapters and write it
+    fn write_feed(&self, titles: &[String], chapters: &[String]) -> Result<()> {
+        let url = self.html.get_absolute_url("").ok_or_else(|| {
+            Error::render(&self.html.book.source, t!("html.feed_base_url"))
+        })?;
+        let book_date = self.html.book.options.get_str("date").ok();
+        let book_date = book_date.filter(|date| Date::parse(date).is_some());
+        let mut entries = vec![];
+        for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+            // Chapters that don't have a date are not published yet
+            if let Some(date) = chapter.date.as_deref().or(book_date) {
+                entries.push(FeedEntry {
+                    url: self.html.get_absolute_url(&self.filenames[i]).unwrap(),
+                    title: titles[i].clone(),
+                    date: date.to_owned(),
+                    summary: match chapter.synopsis {
+                        Some(ref synopsis) => Some(view_as_text(
+                            &Parser::from(self.html.book).parse_inline(synopsis)?,
+                        )),
+                        None => None,
+                    },
+                    content: chapters[i].clone(),
+                });
+            }
+        }
+        let feed = html_feed::atom_feed(
+            &view_as_text(&Parser::from(self.html.book).parse_inline(
+                self.html.book.options.get_str("title").unwrap(),
+            )?),
+            &view_as_text(&Parser::from(self.html.book).parse_inline(
+                self.html.book.options.get_str("author").unwrap(),
+            )?),
+            &url,
+            &self.html.get_absolute_url("feed.xml").unwrap(),
+            entries,
+        )?;
+        self.write_file("feed.xml", feed.as_bytes())
+    }
+
+    // Render sitemap.xml and robots.txt and write them, if html.base_url is set
+    fn write_sitemap(&self) -> Result<()> {
+        let url = match self.html.get_absolute_url("") {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+        let book_date = self.html.book.options.get_str("date").ok();
+        let mut entries = vec![SitemapEntry {
+            url,
+            date: book_date.map(str::to_owned),
+        }];
+        for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+            entries.push(SitemapEntry {
+                url: self.html.get_absolute_url(&self.filenames[i]).unwrap(),
+                date: chapter.date.as_deref().or(book_date).map(str::to_owned),
+            });
+        }
+        self.write_file("sitemap.xml", html_sitemap::sitemap(&entries).as_bytes())?;
+        let robots = html_sitemap::robots(&self.html.get_absolute_url("sitemap.xml").unwrap());
+        self.write_file("robots.txt", robots.as_bytes())
+    }
+
     // Render the CSS file and write it
     fn write_css(&self) -> Result<()> {
         // Render the CSS
@@ -443,9 +644,13 @@ impl<'a> HtmlDirRenderer<'a> {
         )?;
         let mut data = self.html.book.get_metadata(|s| Ok(s.to_owned()))?;
         data.insert("colors".into(), self.html.book.get_template("html.css.colors")?.into());
-        let html_css_add = self.html.book.options.get_str("html.css.add").unwrap_or("".into());
-        data.insert("additional_code".into(), html_css_add.into());
+        data.insert(
+            "additional_code".into(),
+            self.html.get_additional_code("html.css.add", ".css")?.into(),
+        );
         
+        data.insert("theme".into(), self.html.get_css_theme("html").into());
+        self.html.book.template_vars.record("html.css", &data);
         let css = template_css.render(&data).to_string()?;
 
         // Write it
@@ -454,21 +659,11 @@ impl<'a> HtmlDirRenderer<'a> {
 
     // Write content to a file
     fn write_file(&self, file: &str, content: &[u8]) -> Result<()> {
-        let dir_name = if self.html.proofread {
-            self.html
-                .book
-                .options
-                .get_path("output.proofread.html.dir")
-                .unwrap()
-        } else {
-            self.html.book.options.get_path("output.html.dir").unwrap()
-        };
-        let dest_path = PathBuf::from(&dir_name);
-        assert!(dest_path.starts_with(dir_name),
-                "multifile HTML renderer is asked to create a file ({dest_path}) outside of its directory, no way!",
-                dest_path = dest_path.display());
-        let dest_file = dest_path.join(file);
-        let dest_dir = dest_file.parent().unwrap();
+        if self.dry_run {
+            return Ok(());
+        }
+        let dest_file = self.dest_path.join(file);
+        let dest_dir = dest_file.parent().unwrap_or(&self.dest_path);
         if fs::metadata(dest_dir).is_err() {
             // dir does not exist, create it
             fs::DirBuilder::new()
@@ -505,9 +700,27 @@ impl<'a> HtmlDirRenderer<'a> {
     }
 }
 
-/// Generate a file name given an int
-fn filenamer(i: usize) -> String {
-    format!("chapter_{i:03}.html")
+/// Returns the names of the files of the chapters, set by `html.dir.filenames`
+fn filenames(book: &Book) -> Vec<String> {
+    let naming = Naming::from(&book.options, "html.dir.filenames");
+    let slugifier = Slugifier::new(&book.options);
+    // The slug of a chapter can't be the name of the index
+    let mut used = HashSet::from([String::from("index")]);
+    book.chapters
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| {
+            let title = chapter.content.iter().find_map(|token| match *token {
+                Token::Header(1, ref vec) => Some(view_as_text(vec)),
+                _ => None,
+            });
+            let slug = match (naming, title) {
+                (Naming::Slug, Some(title)) => slugifier.unique_slug(&title, &mut used),
+                _ => None,
+            };
+            format!("{}.html", slug.unwrap_or_else(|| format!("chapter_{i:03}")))
+        })
+        .collect()
 }
 
 derive_html! {HtmlDirRenderer<'a>, HtmlRenderer::static_render_token}
@@ -530,4 +743,11 @@ impl BookRenderer for HtmlDir {
         HtmlDirRenderer::new(book)?.render_book(path)?;
         Ok(())
     }
+
+    fn dry_run(&self, book: &Book) -> Result<()> {
+        HtmlDirRenderer::new(book)?
+            .dry_run()
+            .render_book(Path::new(""))?;
+        Ok(())
+    }
 }
@@ -0,0 +1,109 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of an Atom feed of the chapters, for books that are
+//! published progressively on the web.
+
+use crate::error::Result;
+
+use crowbook_text_processing::escape;
+use std::fmt::Write;
+
+/// A chapter to include in the feed
+pub struct FeedEntry {
+    /// Absolute URL of the chapter
+    pub url: String,
+    /// Title of the chapter, as raw text
+    pub title: String,
+    /// Publication date of the chapter
+    pub date: String,
+    /// Summary of the chapter, as raw text
+    pub summary: Option<String>,
+    /// Content of the chapter, as HTML
+    pub content: String,
+}
+
+/// Converts a date to the RFC 3339 format required by Atom, if it only contains a day
+fn atom_date(date: &str) -> String {
+    if date.len() == 10 {
+        format!("{date}T00:00:00Z")
+    } else {
+        date.to_owned()
+    }
+}
+
+/// Renders an Atom feed
+///
+/// * `title`, `author`: metadata of the book, as raw text
+/// * `url`: absolute URL of the book's index
+/// * `feed_url`: absolute URL of the feed itself
+/// * `entries`: the published chapters, which are sorted from the most recent
+pub fn atom_feed(
+    title: &str,
+    author: &str,
+    url: &str,
+    feed_url: &str,
+    mut entries: Vec<FeedEntry>,
+) -> Result<String> {
+    entries.sort_by(|a, b| atom_date(&b.date).cmp(&atom_date(&a.date)));
+    let updated = entries
+        .first()
+        .map(|entry| atom_date(&entry.date))
+        .unwrap_or_else(|| String::from("1970-01-01T00:00:00Z"));
+
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:base=\"{url}\">
+  <title>{title}</title>
+  <author><name>{author}</name></author>
+  <id>{url}</id>
+  <link href=\"{url}\" />
+  <link rel=\"self\" href=\"{feed_url}\" />
+  <updated>{updated}</updated>
+  <generator>Crowbook</generator>
+",
+        title = escape::html(title),
+        author = escape::html(author),
+        url = html_escape::encode_double_quoted_attribute(url),
+        feed_url = html_escape::encode_double_quoted_attribute(feed_url),
+    );
+    for entry in &entries {
+        let date = atom_date(&entry.date);
+        write!(
+            feed,
+            "  <entry>
+    <title>{title}</title>
+    <id>{url}</id>
+    <link href=\"{url}\" />
+    <published>{date}</published>
+    <updated>{date}</updated>
+{summary}    <content type=\"html\">{content}</content>
+  </entry>
+",
+            title = escape::html(entry.title.as_str()),
+            url = html_escape::encode_double_quoted_attribute(&entry.url),
+            date = escape::html(date.as_str()),
+            summary = match entry.summary {
+                Some(ref summary) => format!("    <summary>{}</summary>\n", escape::html(summary.as_str())),
+                None => String::new(),
+            },
+            content = escape::html(entry.content.as_str()),
+        )?;
+    }
+    feed.push_str("</feed>\n");
+    Ok(feed)
+}
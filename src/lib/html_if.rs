@@ -19,7 +19,7 @@ use crate::book::Book;
 use crate::book_renderer::BookRenderer;
 use crate::error::{Error, Result, Source};
 use crate::html::Highlight;
-use crate::html::HtmlRenderer;
+use crate::html::{self, HtmlRenderer};
 use crate::parser::Parser;
 use crate::renderer::Renderer;
 use crate::token::Token;
@@ -137,7 +137,10 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             + AsRef<HtmlRenderer<'a>>
             + Renderer,
     {
+        // Code blocks contain JavaScript, so they are displayed as normal code blocks in sanitize mode
+        let sanitize = (this.as_ref() as &HtmlRenderer).sanitize;
         match *token {
+            Token::CodeBlock(..) if sanitize => HtmlRenderer::static_render_token(this, token),
             Token::CodeBlock(ref language, ref code) if language.is_empty() => {
                 let html_if: &mut HtmlIfRenderer = this.as_mut();
                 let content = html_if.parse_inner_code(code)?;
@@ -199,6 +202,7 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             .unwrap_or("");
 
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+            self.html.book.check_cancelled()?;
             let n = chapter.number;
             let v = &chapter.content;
             self.html.chapter_config(i, n, String::new());
@@ -207,7 +211,10 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             for token in v {
                 match *token {
                     Token::Header(1, ref vec) => {
-                        if self.html.current_hide || self.html.current_numbering == 0 {
+                        if self.html.current_hide
+                            || self.html.current_numbering == 0
+                            || token.has_class("unnumbered")
+                        {
                             title = self.html.render_vec(vec)?;
                         } else {
                             title = self
@@ -273,9 +280,12 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             .book
             .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
         data.insert("colors".into(), self.html.book.get_template("html.css.colors")?.into());
-        if let Ok(html_css_add) = self.html.book.options.get_str("html.css.add") {
-            data.insert("additional_code".into(), html_css_add.into());
-        }
+        data.insert(
+            "additional_code".into(),
+            self.html.get_additional_code("html.css.add", ".css")?.into(),
+        );
+        data.insert("theme".into(), self.html.get_css_theme("html").into());
+        self.html.book.template_vars.record("html.css", &data);
         let css:String = template_css.render(&data).to_string()?;
 
         // Render the JS
@@ -293,12 +303,13 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
         data.insert("js_prelude".into(), self.fn_defs.clone().into());
         data.insert(
                 "new_game".into(),
-                self.html.book.get_template("html.if.new_game").unwrap().into(),
+                self.html.book.get_template("html.if.new_game")?.into(),
         );
         data.insert(
                 "common_script".into(),
-                self.html.book.get_template("html.js").unwrap().into(),
+                self.html.book.get_template("html.js")?.into(),
         );
+        self.html.book.template_vars.record("html.if.js", &data);
         let js = template_js.render(&data).to_string()?;
 
 
@@ -309,16 +320,22 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
         data.insert("content".into(), content.into());
         data.insert("script".into(), js.into());
+        data.insert(
+            "additional_script".into(),
+            self.html.get_additional_code("html.js.add", ".js")?.into(),
+        );
         data.insert(self.html.book.options.get_str("lang").unwrap().into(), true.into());
         data.insert("one_chapter".into(), true.into());
         data.insert("style".into(), css.into());
         data.insert(
                 "print_style".into(),
-                self.html.book.get_template("html.css.print").unwrap().into(),
+                self.html.book.get_template("html.css.print")?.into(),
         );
         data.insert("footer".into(), HtmlRenderer::get_footer(self)?.into());
         data.insert("header".into(), HtmlRenderer::get_header(self)?.into());
         data.insert("has_toc".into(), false.into());
+        data.insert("interactive".into(), false.into());
+        data.insert("meta_tags".into(), self.html.get_meta_tags("")?.into());
         if let Ok(favicon) = self.html.book.options.get_path("html.icon") {
             let favicon = self
                 .html
@@ -330,7 +347,7 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             );
         }
         if self.html.highlight == Highlight::Js {
-            let highlight_js = misc::u8_to_base64(&self
+            let highlight_js = misc::u8_to_base64(self
                 .html
                 .book
                 .get_template("html.highlight.js")?
@@ -349,6 +366,8 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             &self.html.book.source,
             "html.standalone.template",
         )?;
+        html::escape_raw(&mut data);
+        self.html.book.template_vars.record("html.standalone.template", &data);
         Ok(template.render(&data).to_string()?)
     }
 }
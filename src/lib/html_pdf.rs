@@ -0,0 +1,92 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of PDF files from the standalone HTML output, with a web
+//! browser or WeasyPrint instead of LaTeX.
+
+use crate::book::Book;
+use crate::error::{Error, Result};
+use crate::html_single::HtmlSingleRenderer;
+use crate::misc;
+use crate::zipper::Zipper;
+
+use std::io;
+use rust_i18n::t;
+
+/// Default command used by the `browser` backend
+const BROWSER_COMMAND: &str =
+    "chromium --headless --disable-gpu --no-pdf-header-footer --print-to-pdf={output} {input}";
+
+/// Default command used by the `weasyprint` backend
+const WEASYPRINT_COMMAND: &str = "weasyprint {input} {output}";
+
+/// Returns the command line used by a `pdf.backend`, either set by `pdf.command` or the default one
+fn command_line<'b>(book: &'b Book, backend: &str) -> Result<&'b str> {
+    let default = match backend {
+        "browser" => BROWSER_COMMAND,
+        "weasyprint" => WEASYPRINT_COMMAND,
+        _ => {
+            return Err(Error::render(
+                &book.source,
+                t!("latex.backend", backend = backend),
+            ))
+        }
+    };
+    Ok(book.options.get_str("pdf.command").unwrap_or(default))
+}
+
+/// Returns the program run by a `pdf.backend`
+pub fn command_program(book: &Book, backend: &str) -> Result<String> {
+    misc::split_command(command_line(book, backend)?)
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::render(&book.source, t!("latex.backend_command")))
+}
+
+/// Returns the program run by a `pdf.backend` and its arguments, where `{input}` and `{output}`
+/// are replaced by the names of the HTML and PDF files in the temporary directory
+pub(crate) fn command(book: &Book, backend: &str) -> Result<(String, Vec<String>)> {
+    let program = command_program(book, backend)?;
+    let args = misc::split_command(command_line(book, backend)?)
+        .into_iter()
+        .skip(1)
+        .map(|arg| arg.replace("{input}", "result.html").replace("{output}", "result.pdf"))
+        .collect();
+    Ok((program, args))
+}
+
+/// Renders the book to PDF using the given `pdf.backend` (either `browser` or `weasyprint`)
+///
+/// The command that is run can be overriden with `pdf.command`, where `{input}` and `{output}`
+/// are replaced by the names of the HTML and PDF files.
+pub fn render_pdf(book: &Book, backend: &str, to: &mut dyn io::Write) -> Result<String> {
+    let (program, args) = command(book, backend)?;
+
+    let mut zipper = Zipper::new(book.temp_dir()?, book.command_limits());
+    let mut html = io::BufWriter::new(zipper.create("result.html")?);
+    HtmlSingleRenderer::new(book)?.render_book(&mut html)?;
+    io::Write::flush(&mut html).map_err(|e| {
+        Error::render(&book.source, t!("html.write_error", error = e))
+    })?;
+    drop(html);
+
+    let mut command = misc::command(&program);
+    command.current_dir(zipper.path()).args(args);
+    book.timings().time(&format!("command: {program}"), || {
+        zipper.run_command(command, &program, "result.pdf", to)
+    })
+}
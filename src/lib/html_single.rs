@@ -17,20 +17,27 @@
 
 use crate::book::Book;
 use crate::book_renderer::BookRenderer;
-use crate::error::{Error, Result, Source};
+use crate::error::{self, Error, Result, Source};
 use crate::html::Highlight;
-use crate::html::HtmlRenderer;
+use crate::html::{self, HtmlRenderer};
 use crate::parser::Parser;
 use crate::renderer::Renderer;
 use crate::templates::img;
 use crate::token::Token;
 use crate::misc;
+use crate::resource_handler::ResourceHandler;
 
 use std::convert::{AsMut, AsRef};
 use std::fmt::Write;
-use std::io;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
 use rust_i18n::t;
 
+/// Where the chapters are rendered before being copied to the final file
+trait Buffer: io::Read + io::Write + io::Seek {}
+
+impl<T: io::Read + io::Write + io::Seek> Buffer for T {}
+
 /// Single file HTML renderer
 ///
 /// Renders a standalone, self-contained HTML file
@@ -76,21 +83,99 @@ impl<'a> HtmlSingleRenderer<'a> {
         HtmlRenderer::static_render_token(this, token)
     }
 
+    /// Replaces `url(...)` references to local files in CSS code (e.g. fonts or background images)
+    /// by data URIs, so the document doesn't depend on any other file.
+    ///
+    /// Paths are relative to the book's directory.
+    fn embed_css_resources(&self, css: &str) -> Result<String> {
+        let book = self.html.book;
+        let strict = book.options.get_bool("rendering.strict").unwrap();
+        let mut res = String::with_capacity(css.len());
+        let mut rest = css;
+        while let Some(start) = rest.find("url(") {
+            let (before, after) = rest.split_at(start + "url(".len());
+            res.push_str(before);
+            let end = match after.find(')') {
+                Some(end) => end,
+                None => {
+                    rest = after;
+                    break;
+                }
+            };
+            let url = after[..end].trim().trim_matches(|c| c == '"' || c == '\'');
+            rest = &after[end..];
+
+            let path = book.root.join(url);
+            let embedded = if url.is_empty()
+                || url.starts_with('#')
+                || !ResourceHandler::is_local(url)
+                || (book.options.is_sandboxed() && !misc::is_inside(&path, &book.root))
+            {
+                None
+            } else {
+                match (fs::read(&path), mime_guess::from_path(url).first()) {
+                    (Ok(content), Some(mime)) => Some(format!(
+                        "\"data:{mime};base64,{}\"",
+                        misc::u8_to_base64(&content)
+                    )),
+                    (Err(_), _) => {
+                        let problem = t!("resources.read_error", file = path.display());
+                        error::lossy(strict, Source::empty(), problem)?;
+                        None
+                    }
+                    (_, None) => {
+                        error::lossy(strict, Source::empty(), t!("resources.guess", file = url))?;
+                        None
+                    }
+                }
+            };
+            match embedded {
+                Some(data) => res.push_str(&data),
+                None => res.push_str(&after[..end]),
+            }
+        }
+        res.push_str(rest);
+        Ok(res)
+    }
+
     /// Render books as a standalone HTML file
-    pub fn render_book(&mut self) -> Result<String> {
-        let menu_svg = misc::u8_to_base64(&img::MENU_SVG);
+    ///
+    /// Since the table of contents comes before the chapters, they are first rendered one
+    /// at a time to a temporary file, so memory usage doesn't grow with the size of the book.
+    pub fn render_book(&mut self, to: &mut dyn io::Write) -> Result<()> {
+        let menu_svg = misc::u8_to_base64(img::MENU_SVG);
         let menu_svg = format!("data:image/svg+xml;base64,{menu_svg}");
 
-        let book_svg = misc::u8_to_base64(&img::BOOK_SVG);
+        let book_svg = misc::u8_to_base64(img::BOOK_SVG);
         let book_svg = format!("data:image/svg+xml;base64,{book_svg}");
 
-        let pages_svg = misc::u8_to_base64(&img::PAGES_SVG);
+        let pages_svg = misc::u8_to_base64(img::PAGES_SVG);
         let pages_svg = format!("data:image/svg+xml;base64,{pages_svg}");
 
-        let mut content = String::new();
+        // With crowbook.in_memory, they are kept in memory instead
+        let mut temp_dir = None;
+        let chapters: Box<dyn Buffer> =
+            if self.html.book.options.get_bool("crowbook.in_memory").unwrap() {
+                Box::new(io::Cursor::new(vec![]))
+            } else {
+                let temp_dir = temp_dir.insert(self.html.book.temp_dir()?);
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(temp_dir.path().join("chapters.html"))
+                    .map_err(|e| {
+                        Error::render(&self.html.book.source, t!("html.write_error", error = e))
+                    })?;
+                Box::new(file)
+            };
+        let mut chapters = io::BufWriter::new(chapters);
 
         let mut titles = vec![];
-        let mut chapters = vec![];
+        // A chapter is only written once the title of the next one is known, for the
+        // navigation links between chapters
+        let mut previous: Option<String> = None;
         let render_notes_chapter = true;
         // self
         //     .html
@@ -106,6 +191,7 @@ impl<'a> HtmlSingleRenderer<'a> {
         }
 
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+            self.html.book.check_cancelled()?;
             let n = chapter.number;
             let v = &chapter.content;
             self.html.chapter_config(i, n, String::new());
@@ -115,7 +201,10 @@ impl<'a> HtmlSingleRenderer<'a> {
             for token in v {
                 match *token {
                     Token::Header(1, ref vec) => {
-                        if self.html.current_hide || self.html.current_numbering == 0 {
+                        if self.html.current_hide
+                            || self.html.current_numbering == 0
+                            || token.has_class("unnumbered")
+                        {
                             title = self.html.render_vec(vec)?;
                         } else {
                             title = self
@@ -137,85 +226,49 @@ impl<'a> HtmlSingleRenderer<'a> {
             }
             titles.push(title);
 
-            chapters.push(format!(
+            let content = format!(
                 "<div id = \"chapter-{}\" class = \"chapter\">
   {}
 </div>",
                 i,
                 HtmlRenderer::render_html(self, v, render_notes_chapter)?
-            ));
-        }
-        self.html.source = Source::empty();
-
-        for (i, chapter) in chapters.iter().enumerate() {
-            if self
-                .html
-                .book
-                .options
-                .get_bool("html.standalone.one_chapter")
-                .unwrap()
-                && i != 0
-            {
-                write!(
-                    content,
-                    "<p onclick = \"javascript:showChapter({})\" class = \
-                        \"chapterControls prev_chapter chapter-{}\">
-  <a href = \"#chapter-{}\">
-  « {}
-  </a>
-</p>",
-                    i - 1,
-                    i,
-                    i - 1,
-                    titles[i - 1]
-                )?;
-            }
-            content.push_str(chapter);
-            if self
-                .html
-                .book
-                .options
-                .get_bool("html.standalone.one_chapter")
-                .unwrap()
-                && i < titles.len() - 1
-            {
-                write!(
-                    content,
-                    "<p onclick = \"javascript:showChapter({})\" class = \
-                           \"chapterControls next_chapter chapter-{}\">
-  <a href = \"#chapter-{}\">
-  {} »
-  </a>
-</p>",
-                    i + 1,
-                    i,
-                    i + 1,
-                    titles[i + 1]
-                )?;
+            );
+            if let Some(previous) = previous.replace(content) {
+                self.write_chapter(&mut chapters, i - 1, &previous, &titles)?;
             }
         }
-        self.html.render_end_notes(&mut content, "section", "");
+        if let Some(last) = previous {
+            self.write_chapter(&mut chapters, titles.len() - 1, &last, &titles)?;
+        }
+        self.html.source = Source::empty();
+        let mut end_notes = String::new();
+        self.html.render_end_notes(&mut end_notes, "section", "");
+        self.write(&mut chapters, &end_notes)?;
+        let mut chapters = chapters.into_inner().map_err(|e| {
+            Error::render(&self.html.book.source, t!("html.write_error", error = e.error()))
+        })?;
 
         let toc = self.html.toc.render(false, false);
         // If display_toc, display the toc inline
-        if self
+        let inline_toc = if self
             .html
             .book
             .options
             .get_bool("rendering.inline_toc")
             .unwrap()
         {
-            content = format!(
+            format!(
                 "<div id = \"toc\">
   <h1>{title}</h1>
   {toc}
 </div>
-{content}",
+",
                 title = self.html.get_toc_name()?,
                 toc = &toc,
-                content = content
-            );
-        }
+            )
+        } else {
+            String::new()
+        };
 
         // Render the CSS
         let template_css_src = self.html.book.get_template("html.css")?;
@@ -229,12 +282,17 @@ impl<'a> HtmlSingleRenderer<'a> {
             .book
             .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
         data.insert("colors".into(), self.html.book.get_template("html.css.colors")?.into());
-        if let Ok(html_css_add) = self.html.book.options.get_str("html.css.add") {
-            data.insert("additional_code".into(), html_css_add.into());
-        } else {
-            data.insert("additional_code".into(), "".into());
+        data.insert(
+            "additional_code".into(),
+            self.html.get_additional_code("html.css.add", ".css")?.into(),
+        );
+        data.insert("theme".into(), self.html.get_css_theme("html").into());
+        self.html.book.template_vars.record("html.css", &data);
+        let mut css = template_css.render(&data).to_string()?;
+        let self_contained = self.html.book.options.get_bool("html.standalone").unwrap();
+        if self_contained {
+            css = self.embed_css_resources(&css)?;
         }
-        let css = template_css.render(&data).to_string()?;
 
 
         // Render the JS
@@ -261,15 +319,16 @@ impl<'a> HtmlSingleRenderer<'a> {
         );
         data.insert(
             "common_script".into(),
-            self.html.book.get_template("html.js").unwrap().into(),
+            self.html.book.get_template("html.js")?.into(),
         );
+        self.html.book.template_vars.record("html.standalone.js", &data);
         let js = template_js.render(&data).to_string()?;
 
         // Render the HTML document
         let mut data = self
             .html
             .get_metadata()?;
-        data.insert("content".into(), content.into());
+        data.insert("content".into(), misc::CONTENT_PLACEHOLDER.into());
         data.insert(
                 "one_chapter".into(),
                 self.html
@@ -281,13 +340,28 @@ impl<'a> HtmlSingleRenderer<'a> {
         );
         data.insert("style".into(), css.into());
         data.insert("script".into(), js.into()); // Need to override this for html_single
-        data.insert(
-                "print_style".into(),
-                self.html.book.get_template("html.css.print").unwrap().into(),
-        );
+        let mut print_css = self.html.book.get_template("html.css.print")?.into_owned();
+        if self_contained {
+            print_css = self.embed_css_resources(&print_css)?;
+        }
+        data.insert("print_style".into(), print_css.into());
         data.insert("menu_svg".into(), menu_svg.clone().into());
         data.insert("book_svg".into(), book_svg.clone().into());
         data.insert("pages_svg".into(), pages_svg.clone().into());
+        if self.html.book.options.get_bool("html.interactive").unwrap() {
+            data.insert("interactive".into(), true.into());
+            let mut interactive_css = self.html.book.get_template("html.interactive.css")?.into_owned();
+            if self_contained {
+                interactive_css = self.embed_css_resources(&interactive_css)?;
+            }
+            data.insert("interactive_css".into(), interactive_css.into());
+            data.insert(
+                "interactive_js".into(),
+                self.html.book.get_template("html.interactive.js")?.into(),
+            );
+        } else {
+            data.insert("interactive".into(), false.into());
+        }
         if let Ok(favicon) = self.html.book.options.get_path("html.icon") {
             let favicon = self
                 .html
@@ -307,7 +381,7 @@ impl<'a> HtmlSingleRenderer<'a> {
             data.insert("has_toc".into(), false.into());
         }
         if self.html.highlight == Highlight::Js {
-            let highlight_js = misc::u8_to_base64(&self
+            let highlight_js = misc::u8_to_base64(self
                 .html
                 .book
                 .get_template("html.highlight.js")?
@@ -326,7 +400,72 @@ impl<'a> HtmlSingleRenderer<'a> {
             &self.html.book.source,
             "html.standalone.template",
         )?;
-        Ok(template.render(&data).to_string()?)
+        html::escape_raw(&mut data);
+        self.html.book.template_vars.record("html.standalone.template", &data);
+        let rendered = template.render(&data).to_string()?;
+        let (head, tail) =
+            misc::split_content(&rendered, "html.standalone.template", &self.html.book.source)?;
+        self.write(to, head)?;
+        self.write(to, &inline_toc)?;
+        chapters
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| io::copy(&mut chapters, to))
+            .map_err(|e| Error::render(&self.html.book.source, t!("html.write_error", error = e)))?;
+        self.write(to, tail)
+    }
+
+    /// Writes a chapter, with links to the previous and next ones if `html.standalone.one_chapter`
+    /// is set
+    fn write_chapter(&self, to: &mut dyn io::Write, i: usize, chapter: &str, titles: &[String]) -> Result<()> {
+        let one_chapter = self
+            .html
+            .book
+            .options
+            .get_bool("html.standalone.one_chapter")
+            .unwrap();
+        let mut content = String::new();
+        if one_chapter && i != 0 {
+            write!(
+                content,
+                "<p onclick = \"javascript:showChapter({})\" class = \
+                    \"chapterControls prev_chapter chapter-{}\">
+  <a href = \"#chapter-{}\">
+  « {}
+  </a>
+</p>",
+                i - 1,
+                i,
+                i - 1,
+                titles[i - 1]
+            )?;
+        }
+        content.push_str(chapter);
+        if one_chapter && i < self.html.book.chapters.len() - 1 {
+            write!(
+                content,
+                "<p onclick = \"javascript:showChapter({})\" class = \
+                       \"chapterControls next_chapter chapter-{}\">
+  <a href = \"#chapter-{}\">
+  {} »
+  </a>
+</p>",
+                i + 1,
+                i,
+                i + 1,
+                titles[i + 1]
+            )?;
+        }
+        self.write(to, &content)
+    }
+
+    /// Writes a part of the HTML file
+    fn write(&self, to: &mut dyn io::Write, content: &str) -> Result<()> {
+        to.write_all(content.as_bytes()).map_err(|e| {
+            Error::render(
+                &self.html.book.source,
+                t!("html.write_error", error = e),
+            )
+        })
     }
 }
 
@@ -341,15 +480,7 @@ impl BookRenderer for HtmlSingle {
     }
 
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        let mut html = HtmlSingleRenderer::new(book)?;
-        let result = html.render_book()?;
-        to.write_all(result.as_bytes()).map_err(|e| {
-            Error::render(
-                &book.source,
-                t!("html.write_error", error = e),
-            )
-        })?;
-        Ok(())
+        HtmlSingleRenderer::new(book)?.render_book(to)
     }
 }
 
@@ -359,14 +490,6 @@ impl BookRenderer for ProofHtmlSingle {
     }
 
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        let mut html = HtmlSingleRenderer::new(book)?.proofread();
-        let result = html.render_book()?;
-        to.write_all(result.as_bytes()).map_err(|e| {
-            Error::render(
-                &book.source,
-                t!("html.write_error", error = e),
-            )
-        })?;
-        Ok(())
+        HtmlSingleRenderer::new(book)?.proofread().render_book(to)
     }
 }
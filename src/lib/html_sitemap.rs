@@ -0,0 +1,73 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of `sitemap.xml` and `robots.txt`, so search engines can find all the pages
+//! of a book published on the web.
+
+use std::fmt::Write;
+
+/// A page to include in the sitemap
+pub struct SitemapEntry {
+    /// Absolute URL of the page
+    pub url: String,
+    /// Date of the last modification of the page, if any
+    pub date: Option<String>,
+}
+
+/// Returns the day of a date in the `YYYY-MM-DD` format that sitemaps require, or None
+/// if it doesn't start with one (e.g. "Spring 2024")
+fn sitemap_date(date: &str) -> Option<&str> {
+    let day = date.get(..10)?;
+    let valid = day.char_indices().all(|(i, c)| match i {
+        4 | 7 => c == '-',
+        _ => c.is_ascii_digit(),
+    });
+    if valid {
+        Some(day)
+    } else {
+        None
+    }
+}
+
+/// Renders a sitemap listing the given pages
+pub fn sitemap(entries: &[SitemapEntry]) -> String {
+    let mut sitemap = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">
+",
+    );
+    for entry in entries {
+        sitemap.push_str("  <url>\n");
+        writeln!(
+            sitemap,
+            "    <loc>{}</loc>",
+            html_escape::encode_text(&entry.url)
+        )
+        .unwrap();
+        if let Some(date) = entry.date.as_deref().and_then(sitemap_date) {
+            writeln!(sitemap, "    <lastmod>{date}</lastmod>").unwrap();
+        }
+        sitemap.push_str("  </url>\n");
+    }
+    sitemap.push_str("</urlset>\n");
+    sitemap
+}
+
+/// Renders a `robots.txt` file allowing all crawlers and pointing them to the sitemap
+pub fn robots(sitemap_url: &str) -> String {
+    format!("User-agent: *\nAllow: /\n\nSitemap: {sitemap_url}\n")
+}
@@ -0,0 +1,84 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Identifiers of the book (ISBNs, DOI and UUID), which are checked when they are set.
+
+use crate::bookoptions::BookOptions;
+
+/// Returns an ISBN without the hyphens and spaces that usually separate its parts
+pub fn normalize_isbn(isbn: &str) -> String {
+    isbn.chars().filter(|c| *c != '-' && *c != ' ').collect()
+}
+
+/// Returns true if `isbn` is a valid ISBN-13, i.e. 13 digits starting with 978 or 979 whose
+/// checksum is correct, possibly separated by hyphens or spaces
+pub fn is_isbn13(isbn: &str) -> bool {
+    let isbn = normalize_isbn(isbn);
+    if isbn.len() != 13
+        || !isbn.bytes().all(|b| b.is_ascii_digit())
+        || !(isbn.starts_with("978") || isbn.starts_with("979"))
+    {
+        return false;
+    }
+    let sum: u32 = isbn
+        .bytes()
+        .enumerate()
+        .map(|(i, b)| u32::from(b - b'0') * if i % 2 == 0 { 1 } else { 3 })
+        .sum();
+    sum % 10 == 0
+}
+
+/// Returns true if `doi` looks like a DOI, e.g. `10.1000/182`
+pub fn is_doi(doi: &str) -> bool {
+    let (prefix, suffix) = match doi.split_once('/') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let registrant = match prefix.strip_prefix("10.") {
+        Some(registrant) => registrant,
+        None => return false,
+    };
+    !registrant.is_empty()
+        && registrant.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+        && !suffix.is_empty()
+        && !suffix.contains(char::is_whitespace)
+}
+
+/// Returns the kind of identifier expected by `key` if `value` isn't a valid one, or None
+/// if it is valid or `key` isn't an identifier option
+pub fn invalid(key: &str, value: &str) -> Option<&'static str> {
+    let (kind, valid) = match key {
+        "isbn" | "isbn.epub" | "isbn.print" | "onix.isbn" => ("ISBN-13", is_isbn13(value)),
+        "doi" => ("DOI", is_doi(value)),
+        "uuid" => ("UUID", uuid::Uuid::parse_str(value).is_ok()),
+        _ => return None,
+    };
+    if valid {
+        None
+    } else {
+        Some(kind)
+    }
+}
+
+/// Returns the ISBN of an edition of the book (`epub` or `print`), i.e. `isbn.{edition}`,
+/// or `isbn` if it isn't set
+pub fn isbn<'a>(options: &'a BookOptions, edition: &str) -> Option<&'a str> {
+    options
+        .get_str(&format!("isbn.{edition}"))
+        .or_else(|_| options.get_str("isbn"))
+        .ok()
+}
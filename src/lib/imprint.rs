@@ -0,0 +1,40 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Revision history of the book, shown with the edition and imprint on its copyright page.
+
+/// Splits an entry of `revisions`, written as `date: changes`, into its date and changes
+pub fn parse_revision(entry: &str) -> Option<(&str, &str)> {
+    let (date, changes) = entry.split_once(':')?;
+    let (date, changes) = (date.trim(), changes.trim());
+    if date.is_empty() || changes.is_empty() {
+        None
+    } else {
+        Some((date, changes))
+    }
+}
+
+/// Returns the first entry of `entries` that isn't a valid revision, if `key` is `revisions`
+pub fn invalid_revision<'a>(key: &str, entries: &'a [String]) -> Option<&'a str> {
+    if key != "revisions" {
+        return None;
+    }
+    entries
+        .iter()
+        .find(|entry| parse_revision(entry).is_none())
+        .map(String::as_str)
+}
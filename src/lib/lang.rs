@@ -39,22 +39,27 @@ pub fn get_hash(lang: &str) -> Hash {
 }
 
 /// Get a string for a given language
+///
+/// Falls back to English if the string isn't translated, and to the key itself if
+/// it doesn't exist.
 pub fn get_str(lang: &str, s: &str) -> String {
+    let key = Yaml::String(s.to_owned());
     let hash = get_hash(lang);
-    let yaml = hash.get(&Yaml::String(s.to_owned())).expect(&t!(
-        "error.yaml_translation",
-        key = s,
-        lang = lang
-    ));
-    if let Yaml::String(result) = yaml {
-        result.clone()
-    } else {
-        panic!(
+    let yaml = hash.get(&key).cloned().or_else(|| {
+        warn!(
             "{}",
-            t!("error.yaml_translation_string",
-                key = s,
-                lang = lang
-            )
+            t!("error.yaml_translation", key = s, lang = lang)
         );
+        get_hash("en").get(&key).cloned()
+    });
+    match yaml {
+        Some(Yaml::String(result)) => result,
+        _ => {
+            error!(
+                "{}",
+                t!("error.yaml_translation_string", key = s, lang = lang)
+            );
+            s.to_owned()
+        }
     }
 }
@@ -15,13 +15,26 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::book::Book;
+use crate::book::{Book, Progress};
 use crate::book_renderer::BookRenderer;
+use crate::capabilities::{Fallback, Feature};
+use crate::code_block::CodeInfo;
 use crate::error::{Error, Result, Source};
+use crate::html_pdf;
+use crate::html_single::HtmlSingle;
+use crate::identifier;
+use crate::lang;
+use crate::latex_escape;
+use crate::latex_log;
+use crate::link_refs::LinkStyle;
+use crate::misc;
+use crate::notes::{NoteNumbering, SideNotes};
 use crate::number::Number;
-use crate::parser::Parser;
+use crate::parser::{self, Parser};
+use crate::pdf_fonts;
 use crate::renderer::Renderer;
 use crate::resource_handler::ResourceHandler;
+use crate::subject;
 use crate::syntax::Syntax;
 use crate::token::Data;
 use crate::token::Token;
@@ -30,11 +43,13 @@ use crate::zipper::Zipper;
 use crowbook_text_processing::escape;
 
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fmt::Write;
 use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::Read;
+use std::path::Path;
 use std::iter::Iterator;
 use rust_i18n::t;
 
@@ -42,6 +57,7 @@ use rust_i18n::t;
 pub struct LatexRenderer<'a> {
     book: &'a Book<'a>,
     current_chapter: Number,
+    current_unlisted: bool,
     handler: ResourceHandler,
     source: Source,
     escape: bool,
@@ -52,6 +68,17 @@ pub struct LatexRenderer<'a> {
     syntax: Option<Syntax>,
     hyperref: bool,
     enum_level: usize,
+    /// Whether the current chapter has a layout (landscape or columns), so wide tables
+    /// can't be put on landscape pages
+    in_layout: bool,
+    /// Number of the last line of the previous code block
+    code_line: u32,
+    link_style: LinkStyle,
+    /// URLs of the links listed at the end of the book, when `link_style` is `Endnote`
+    links: Vec<String>,
+    /// Footnotes of the current chapter, if they are displayed as margin notes
+    side_notes: Option<SideNotes>,
+    note_numbering: NoteNumbering,
 }
 
 impl<'a> LatexRenderer<'a> {
@@ -59,6 +86,9 @@ impl<'a> LatexRenderer<'a> {
     pub fn new(book: &'a Book) -> LatexRenderer<'a> {
         let mut handler = ResourceHandler::new();
         handler.set_images_mapping(true);
+        if book.options.is_sandboxed() {
+            handler.set_sandbox(Some(book.root.clone()));
+        }
         let syntax = if book.options.get_str("rendering.highlight").unwrap() == "syntect"
             && book.features.codeblock
         {
@@ -73,6 +103,7 @@ impl<'a> LatexRenderer<'a> {
         LatexRenderer {
             book,
             current_chapter: Number::Default,
+            current_unlisted: false,
             handler,
             source: Source::empty(),
             escape: true,
@@ -83,6 +114,16 @@ impl<'a> LatexRenderer<'a> {
             syntax,
             hyperref: book.options.get_bool("tex.hyperref").unwrap(),
             enum_level: 0,
+            in_layout: false,
+            code_line: 0,
+            link_style: LinkStyle::from(&book.options, "tex"),
+            links: vec![],
+            side_notes: if book.options.get_bool("tex.side_notes").unwrap() {
+                Some(SideNotes::default())
+            } else {
+                None
+            },
+            note_numbering: NoteNumbering::from(&book.options),
         }
     }
 
@@ -90,7 +131,7 @@ impl<'a> LatexRenderer<'a> {
     ///
     /// * 1 -> chapter
     /// * 2 -> section
-    /// ...
+    /// * ...
     fn get_header(&self, n: i32) -> &'static str {
         match n {
             1 => {
@@ -111,12 +152,25 @@ impl<'a> LatexRenderer<'a> {
         }
     }
 
+    /// Returns the LaTeX code of an anchor that `#label` links point to
+    fn anchor(&self, label: &str) -> String {
+        if self.hyperref {
+            format!("\\phantomsection\\label{{{}}}", latex_escape::label(label))
+        } else {
+            String::new()
+        }
+    }
+
     /// Render pdf to a file
     pub fn render_pdf(&mut self, to: &mut dyn io::Write) -> Result<String> {
-        let content = self.render_book()?;
         debug!("{}", t!("latex.attempting"));
-        let mut zipper = Zipper::new(&self.book.options.get_path("crowbook.temp_dir").unwrap())?;
-        zipper.write("result.tex", content.as_bytes(), false)?;
+        let mut zipper = Zipper::new(self.book.temp_dir()?, self.book.command_limits());
+        let mut tex = io::BufWriter::new(zipper.create("result.tex")?);
+        self.render_book(&mut tex)?;
+        io::Write::flush(&mut tex).map_err(|e| {
+            Error::render(&self.book.source, t!("latex.write_error", error = e))
+        })?;
+        drop(tex);
 
         // write image files
         for (source, dest) in self.handler.images_mapping() {
@@ -130,52 +184,311 @@ impl<'a> LatexRenderer<'a> {
                     t!("latex.image_error", error = e),
                 )
             })?;
+            if self.book.options.get_bool("pdf.grayscale").unwrap() {
+                content = grayscale(content)?;
+            }
             zipper.write(dest, &content, true)?;
         }
 
-        zipper.generate_pdf(
-            self.book.options.get_str("tex.command").unwrap(),
-            "result.tex",
-            to,
-        )
+        let (command, args, runs) = pdf_command(self.book);
+        let on_run = |i| {
+            self.book
+                .report_progress(Progress::Step("pdf", i as usize, runs as usize))
+        };
+        let mut pdf = vec![];
+        let result = self.book.timings().time(&format!("command: {command}"), || {
+            zipper.generate_pdf(command, args, "result.tex", runs, &on_run, &mut pdf)
+        });
+        let problems = zipper
+            .read_to_string("result.log")
+            .map(|log| latex_log::parse_log(&log))
+            .unwrap_or_default();
+        match result {
+            Ok(output) => {
+                for problem in &problems {
+                    warn!("{}", t!("latex_log.warning", problem = problem.message));
+                }
+                if self.book.options.get_bool("pdf.pdfa").unwrap() {
+                    let fonts = pdf_fonts::unembedded(&pdf);
+                    if !fonts.is_empty() {
+                        return Err(Error::render(
+                            &self.book.source,
+                            t!("latex.pdfa_fonts", fonts = fonts.join(", ")),
+                        ));
+                    }
+                }
+                to.write_all(&pdf).map_err(|e| {
+                    Error::render(&self.book.source, t!("latex.write_error", error = e))
+                })?;
+                Ok(output)
+            }
+            Err(err) if problems.is_empty() => Err(err),
+            Err(_) => {
+                let content = zipper.read_to_string("result.tex").unwrap_or_default();
+                let mut msg = t!("latex_log.failed", command = command);
+                for problem in &problems {
+                    let location = match problem.line {
+                        Some(line) => match latex_log::chapter_of_line(&content, line) {
+                            Some(i) => t!(
+                                "latex_log.location_chapter",
+                                chapter = self.book.chapters[i].filename,
+                                line = line
+                            ),
+                            None => t!("latex_log.location", line = line),
+                        },
+                        None => String::from("-"),
+                    };
+                    write!(msg, "\n{location}: {}", problem.message)?;
+                }
+                Err(Error::render(&self.book.source, msg))
+            }
+        }
+    }
+
+    /// Inserts the variables of the print production and archival options (`pdf.bleed`,
+    /// `pdf.crop_marks`, `pdf.grayscale`, `pdf.icc_profile`, `pdf.pdfx` and `pdf.pdfa`) in the
+    /// template's data
+    fn insert_print_options(&self, data: &mut BTreeMap<String, upon::Value>) -> Result<()> {
+        let options = &self.book.options;
+        let bleed = options.get_str("pdf.bleed").ok();
+        if let Some(bleed) = bleed {
+            if !is_length(bleed) {
+                return Err(Error::book_option(
+                    &self.source,
+                    t!("latex.length", key = "pdf.bleed", value = bleed),
+                ));
+            }
+        }
+        let crop_marks = options.get_bool("pdf.crop_marks").unwrap();
+        data.insert("print_layout".into(), (bleed.is_some() || crop_marks).into());
+        data.insert("bleed".into(), bleed.unwrap_or("0pt").into());
+        data.insert("crop_marks".into(), crop_marks.into());
+        data.insert("grayscale".into(), options.get_bool("pdf.grayscale").unwrap().into());
+        // Both use the pdfx package, with a different conformance level
+        let pdfx = match (options.get_bool("pdf.pdfx").unwrap(), options.get_bool("pdf.pdfa").unwrap()) {
+            (true, true) => {
+                return Err(Error::book_option(&self.source, t!("latex.pdfx_pdfa")));
+            }
+            (true, false) => Some("x-1a"),
+            (false, true) => Some("a-2b"),
+            (false, false) => None,
+        };
+        data.insert("use_pdfx".into(), pdfx.is_some().into());
+        data.insert("pdfa".into(), (pdfx == Some("a-2b")).into());
+        data.insert("pdfx".into(), pdfx.unwrap_or("").into());
+        let icc_profile = options.get_path("pdf.icc_profile").ok();
+        data.insert("has_icc_profile".into(), icc_profile.is_some().into());
+        if let Some(icc_profile) = icc_profile {
+            fs::metadata(&icc_profile).map_err(|_| {
+                Error::file_not_found(&self.source, t!("format.icc_profile"), icc_profile.clone())
+            })?;
+            let name = Path::new(&icc_profile)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            data.insert("icc_name".into(), latex_escape::text(name).into());
+            data.insert("icc_profile".into(), icc_profile.replace('\\', "/").into());
+        }
+        Ok(())
     }
 
-    /// Render latex in a string
-    pub fn render_book(&mut self) -> Result<String> {
-        let mut content = String::new();
+    /// Inserts the variables of the typographic quality options (widows and orphans, microtype,
+    /// line breaking and hyphenation exceptions) in the template's data
+    fn insert_line_breaking(&self, data: &mut BTreeMap<String, upon::Value>) -> Result<()> {
+        let options = &self.book.options;
+        // LaTeX can only forbid single-line widows and orphans, with the highest penalty
+        for (option, penalty) in [("rendering.widows", "widow_penalty"), ("rendering.orphans", "club_penalty")] {
+            let lines = options.get_i32(option).ok();
+            data.insert(format!("has_{penalty}"), lines.is_some().into());
+            let value = if lines.unwrap_or(0) >= 2 { "10000" } else { "0" };
+            data.insert(penalty.into(), value.into());
+        }
+        data.insert("microtype".into(), options.get_bool("tex.microtype").unwrap().into());
+        let line_breaking = options.get_str("tex.line_breaking").unwrap();
+        if !["normal", "sloppy", "strict"].contains(&line_breaking) {
+            return Err(Error::book_option(
+                &self.source,
+                t!("latex.line_breaking", value = line_breaking),
+            ));
+        }
+        data.insert("sloppy".into(), (line_breaking == "sloppy").into());
+        data.insert("strict".into(), (line_breaking == "strict").into());
+        let exceptions = options
+            .get_str_vec("tex.hyphenation.exceptions")
+            .map(|words| words.iter().map(|w| latex_escape::text(w.trim())).collect::<Vec<_>>())
+            .unwrap_or_default();
+        data.insert("has_hyphenation".into(), (!exceptions.is_empty()).into());
+        data.insert("hyphenation".into(), exceptions.join(" ").into());
+        Ok(())
+    }
 
-        // set tex numbering and toc display to book's parameters
-        let numbering = self.book.options.get_i32("rendering.num_depth").unwrap() - 1;
-        write!(
-            content,
-            "\\setcounter{{tocdepth}}{{{numbering}}}
-\\setcounter{{secnumdepth}}{{{numbering}}}\n",
-        )?;
+    /// Inserts the variables of the chapter-start layout options (`tex.chapter.recto`,
+    /// `tex.chapter.drop` and `tex.chapter.style`) in the template's data
+    fn insert_chapter_layout(&self, data: &mut BTreeMap<String, upon::Value>) -> Result<()> {
+        let options = &self.book.options;
+        let drop = options.get_str("tex.chapter.drop").ok();
+        if let Some(drop) = drop {
+            if !is_length(drop) {
+                return Err(Error::book_option(
+                    &self.source,
+                    t!("latex.length", key = "tex.chapter.drop", value = drop),
+                ));
+            }
+        }
+        data.insert("has_chapter_drop".into(), drop.is_some().into());
+        data.insert("chapter_drop".into(), drop.unwrap_or("").into());
+        let style = options.get_str("tex.chapter.style").unwrap();
+        let styles = ["centered", "simple", "rule", "number"];
+        if style != "default" && !styles.contains(&style) {
+            return Err(Error::book_option(
+                &self.source,
+                t!("latex.chapter_style", value = style),
+            ));
+        }
+        for name in styles {
+            data.insert(format!("chapter_style_{name}"), (style == name).into());
+        }
+        // Articles don't have chapters
+        let use_titlesec = !self.is_short && (style != "default" || drop.is_some());
+        data.insert("use_titlesec".into(), use_titlesec.into());
+        data.insert("chapter_recto".into(), options.get_bool("tex.chapter.recto").unwrap().into());
+        Ok(())
+    }
 
-        if self.book.options.get_bool("rendering.inline_toc").unwrap() {
-            content.push_str("\\tableofcontents\n");
+    /// Inserts the variables setting the PDF's bookmarks, links and metadata in the template's data
+    fn insert_pdf_metadata(&self, data: &mut BTreeMap<String, upon::Value>) {
+        let options = &self.book.options;
+        // Bookmarks go as deep as the table of contents
+        let depth = options.get_i32("rendering.num_depth").unwrap() - 1;
+        data.insert("bookmarks_depth".into(), format!("{depth}").into());
+        data.insert("link_colors".into(), options.get_bool("pdf.link_colors").unwrap().into());
+        let keywords: Vec<_> = subject::keywords(options)
+            .into_iter()
+            .map(latex_escape::text)
+            .collect();
+        data.insert("has_keywords".into(), (!keywords.is_empty()).into());
+        data.insert("pdf_keywords".into(), keywords.join(", ").into());
+        data.insert("xmp_keywords".into(), keywords.join("\\sep ").into());
+        data.insert("crowbook_version".into(), env!("CARGO_PKG_VERSION").into());
+    }
+
+    /// Renders the LaTeX file and checks that the images it needs exist, without running LaTeX
+    pub fn dry_run(&mut self) -> Result<()> {
+        self.render_book(&mut io::sink())?;
+        for source in self.handler.images_mapping().keys() {
+            fs::metadata(source).map_err(|_| {
+                Error::file_not_found(&self.source, t!("format.image"), source.to_owned())
+            })?;
         }
+        Ok(())
+    }
 
+    /// Render latex to a writer
+    ///
+    /// Chapters are rendered and written one at a time, so memory usage doesn't grow with
+    /// the size of the book.
+    pub fn render_book(&mut self, to: &mut dyn io::Write) -> Result<()> {
         for (i, chapter) in self.book.chapters.iter().enumerate() {
             self.handler
                 .add_link(chapter.filename.as_str(), format!("chapter-{i}"));
         }
 
+        let template = self.render_template()?;
+        let (head, tail) = misc::split_content(&template, "tex.template", &self.book.source)?;
+        self.write(to, head)?;
+
+        // set tex numbering and toc display to book's parameters
+        let numbering = self.book.options.get_i32("rendering.num_depth").unwrap() - 1;
+        self.write(
+            to,
+            &format!(
+                "\\setcounter{{tocdepth}}{{{numbering}}}
+\\setcounter{{secnumdepth}}{{{numbering}}}\n",
+            ),
+        )?;
+
+        if self.book.options.get_bool("rendering.inline_toc").unwrap() {
+            self.write(to, "\\tableofcontents\n")?;
+        }
+
         for (i, chapter) in self.book.chapters.iter().enumerate() {
+            self.book.check_cancelled()?;
+            let mut content = String::new();
             let n = chapter.number;
             self.current_chapter = n;
+            self.current_unlisted = chapter.unlisted;
             let v = &chapter.content;
             self.source = Source::new(chapter.filename.as_str());
+            if let Some(ref mut side_notes) = self.side_notes {
+                side_notes.collect(v);
+            }
+            let layout = chapter.layout.as_deref();
+            self.in_layout = layout.is_some();
+            if layout == Some("landscape") {
+                content.push_str("\\begin{landscape}\n");
+            }
+            // Unlisted chapters are kept out of the toc by lowering its depth while they last
+            if chapter.unlisted {
+                content.push_str("\\addtocontents{toc}{\\protect\\setcounter{tocdepth}{-10}}\n");
+            }
             let mut offset = 0;
             if !v.is_empty() && v[0].is_header() {
                 content.push_str(&self.render_token(&v[0])?);
                 offset = 1;
             }
             writeln!(content, "\\label{{chapter-{i}}}")?;
-            content.push_str(&self.render_vec(&v[offset..])?);
+            // The title of the chapter spans both columns
+            if layout == Some("two_columns") {
+                content.push_str("\\begin{multicols}{2}\n");
+                content.push_str(&self.render_vec(&v[offset..])?);
+                content.push_str("\\end{multicols}\n");
+            } else {
+                content.push_str(&self.render_vec(&v[offset..])?);
+            }
+            if layout == Some("landscape") {
+                content.push_str("\\end{landscape}\n");
+            }
+            if chapter.unlisted {
+                let depth = self.book.options.get_i32("rendering.num_depth").unwrap() - 1;
+                writeln!(content, "\\addtocontents{{toc}}{{\\protect\\setcounter{{tocdepth}}{{{depth}}}}}")?;
+            }
+            self.write(to, &content)?;
         }
         self.source = Source::empty();
+        if !self.links.is_empty() {
+            let content = self.render_links()?;
+            self.write(to, &content)?;
+        }
+        self.write(to, tail)
+    }
 
+    /// Renders the list of the links used as endnotes
+    fn render_links(&self) -> Result<String> {
+        let title = lang::get_str(self.book.options.get_str("lang").unwrap(), "links");
+        let mut content = format!(
+            "\\{}*{{{}}}\n\\begin{{itemize}}\n",
+            if self.is_short { "section" } else { "chapter" },
+            latex_escape::text(title)
+        );
+        for (i, url) in self.links.iter().enumerate() {
+            writeln!(content, "\\item[{{[{}]}}] \\url{{{url}}}", i + 1)?;
+        }
+        content.push_str("\\end{itemize}\n");
+        Ok(content)
+    }
+
+    /// Writes a part of the LaTeX file
+    fn write(&self, to: &mut dyn io::Write, content: &str) -> Result<()> {
+        to.write_all(content.as_bytes()).map_err(|e| {
+            Error::render(
+                &self.book.source,
+                t!("latex.write_error", error = e),
+            )
+        })
+    }
+
+    /// Renders the LaTeX template, with `misc::CONTENT_PLACEHOLDER` instead of the chapters
+    fn render_template(&mut self) -> Result<String> {
         let tex_lang = String::from(match self.book.options.get_str("lang").unwrap() {
             "af" => "afrikaans",
             "sq" => "albanian",
@@ -236,25 +549,76 @@ impl<'a> LatexRenderer<'a> {
             .comment("<%", "%>")
             .build(); 
         let mut engine = upon::Engine::with_syntax(syntax);
+        // Fragments included by the template, which can be overridden separately
+        for fragment in [
+            "tex.template.preamble",
+            "tex.template.titlepage",
+            "tex.template.headers",
+            "tex.template.chapter_heading",
+        ] {
+            engine.add_template(fragment, self.book.get_template(fragment)?)?;
+        }
         engine.add_template("tex.template", template_src)?;
         let template = engine.get_template("tex.template").unwrap();
         let mut data = self
             .book
             .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
-        data.insert("content".into(), content.into());
+        data.insert("content".into(), misc::CONTENT_PLACEHOLDER.into());
         data.insert("class".into(), self.book.options.get_str("tex.class").unwrap().into());
         data.insert("tex_title".into(), self.book.options.get_bool("tex.title").unwrap().into());
         data.insert("papersize".into(), self.book.options.get_str("tex.paper.size").unwrap().into());
         data.insert("stdpage".into(), self.book.options.get_bool("tex.stdpage").unwrap().into());
+        let isbn = identifier::isbn(&self.book.options, "print");
+        data.insert("has_isbn".into(), isbn.is_some().into());
+        if let Some(isbn) = isbn {
+            data.insert("isbn".into(), isbn.into());
+        }
 
         data.insert("use_url".into(), self.book.features.url.into());
         data.insert("use_taskitem".into(), self.book.features.taskitem.into());
         data.insert("use_tables".into(), self.book.features.table.into());
         data.insert("use_codeblocks".into(), self.book.features.codeblock.into());
         data.insert("use_images".into(), self.book.features.image.into());
+        let has_layout = |layout| {
+            self.book
+                .chapters
+                .iter()
+                .any(|chapter| chapter.layout.as_deref() == Some(layout))
+        };
+        let landscape_tables = self.book.features.table
+            && self.book.options.get_i32("tex.landscape_tables").is_ok();
+        data.insert("use_landscape".into(), (landscape_tables || has_layout("landscape")).into());
+        data.insert("use_multicol".into(), has_layout("two_columns").into());
+        data.insert("use_letters".into(), self.book.features.letter.into());
+        data.insert("use_play".into(), self.book.features.play.into());
+        data.insert("use_side_notes".into(), self.side_notes.is_some().into());
+        data.insert(
+            "use_notes_numbering".into(),
+            (self.note_numbering != NoteNumbering::Arabic).into(),
+        );
+        data.insert("notes_numbering".into(), self.note_numbering.latex_command().into());
+        let placement = self.book.options.get_str("tex.figure_placement").unwrap();
+        if placement.is_empty() || !placement.chars().all(|c| "htbpH!".contains(c)) {
+            return Err(Error::book_option(
+                &self.source,
+                t!("latex.figure_placement", placement = placement),
+            ));
+        }
+        data.insert("figure_float".into(), self.book.options.get_bool("tex.figure_float").unwrap().into());
+        data.insert("figure_placement".into(), placement.into());
+        data.insert("use_float".into(), placement.contains('H').into());
+        data.insert("figure_caption".into(), self.book.options.get_bool("tex.figure_caption").unwrap().into());
+        data.insert(
+            "image_max_width".into(),
+            format!("{}", self.book.options.get_f32("tex.image_max_width").unwrap()).into(),
+        );
         data.insert("use_strikethrough".into(), self.book.features.strikethrough.into());
         data.insert("tex_lang".into(), tex_lang.into());
-        let tex_tmpl_add = self.book.options.get_str("tex.template.add").unwrap_or("".into());
+        self.insert_print_options(&mut data)?;
+        self.insert_pdf_metadata(&mut data);
+        self.insert_line_breaking(&mut data)?;
+        self.insert_chapter_layout(&mut data)?;
+        let tex_tmpl_add = self.book.options.get_str("tex.template.add").unwrap_or("");
         data.insert("additional_code".into(), tex_tmpl_add.into());
         let mut use_cover = false;
         if self.book.options.get_bool("tex.cover").unwrap() {
@@ -269,9 +633,11 @@ impl<'a> LatexRenderer<'a> {
         data.insert("use_cover".into(), use_cover.into());
 
 
+        let mut class_options = vec![];
         if let Ok(tex_font_size) = self.book.options.get_i32("tex.font.size") {
             data.insert("has_tex_size".into(), true.into());
             data.insert("tex_size".into(), format!("{tex_font_size}").into());
+            class_options.push(format!("{tex_font_size}pt"));
         } else {
             data.insert("has_tex_size".into(), false.into());
         }
@@ -282,6 +648,11 @@ impl<'a> LatexRenderer<'a> {
             book = true;
         }
         data.insert("book".into(), book.into());
+        if book && !self.book.options.get_bool("tex.chapter.recto").unwrap() {
+            class_options.push(String::from("openany"));
+        }
+        data.insert("has_class_options".into(), (!class_options.is_empty()).into());
+        data.insert("class_options".into(), class_options.join(",").into());
         data.insert(
                 "margin_left".into(),
                 self.book
@@ -305,21 +676,20 @@ impl<'a> LatexRenderer<'a> {
                 self.book.options.get_str("tex.margin.top").unwrap().into(),
         );
 
-        let chapter_name = self.book.options.get_str("rendering.chapter").unwrap_or("".into());
-        data.insert("chapter_name".into(), chapter_name.into());
+        let chapter_name = self.book.options.get_str("rendering.chapter").unwrap_or("");
+        data.insert("chapter_name".into(), latex_escape::text(chapter_name).into());
         
-        let part_name = self.book.options.get_str("rendering.part").unwrap_or("".into());
-        data.insert("part_name".into(), part_name.into());
+        let part_name = self.book.options.get_str("rendering.part").unwrap_or("");
+        data.insert("part_name".into(), latex_escape::text(part_name).into());
         data.insert("initials".into(), self.book.options.get_bool("rendering.initials").unwrap().into());
         // Insert xelatex if tex.command is set to xelatex or tectonic
-        if (self.book.options.get_str("tex.command") == Ok("xelatex"))
-            | (self.book.options.get_str("tex.command") == Ok("tectonic"))
-        {
+        let command = self.book.options.get_str("tex.command").unwrap();
+        if command == "xelatex" || is_tectonic(command) {
             data.insert("xelatex".into(), true.into());
-        } else 
-        { 
+        } else {
             data.insert("xelatex".into(), false.into());
         }
+        self.book.template_vars.record("tex.template", &data);
         Ok(template.render(&data).to_string()?)
     }
 }
@@ -329,7 +699,7 @@ impl<'a> Renderer for LatexRenderer<'a> {
         match *token {
             Token::Str(ref text) => {
                 let content = if self.escape {
-                    let mut escaped = escape::tex(self.book.clean(text.as_str()));
+                    let mut escaped = latex_escape::text(self.book.clean(text.as_str()));
                     if self.book.options.get_bool("tex.escape_nb_spaces").unwrap() {
                         escaped = escape::nb_spaces_tex(escaped)
                     }
@@ -383,6 +753,9 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 Ok(format!("{}\n\n", self.render_vec(vec)?))
             }
             Token::Header(n, ref vec) => {
+                let (id, _) = token.header_attributes();
+                let unnumbered = !self.current_chapter.is_numbered() || token.has_class("unnumbered");
+                let unlisted = token.has_class("unlisted") || self.current_unlisted;
                 let mut content = String::new();
                 let tex_header = self.get_header(n);
                 let title = self.render_vec(vec)?;
@@ -400,30 +773,42 @@ impl<'a> Renderer for LatexRenderer<'a> {
                         write!(content, "{}", n - 1)?;
                         content.push_str("}\n");
                     }
-                    if self.current_chapter.is_part() {
-                        if self
+                    if self.current_chapter.is_part()
+                        && self
                             .book
                             .options
                             .get_bool("rendering.part.reset_counter")
                             .unwrap()
-                        {
-                            content.push_str(r"\setcounter{chapter}{0}");
-                        }
+                    {
+                        content.push_str(r"\setcounter{chapter}{0}");
                     }
                 }
+                // Numbered headers are kept out of the toc by lowering its depth around them
+                let hide_from_toc = unlisted && !unnumbered && !self.current_unlisted;
+                if hide_from_toc {
+                    content.push_str("\\addtocontents{toc}{\\protect\\setcounter{tocdepth}{-10}}\n");
+                }
                 // Add header command
                 content.push_str(&format!("\\{tex_header}"));
-                if !self.current_chapter.is_numbered() {
+                if unnumbered {
                     content.push('*');
                 }
                 content.push('{');
                 content.push_str(&title);
                 content.push_str("}\n");
+                if hide_from_toc {
+                    let depth = self.book.options.get_i32("rendering.num_depth").unwrap() - 1;
+                    writeln!(content, "\\addtocontents{{toc}}{{\\protect\\setcounter{{tocdepth}}{{{depth}}}}}")?;
+                }
                 // Mhen using eg \chapter*, latex doesn't add it to the toc,
                 // but we want it so be add it back manually
-                if !self.current_chapter.is_numbered() && !self.current_chapter.is_hidden() {
+                if unnumbered && !unlisted && !self.current_chapter.is_hidden() {
                     content.push_str(&format!("\\addcontentsline{{toc}}{{{tex_header}}}{{{title}}}\n"))
                 }
+                if !id.is_empty() {
+                    content.push_str(&self.anchor(id));
+                    content.push('\n');
+                }
                 Ok(content)
             }
             Token::TaskItem(checked, ref vec) => Ok(format!(
@@ -438,7 +823,24 @@ impl<'a> Renderer for LatexRenderer<'a> {
             Token::Emphasis(ref vec) => Ok(format!("\\emph{{{}}}", self.render_vec(vec)?)),
             Token::Strong(ref vec) => Ok(format!("\\mdstrong{{{}}}", self.render_vec(vec)?)),
             Token::Strikethrough(ref vec) => Ok(format!("\\sout{{{}}}", self.render_vec(vec)?)),
-            Token::Code(ref s) => Ok(format!("\\mdcode{{{}}}", insert_breaks(&escape::tex(s)))),
+            Token::Code(ref s) => Ok(format!("\\mdcode{{{}}}", latex_escape::code(s))),
+            Token::Raw(ref format, ref s) => {
+                if format == "latex" || format == "tex" {
+                    Ok(s.clone())
+                } else {
+                    Ok(String::new())
+                }
+            }
+            Token::Shortcode(ref raws, ref markdown) => {
+                if raws
+                    .iter()
+                    .any(|raw| matches!(*raw, Token::Raw(ref format, _) if format == "latex" || format == "tex"))
+                {
+                    self.render_vec(raws)
+                } else {
+                    self.render_vec(markdown)
+                }
+            }
             Token::Superscript(ref vec) => {
                 Ok(format!("\\textsuperscript{{{}}}", self.render_vec(vec)?))
             }
@@ -449,9 +851,70 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 "\\begin{{mdblockquote}}\n{}\n\\end{{mdblockquote}}\n",
                 self.render_vec(vec)?
             )),
-            Token::CodeBlock(ref language, ref code) => {
+            Token::CodeBlock(ref info, ref code) => {
+                let info = CodeInfo::new(info);
+                let language = info.language.as_str();
+                let numbered = info.start.is_some()
+                    || self.book.options.get_bool("rendering.code.line_numbers").unwrap();
+                let (code, labels) = if numbered {
+                    let (code, labels) = parser::code_labels(code);
+                    (Cow::Owned(code), labels)
+                } else {
+                    (Cow::Borrowed(code.as_str()), vec![])
+                };
+                let count = code.lines().count();
+                let first = info.first_line(self.code_line);
+                self.code_line = (first + count as u32).saturating_sub(1);
+                let anchors: Vec<String> = labels
+                    .iter()
+                    .map(|label| match *label {
+                        Some(ref label) => self.anchor(label),
+                        None => String::new(),
+                    })
+                    .collect();
                 let mut res: String = if let Some(ref syntax) = self.syntax {
-                    syntax.to_tex(code, language)?
+                    let format_line = |i: usize, line: String| {
+                        if i >= count {
+                            return line;
+                        }
+                        let line = if info.is_highlighted(i as u32 + 1) {
+                            format!("\\mdhighlightedline{{{line}}}")
+                        } else {
+                            line
+                        };
+                        if numbered {
+                            format!(
+                                "\\makebox[1.5em][r]{{\\tiny {}}}\\quad{}{line}",
+                                first + i as u32,
+                                anchors[i]
+                            )
+                        } else {
+                            line
+                        }
+                    };
+                    syntax.to_tex(&code, language, &format_line)?
+                } else if numbered || !info.highlight.is_empty() {
+                    // Labels can't be put inside verbatim, so they point to the whole block,
+                    // and highlighted lines are marked with a star
+                    let code: Vec<String> = code
+                        .lines()
+                        .enumerate()
+                        .map(|(i, line)| {
+                            let mark = if info.is_highlighted(i as u32 + 1) { '*' } else { ' ' };
+                            if numbered {
+                                format!("{:>3}{mark} {line}", first + i as u32)
+                            } else {
+                                format!("{mark} {line}")
+                            }
+                        })
+                        .collect();
+                    format!(
+                        "{}\\begin{{spverbatim}}
+{}
+\\end{{spverbatim}}",
+                        anchors.concat(),
+                        code.join("\n")
+                    )
                 } else {
                     format!(
                         "\\begin{{spverbatim}}
@@ -464,6 +927,19 @@ impl<'a> Renderer for LatexRenderer<'a> {
 {res}
 \\end{{mdcodeblock}}"
                 );
+                if info.caption.is_some() || info.file.is_some() {
+                    let mut caption = String::new();
+                    if let Some(ref file) = info.file {
+                        caption = format!("\\texttt{{{}}}", escape::tex(file.as_str()));
+                    }
+                    if let Some(ref text) = info.caption {
+                        if !caption.is_empty() {
+                            caption.push(' ');
+                        }
+                        caption.push_str(&escape::tex(text.as_str()));
+                    }
+                    res = format!("\\mdcodecaption{{{caption}}}\n{res}");
+                }
                 Ok(res)
             }
             Token::Rule => Ok(String::from("\\mdrule\n")),
@@ -521,33 +997,136 @@ impl<'a> Renderer for LatexRenderer<'a> {
             Token::Link(ref url, _, ref vec) => {
                 let content = self.render_vec(vec)?;
 
+                if let Some(label) = url.strip_prefix('#') {
+                    if !self.hyperref {
+                        return Ok(content);
+                    }
+                    return Ok(format!("\\hyperref[{}]{{{content}}}", latex_escape::label(label)));
+                }
+
                 if self.hyperref && self.handler.contains_link(url) {
                     Ok(format!(
                         "\\hyperref[{}]{{{content}}}",
-                        escape::tex(self.handler.get_link(url)),
+                        latex_escape::label(self.handler.get_link(url)),
                     ))
                 } else {
-                    let url = escape::tex(url.as_str());
-                    if content == url {
-                        Ok(format!("\\url{{{content}}}"))
-                    } else if self
-                        .book
-                        .options
-                        .get_bool("tex.links_as_footnotes")
-                        .unwrap()
-                    {
-                        Ok(format!(
+                    let text = latex_escape::text(url.as_str());
+                    let url = latex_escape::url(url);
+                    if content == text {
+                        // Use \url so the displayed link can be broken over several lines
+                        return Ok(format!("\\url{{{url}}}"));
+                    }
+                    match self.link_style {
+                        LinkStyle::Hyperlink => Ok(format!("\\href{{{url}}}{{{content}}}")),
+                        LinkStyle::Inline => {
+                            Ok(format!("\\href{{{url}}}{{{content}}} (\\url{{{url}}})"))
+                        }
+                        LinkStyle::Footnote => Ok(format!(
                             "\\href{{{url}}}{{{content}}}\\protect\\footnote{{\\url{{{url}}}}}"
-                        ))
-                    } else {
-                        Ok(format!("\\href{{{url}}}{{{content}}}"))
+                        )),
+                        LinkStyle::Endnote => {
+                            // A URL used several times is listed once
+                            let n = match self.links.iter().position(|link| *link == url) {
+                                Some(i) => i + 1,
+                                None => {
+                                    self.links.push(url.clone());
+                                    self.links.len()
+                                }
+                            };
+                            Ok(format!("\\href{{{url}}}{{{content}}}\\textsuperscript{{[{n}]}}"))
+                        }
+                    }
+                }
+            }
+            Token::Image(ref url, _, ref alt) | Token::StandaloneImage(ref url, _, ref alt)
+                if misc::media_type(url).is_some() =>
+            {
+                // Audio and video are replaced by a placeholder
+                let text = self.render_vec(alt)?;
+                let link = match self.book.media_url(url) {
+                    Some(url) => format!("\\href{{{}}}{{{text}}}", latex_escape::url(&url)),
+                    None => text,
+                };
+                if token.is_image() {
+                    return Ok(link);
+                }
+                let poster = match self.book.options.get_path("media.poster") {
+                    Ok(poster) => format!(
+                        "\\mdstandaloneimage{{{}}}\n",
+                        self.handler.map_image(&self.source, poster)?
+                    ),
+                    Err(_) => String::new(),
+                };
+                Ok(format!("{poster}\\begin{{center}}\n{link}\n\\end{{center}}\n"))
+            }
+            Token::Verse(ref vec) => {
+                let every = self.book.options.get_i32("rendering.verse.line_numbers").unwrap();
+                let mut n = 0;
+                let mut stanzas = vec![vec![]];
+                for line in vec {
+                    match *line {
+                        // An empty line separates stanzas
+                        Token::VerseLine(_, ref v) if v.is_empty() => stanzas.push(vec![]),
+                        _ => {
+                            n += 1;
+                            let mut line = self.render_token(line)?;
+                            if every > 0 && n % every == 0 {
+                                line = format!("{line}\\hfill{{\\footnotesize {n}}}");
+                            }
+                            stanzas.last_mut().unwrap().push(line);
+                        }
                     }
                 }
+                let stanzas: Vec<String> = stanzas
+                    .iter()
+                    .filter(|lines| !lines.is_empty())
+                    .map(|lines| lines.join(" \\\\\n"))
+                    .collect();
+                Ok(format!("\\begin{{verse}}\n{}\n\\end{{verse}}\n\n", stanzas.join("\n\n")))
+            }
+            Token::Anchor(ref label) => Ok(self.anchor(label)),
+            // Rendered by the header containing them
+            Token::HeaderAttributes(..) => Ok(String::new()),
+            Token::VerseLine(indent, ref vec) => {
+                let content = self.render_vec(vec)?;
+                if indent == 0 {
+                    Ok(content)
+                } else {
+                    Ok(format!("\\hspace*{{{}em}}{content}", indent as f32 / 2.0))
+                }
             }
-            Token::StandaloneImage(ref url, _, _) => {
+            Token::Letter(ref vec) => Ok(format!(
+                "\\begin{{mdletter}}\n{}\\end{{mdletter}}\n\n",
+                self.render_vec(vec)?
+            )),
+            Token::LetterField(ref field, ref vec) => Ok(format!(
+                "\\mdletter{field}{{{}}}\n\n",
+                self.render_vec(vec)?
+            )),
+            Token::Play(ref vec) => Ok(format!(
+                "\\begin{{mdplay}}\n{}\\end{{mdplay}}\n\n",
+                self.render_vec(vec)?
+            )),
+            Token::Speech(ref speaker, ref vec) => Ok(format!(
+                "\\mdspeech{{{}}}{{{}}}\n",
+                latex_escape::text(speaker.as_str()),
+                self.render_vec(vec)?
+            )),
+            Token::StageDirection(ref vec) => Ok(format!(
+                "\\mdstagedirection{{{}}}\n",
+                self.render_vec(vec)?
+            )),
+            // The long description is only useful to readers who can't see the image
+            Token::DescribedImage(ref vec) => self.render_token(&vec[0]),
+            Token::StandaloneImage(ref url, _, ref alt) => {
                 if ResourceHandler::is_local(url) {
-                    let img = self.handler.map_image(&self.source, url.as_str())?;
-                    Ok(format!("\\mdstandaloneimage{{{img}}}\n"))
+                    let img = self.handler.map_image(&self.source, url.as_str())?.into_owned();
+                    if self.book.options.get_bool("tex.figure_caption").unwrap() && !alt.is_empty() {
+                        let caption = self.render_vec(alt)?;
+                        Ok(format!("\\mdcaptionedimage{{{img}}}{{{caption}}}\n"))
+                    } else {
+                        Ok(format!("\\mdstandaloneimage{{{img}}}\n"))
+                    }
                 } else {
                     debug!(
                         "{}",
@@ -576,7 +1155,32 @@ impl<'a> Renderer for LatexRenderer<'a> {
                     Ok(String::new())
                 }
             }
-            Token::FootnoteReference(ref reference) => Ok(format!("\\footnotemark[{reference}]")),
+            Token::FootnoteReference(ref reference) => {
+                let side_note = self
+                    .side_notes
+                    .as_mut()
+                    .and_then(|side_notes| side_notes.take(reference));
+                match side_note {
+                    Some(content) => {
+                        // The note is inside a paragraph, so its own paragraphs are rendered inline
+                        let mut paragraphs = vec![];
+                        for token in &content {
+                            paragraphs.push(match *token {
+                                Token::Paragraph(ref inner) => self.render_vec(inner)?,
+                                _ => self.render_token(token)?,
+                            });
+                        }
+                        let number = latex_escape::text(self.note_numbering.format(reference));
+                        Ok(format!(
+                            "\\textsuperscript{{{number}}}\\marginnote{{\\textsuperscript{{{number}}}~{}}}",
+                            paragraphs.join("\\par ")
+                        ))
+                    }
+                    None => Ok(format!("\\footnotemark[{reference}]")),
+                }
+            }
+            // Side notes are rendered with their reference
+            Token::FootnoteDefinition(..) if self.side_notes.is_some() => Ok(String::new()),
             Token::FootnoteDefinition(ref reference, ref v) => Ok(format!(
                 "\\footnotetext[{}]{{{}}}",
                 reference,
@@ -588,7 +1192,7 @@ impl<'a> Renderer for LatexRenderer<'a> {
                     cols.push_str("|X");
                 }
                 cols.push('|');
-                Ok(format!(
+                let table = format!(
                     "\\begin{{mdtable}}{{{}}}
 \\hline
 {}
@@ -596,7 +1200,14 @@ impl<'a> Renderer for LatexRenderer<'a> {
 \\end{{mdtable}}\n\n",
                     cols,
                     self.render_vec(vec)?
-                ))
+                );
+                // Wide tables are displayed on their own landscape page
+                match self.book.options.get_i32("tex.landscape_tables") {
+                    Ok(min) if !self.in_layout && n >= min => {
+                        Ok(format!("\\begin{{landscape}}\n{table}\\end{{landscape}}\n\n"))
+                    }
+                    _ => Ok(table),
+                }
             }
             Token::TableRow(ref vec) | Token::TableHead(ref vec) => {
                 let mut res: String = vec
@@ -617,7 +1228,7 @@ impl<'a> Renderer for LatexRenderer<'a> {
                     match *annotation {
                         Data::GrammarError(ref s) => Ok(format!(
                             "\\underline{{{content}}}\\protect\\footnote{{{}}}",
-                            escape::tex(s.as_str())
+                            latex_escape::text(s.as_str())
                         )),
                         Data::Repetition(ref colour) => {
                             if !self.escape && colour == "red" {
@@ -640,21 +1251,25 @@ pub struct ProofLatex;
 pub struct Pdf;
 pub struct ProofPdf;
 
+/// Features that LaTeX can't render as they are
+const UNSUPPORTED: &[(Feature, Fallback)] = &[
+    (Feature::Audio, Fallback::Link),
+    (Feature::Video, Fallback::Link),
+    (Feature::RemoteImage, Fallback::Omitted),
+    (Feature::ImageDescription, Fallback::Omitted),
+];
+
 impl BookRenderer for Latex {
     fn auto_path(&self, book_name: &str) -> Result<String> {
         Ok(format!("{book_name}.tex"))
     }
 
+    fn unsupported(&self, _: &Book) -> Vec<(Feature, Fallback)> {
+        UNSUPPORTED.to_vec()
+    }
+
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        let mut latex = LatexRenderer::new(book);
-        let result = latex.render_book()?;
-        to.write_all(result.as_bytes()).map_err(|e| {
-            Error::render(
-                &book.source,
-                t!("latex.write_error", error = e),
-            )
-        })?;
-        Ok(())
+        LatexRenderer::new(book).render_book(to)
     }
 }
 
@@ -663,16 +1278,56 @@ impl BookRenderer for ProofLatex {
         Ok(format!("{book_name}.proof.tex"))
     }
 
+    fn unsupported(&self, _: &Book) -> Vec<(Feature, Fallback)> {
+        UNSUPPORTED.to_vec()
+    }
+
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        let mut latex = LatexRenderer::new(book);
-        let result = latex.render_book()?;
-        to.write_all(result.as_bytes()).map_err(|e| {
-            Error::render(
-                &book.source,
-                t!("latex.write_error", error = e),
-            )
-        })?;
-        Ok(())
+        LatexRenderer::new(book).render_book(to)
+    }
+}
+
+/// Returns true if `s` is a LaTeX length with an absolute unit, e.g. `3mm` or `0.125in`
+fn is_length(s: &str) -> bool {
+    let unit_start = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = s.trim().split_at(unit_start);
+    !number.is_empty()
+        && number.parse::<f32>().map_or(false, |n| n >= 0.0)
+        && ["pt", "mm", "cm", "in", "bp", "pc", "dd", "cc"].contains(&unit)
+}
+
+/// Converts an image to grayscale for `pdf.grayscale`, keeping its format; images whose format
+/// isn't supported (e.g. PDF or SVG) are left unchanged
+fn grayscale(content: Vec<u8>) -> Result<Vec<u8>> {
+    let format = match image::guess_format(&content) {
+        Ok(format @ (image::ImageFormat::Png | image::ImageFormat::Jpeg)) => format,
+        _ => return Ok(content),
+    };
+    let image = image::load_from_memory_with_format(&content, format)
+        .map_err(|e| Error::render(Source::empty(), t!("latex.image_error", error = e)))?;
+    let mut converted = io::Cursor::new(vec![]);
+    image
+        .grayscale()
+        .write_to(&mut converted, format)
+        .map_err(|e| Error::render(Source::empty(), t!("latex.image_error", error = e)))?;
+    Ok(converted.into_inner())
+}
+
+/// Returns true if `command` runs the external Tectonic executable, which uses XeTeX
+fn is_tectonic(command: &str) -> bool {
+    Path::new(command).file_stem() == Some("tectonic".as_ref())
+}
+
+/// Returns the command used to generate a PDF file from LaTeX, set by `tex.command`, its
+/// arguments and the number of times it must be run
+pub(crate) fn pdf_command<'b>(book: &'b Book) -> (&'b str, &'static [&'static str], u32) {
+    let command = book.options.get_str("tex.command").unwrap();
+    if is_tectonic(command) {
+        // Tectonic runs as many passes as needed by itself, and deletes its log unless told
+        // not to, which is needed to explain errors
+        (command, &["--keep-logs"], 1)
+    } else {
+        (command, &[], book.options.get_i32("tex.runs").unwrap().max(1) as u32)
     }
 }
 
@@ -681,8 +1336,33 @@ impl BookRenderer for Pdf {
         Ok(format!("{book_name}.pdf"))
     }
 
+    fn required_commands(&self, book: &Book) -> Result<Vec<String>> {
+        let command = match book.options.get_str("pdf.backend").unwrap() {
+            "latex" => pdf_command(book).0.to_owned(),
+            backend => html_pdf::command_program(book, backend)?,
+        };
+        Ok(vec![command])
+    }
+
+    fn unsupported(&self, book: &Book) -> Vec<(Feature, Fallback)> {
+        match book.options.get_str("pdf.backend").unwrap() {
+            "latex" => UNSUPPORTED.to_vec(),
+            _ => vec![],
+        }
+    }
+
+    fn dry_run(&self, book: &Book) -> Result<()> {
+        match book.options.get_str("pdf.backend").unwrap() {
+            "latex" => LatexRenderer::new(book).dry_run(),
+            _ => HtmlSingle {}.dry_run(book),
+        }
+    }
+
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        LatexRenderer::new(book).render_pdf(to)?;
+        match book.options.get_str("pdf.backend").unwrap() {
+            "latex" => LatexRenderer::new(book).render_pdf(to)?,
+            backend => html_pdf::render_pdf(book, backend, to)?,
+        };
         Ok(())
     }
 }
@@ -692,24 +1372,20 @@ impl BookRenderer for ProofPdf {
         Ok(format!("{book_name}.proof.pdf"))
     }
 
+    fn required_commands(&self, book: &Book) -> Result<Vec<String>> {
+        Ok(vec![pdf_command(book).0.to_owned()])
+    }
+
+    fn unsupported(&self, _: &Book) -> Vec<(Feature, Fallback)> {
+        UNSUPPORTED.to_vec()
+    }
+
+    fn dry_run(&self, book: &Book) -> Result<()> {
+        LatexRenderer::new(book).dry_run()
+    }
+
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
         LatexRenderer::new(book).render_pdf(to)?;
         Ok(())
     }
 }
-
-/// Insert possible breaks after characters '-', '/', '_', '.', ... to avoid code exploding
-/// the page
-pub fn insert_breaks(text: &str) -> String {
-    let mut result = String::with_capacity(text.len());
-    for c in text.chars() {
-        match c {
-            '.' | '_' | ')' | '(' | '-' | '/' | ':' => {
-                result.push(c);
-                result.push_str("\\allowbreak{}");
-            }
-            _ => result.push(c),
-        }
-    }
-    result
-}
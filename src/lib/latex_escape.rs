@@ -0,0 +1,85 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Escaping of strings for the different contexts in which they can
+//! appear in a LaTeX document.
+
+use crowbook_text_processing::escape;
+
+use std::borrow::Cow;
+
+/// Escapes ordinary text, so all characters are displayed as is.
+pub fn text<'a, S: Into<Cow<'a, str>>>(s: S) -> Cow<'a, str> {
+    escape::tex(s)
+}
+
+/// Escapes inline code, allowing line breaks after some characters so it
+/// doesn't overflow the page.
+pub fn code(s: &str) -> String {
+    insert_breaks(&escape::tex(s))
+}
+
+/// Escapes an URL so it can be used as argument of `\url` or `\href`, even
+/// when these commands are themselves in the argument of another one
+/// (e.g. a footnote or a table cell).
+///
+/// `%` and `#` are escaped with a backslash, which hyperref understands;
+/// characters that can't appear in such an argument are percent-encoded.
+pub fn url(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' | '#' => {
+                res.push('\\');
+                res.push(c);
+            }
+            '\\' | '{' | '}' | '^' | ' ' | '"' => res.push_str(&format!("%{:02X}", c as u32)),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Converts a string to something that can safely be used as a label
+/// (for `\label` or `\hyperref`).
+pub fn label(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | ':' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Insert possible breaks after characters '-', '/', '_', '.', ... to avoid code exploding
+/// the page
+pub fn insert_breaks(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '.' | '_' | ')' | '(' | '-' | '/' | ':' => {
+                result.push(c);
+                result.push_str("\\allowbreak{}");
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
@@ -0,0 +1,135 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parsing of LaTeX log files, to explain why PDF generation failed.
+
+use rust_i18n::t;
+
+/// A problem found in a LaTeX log file
+#[derive(Debug, PartialEq)]
+pub struct LogError {
+    /// Readable explanation of the problem
+    pub message: String,
+    /// Line of the `.tex` file where it occurred, if known
+    pub line: Option<usize>,
+}
+
+/// Joins lines that were wrapped by TeX, which breaks lines at 79 characters
+fn unwrap_lines(log: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut wrapped = false;
+    for line in log.lines() {
+        match lines.last_mut() {
+            Some(last) if wrapped => last.push_str(line),
+            _ => lines.push(line.to_owned()),
+        }
+        wrapped = line.chars().count() == 79;
+    }
+    lines
+}
+
+/// Returns the text between the first occurrences of `start` and `end`
+fn between<'a>(s: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let i = s.find(start)? + start.len();
+    let j = s[i..].find(end)? + i;
+    Some(&s[i..j])
+}
+
+/// Converts a TeX error message to a readable explanation
+///
+/// `context` is the part of the offending line that TeX displays, up to the error.
+fn explain(error: &str, context: &str) -> String {
+    if let Some(file) = between(error, "LaTeX Error: File `", "' not found") {
+        t!("latex_log.missing_package", file = file)
+    } else if let Some(font) = between(error, "The font \"", "\" cannot be found") {
+        t!("latex_log.missing_font", font = font)
+    } else if error.contains("not loadable: Metric (TFM) file") {
+        let font = between(error, "=", " not loadable").unwrap_or(error);
+        t!("latex_log.missing_font", font = font)
+    } else if let Some(c) = between(error, "Unicode character ", " (U+") {
+        t!("latex_log.unicode", character = c)
+    } else if error == "Undefined control sequence." {
+        let command = context.split_whitespace().last().unwrap_or("");
+        t!("latex_log.undefined", command = command)
+    } else {
+        error.to_owned()
+    }
+}
+
+/// Parses a LaTeX log, returning the errors and the missing characters it reports
+pub fn parse_log(log: &str) -> Vec<LogError> {
+    let lines = unwrap_lines(log);
+    let mut errors: Vec<LogError> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(error) = line.strip_prefix("! ") {
+            let error = error.trim();
+            if error == "Emergency stop." || error.starts_with("==> Fatal error") {
+                continue;
+            }
+            // The offending line is displayed a few lines later, e.g. `l.42 some \badcommand`
+            // (possibly after an emergency stop, when TeX asked for user input)
+            let mut tex_line = None;
+            let mut context = "";
+            for next in lines.iter().skip(i + 1).take(12) {
+                if next.starts_with("! ") && next != "! Emergency stop." {
+                    break;
+                }
+                if let Some(rest) = next.strip_prefix("l.") {
+                    let n: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+                    tex_line = n.parse().ok();
+                    context = rest[n.len()..].trim();
+                    break;
+                }
+            }
+            errors.push(LogError {
+                message: explain(error, context),
+                line: tex_line,
+            });
+        } else if let Some(rest) = line.strip_prefix("Missing character: There is no ") {
+            // xelatex only warns about characters that are not in the font
+            if let Some(end) = rest.find(" in font ") {
+                let message = t!(
+                    "latex_log.missing_char",
+                    character = &rest[..end],
+                    font = rest[end + " in font ".len()..].trim_end_matches('!')
+                );
+                if !errors.iter().any(|e| e.message == message) {
+                    errors.push(LogError {
+                        message,
+                        line: None,
+                    });
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// Returns the index of the chapter containing a line of the generated `.tex` file,
+/// using the labels that are inserted at the beginning of each chapter
+pub fn chapter_of_line(tex: &str, line: usize) -> Option<usize> {
+    let mut chapter = None;
+    for l in tex.lines().take(line) {
+        if let Some(n) = l
+            .strip_prefix("\\label{chapter-")
+            .and_then(|s| s.strip_suffix('}'))
+        {
+            chapter = n.parse().ok();
+        }
+    }
+    chapter
+}
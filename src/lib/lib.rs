@@ -39,7 +39,7 @@
 //! * read the chapters (written in Markdown) listed in this
 //!   configuration file and pass them to to `Parser`, get back an AST and store it in memory
 //! * call the various renderers according to the book's parameters
-//! and generate the appropriate files.
+//!   and generate the appropriate files.
 //!
 //! ## Example
 //!
@@ -56,6 +56,19 @@
 //! bit more error handling, checking parameters from command line and so on).
 //! This is, however, not very interesting for a library usage.
 //!
+//! To embed Crowbook in another application, a book can also be built in memory,
+//! without any configuration file:
+//!
+//! ```ignore
+//! use crowbook::BookBuilder;
+//! let book = BookBuilder::new()
+//!     .title("A book")
+//!     .author("Joan Doe")
+//!     .add_chapter_from_str("# The beginning\n\nBla, bla, bla")
+//!     .build()?;
+//! book.render_format_to("epub", &mut file)?;
+//! ```
+//!
 //! The `Book` structure, however, exposes its `chapter` fields, which contains
 //! a vector with an element by chapter. With it, you can access the Markdown
 //! for all chapters represented as an Abstract Syntax Tree (i.e., a vector of `Token`s).
@@ -109,17 +122,31 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
-pub use book::Book;
+pub use book::{Book, PostRenderHook, PreRenderHook, Progress, ProgressCallback};
+pub use book_builder::BookBuilder;
 pub use book_renderer::BookRenderer;
 pub use bookoption::BookOption;
 pub use bookoptions::BookOptions;
+pub use cancellation::CancellationToken;
+pub use capabilities::{Downgrade, Fallback, Feature};
 pub use chapter::Chapter;
-pub use error::{Error, Result, Source};
+pub use config_file::ConfigFile;
+pub use date::Date;
+pub use diff::{checkout_revision, diff_books};
+pub use epub_import::import_epub;
+pub use error::{Error, ErrorKind, Result, Source};
+pub use filter::{ExternalFilter, Filter};
+pub use git::GitInfo;
+pub use migrate::{migrate, CONFIG_VERSION};
 pub use number::Number;
 pub use parser::Parser;
+pub use preview::render_html;
 pub use renderer::Renderer;
 pub use resource_handler::ResourceHandler;
+pub use slug::Slugifier;
 pub use stats::Stats;
+pub use template_vars::TemplateVar;
+pub use timings::Timings;
 pub use token::Data;
 pub use token::Token;
 
@@ -128,24 +155,66 @@ rust_i18n::i18n!("lang/lib", fallback="en");
 #[macro_use]
 mod html;
 mod book;
+mod book_builder;
 mod book_renderer;
 mod bookoptions;
+mod calibre;
+mod cancellation;
+mod capabilities;
 mod chapter;
 mod cleaner;
+mod code_block;
+mod config_file;
+mod date;
+mod diff;
+mod encoding;
 mod epub;
+mod epub_import;
 mod error;
+mod filter;
+mod git;
+mod hooks;
+mod html_cache;
 mod html_dir;
+mod html_feed;
 mod html_if;
+mod html_pdf;
 mod html_single;
+mod html_sitemap;
+mod identifier;
+mod imprint;
 mod lang;
 mod latex;
+mod latex_escape;
+mod latex_log;
+mod link_refs;
+mod manifest;
+mod migrate;
+mod notes;
 mod number;
+mod onix;
+mod opds;
+mod org;
+mod pandoc;
 mod parser;
+mod pdf_fonts;
+mod preview;
+mod publish;
+mod references;
 mod renderer;
 mod resource_handler;
+mod search_index;
+mod shortcodes;
+mod site;
+mod slug;
 mod stats;
+mod subject;
 mod syntax;
+mod template_blocks;
+mod template_vars;
+mod timings;
 mod token;
+mod units;
 
 #[cfg(feature = "binary")]
 mod style;
@@ -154,6 +223,11 @@ mod style_stubs;
 #[cfg(not(feature = "binary"))]
 use style_stubs as style;
 
+#[cfg(feature = "capi")]
+mod capi;
+#[cfg(feature = "wasm")]
+mod wasm;
+
 #[cfg(feature = "indicatif")]
 mod book_bars;
 #[cfg(not(feature = "indicatif"))]
@@ -163,6 +237,7 @@ use book_bars_stubs as book_bars;
 
 mod bookoption;
 mod misc;
+mod temp_dir;
 mod templates;
 mod text_view;
 mod zipper;
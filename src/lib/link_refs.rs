@@ -0,0 +1,234 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reference-style link definitions (e.g. `[rust]: https://rust-lang.org`) shared by all
+//! chapters, set by the `links.file` and `links.refs.{label}` options, and the way
+//! external links are displayed in each format, set by `links.style.{format}` options.
+
+use crate::bookoptions::BookOptions;
+use crate::error::{Error, Result};
+use crate::misc;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use rust_i18n::t;
+
+/// How the URL of an external link is displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStyle {
+    /// As a hyperlink only
+    Hyperlink,
+    /// In parentheses after the text of the link
+    Inline,
+    /// In a footnote
+    Footnote,
+    /// In a list of links at the end of the book
+    Endnote,
+}
+
+impl LinkStyle {
+    /// Gets the style set by the `links.style.{format}` option
+    ///
+    /// Only LaTeX supports footnotes and endnotes. If the option isn't valid, an error is
+    /// displayed and links are rendered as hyperlinks.
+    pub fn from(options: &BookOptions, format: &str) -> LinkStyle {
+        let key = format!("links.style.{format}");
+        let value = match options.get_str(&key) {
+            Ok(value) => value,
+            // Only the LaTeX option has no default value, for compatibility
+            Err(_) => {
+                return if options.get_bool("tex.links_as_footnotes").unwrap() {
+                    LinkStyle::Footnote
+                } else {
+                    LinkStyle::Hyperlink
+                }
+            }
+        };
+        let values: &[&str] = if format == "tex" {
+            &["hyperlink", "inline", "footnote", "endnote"]
+        } else {
+            &["hyperlink", "inline"]
+        };
+        match value {
+            "hyperlink" => LinkStyle::Hyperlink,
+            "inline" => LinkStyle::Inline,
+            "footnote" if format == "tex" => LinkStyle::Footnote,
+            "endnote" if format == "tex" => LinkStyle::Endnote,
+            _ => {
+                error!(
+                    "{}",
+                    t!(
+                        "error.links_style",
+                        key = key,
+                        value = value,
+                        values = values.join(", ")
+                    )
+                );
+                LinkStyle::Hyperlink
+            }
+        }
+    }
+}
+
+/// Link definitions of a book
+#[derive(Debug, Clone, Default)]
+pub struct LinkRefs {
+    /// Destination and title of each (normalized) label
+    definitions: HashMap<String, (String, String)>,
+    /// Labels, in the order they were defined
+    labels: Vec<String>,
+    /// Labels that were used by at least one chapter
+    used: HashSet<String>,
+}
+
+impl LinkRefs {
+    /// Gets the definitions of the `links.file` file, then of `links.refs.{label}` options,
+    /// which override them
+    pub fn from(options: &BookOptions) -> Result<LinkRefs> {
+        let mut refs = LinkRefs::default();
+        if let Ok(file) = options.get_path("links.file") {
+            let path = Path::new(&file);
+            if options.is_sandboxed() && !misc::is_inside(path, &options.root) {
+                return Err(Error::book_option(
+                    &options.source,
+                    t!("opt.sandbox_path", key = "links.file", value = file),
+                ));
+            }
+            let content = fs::read_to_string(path).map_err(|err| {
+                Error::file_not_found(&options.source, t!("format.links"), file.clone())
+                    .with_cause(err)
+            })?;
+            for (i, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match parse_definition(line) {
+                    Some((label, url, title)) => refs.insert(label, url, title),
+                    None => warn!(
+                        "{}",
+                        t!("warn.invalid_link", line = i + 1, file = misc::normalize(path))
+                    ),
+                }
+            }
+        }
+        for key in options.get_link_refs() {
+            let (url, title) = parse_destination(options.get_str(key)?);
+            refs.insert(normalize_label(&key["links.refs.".len()..]), url, title);
+        }
+        Ok(refs)
+    }
+
+    /// Adds a definition, replacing a previous one with the same label
+    fn insert(&mut self, label: String, url: String, title: String) {
+        if self.definitions.insert(label.clone(), (url, title)).is_none() {
+            self.labels.push(label);
+        }
+    }
+
+    /// Returns the destination and title of a normalized label
+    pub fn get(&self, label: &str) -> Option<(String, String)> {
+        self.definitions.get(label).cloned()
+    }
+
+    /// Records that a label was used by a chapter
+    pub fn mark_used(&mut self, label: &str) {
+        self.used.insert(label.to_owned());
+    }
+
+    /// Returns the labels that no chapter used
+    pub fn unused(&self) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(|label| !self.used.contains(*label))
+            .map(String::as_str)
+    }
+}
+
+/// Normalizes a label the way CommonMark does to match references with definitions,
+/// i.e. case-insensitively and collapsing whitespace
+pub fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Parses a line such as `[label]: url "title"`
+fn parse_definition(line: &str) -> Option<(String, String, String)> {
+    let line = line.trim();
+    let rest = line.strip_prefix('[')?;
+    let (label, destination) = rest.split_once("]:")?;
+    if label.trim().is_empty() || destination.trim().is_empty() {
+        return None;
+    }
+    let (url, title) = parse_destination(destination);
+    Some((normalize_label(label), url, title))
+}
+
+/// Parses the destination of a definition, e.g. `<url> "title"`, into its URL and
+/// (possibly empty) title
+fn parse_destination(destination: &str) -> (String, String) {
+    let destination = destination.trim();
+    let (url, rest) = match destination.strip_prefix('<').and_then(|s| s.split_once('>')) {
+        Some((url, rest)) => (url, rest),
+        None => destination.split_at(
+            destination
+                .find(char::is_whitespace)
+                .unwrap_or(destination.len()),
+        ),
+    };
+    let rest = rest.trim();
+    let title = ["\"\"", "''", "()"]
+        .iter()
+        .filter_map(|delimiters| {
+            let mut delimiters = delimiters.chars();
+            let (open, close) = (delimiters.next()?, delimiters.next()?);
+            rest.strip_prefix(open)?.strip_suffix(close)
+        })
+        .next()
+        .unwrap_or(rest);
+    (url.to_owned(), title.to_owned())
+}
+
+/// Returns the normalized labels of the references written explicitly in some Markdown,
+/// i.e. `[text][label]` and `[label][]`
+///
+/// Shortcut references (`[label]`) aren't returned, since they can't be distinguished from
+/// text between brackets.
+pub fn explicit_references(s: &str) -> Vec<String> {
+    let mut res = vec![];
+    let mut rest = s;
+    while let Some(start) = rest.find("][") {
+        let before = &rest[..start];
+        let after = &rest[start + 2..];
+        let label = if after.starts_with(']') {
+            before.rfind('[').map(|open| &before[open + 1..])
+        } else {
+            match after.find(|c| c == ']' || c == '[') {
+                Some(end) if after[end..].starts_with(']') => Some(&after[..end]),
+                _ => None,
+            }
+        };
+        if let Some(label) = label {
+            res.push(normalize_label(label));
+        }
+        rest = after;
+    }
+    res
+}
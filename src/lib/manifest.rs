@@ -0,0 +1,171 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Integrity manifest listing the files generated by a rendering, with their size and
+//! SHA-256 hash, so release pipelines can check the files they publish.
+
+use crate::book::Book;
+use crate::error::{Error, Result, Source};
+use crate::misc;
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust_i18n::t;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+/// A file listed in the manifest
+struct Entry {
+    format: String,
+    path: PathBuf,
+}
+
+/// Returns the hexadecimal SHA-256 hash of some content
+pub fn sha256(content: &[u8]) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in Sha256::digest(content) {
+        write!(hex, "{byte:02x}").unwrap();
+    }
+    hex
+}
+
+/// Returns the path of a file as written in the manifest: relative to the manifest's
+/// directory if it is inside it, with forward slashes on all platforms
+fn display_path(path: &Path, base: &Path) -> String {
+    let path = path.strip_prefix(base).unwrap_or(path);
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Returns the files of the rendered formats, the ones in a directory (e.g. for
+/// `html.dir`) being listed one by one, sorted by path
+fn entries(rendered: &[(String, PathBuf)]) -> Vec<Entry> {
+    let mut entries = vec![];
+    for (format, path) in rendered {
+        if path.is_dir() {
+            for file in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+                if file.file_type().is_file() {
+                    entries.push(Entry {
+                        format: format.clone(),
+                        path: file.into_path(),
+                    });
+                }
+            }
+        } else {
+            entries.push(Entry {
+                format: format.clone(),
+                path: path.clone(),
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+/// Writes the manifest of the files generated by a rendering to `manifest.file`, if it is
+/// set, then signs it if `manifest.sign.key` is set
+pub fn write(book: &Book, rendered: &[(String, PathBuf)]) -> Result<()> {
+    let file = match book.options.get_path("manifest.file") {
+        Ok(file) => PathBuf::from(file),
+        Err(_) => return Ok(()),
+    };
+    let base = file.parent().unwrap_or(Path::new(""));
+    let mut files = vec![];
+    for entry in entries(rendered) {
+        let content = fs::read(&entry.path).map_err(|err| {
+            Error::render(
+                &book.source,
+                t!("manifest.read_error", file = misc::normalize(&entry.path), error = err),
+            )
+        })?;
+        files.push(serde_json::json!({
+            "path": display_path(&entry.path, base),
+            "format": entry.format,
+            "size": content.len(),
+            "sha256": sha256(&content),
+        }));
+    }
+    let generated = chrono::DateTime::from_timestamp(book.build_timestamp(), 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string();
+    let manifest = serde_json::json!({
+        "title": book.options.get_str("title").unwrap(),
+        "version": book.options.get_str("version").ok(),
+        "generated": generated,
+        "generator": format!("crowbook {}", env!("CARGO_PKG_VERSION")),
+        "files": files,
+    });
+    let mut content = serde_json::to_string_pretty(&manifest).unwrap();
+    content.push('\n');
+    fs::write(&file, content).map_err(|err| {
+        Error::render(
+            &book.source,
+            t!("manifest.write_error", file = misc::normalize(&file), error = err),
+        )
+    })?;
+    sign(book, &file)
+}
+
+/// Signs the manifest with `manifest.sign.command` (gpg by default), writing an ASCII
+/// armored detached signature next to it
+fn sign(book: &Book, file: &Path) -> Result<()> {
+    let key = match book.options.get_str("manifest.sign.key") {
+        Ok(key) => key,
+        Err(_) => return Ok(()),
+    };
+    let line = misc::split_command(book.options.get_str("manifest.sign.command").unwrap());
+    let (program, args) = match line.split_first() {
+        Some(line) => line,
+        None => {
+            return Err(Error::render(Source::empty(), t!("manifest.no_command")));
+        }
+    };
+    let mut signature = file.as_os_str().to_owned();
+    signature.push(".asc");
+    let mut command = misc::command(program);
+    command
+        .args(args)
+        .args(["--batch", "--yes", "--local-user", key, "--armor", "--detach-sign", "--output"])
+        .arg(&signature)
+        .arg(file);
+    let output = book
+        .command_limits()
+        .output(&mut command, program)?
+        .map_err(|err| {
+            Error::render(
+                Source::empty(),
+                t!("manifest.sign_error", command = program, error = err),
+            )
+        })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::render(
+            Source::empty(),
+            t!(
+                "manifest.sign_error",
+                command = program,
+                error = String::from_utf8_lossy(&output.stderr).trim_end()
+            ),
+        ))
+    }
+}
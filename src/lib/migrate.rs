@@ -0,0 +1,130 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Migration of book configuration files written for older versions of Crowbook, translating
+//! the options that were renamed or whose value changed.
+
+use crate::bookoptions::BookOptions;
+use crate::config_file::ConfigFile;
+use crate::error::{Error, Result, Source};
+
+use rust_i18n::t;
+use yaml_rust::Yaml;
+
+/// Current version of the book configuration format, set by `crowbook.version`
+///
+/// Files without `crowbook.version` are considered to be of version 1, where deprecated
+/// options could still be used.
+pub const CONFIG_VERSION: i64 = 2;
+
+/// Translates a book configuration file to the current version of the format, returning a
+/// description of each change
+///
+/// This doesn't set `crowbook.version`. Options that were removed, or set twice (by their
+/// old and new keys), are commented out rather than deleted, so the file keeps its line
+/// numbers.
+///
+/// # Errors
+///
+/// Returns an error if the file was written for a newer version of Crowbook.
+pub fn migrate(config: &mut ConfigFile) -> Result<Vec<String>> {
+    let version = match config.get("crowbook.version") {
+        None => 1,
+        Some(Yaml::Integer(version)) => version,
+        Some(value) => {
+            return Err(Error::config_parser(
+                Source::empty(),
+                t!("migrate.not_int", value = format!("{value:?}")),
+            ))
+        }
+    };
+    if version > CONFIG_VERSION {
+        return Err(Error::config_parser(
+            Source::empty(),
+            t!("migrate.too_new", version = version, latest = CONFIG_VERSION),
+        ));
+    }
+    let mut changes = vec![];
+    if version < 2 {
+        to_v2(config, &mut changes);
+    }
+    Ok(changes)
+}
+
+/// Version 2 removed the deprecated keys
+fn to_v2(config: &mut ConfigFile, changes: &mut Vec<String>) {
+    // `html.highlight_code` was a boolean, `rendering.highlight` is the library to use
+    if let Some(Yaml::Boolean(value)) = config.get("html.highlight_code") {
+        let highlight = if value { "highlight.js" } else { "none" };
+        if config.get("rendering.highlight").is_none() {
+            config.rename("html.highlight_code", "rendering.highlight");
+            config.set("rendering.highlight", Yaml::String(highlight.to_owned()));
+            changes.push(t!(
+                "migrate.highlight",
+                value = value,
+                highlight = highlight
+            ));
+        }
+    }
+
+    // The multiple files HTML format was named `html_dir`
+    if let Some(Yaml::Array(formats)) = config.get("output") {
+        let old = Yaml::String(String::from("html_dir"));
+        if formats.contains(&old) {
+            let formats = formats
+                .into_iter()
+                .map(|format| {
+                    if format == old {
+                        Yaml::String(String::from("html.dir"))
+                    } else {
+                        format
+                    }
+                })
+                .collect();
+            config.set("output", Yaml::Array(formats));
+            changes.push(t!("migrate.output"));
+        }
+    }
+
+    let options = BookOptions::new();
+    let deprecated = options.deprecated();
+    let keys: Vec<String> = config.keys().into_iter().map(String::from).collect();
+    for key in keys {
+        let mut new_key = match deprecated.get(&key) {
+            None => continue,
+            Some(new_key) => new_key,
+        };
+        // Some keys were renamed more than once
+        while let Some(Some(next)) = new_key.as_ref().map(|k| deprecated.get(k)) {
+            new_key = next;
+        }
+        match new_key {
+            None => {
+                config.comment_out(&key);
+                changes.push(t!("migrate.removed", key = &key));
+            }
+            Some(new_key) if config.keys().contains(&new_key.as_str()) => {
+                config.comment_out(&key);
+                changes.push(t!("migrate.duplicate", key = &key, new_key = new_key));
+            }
+            Some(new_key) => {
+                config.rename(&key, new_key);
+                changes.push(t!("migrate.renamed", old_key = &key, new_key = new_key));
+            }
+        }
+    }
+}
@@ -17,10 +17,14 @@
 
 //! Misc utility functions used across crowbook
 
+use crate::error::{Error, Source};
 use crate::token::Token;
+use rust_i18n::t;
 
+use std::env;
 use std::io::Result;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
 use base64::Engine;
 
 
@@ -31,12 +35,12 @@ pub fn normalize<P: AsRef<Path>>(path: P) -> String {
 }
 
 fn try_normalize<P: AsRef<Path>>(path: P) -> Result<String> {
-    let full_path = std::fs::canonicalize(path.as_ref())?;
+    let full_path = canonicalize(path.as_ref())?;
     let mut cwd = std::env::current_dir()?;
     let mut ups = 0;
 
     loop {
-        if let Ok(path) = full_path.strip_prefix(&cwd.clone()) {
+        if let Ok(path) = full_path.strip_prefix(&cwd) {
             let mut new_path = PathBuf::new();
             for _ in 0..ups {
                 new_path.push("../");
@@ -51,6 +55,37 @@ fn try_normalize<P: AsRef<Path>>(path: P) -> Result<String> {
     }
 }
 
+/// Canonicalizes a path like `std::fs::canonicalize`, but without the `\\?\` prefix
+/// of verbatim paths on Windows (e.g. `\\?\C:\book` or `\\?\UNC\server\share`), which
+/// can't be compared with other paths nor be displayed to users
+pub fn canonicalize<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = std::fs::canonicalize(path)?;
+    let s = path.to_string_lossy();
+    Ok(if let Some(unc) = s.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{unc}"))
+    } else if let Some(local) = s.strip_prefix(r"\\?\") {
+        PathBuf::from(local)
+    } else {
+        path
+    })
+}
+
+/// Removes the `.` and `..` components of a relative path, without accessing the file system,
+/// and uses `/` as separator, e.g. `chapters/../data\foo.csv` becomes `data/foo.csv`
+pub fn clean_path<P: AsRef<Path>>(path: P) -> String {
+    let mut res: Vec<String> = vec![];
+    for component in path.as_ref().to_string_lossy().split(|c| c == '/' || c == '\\') {
+        match component {
+            "" | "." => (),
+            ".." if res.last().map_or(false, |c| c != "..") => {
+                res.pop();
+            }
+            _ => res.push(component.to_owned()),
+        }
+    }
+    res.join("/")
+}
+
 /// Insert a title (if there is none) to a vec of tokens
 pub fn insert_title(tokens: &mut Vec<Token>) {
     for token in tokens.iter() {
@@ -65,3 +100,202 @@ pub fn insert_title(tokens: &mut Vec<Token>) {
 pub fn u8_to_base64(s: &[u8]) -> String {
     base64::engine::general_purpose::STANDARD_NO_PAD.encode(s)
 }
+
+/// Checks that a path is relative and doesn't go above its base directory
+/// (i.e. it contains no `..` component)
+pub fn is_contained<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Checks that a file is located inside a directory, once symbolic
+/// links and `..` components have been resolved.
+///
+/// Returns false if either path can't be canonicalized.
+pub fn is_inside<P1: AsRef<Path>, P2: AsRef<Path>>(file: P1, dir: P2) -> bool {
+    let dir = if dir.as_ref() == Path::new("") {
+        Path::new(".")
+    } else {
+        dir.as_ref()
+    };
+    match (std::fs::canonicalize(file), std::fs::canonicalize(dir)) {
+        (Ok(file), Ok(dir)) => file.starts_with(dir),
+        _ => false,
+    }
+}
+
+/// Checks that a path, relative to a directory, stays inside it: it must contain no `..`
+/// component, and its longest existing part must be inside the directory once symbolic
+/// links are resolved. Contrary to `is_inside`, the file itself doesn't have to exist
+/// (e.g. output files).
+pub fn stays_inside<P1: AsRef<Path>, P2: AsRef<Path>>(path: P1, dir: P2) -> bool {
+    let dir = if dir.as_ref() == Path::new("") {
+        Path::new(".")
+    } else {
+        dir.as_ref()
+    };
+    if !is_contained(&path) {
+        return false;
+    }
+    let full = dir.join(path);
+    full.ancestors()
+        .find(|p| p.exists())
+        .map_or(false, |existing| is_inside(existing, dir))
+}
+
+/// Placeholder given to templates instead of the content of the book, so that the parts of
+/// the rendered template around it can be written before and after the chapters, which are
+/// streamed one by one instead of being rendered to a single string
+pub const CONTENT_PLACEHOLDER: &str = "\u{1}crowbook-content\u{1}";
+
+/// Splits a template rendered with `CONTENT_PLACEHOLDER` into the parts before and after
+/// the content
+///
+/// **Returns** an error if the template doesn't display the placeholder exactly once as it
+/// is, e.g. if it is missing, repeated or passed through a filter: the chapters would then
+/// be dropped or not written where expected.
+pub fn split_content<'r>(
+    rendered: &'r str,
+    template: &str,
+    source: &Source,
+) -> crate::error::Result<(&'r str, &'r str)> {
+    let n = rendered.matches(CONTENT_PLACEHOLDER).count();
+    match rendered.split_once(CONTENT_PLACEHOLDER) {
+        Some(parts) if n == 1 => Ok(parts),
+        _ => Err(Error::template(
+            source,
+            t!("template.content", template = template, n = n),
+        )),
+    }
+}
+
+/// Hashes some bytes with FNV-1a, which (contrary to the hasher of the standard library)
+/// is guaranteed to give the same result on all platforms and Rust versions
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Checks whether a command can be run, i.e. the program exists, looking for it
+/// in the `PATH` if it is not a path.
+pub fn command_exists(program: &str) -> bool {
+    find_program(program).is_some()
+}
+
+/// Finds the file of a program, looking for it in the `PATH` if it is not a path
+///
+/// On Windows, the extensions listed in `PATHEXT` (e.g. `.exe` or `.bat`) are also tried.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let mut extensions = vec![String::new()];
+    if cfg!(windows) {
+        let pathext = env::var("PATHEXT").unwrap_or_else(|_| String::from(".EXE;.BAT;.CMD"));
+        extensions.extend(
+            pathext
+                .split(';')
+                .filter(|ext| !ext.is_empty())
+                .map(|ext| ext.to_owned()),
+        );
+    }
+    let find = |path: &Path| {
+        extensions
+            .iter()
+            .map(|ext| PathBuf::from(format!("{}{ext}", path.display())))
+            .find(|file| file.is_file())
+    };
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return find(path);
+    }
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths).find_map(|dir| find(&dir.join(program)))
+}
+
+/// Returns a command running a program, which is looked for in the `PATH`
+///
+/// Contrary to `Command::new`, this also finds batch files on Windows.
+pub fn command(program: &str) -> Command {
+    match find_program(program) {
+        Some(file) => Command::new(file),
+        None => Command::new(program),
+    }
+}
+
+/// Splits a command line into its program and arguments
+///
+/// Arguments containing spaces (e.g. `"C:\Program Files\app.exe"`) can be put in double or
+/// single quotes. Backslashes are kept as is, except before a double quote in double quotes.
+pub fn split_command(line: &str) -> Vec<String> {
+    let mut res = vec![];
+    let mut current: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => {
+                if let Some(arg) = current.take() {
+                    res.push(arg);
+                }
+            }
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            }
+            (Some('"'), '\\') if chars.peek() == Some(&'"') => {
+                current.get_or_insert_with(String::new).push('"');
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (_, c) => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    res.extend(current);
+    res
+}
+
+/// Converts a path to a relative URL, using `/` as separator whatever the platform
+pub fn path_to_url<P: AsRef<Path>>(path: P) -> String {
+    path.as_ref().display().to_string().replace('\\', "/")
+}
+
+/// Returns true if a path is absolute, including Windows paths such as `C:\file` or `\\server\share`
+pub fn is_absolute_path(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    path.starts_with('/')
+        || path.starts_with('\\')
+        || (bytes.len() > 2
+            && bytes[0].is_ascii_alphabetic()
+            && bytes[1] == b':'
+            && matches!(bytes[2], b'/' | b'\\'))
+}
+
+/// Returns `audio` or `video` if an URL points to an audio or video file, based on its extension
+pub fn media_type(url: &str) -> Option<&'static str> {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or_default();
+    let mime = mime_guess::from_path(path).first()?;
+    match mime.type_().as_str() {
+        "audio" => Some("audio"),
+        "video" => Some("video"),
+        _ => None,
+    }
+}
+
+/// Checks that following an URL can't run code, i.e. it doesn't use the
+/// `javascript:` or `vbscript:` schemes, nor `data:` unless it is an image.
+pub fn is_safe_url(url: &str, image: bool) -> bool {
+    // Browsers ignore whitespace and control characters in schemes
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .take(16)
+        .collect::<String>()
+        .to_lowercase();
+    if url.starts_with("javascript:") || url.starts_with("vbscript:") {
+        false
+    } else if url.starts_with("data:") {
+        image && url.starts_with("data:image/")
+    } else {
+        true
+    }
+}
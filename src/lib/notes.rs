@@ -0,0 +1,126 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Numbering of footnotes (`rendering.notes.numbering` option) and side notes, i.e.
+//! footnotes displayed in the margin next to their reference (`html.side_notes` and
+//! `tex.side_notes` options).
+
+use crate::bookoptions::BookOptions;
+use crate::token::Token;
+
+use std::collections::HashMap;
+
+use numerals::roman::Roman;
+use rust_i18n::t;
+
+/// Symbols used by the `symbols` numbering, doubled, then tripled, and so on when
+/// there are more notes
+const SYMBOLS: [char; 6] = ['*', '†', '‡', '§', '‖', '¶'];
+
+/// How footnotes are numbered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteNumbering {
+    Arabic,
+    Roman,
+    Alpha,
+    Symbols,
+}
+
+impl NoteNumbering {
+    /// Gets the numbering set by the `rendering.notes.numbering` option
+    pub fn from(options: &BookOptions) -> NoteNumbering {
+        match options.get_str("rendering.notes.numbering").unwrap() {
+            "arabic" => NoteNumbering::Arabic,
+            "roman" => NoteNumbering::Roman,
+            "alpha" => NoteNumbering::Alpha,
+            "symbols" => NoteNumbering::Symbols,
+            value => {
+                error!("{}", t!("error.notes_numbering", value = value));
+                NoteNumbering::Arabic
+            }
+        }
+    }
+
+    /// Returns the name of the LaTeX command displaying a counter with this numbering
+    pub fn latex_command(self) -> &'static str {
+        match self {
+            NoteNumbering::Arabic => "arabic",
+            NoteNumbering::Roman => "roman",
+            NoteNumbering::Alpha => "alph",
+            NoteNumbering::Symbols => "fnsymbol",
+        }
+    }
+
+    /// Returns the text displayed for a note
+    ///
+    /// Footnotes are numbered (from 1) by the Markdown parser, so the label of the note is
+    /// its number; other labels are kept as is.
+    pub fn format(self, label: &str) -> String {
+        let n = match label.parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => return label.to_owned(),
+        };
+        match self {
+            NoteNumbering::Arabic => n.to_string(),
+            NoteNumbering::Roman => format!("{:x}", Roman::from(n as i16)),
+            NoteNumbering::Alpha => alpha(n),
+            NoteNumbering::Symbols => {
+                let symbol = SYMBOLS[(n as usize - 1) % SYMBOLS.len()];
+                std::iter::repeat(symbol)
+                    .take((n as usize - 1) / SYMBOLS.len() + 1)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Definitions of the footnotes of a chapter, when they are displayed as side notes
+#[derive(Debug, Clone, Default)]
+pub struct SideNotes {
+    /// Content of the footnote definitions not rendered yet
+    definitions: HashMap<String, Vec<Token>>,
+}
+
+impl SideNotes {
+    /// Collects the footnote definitions of a chapter, so they can be rendered where they are
+    /// referenced
+    pub fn collect(&mut self, tokens: &[Token]) {
+        self.definitions.clear();
+        for token in tokens {
+            if let Token::FootnoteDefinition(ref label, ref content) = *token {
+                self.definitions.insert(label.clone(), content.clone());
+            }
+        }
+    }
+
+    /// Returns the content of the note referenced by a label, the first time it is referenced
+    pub fn take(&mut self, label: &str) -> Option<Vec<Token>> {
+        self.definitions.remove(label)
+    }
+}
+
+/// Returns a, b, …, z, aa, ab, …
+fn alpha(mut n: u32) -> String {
+    let mut res = vec![];
+    while n > 0 {
+        n -= 1;
+        res.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    res.reverse();
+    String::from_utf8(res).unwrap()
+}
@@ -0,0 +1,395 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Export of the book's metadata as an ONIX 3.0 product record, the format publishers
+//! and distributors exchange book metadata in.
+
+use crate::book::Book;
+use crate::book_renderer::BookRenderer;
+use crate::error::{Error, Result};
+use crate::identifier;
+use crate::subject;
+
+use std::fmt::Write as _;
+use std::io::Write;
+
+use crowbook_text_processing::escape;
+use rust_i18n::t;
+
+/// ONIX codes (list 17) of the roles that can be used in `onix.contributors`
+const ROLES: &[(&str, &str)] = &[
+    ("author", "A01"),
+    ("illustrator", "A12"),
+    ("foreword", "A23"),
+    ("introduction", "A24"),
+    ("afterword", "A19"),
+    ("cover_designer", "A36"),
+    ("editor", "B01"),
+    ("translator", "B06"),
+    ("narrator", "E07"),
+];
+
+/// ISO 639-2/B codes, required by ONIX, of the languages Crowbook is most used with
+const LANGUAGES: &[(&str, &str)] = &[
+    ("ca", "cat"),
+    ("de", "ger"),
+    ("en", "eng"),
+    ("es", "spa"),
+    ("fr", "fre"),
+    ("it", "ita"),
+    ("ja", "jpn"),
+    ("nl", "dut"),
+    ("pt", "por"),
+    ("ru", "rus"),
+    ("zh", "chi"),
+];
+
+/// Returns the ONIX code of a contributor role, which is either a name of `ROLES` or a code
+fn role_code(role: &str) -> Option<&str> {
+    let role = role.trim();
+    if let Some((_, code)) = ROLES.iter().find(|(name, _)| name.eq_ignore_ascii_case(role)) {
+        return Some(code);
+    }
+    let bytes = role.as_bytes();
+    if bytes.len() == 3 && bytes[0].is_ascii_uppercase() && bytes[1..].iter().all(u8::is_ascii_digit) {
+        Some(role)
+    } else {
+        None
+    }
+}
+
+/// Returns the ISO 639-2/B code of a language set by `lang`, e.g. `fre` for `fr-FR`
+fn language_code(lang: &str) -> String {
+    let primary = lang.split(['-', '_']).next().unwrap_or(lang).to_lowercase();
+    match LANGUAGES.iter().find(|(code, _)| *code == primary) {
+        Some((_, code)) => code.to_string(),
+        None => primary,
+    }
+}
+
+/// Returns a date in the `YYYYMMDD` format of ONIX, if it starts with a `YYYY-MM-DD` date
+fn onix_date(date: &str) -> Option<String> {
+    let day = date.get(..10)?;
+    let valid = day.char_indices().all(|(i, c)| match i {
+        4 | 7 => c == '-',
+        _ => c.is_ascii_digit(),
+    });
+    if valid {
+        Some(day.replace('-', ""))
+    } else {
+        None
+    }
+}
+
+/// Renders the book's metadata as an ONIX 3.0 product record
+pub struct Onix {}
+
+impl Onix {
+    /// Returns the `<Contributor>` elements: the author, then `onix.contributors`
+    fn contributors(book: &Book) -> Result<Vec<String>> {
+        let mut contributors = vec![];
+        let author = book.options.get_str("author").unwrap();
+        if !author.is_empty() {
+            contributors.push(("A01", author));
+        }
+        if let Ok(list) = book.options.get_str_vec("onix.contributors") {
+            for item in list {
+                let code = item
+                    .split_once(':')
+                    .and_then(|(role, name)| Some((role_code(role)?, name.trim())))
+                    .filter(|(_, name)| !name.is_empty());
+                match code {
+                    Some(contributor) => contributors.push(contributor),
+                    None => {
+                        return Err(Error::book_option(
+                            &book.options.source,
+                            t!("onix.invalid_contributor", value = item),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(contributors
+            .into_iter()
+            .enumerate()
+            .map(|(i, (role, name))| {
+                format!(
+                    "<Contributor>
+          <SequenceNumber>{}</SequenceNumber>
+          <ContributorRole>{role}</ContributorRole>
+          <PersonName>{}</PersonName>
+        </Contributor>",
+                    i + 1,
+                    escape::html(name)
+                )
+            })
+            .collect())
+    }
+
+    /// Returns the `<Price>` elements set by `onix.prices`
+    fn prices(book: &Book) -> Result<Vec<String>> {
+        let mut prices = vec![];
+        if let Ok(list) = book.options.get_str_vec("onix.prices") {
+            for item in list {
+                let price = item.split_once(' ').and_then(|(amount, currency)| {
+                    amount.parse::<f64>().ok()?;
+                    let currency = currency.trim();
+                    if currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase()) {
+                        Some((amount, currency))
+                    } else {
+                        None
+                    }
+                });
+                match price {
+                    Some((amount, currency)) => prices.push(format!(
+                        "<Price>
+          <PriceType>{}</PriceType>
+          <PriceAmount>{amount}</PriceAmount>
+          <CurrencyCode>{currency}</CurrencyCode>
+        </Price>",
+                        escape::html(book.options.get_str("onix.price_type").unwrap())
+                    )),
+                    None => {
+                        return Err(Error::book_option(
+                            &book.options.source,
+                            t!("onix.invalid_price", value = item),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(prices)
+    }
+
+    /// Returns the ONIX record of the book
+    pub fn record(book: &Book) -> Result<String> {
+        let sent = chrono::DateTime::from_timestamp(book.build_timestamp(), 0)
+            .unwrap_or_default()
+            .format("%Y%m%dT%H%MZ");
+        let publisher = book.options.get_str("onix.publisher").ok();
+        let sender = publisher.unwrap_or(book.options.get_str("author").unwrap());
+        // Digital products (whose form starts with E) get the EPUB ISBN, other ones the print one
+        let product_form = book.options.get_str("onix.product_form").unwrap();
+        let edition = if product_form.starts_with('E') { "epub" } else { "print" };
+        let isbn = book
+            .options
+            .get_str("onix.isbn")
+            .ok()
+            .or_else(|| identifier::isbn(&book.options, edition));
+        let mut identifier = match isbn {
+            Some(isbn) => format!(
+                "<ProductIDType>15</ProductIDType>
+      <IDValue>{}</IDValue>",
+                identifier::normalize_isbn(isbn)
+            ),
+            None => format!(
+                "<ProductIDType>01</ProductIDType>
+      <IDTypeName>UUID</IDTypeName>
+      <IDValue>{}</IDValue>",
+                book.uuid()
+            ),
+        };
+        if let Ok(doi) = book.options.get_str("doi") {
+            write!(
+                identifier,
+                "
+    </ProductIdentifier>
+    <ProductIdentifier>
+      <ProductIDType>06</ProductIDType>
+      <IDValue>{}</IDValue>",
+                escape::html(doi)
+            )
+            .unwrap();
+        }
+
+        let mut descriptive = vec![
+            String::from("<ProductComposition>00</ProductComposition>"),
+            format!(
+                "<ProductForm>{}</ProductForm>",
+                escape::html(product_form)
+            ),
+        ];
+        let mut title = format!(
+            "<TitleDetail>
+        <TitleType>01</TitleType>
+        <TitleElement>
+          <TitleElementLevel>01</TitleElementLevel>
+          <TitleText>{}</TitleText>",
+            escape::html(book.options.get_str("title").unwrap())
+        );
+        if let Ok(subtitle) = book.options.get_str("subtitle") {
+            write!(title, "\n          <Subtitle>{}</Subtitle>", escape::html(subtitle)).unwrap();
+        }
+        title.push_str("\n        </TitleElement>\n      </TitleDetail>");
+        descriptive.push(title);
+        descriptive.extend(Onix::contributors(book)?);
+        descriptive.push(format!(
+            "<Language>
+        <LanguageRole>01</LanguageRole>
+        <LanguageCode>{}</LanguageCode>
+      </Language>",
+            escape::html(language_code(book.options.get_str("lang").unwrap()))
+        ));
+        if let Ok(codes) = book.options.get_str_vec("subject.bisac") {
+            for (i, code) in codes.iter().enumerate() {
+                descriptive.push(format!(
+                    "<Subject>{}
+        <SubjectSchemeIdentifier>10</SubjectSchemeIdentifier>
+        <SubjectCode>{}</SubjectCode>
+      </Subject>",
+                    if i == 0 { "\n        <MainSubject/>" } else { "" },
+                    escape::html(code.trim())
+                ));
+            }
+        }
+        if let Ok(codes) = book.options.get_str_vec("subject.thema") {
+            let mut main = true;
+            for code in codes {
+                let code = code.trim();
+                // Qualifiers have their own schemes (94 to 99), depending on their first digit
+                let scheme = match code.as_bytes()[0] {
+                    digit @ b'1'..=b'6' => 93 + u32::from(digit - b'0'),
+                    _ => 93,
+                };
+                let main_subject = if scheme == 93 && main {
+                    main = false;
+                    "\n        <MainSubject/>"
+                } else {
+                    ""
+                };
+                descriptive.push(format!(
+                    "<Subject>{main_subject}
+        <SubjectSchemeIdentifier>{scheme}</SubjectSchemeIdentifier>
+        <SubjectCode>{}</SubjectCode>
+      </Subject>",
+                    escape::html(code)
+                ));
+            }
+        }
+        let keywords = subject::keywords(&book.options);
+        if !keywords.is_empty() {
+            descriptive.push(format!(
+                "<Subject>
+        <SubjectSchemeIdentifier>20</SubjectSchemeIdentifier>
+        <SubjectHeadingText>{}</SubjectHeadingText>
+      </Subject>",
+                escape::html(keywords.join("; "))
+            ));
+        }
+
+        let mut record = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<ONIXMessage release=\"3.0\" xmlns=\"http://ns.editeur.org/onix/3.0/reference\">
+  <Header>
+    <Sender>
+      <SenderName>{sender}</SenderName>
+    </Sender>
+    <SentDateTime>{sent}</SentDateTime>
+  </Header>
+  <Product>
+    <RecordReference>urn:uuid:{reference}</RecordReference>
+    <NotificationType>03</NotificationType>
+    <ProductIdentifier>
+      {identifier}
+    </ProductIdentifier>
+    <DescriptiveDetail>
+      {descriptive}
+    </DescriptiveDetail>
+",
+            sender = escape::html(sender),
+            reference = book.uuid(),
+            descriptive = descriptive.join("\n      "),
+        );
+        if let Ok(description) = book.options.get_str("description") {
+            write!(
+                record,
+                "    <CollateralDetail>
+      <TextContent>
+        <TextType>03</TextType>
+        <ContentAudience>00</ContentAudience>
+        <Text>{}</Text>
+      </TextContent>
+    </CollateralDetail>
+",
+                escape::html(description)
+            )
+            .unwrap();
+        }
+        let mut publishing = vec![];
+        if let Some(publisher) = publisher {
+            publishing.push(format!(
+                "<Publisher>
+        <PublishingRole>01</PublishingRole>
+        <PublisherName>{}</PublisherName>
+      </Publisher>",
+                escape::html(publisher)
+            ));
+        }
+        let date = book.options.get_str("publication_date").or_else(|_| book.options.get_str("date"));
+        if let Some(date) = date.ok().and_then(onix_date) {
+            publishing.push(format!(
+                "<PublishingDate>
+        <PublishingDateRole>01</PublishingDateRole>
+        <Date>{date}</Date>
+      </PublishingDate>"
+            ));
+        }
+        if !publishing.is_empty() {
+            write!(
+                record,
+                "    <PublishingDetail>\n      {}\n    </PublishingDetail>\n",
+                publishing.join("\n      ")
+            )
+            .unwrap();
+        }
+        let prices = Onix::prices(book)?;
+        if !prices.is_empty() {
+            write!(
+                record,
+                "    <ProductSupply>
+      <SupplyDetail>
+        <Supplier>
+          <SupplierRole>01</SupplierRole>
+          <SupplierName>{}</SupplierName>
+        </Supplier>
+        <ProductAvailability>20</ProductAvailability>
+        {}
+      </SupplyDetail>
+    </ProductSupply>
+",
+                escape::html(sender),
+                prices.join("\n        ")
+            )
+            .unwrap();
+        }
+        record.push_str("  </Product>\n</ONIXMessage>\n");
+        Ok(record)
+    }
+}
+
+impl BookRenderer for Onix {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.onix.xml"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn Write) -> Result<()> {
+        let record = Onix::record(book)?;
+        to.write_all(record.as_bytes()).map_err(|e| {
+            Error::render(&book.source, t!("onix.write_error", error = e))
+        })
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! OPDS 1.2 catalog of the book, so e-reader apps can download the files it links to.
+
+use crate::book::Book;
+use crate::book_renderer::BookRenderer;
+use crate::error::{Error, Result};
+
+use std::io::Write;
+use std::path::Path;
+
+use crowbook_text_processing::escape;
+use rust_i18n::t;
+
+/// Formats linked by the catalog if `opds.formats` isn't set
+const FORMATS: &[&str] = &["epub"];
+
+/// Renders an OPDS acquisition feed whose only entry is the book
+pub struct Opds {}
+
+impl Opds {
+    /// Returns the URL of a file published next to the catalog
+    fn url(book: &Book, file: &str) -> String {
+        match book.options.get_str("opds.base_url") {
+            Ok(base) => format!("{}/{file}", base.trim_end_matches('/')),
+            Err(_) => file.to_owned(),
+        }
+    }
+
+    /// Returns the name of the file a format is rendered to, i.e. the file name of
+    /// `output.{format}`, or the one inferred from the book's file if it isn't set or is `auto`
+    fn file_name(book: &Book, format: &str) -> Result<String> {
+        let (_, renderer) = book.renderer(format)?;
+        if let Ok(path) = book.options.get_path(&format!("output.{format}")) {
+            let path = Path::new(&path);
+            if !path.ends_with("auto") {
+                if let Some(name) = path.file_name() {
+                    return Ok(name.to_string_lossy().into_owned());
+                }
+            }
+        }
+        renderer.auto_path(&book.file_stem().unwrap_or_else(|| String::from("book")))
+    }
+
+    /// Returns the date the catalog was updated, in the RFC 3339 format required by Atom
+    fn updated(book: &Book) -> String {
+        chrono::DateTime::from_timestamp(book.build_timestamp(), 0)
+            .unwrap_or_default()
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string()
+    }
+
+    /// Returns the `<link>` elements of the entry: the cover and the files to download
+    fn links(book: &Book) -> Result<Vec<String>> {
+        let mut links = vec![];
+        if let Ok(cover) = book.options.get_path("cover") {
+            let file = Path::new(&cover)
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or(cover.clone());
+            let mime = mime_guess::from_path(&file).first_or_octet_stream();
+            for rel in ["http://opds-spec.org/image", "http://opds-spec.org/image/thumbnail"] {
+                links.push(format!(
+                    "<link rel=\"{rel}\" type=\"{mime}\" href=\"{}\"/>",
+                    html_escape::encode_double_quoted_attribute(&Opds::url(book, &file))
+                ));
+            }
+        }
+        let formats = match book.options.get_str_vec("opds.formats") {
+            Ok(formats) => formats.to_vec(),
+            Err(_) => FORMATS.iter().map(|f| f.to_string()).collect(),
+        };
+        for format in formats {
+            let file = Opds::file_name(book, &format)?;
+            let mime = mime_guess::from_path(&file).first_or_octet_stream();
+            links.push(format!(
+                "<link rel=\"http://opds-spec.org/acquisition\" type=\"{mime}\" href=\"{}\"/>",
+                html_escape::encode_double_quoted_attribute(&Opds::url(book, &file))
+            ));
+        }
+        Ok(links)
+    }
+
+    /// Returns the OPDS catalog of the book
+    pub fn catalog(book: &Book) -> Result<String> {
+        let updated = Opds::updated(book);
+        let title = escape::html(book.options.get_str("title").unwrap()).into_owned();
+        let author = escape::html(book.options.get_str("author").unwrap()).into_owned();
+
+        let mut entry = vec![
+            format!("<title>{title}</title>"),
+            format!("<author><name>{author}</name></author>"),
+            format!("<id>urn:uuid:{}</id>", book.uuid()),
+            format!("<updated>{updated}</updated>"),
+            format!(
+                "<dc:language>{}</dc:language>",
+                escape::html(book.options.get_str("lang").unwrap())
+            ),
+        ];
+        let date = book.options.get_str("publication_date").or_else(|_| book.options.get_str("date"));
+        if let Ok(date) = date {
+            entry.push(format!("<dc:issued>{}</dc:issued>", escape::html(date)));
+        }
+        if let Ok(subject) = book.options.get_str("subject") {
+            entry.push(format!(
+                "<category term=\"{}\"/>",
+                html_escape::encode_double_quoted_attribute(subject)
+            ));
+        }
+        if let Ok(description) = book.options.get_str("description") {
+            entry.push(format!("<summary>{}</summary>", escape::html(description)));
+        }
+        entry.extend(Opds::links(book)?);
+
+        Ok(format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/terms/\" xmlns:opds=\"http://opds-spec.org/2010/catalog\">
+  <id>urn:uuid:{feed_id}</id>
+  <title>{title}</title>
+  <updated>{updated}</updated>
+  <author><name>{author}</name></author>
+  <generator>Crowbook</generator>
+  <entry>
+    {entry}
+  </entry>
+</feed>
+",
+            feed_id = book.stable_uuid("opds"),
+            entry = entry.join("\n    "),
+        ))
+    }
+}
+
+impl BookRenderer for Opds {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.opds.xml"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn Write) -> Result<()> {
+        to.write_all(Opds::catalog(book)?.as_bytes()).map_err(|e| {
+            Error::render(&book.source, t!("opds.write_error", error = e))
+        })
+    }
+}
@@ -0,0 +1,559 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A minimal parser for Emacs org-mode documents.
+//!
+//! Supports headings, paragraphs, lists (including checkboxes and description
+//! lists), source, example, quote and verse blocks, tables, footnotes, links,
+//! images and emphasis markers. In-buffer settings such as `#+TITLE:` are
+//! converted to book options.
+
+use crate::parser::Features;
+use crate::token::Token;
+
+
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter};
+
+/// Extensions of links that are displayed as images
+const IMAGE_EXTENSIONS: &[&str] = &[".png", ".jpg", ".jpeg", ".gif", ".svg", ".webp"];
+
+/// In-buffer settings that are converted to book options
+const SETTINGS: &[(&str, &str)] = &[
+    ("title", "title"),
+    ("author", "author"),
+    ("subtitle", "subtitle"),
+    ("date", "date"),
+    ("description", "description"),
+    ("language", "lang"),
+    ("subject", "subject"),
+];
+
+/// Parses an org-mode document and returns a list of tokens.
+///
+/// If `yaml` is set, fill it with a YAML block corresponding to the document's
+/// in-buffer settings (e.g. `#+TITLE:`).
+pub fn parse_org(s: &str, yaml: Option<&mut String>, features: &mut Features) -> Vec<Token> {
+    let lines: Vec<&str> = s.lines().collect();
+    let mut parser = OrgParser {
+        features,
+        settings: Hash::new(),
+        notes: vec![],
+        anonymous_notes: 0,
+    };
+    let mut tokens = parser.blocks(&lines);
+    tokens.append(&mut parser.notes);
+
+    if let Some(yaml) = yaml {
+        if !parser.settings.is_empty() {
+            let mut emitter = YamlEmitter::new(yaml);
+            // Only contains strings, so this can't fail
+            emitter.dump(&Yaml::Hash(parser.settings)).unwrap();
+        }
+    }
+    tokens
+}
+
+struct OrgParser<'a> {
+    features: &'a mut Features,
+    settings: Hash,
+    notes: Vec<Token>,
+    anonymous_notes: usize,
+}
+
+/// Returns the indentation of a line
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// If the line is a heading, returns its level and title
+fn heading(line: &str) -> Option<(i32, &str)> {
+    let level = line.chars().take_while(|c| *c == '*').count();
+    if level == 0 || !line[level..].starts_with(' ') {
+        return None;
+    }
+    let mut title = line[level..].trim();
+    // Remove TODO keywords and tags
+    for keyword in &["TODO ", "DONE "] {
+        if let Some(rest) = title.strip_prefix(keyword) {
+            title = rest.trim_start();
+        }
+    }
+    if title.ends_with(':') {
+        if let Some(pos) = title.trim_end_matches(':').rfind(|c: char| c.is_whitespace()) {
+            let tags = &title[pos..].trim();
+            if tags.starts_with(':') && !tags.contains(' ') {
+                title = title[..pos].trim_end();
+            }
+        }
+    }
+    Some((level as i32, title))
+}
+
+/// If the line is a list item, returns its bullet and content
+fn list_item(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start();
+    let end = trimmed.find(' ').unwrap_or(trimmed.len());
+    let bullet = &trimmed[..end];
+    let is_bullet = bullet == "-"
+        || bullet == "+"
+        || (bullet == "*" && indent(line) > 0)
+        || (bullet.len() > 1
+            && (bullet.ends_with('.') || bullet.ends_with(')'))
+            && bullet[..bullet.len() - 1].chars().all(|c| c.is_ascii_digit()));
+    if is_bullet {
+        Some((bullet, trimmed[end..].trim_start()))
+    } else {
+        None
+    }
+}
+
+/// If the line is a block delimiter (e.g. `#+BEGIN_SRC rust`), returns its
+/// (lowercased) name and parameters
+fn block_begin(line: &str) -> Option<(String, &str)> {
+    let trimmed = line.trim_start();
+    if trimmed.len() > 8 && trimmed[..8].eq_ignore_ascii_case("#+begin_") {
+        let rest = &trimmed[8..];
+        let end = rest.find(' ').unwrap_or(rest.len());
+        Some((rest[..end].to_lowercase(), rest[end..].trim()))
+    } else {
+        None
+    }
+}
+
+/// If the line starts a footnote definition, returns its label and content
+fn footnote_definition(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix("[fn:")?;
+    let end = rest.find(']')?;
+    let label = &rest[..end];
+    if label.is_empty() || label.contains(':') {
+        return None;
+    }
+    Some((label, rest[end + 1..].trim_start()))
+}
+
+fn is_rule(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.len() >= 5 && trimmed.chars().all(|c| c == '-')
+}
+
+fn is_comment(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed == "#" || trimmed.starts_with("# ") || (trimmed.starts_with("#+") && block_begin(line).is_none())
+}
+
+/// Returns true if the line starts a new block, interrupting a paragraph
+fn starts_block(line: &str) -> bool {
+    line.trim().is_empty()
+        || heading(line).is_some()
+        || list_item(line).is_some()
+        || block_begin(line).is_some()
+        || footnote_definition(line).is_some()
+        || line.trim_start().starts_with('|')
+        || is_rule(line)
+        || is_comment(line)
+}
+
+impl<'a> OrgParser<'a> {
+    /// Parses a list of lines into block tokens
+    fn blocks(&mut self, lines: &[&str]) -> Vec<Token> {
+        let mut res = vec![];
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                i += 1;
+            } else if let Some((name, params)) = block_begin(line) {
+                let end = format!("#+end_{name}");
+                let start = i + 1;
+                i = start;
+                while i < lines.len() && !lines[i].trim().eq_ignore_ascii_case(&end) {
+                    i += 1;
+                }
+                let content = &lines[start..i];
+                i += 1;
+                match name.as_str() {
+                    "src" | "example" => {
+                        self.features.codeblock = true;
+                        let lang = if name == "src" {
+                            params.split_whitespace().next().unwrap_or("")
+                        } else {
+                            ""
+                        };
+                        let margin = content
+                            .iter()
+                            .filter(|l| !l.trim().is_empty())
+                            .map(|l| indent(l))
+                            .min()
+                            .unwrap_or(0);
+                        let mut code = String::new();
+                        for l in content {
+                            code.push_str(l.get(margin..).unwrap_or(""));
+                            code.push('\n');
+                        }
+                        res.push(Token::CodeBlock(lang.to_owned(), code));
+                    }
+                    "quote" => {
+                        self.features.blockquote = true;
+                        res.push(Token::BlockQuote(self.blocks(content)));
+                    }
+                    "verse" => {
+                        let margin = content
+                            .iter()
+                            .filter(|l| !l.trim().is_empty())
+                            .map(|l| indent(l))
+                            .min()
+                            .unwrap_or(0);
+                        let lines = content
+                            .iter()
+                            .map(|l| {
+                                let indent = if l.trim().is_empty() { 0 } else { indent(l) - margin };
+                                Token::VerseLine(indent as u32, self.inlines(l.trim()))
+                            })
+                            .collect();
+                        res.push(Token::verse(lines));
+                    }
+                    _ => res.append(&mut self.blocks(content)),
+                }
+            } else if let Some(setting) = trimmed.strip_prefix("#+") {
+                if let Some(pos) = setting.find(':') {
+                    let key = setting[..pos].to_lowercase();
+                    let value = setting[pos + 1..].trim();
+                    if let Some(&(_, option)) = SETTINGS.iter().find(|(k, _)| *k == key) {
+                        self.settings
+                            .insert(Yaml::String(option.to_owned()), Yaml::String(value.to_owned()));
+                    }
+                }
+                i += 1;
+            } else if is_comment(line) {
+                i += 1;
+            } else if let Some((level, title)) = heading(line) {
+                res.push(Token::Header(level.min(6), self.inlines(title)));
+                i += 1;
+            } else if is_rule(line) {
+                res.push(Token::Rule);
+                i += 1;
+            } else if let Some((label, content)) = footnote_definition(line) {
+                let mut content = vec![content];
+                i += 1;
+                while i < lines.len() && !lines[i].trim().is_empty() && !starts_block(lines[i]) {
+                    content.push(lines[i].trim());
+                    i += 1;
+                }
+                self.features.footnote = true;
+                let inner = self.blocks(&content);
+                self.notes
+                    .push(Token::FootnoteDefinition(label.to_owned(), inner));
+            } else if trimmed.starts_with('|') {
+                let start = i;
+                while i < lines.len() && lines[i].trim_start().starts_with('|') {
+                    i += 1;
+                }
+                res.push(self.table(&lines[start..i]));
+            } else if list_item(line).is_some() {
+                i = self.list(lines, i, &mut res);
+            } else {
+                let start = i;
+                i += 1;
+                while i < lines.len() && !starts_block(lines[i]) {
+                    i += 1;
+                }
+                let text = lines[start..i]
+                    .iter()
+                    .map(|l| l.trim())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let inlines = self.inlines(&text);
+                match inlines.as_slice() {
+                    [Token::Image(..)] => {
+                        if let Some(Token::Image(url, title, alt)) = inlines.into_iter().next() {
+                            res.push(Token::StandaloneImage(url, title, alt));
+                        }
+                    }
+                    _ => res.push(Token::Paragraph(inlines)),
+                }
+            }
+        }
+        res
+    }
+
+    /// Parses a list starting at line `start`, pushes it to `res` and
+    /// returns the index of the first line after the list
+    fn list(&mut self, lines: &[&str], start: usize, res: &mut Vec<Token>) -> usize {
+        let margin = indent(lines[start]);
+        let (first_bullet, first_content) = list_item(lines[start]).unwrap();
+        let ordered = first_bullet.ends_with('.') || first_bullet.ends_with(')');
+        let description = !ordered && first_content.contains(" :: ");
+        let mut items = vec![];
+        let mut i = start;
+        while i < lines.len() {
+            let line = lines[i];
+            let item = match list_item(line) {
+                Some(item) if indent(line) == margin => item,
+                _ => break,
+            };
+            // A different kind of bullet starts a new list
+            let is_ordered = item.0.ends_with('.') || item.0.ends_with(')');
+            if is_ordered != ordered || (!ordered && item.1.contains(" :: ") != description) {
+                break;
+            }
+            // Collect the item content: first line, and following lines
+            // that are more indented (possibly separated by a blank line)
+            let mut content = vec![item.1.to_owned()];
+            i += 1;
+            while i < lines.len() {
+                if lines[i].trim().is_empty() {
+                    if i + 1 < lines.len() && indent(lines[i + 1]) > margin && !lines[i + 1].trim().is_empty() {
+                        content.push(String::new());
+                        i += 1;
+                        continue;
+                    }
+                    break;
+                }
+                if indent(lines[i]) <= margin {
+                    break;
+                }
+                content.push(lines[i].get(margin + 2..).unwrap_or(lines[i].trim_start()).to_owned());
+                i += 1;
+            }
+            items.push(content);
+            // A single blank line between items doesn't end the list
+            if i + 1 < lines.len()
+                && lines[i].trim().is_empty()
+                && indent(lines[i + 1]) == margin
+                && list_item(lines[i + 1]).is_some()
+            {
+                i += 1;
+            }
+        }
+
+        let mut tokens = vec![];
+        for mut content in items {
+            if description {
+                if let Some(pos) = content[0].find(" :: ") {
+                    let term = content[0][..pos].to_owned();
+                    content[0] = content[0][pos + 4..].to_owned();
+                    let lines: Vec<&str> = content.iter().map(|s| s.as_str()).collect();
+                    tokens.push(Token::DescriptionItem(vec![
+                        Token::DescriptionTerm(self.inlines(&term)),
+                        Token::DescriptionDetails(self.blocks(&lines)),
+                    ]));
+                    continue;
+                }
+            }
+            let mut checkbox = None;
+            for (mark, checked) in &[("[ ] ", false), ("[X] ", true), ("[x] ", true), ("[-] ", false)] {
+                if let Some(rest) = content[0].strip_prefix(mark) {
+                    checkbox = Some(*checked);
+                    content[0] = rest.to_owned();
+                    break;
+                }
+            }
+            let lines: Vec<&str> = content.iter().map(|s| s.as_str()).collect();
+            let inner = self.blocks(&lines);
+            if let Some(checked) = checkbox {
+                self.features.taskitem = true;
+                tokens.push(Token::TaskItem(checked, inner));
+            } else {
+                tokens.push(Token::Item(inner));
+            }
+        }
+
+        if description {
+            res.push(Token::DescriptionList(tokens));
+        } else if ordered {
+            self.features.ordered_list = true;
+            let n = first_bullet[..first_bullet.len() - 1].parse().unwrap_or(1);
+            res.push(Token::OrderedList(n, tokens));
+        } else {
+            res.push(Token::List(tokens));
+        }
+        i
+    }
+
+    /// Parses a table. If the table contains a separator line (e.g. `|---+---|`),
+    /// the rows before it are the table head.
+    fn table(&mut self, lines: &[&str]) -> Token {
+        self.features.table = true;
+        let is_separator = |line: &&str| line.trim_start().starts_with("|-");
+        let has_head = lines.iter().any(is_separator);
+        let mut in_head = has_head;
+        let mut rows = vec![];
+        let mut columns = 0;
+        for line in lines {
+            if is_separator(line) {
+                in_head = false;
+                continue;
+            }
+            let line = line.trim();
+            let line = line.strip_prefix('|').unwrap_or(line);
+            let line = line.strip_suffix('|').unwrap_or(line);
+            let cells: Vec<Token> = line
+                .split('|')
+                .map(|cell| Token::TableCell(self.inlines(cell.trim())))
+                .collect();
+            columns = columns.max(cells.len());
+            if in_head {
+                rows.push(Token::TableHead(cells));
+            } else {
+                rows.push(Token::TableRow(cells));
+            }
+        }
+        Token::Table(columns as i32, rows)
+    }
+
+    /// Parses inline markup
+    fn inlines(&mut self, text: &str) -> Vec<Token> {
+        let mut res = vec![];
+        let mut current = String::new();
+        let mut pos = 0;
+        macro_rules! flush {
+            () => {
+                if !current.is_empty() {
+                    res.push(Token::Str(std::mem::take(&mut current)));
+                }
+            };
+        }
+        while pos < text.len() {
+            let rest = &text[pos..];
+            let c = rest.chars().next().unwrap();
+            if c == '\n' {
+                // A line ending with `\\` is a hard break
+                let hard = current.ends_with("\\\\");
+                if hard {
+                    current.truncate(current.len() - 2);
+                }
+                flush!();
+                res.push(if hard { Token::HardBreak } else { Token::SoftBreak });
+            } else if let (true, Some(end)) = (rest.starts_with("[["), rest.find("]]")) {
+                flush!();
+                let inner = &rest[2..end];
+                let (target, desc) = match inner.find("][") {
+                    Some(n) => (&inner[..n], Some(&inner[n + 2..])),
+                    None => (inner, None),
+                };
+                let target = target.strip_prefix("file:").unwrap_or(target);
+                let lowercase = target.to_lowercase();
+                let is_image = IMAGE_EXTENSIONS
+                    .iter()
+                    .any(|ext| lowercase.ends_with(ext));
+                if is_image && desc.is_none() {
+                    self.features.image = true;
+                    res.push(Token::Image(target.to_owned(), String::new(), vec![]));
+                } else {
+                    self.features.url = true;
+                    let content = self.inlines(desc.unwrap_or(target));
+                    res.push(Token::Link(target.to_owned(), String::new(), content));
+                }
+                pos += end + 2;
+                continue;
+            } else if let (true, Some(end)) = (rest.starts_with("[fn:"), rest.find(']')) {
+                flush!();
+                self.features.footnote = true;
+                let inner = &rest[4..end];
+                match inner.find(':') {
+                    None => res.push(Token::FootnoteReference(inner.to_owned())),
+                    Some(n) => {
+                        // Inline definition, possibly anonymous
+                        let label = if n == 0 {
+                            self.anonymous_notes += 1;
+                            format!("anonymous-{}", self.anonymous_notes)
+                        } else {
+                            inner[..n].to_owned()
+                        };
+                        let content = self.inlines(&inner[n + 1..]);
+                        self.notes.push(Token::FootnoteDefinition(
+                            label.clone(),
+                            vec![Token::Paragraph(content)],
+                        ));
+                        res.push(Token::FootnoteReference(label));
+                    }
+                }
+                pos += end + 1;
+                continue;
+            } else if let (true, Some(end)) = (
+                rest.starts_with("^{") || rest.starts_with("_{"),
+                rest.find('}'),
+            ) {
+                flush!();
+                let content = self.inlines(&rest[2..end]);
+                if c == '^' {
+                    self.features.superscript = true;
+                    res.push(Token::Superscript(content));
+                } else {
+                    self.features.subscript = true;
+                    res.push(Token::Subscript(content));
+                }
+                pos += end + 1;
+                continue;
+            } else if let Some(end) = emphasis_end(text, pos) {
+                flush!();
+                let content = &text[pos + 1..end];
+                let token = match c {
+                    '=' | '~' => Token::Code(content.to_owned()),
+                    '*' => Token::Strong(self.inlines(content)),
+                    '+' => {
+                        self.features.strikethrough = true;
+                        Token::Strikethrough(self.inlines(content))
+                    }
+                    _ => Token::Emphasis(self.inlines(content)),
+                };
+                res.push(token);
+                pos = end + 1;
+                continue;
+            } else {
+                current.push(c);
+            }
+            pos += c.len_utf8();
+        }
+        flush!();
+        res
+    }
+}
+
+/// If an emphasis marker starts at byte position `pos`, returns the position of
+/// the closing marker
+fn emphasis_end(text: &str, pos: usize) -> Option<usize> {
+    let marker = text[pos..].chars().next()?;
+    if !"*/_=~+".contains(marker) {
+        return None;
+    }
+    let pre_ok = match text[..pos].chars().next_back() {
+        None => true,
+        Some(c) => c.is_whitespace() || "-({'\"".contains(c),
+    };
+    let next = text[pos + 1..].chars().next()?;
+    if !pre_ok || next.is_whitespace() || next == marker {
+        return None;
+    }
+    let mut previous = next;
+    for (offset, c) in text[pos + 1 + next.len_utf8()..].char_indices() {
+        let j = pos + 1 + next.len_utf8() + offset;
+        if c == marker && !previous.is_whitespace() {
+            let post_ok = match text[j + 1..].chars().next() {
+                None => true,
+                Some(c) => c.is_whitespace() || "-.,:!?;'\")}[".contains(c),
+            };
+            if post_ok {
+                return Some(j);
+            }
+        }
+        previous = c;
+    }
+    None
+}
@@ -0,0 +1,795 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conversion from and to pandoc's JSON representation of a document,
+//! so crowbook can be used inside pandoc pipelines.
+
+use crate::book::Book;
+use crate::book_renderer::BookRenderer;
+use crate::code_block::{CodeInfo, Start};
+use crate::error::{Error, Result, Source};
+use crate::parser::{Features, Parser};
+use crate::token::Token;
+
+use std::collections::HashMap;
+use std::io;
+
+use rust_i18n::t;
+use serde_json::{json, Value};
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter};
+
+/// Version of pandoc's API that is emitted
+const API_VERSION: [u32; 3] = [1, 23, 1];
+
+/// Pandoc metadata keys that correspond to a crowbook option of the same name.
+/// Other keys are mapped to `metadata.<key>`.
+const META_KEYS: &[&str] = &[
+    "author",
+    "title",
+    "lang",
+    "subject",
+    "description",
+    "subtitle",
+    "license",
+    "version",
+    "date",
+];
+
+/// Pandoc JSON renderer
+///
+/// Renders the book as a pandoc AST, so it can be converted by pandoc to
+/// formats crowbook doesn't support.
+pub struct PandocJson {}
+
+impl BookRenderer for PandocJson {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.json"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
+        let document = to_pandoc(book)?;
+        serde_json::to_writer(to, &document).map_err(|e| {
+            Error::render(&book.source, t!("pandoc.write_error", error = e))
+        })
+    }
+}
+
+/// Converts a book to a pandoc document
+fn to_pandoc(book: &Book) -> Result<Value> {
+    let mut meta = serde_json::Map::new();
+    for key in book.options.get_metadata() {
+        if let Ok(value) = book.options.get_str(key) {
+            if value.is_empty() {
+                continue;
+            }
+            let tokens = Parser::new().parse_inline(value)?;
+            let writer = Writer::new(book, &tokens);
+            let key = key.strip_prefix("metadata.").unwrap_or(key);
+            meta.insert(
+                key.to_owned(),
+                json!({"t": "MetaInlines", "c": writer.inlines(&tokens)}),
+            );
+        }
+    }
+
+    let mut blocks = vec![];
+    for chapter in &book.chapters {
+        let writer = Writer::new(book, &chapter.content);
+        let mut chapter_blocks = writer.blocks(&chapter.content);
+        let mut classes = vec![];
+        if !chapter.number.is_numbered() {
+            classes.push("unnumbered");
+        }
+        if chapter.unlisted {
+            classes.push("unlisted");
+        }
+        if !classes.is_empty() {
+            // Mark the chapter title as unnumbered and/or unlisted, keeping its own attributes
+            for block in &mut chapter_blocks {
+                if block["t"] == "Header" && block["c"][0] == 1 {
+                    if let Some(existing) = block["c"][1][1].as_array_mut() {
+                        for class in classes {
+                            if !existing.iter().any(|c| c == class) {
+                                existing.push(json!(class));
+                            }
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+        blocks.append(&mut chapter_blocks);
+    }
+
+    Ok(json!({
+        "pandoc-api-version": API_VERSION,
+        "meta": meta,
+        "blocks": blocks,
+    }))
+}
+
+/// Converts some tokens (e.g. a chapter) to a pandoc document without metadata
+pub fn tokens_to_pandoc(book: &Book, tokens: &[Token]) -> Value {
+    let writer = Writer::new(book, tokens);
+    json!({
+        "pandoc-api-version": API_VERSION,
+        "meta": {},
+        "blocks": writer.blocks(tokens),
+    })
+}
+
+/// Returns pandoc attributes with an identifier and some classes
+fn attr(id: &str, classes: &[&str]) -> Value {
+    json!([id, classes, []])
+}
+
+/// Converts crowbook's tokens to pandoc's elements
+struct Writer<'a> {
+    book: &'a Book<'a>,
+    notes: HashMap<&'a str, &'a [Token]>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(book: &'a Book<'a>, tokens: &'a [Token]) -> Writer<'a> {
+        let mut notes = HashMap::new();
+        for token in tokens {
+            if let Token::FootnoteDefinition(ref reference, ref v) = *token {
+                notes.insert(reference.as_str(), v.as_slice());
+            }
+        }
+        Writer { book, notes }
+    }
+
+    /// Converts tokens to a list of blocks, wrapping inline content in `Plain`
+    fn blocks(&self, tokens: &[Token]) -> Vec<Value> {
+        let mut res = vec![];
+        let mut inlines = vec![];
+        for token in tokens {
+            if let Some(block) = self.block(token) {
+                if !inlines.is_empty() {
+                    res.push(json!({"t": "Plain", "c": std::mem::take(&mut inlines)}));
+                }
+                if !block.is_null() {
+                    res.push(block);
+                }
+            } else {
+                self.inline(token, &mut inlines);
+            }
+        }
+        if !inlines.is_empty() {
+            res.push(json!({"t": "Plain", "c": inlines}));
+        }
+        res
+    }
+
+    /// Converts a token to a block, or returns None if it is an inline element.
+    ///
+    /// Returns `Some(Value::Null)` for block elements that are not rendered.
+    fn block(&self, token: &Token) -> Option<Value> {
+        let block = match *token {
+            Token::Paragraph(ref v) => json!({"t": "Para", "c": self.inlines(v)}),
+            Token::Header(n, ref v) => {
+                let (id, classes) = token.header_attributes();
+                let classes: Vec<&str> = classes.iter().map(String::as_str).collect();
+                json!({"t": "Header", "c": [n, attr(id, &classes), self.inlines(v)]})
+            }
+            Token::BlockQuote(ref v) => json!({"t": "BlockQuote", "c": self.blocks(v)}),
+            Token::CodeBlock(ref info, ref code) => {
+                let info = CodeInfo::new(info);
+                let mut classes: Vec<&str> = info.language.split_whitespace().take(1).collect();
+                // Pandoc's own attributes for line numbering
+                let attributes: Vec<(&str, String)> = info
+                    .attributes()
+                    .into_iter()
+                    .filter_map(|(key, value)| match key {
+                        "start" if info.start == Some(Start::Continue) => None,
+                        "start" => Some(("startFrom", value)),
+                        _ => Some((key, value)),
+                    })
+                    .collect();
+                match info.start {
+                    Some(Start::Continue) => classes.extend(["numberLines", "continuedSourceCode"]),
+                    Some(Start::At(_)) => classes.push("numberLines"),
+                    None => (),
+                }
+                json!({"t": "CodeBlock", "c": [[ "", classes, attributes], code.trim_end_matches('\n')]})
+            }
+            Token::List(ref v) => json!({"t": "BulletList", "c": self.items(v)}),
+            Token::OrderedList(start, ref v) => json!({
+                "t": "OrderedList",
+                "c": [[start, {"t": "Decimal"}, {"t": "Period"}], self.items(v)]
+            }),
+            Token::DescriptionList(ref v) => {
+                let mut items = vec![];
+                for item in v {
+                    let mut term = vec![];
+                    let mut details = vec![];
+                    for token in item.inner().unwrap_or(&[]) {
+                        match *token {
+                            Token::DescriptionTerm(ref v) => term = self.inlines(v),
+                            Token::DescriptionDetails(ref v) => details.push(self.blocks(v)),
+                            _ => (),
+                        }
+                    }
+                    items.push(json!([term, details]));
+                }
+                json!({"t": "DefinitionList", "c": items})
+            }
+            Token::Table(n, ref rows) => self.table(n, rows),
+            Token::Rule => json!({"t": "HorizontalRule"}),
+            Token::Raw(ref format, ref s) => json!({"t": "RawBlock", "c": [format, s]}),
+            Token::StandaloneImage(..) => {
+                let mut inlines = vec![];
+                self.inline(token, &mut inlines);
+                json!({"t": "Para", "c": inlines})
+            }
+            Token::Verse(ref lines) => {
+                let lines: Vec<Vec<Value>> = lines
+                    .iter()
+                    .map(|line| {
+                        let mut inlines = vec![];
+                        if let Token::VerseLine(indent, ref v) = *line {
+                            // Pandoc keeps the indentation of line blocks as non-breaking spaces
+                            if indent > 0 {
+                                inlines.push(json!({"t": "Str", "c": "\u{a0}".repeat(indent as usize)}));
+                            }
+                            inlines.extend(self.inlines(v));
+                        }
+                        inlines
+                    })
+                    .collect();
+                json!({"t": "LineBlock", "c": lines})
+            }
+            Token::Letter(ref v) => json!({"t": "Div", "c": [attr("", &["letter"]), self.blocks(v)]}),
+            Token::LetterField(ref field, ref v) => json!({
+                "t": "Div",
+                "c": [attr("", &[&format!("letter-{field}")]), [{"t": "Para", "c": self.inlines(v)}]]
+            }),
+            Token::Play(ref v) => json!({"t": "Div", "c": [attr("", &["play"]), self.blocks(v)]}),
+            Token::Speech(ref speaker, ref v) => json!({
+                "t": "Div",
+                "c": [["", ["speech"], [["speaker", speaker]]], [{"t": "Para", "c": self.inlines(v)}]]
+            }),
+            Token::StageDirection(ref v) => json!({
+                "t": "Div",
+                "c": [attr("", &["stage-direction"]), [{"t": "Para", "c": self.inlines(v)}]]
+            }),
+            Token::DescribedImage(ref v) => json!({
+                "t": "Div",
+                "c": [["", ["described-image"], []], self.blocks(v)]
+            }),
+            Token::FootnoteDefinition(..) => Value::Null,
+            // Other formats can't be told apart, so shortcodes use their Markdown template;
+            // one alone in a paragraph contains blocks
+            Token::Shortcode(_, ref v) if v.first().map_or(false, |t| self.block(t).is_some()) => {
+                json!({"t": "Div", "c": [attr("", &["shortcode"]), self.blocks(v)]})
+            }
+            _ => return None,
+        };
+        Some(block)
+    }
+
+    /// Converts the items of a list
+    fn items(&self, items: &[Token]) -> Vec<Value> {
+        let mut res = vec![];
+        for item in items {
+            match *item {
+                Token::TaskItem(checked, ref v) => {
+                    let mut blocks = self.blocks(v);
+                    let checkbox = if checked { "☒" } else { "☐" };
+                    if let Some(inlines) = blocks.first_mut().and_then(|b| b["c"].as_array_mut()) {
+                        inlines.insert(0, json!({"t": "Space"}));
+                        inlines.insert(0, json!({"t": "Str", "c": checkbox}));
+                    }
+                    res.push(Value::from(blocks));
+                }
+                _ => res.push(Value::from(self.blocks(item.inner().unwrap_or(&[])))),
+            }
+        }
+        res
+    }
+
+    /// Converts a table, using the first row as header if it is one
+    fn table(&self, n: i32, rows: &[Token]) -> Value {
+        let cells = |v: &[Token]| -> Vec<Value> {
+            v.iter()
+                .map(|cell| {
+                    let content = self.inlines(cell.inner().unwrap_or(&[]));
+                    json!([attr("", &[]), {"t": "AlignDefault"}, 1, 1, [{"t": "Plain", "c": content}]])
+                })
+                .collect()
+        };
+        let mut head = vec![];
+        let mut body = vec![];
+        for row in rows {
+            match *row {
+                Token::TableHead(ref v) => head.push(json!([attr("", &[]), cells(v)])),
+                Token::TableRow(ref v) => body.push(json!([attr("", &[]), cells(v)])),
+                _ => (),
+            }
+        }
+        let colspecs: Vec<Value> = (0..n)
+            .map(|_| json!([{"t": "AlignDefault"}, {"t": "ColWidthDefault"}]))
+            .collect();
+        json!({
+            "t": "Table",
+            "c": [
+                attr("", &[]),
+                [null, []],
+                colspecs,
+                [attr("", &[]), head],
+                [[attr("", &[]), 0, [], body]],
+                [attr("", &[]), []]
+            ]
+        })
+    }
+
+    /// Converts a list of inline tokens
+    fn inlines(&self, tokens: &[Token]) -> Vec<Value> {
+        let mut res = vec![];
+        for token in tokens {
+            self.inline(token, &mut res);
+        }
+        res
+    }
+
+    /// Converts an inline token, adding it to `out`
+    fn inline(&self, token: &Token, out: &mut Vec<Value>) {
+        let value = match *token {
+            Token::Str(ref text) => {
+                push_text(out, &self.book.clean(text.as_str()));
+                return;
+            }
+            Token::Emphasis(ref v) => json!({"t": "Emph", "c": self.inlines(v)}),
+            Token::Strong(ref v) => json!({"t": "Strong", "c": self.inlines(v)}),
+            Token::Strikethrough(ref v) => json!({"t": "Strikeout", "c": self.inlines(v)}),
+            Token::Superscript(ref v) => json!({"t": "Superscript", "c": self.inlines(v)}),
+            Token::Subscript(ref v) => json!({"t": "Subscript", "c": self.inlines(v)}),
+            Token::Code(ref code) => json!({"t": "Code", "c": [attr("", &[]), code]}),
+            Token::Raw(ref format, ref s) => json!({"t": "RawInline", "c": [format, s]}),
+            Token::SoftBreak => json!({"t": "SoftBreak"}),
+            Token::HardBreak => json!({"t": "LineBreak"}),
+            Token::Anchor(ref label) => json!({"t": "Span", "c": [attr(label, &[]), []]}),
+            Token::Link(ref url, ref title, ref v) => json!({
+                "t": "Link",
+                "c": [attr("", &[]), self.inlines(v), [url, title]]
+            }),
+            Token::Image(ref url, ref title, ref v)
+            | Token::StandaloneImage(ref url, ref title, ref v) => json!({
+                "t": "Image",
+                "c": [attr("", &[]), self.inlines(v), [url, title]]
+            }),
+            Token::FootnoteReference(ref reference) => {
+                let content = self.notes.get(reference.as_str()).copied().unwrap_or(&[]);
+                json!({"t": "Note", "c": self.blocks(content)})
+            }
+            _ => {
+                if let Some(inner) = token.inner() {
+                    for token in inner {
+                        self.inline(token, out);
+                    }
+                }
+                return;
+            }
+        };
+        out.push(value);
+    }
+}
+
+/// Adds a string as a list of `Str` and `Space` inlines
+fn push_text(out: &mut Vec<Value>, text: &str) {
+    let space = json!({"t": "Space"});
+    for (i, word) in text.split(|c| c == ' ' || c == '\n' || c == '\t').enumerate() {
+        if i > 0 && out.last() != Some(&space) {
+            out.push(space.clone());
+        }
+        if !word.is_empty() {
+            out.push(json!({"t": "Str", "c": word}));
+        }
+    }
+}
+
+/// Parses a pandoc JSON document and returns a list of tokens.
+///
+/// If `yaml` is set, fill it with a YAML block corresponding to the document's
+/// metadata.
+pub fn from_pandoc_json(
+    source: &Source,
+    s: &str,
+    yaml: Option<&mut String>,
+    features: &mut Features,
+) -> Result<Vec<Token>> {
+    let document: Value = serde_json::from_str(s)
+        .map_err(|e| Error::parser(source, t!("pandoc.invalid_json", error = e)))?;
+    let blocks = document["blocks"]
+        .as_array()
+        .ok_or_else(|| Error::parser(source, t!("pandoc.no_blocks")))?;
+
+    if let Some(yaml) = yaml {
+        if let Some(meta) = document["meta"].as_object() {
+            let mut hash = Hash::new();
+            for (key, value) in meta {
+                let key = if META_KEYS.contains(&key.as_str()) {
+                    key.clone()
+                } else {
+                    format!("metadata.{key}")
+                };
+                if let Some(value) = meta_to_yaml(value) {
+                    hash.insert(Yaml::String(key), value);
+                }
+            }
+            let mut emitter = YamlEmitter::new(yaml);
+            emitter
+                .dump(&Yaml::Hash(hash))
+                .map_err(|e| Error::parser(source, t!("pandoc.invalid_meta", error = format!("{e:?}"))))?;
+        }
+    }
+
+    let mut reader = Reader {
+        notes: vec![],
+        features,
+    };
+    let mut tokens = reader.blocks(blocks);
+    tokens.append(&mut reader.notes);
+    Ok(tokens)
+}
+
+/// Converts a pandoc metadata value to YAML
+fn meta_to_yaml(value: &Value) -> Option<Yaml> {
+    match value["t"].as_str()? {
+        "MetaBool" => Some(Yaml::Boolean(value["c"].as_bool()?)),
+        "MetaString" => Some(Yaml::String(value["c"].as_str()?.to_owned())),
+        "MetaInlines" | "MetaBlocks" => Some(Yaml::String(stringify(&value["c"]))),
+        "MetaList" => {
+            let items: Vec<String> = value["c"]
+                .as_array()?
+                .iter()
+                .filter_map(|v| match meta_to_yaml(v) {
+                    Some(Yaml::String(s)) => Some(s),
+                    _ => None,
+                })
+                .collect();
+            Some(Yaml::String(items.join(", ")))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the plain text content of pandoc elements
+fn stringify(value: &Value) -> String {
+    let mut res = String::new();
+    match *value {
+        Value::Array(ref v) => {
+            for value in v {
+                // Separate paragraphs with a space
+                if !res.is_empty() && (value["t"] == "Para" || value["t"] == "Plain") {
+                    res.push(' ');
+                }
+                res.push_str(&stringify(value));
+            }
+        }
+        Value::Object(_) => match value["t"].as_str() {
+            Some("Str") => res.push_str(value["c"].as_str().unwrap_or("")),
+            Some("Space") | Some("SoftBreak") | Some("LineBreak") => res.push(' '),
+            Some("Code") | Some("Math") | Some("RawInline") => res.push_str(value["c"][1].as_str().unwrap_or("")),
+            Some("Link") | Some("Image") | Some("Span") | Some("Cite") => res.push_str(&stringify(&value["c"][1])),
+            Some("Quoted") => {
+                let quote = if value["c"][0]["t"] == "SingleQuote" { '\'' } else { '"' };
+                res.push(quote);
+                res.push_str(&stringify(&value["c"][1]));
+                res.push(quote);
+            }
+            Some("Note") => (),
+            _ => res.push_str(&stringify(&value["c"])),
+        },
+        _ => (),
+    }
+    res
+}
+
+/// Converts pandoc's elements to crowbook's tokens
+struct Reader<'a> {
+    notes: Vec<Token>,
+    features: &'a mut Features,
+}
+
+impl<'a> Reader<'a> {
+    fn blocks(&mut self, blocks: &[Value]) -> Vec<Token> {
+        let mut res = vec![];
+        for block in blocks {
+            self.block(block, &mut res);
+        }
+        res
+    }
+
+    fn block(&mut self, block: &Value, out: &mut Vec<Token>) {
+        let c = &block["c"];
+        let token = match block["t"].as_str().unwrap_or("") {
+            "Plain" | "Para" => {
+                let inlines = self.inlines(c);
+                match inlines.as_slice() {
+                    [Token::Image(..)] => {
+                        if let Some(Token::Image(url, title, alt)) = inlines.into_iter().next() {
+                            Token::StandaloneImage(url, title, alt)
+                        } else {
+                            unreachable!()
+                        }
+                    }
+                    _ => Token::Paragraph(inlines),
+                }
+            }
+            "Header" => {
+                let mut inner = self.inlines(&c[2]);
+                let id = c[1][0].as_str().unwrap_or("");
+                let classes: Vec<String> = array(&c[1][1])
+                    .iter()
+                    .filter_map(|class| class.as_str().map(str::to_owned))
+                    .collect();
+                if !id.is_empty() || !classes.is_empty() {
+                    inner.insert(0, Token::HeaderAttributes(id.to_owned(), classes));
+                }
+                // Out of range levels would otherwise wrap or panic when numbering headers
+                let level = c[0].as_i64().unwrap_or(1).clamp(1, 6);
+                Token::Header(level as i32, inner)
+            }
+            "CodeBlock" => {
+                self.features.codeblock = true;
+                let mut info = CodeInfo::new(c[0][1][0].as_str().unwrap_or(""));
+                for kv in array(&c[0][2]) {
+                    let value = kv[1].as_str().unwrap_or("");
+                    match kv[0].as_str().unwrap_or("") {
+                        "caption" => info.caption = Some(value.to_owned()),
+                        "file" => info.file = Some(value.to_owned()),
+                        "highlight" => info.highlight = CodeInfo::new(&format!("{{highlight={value}}}")).highlight,
+                        "startFrom" | "start" => info.start = CodeInfo::new(&format!("{{start={value}}}")).start,
+                        _ => (),
+                    }
+                }
+                for class in array(&c[0][1]) {
+                    match class.as_str() {
+                        Some("continuedSourceCode") => info.start = Some(Start::Continue),
+                        Some("numberLines") if info.start.is_none() => info.start = Some(Start::At(1)),
+                        _ => (),
+                    }
+                }
+                let lang = info.to_info();
+                let mut code = c[1].as_str().unwrap_or("").to_owned();
+                code.push('\n');
+                Token::CodeBlock(lang, code)
+            }
+            "RawBlock" => {
+                let mut content = c[1].as_str().unwrap_or("").to_owned();
+                content.push('\n');
+                Token::Raw(c[0].as_str().unwrap_or("").to_owned(), content)
+            }
+            "BlockQuote" => {
+                self.features.blockquote = true;
+                Token::BlockQuote(self.blocks(array(c)))
+            }
+            "BulletList" => Token::List(self.items(c)),
+            "OrderedList" => {
+                self.features.ordered_list = true;
+                let start = c[0][0].as_u64().unwrap_or(1) as usize;
+                Token::OrderedList(start, self.items(&c[1]))
+            }
+            "DefinitionList" => {
+                let mut items = vec![];
+                for item in array(c) {
+                    let mut inner = vec![Token::DescriptionTerm(self.inlines(&item[0]))];
+                    for details in array(&item[1]) {
+                        inner.push(Token::DescriptionDetails(self.blocks(array(details))));
+                    }
+                    items.push(Token::DescriptionItem(inner));
+                }
+                Token::DescriptionList(items)
+            }
+            "LineBlock" => {
+                let mut lines = vec![];
+                for line in array(c) {
+                    let mut inner = self.inlines(line);
+                    let mut indent = 0;
+                    if let Some(Token::Str(ref mut s)) = inner.first_mut() {
+                        let trimmed = s.trim_start_matches('\u{a0}');
+                        indent = (s.len() - trimmed.len()) / '\u{a0}'.len_utf8();
+                        *s = trimmed.to_owned();
+                    }
+                    lines.push(Token::VerseLine(indent as u32, inner));
+                }
+                Token::verse(lines)
+            }
+            "HorizontalRule" => Token::Rule,
+            "Table" => self.table(c),
+            "Div" => {
+                let mut blocks = self.blocks(array(&c[1]));
+                let has_class = |class: &str| array(&c[0][1]).iter().any(|c| c == class);
+                // Content of the divs that only contain a paragraph
+                let inlines = |blocks: Vec<Token>| match blocks.into_iter().next() {
+                    Some(Token::Paragraph(inlines)) => inlines,
+                    Some(token) => vec![token],
+                    None => vec![],
+                };
+                let field = ["from", "date", "signature"]
+                    .iter()
+                    .find(|field| has_class(&format!("letter-{field}")));
+                if has_class("described-image") && matches!(blocks.first(), Some(Token::StandaloneImage(..))) {
+                    Token::DescribedImage(blocks)
+                } else if has_class("letter") {
+                    self.features.letter = true;
+                    Token::Letter(blocks)
+                } else if let Some(field) = field {
+                    Token::LetterField((*field).to_owned(), inlines(blocks))
+                } else if has_class("play") {
+                    self.features.play = true;
+                    Token::Play(blocks)
+                } else if has_class("speech") {
+                    let speaker = array(&c[0][2])
+                        .iter()
+                        .find(|kv| kv[0] == "speaker")
+                        .and_then(|kv| kv[1].as_str())
+                        .unwrap_or("");
+                    Token::Speech(speaker.to_owned(), inlines(blocks))
+                } else if has_class("stage-direction") {
+                    Token::StageDirection(inlines(blocks))
+                } else {
+                    out.append(&mut blocks);
+                    return;
+                }
+            }
+            "Figure" => {
+                out.append(&mut self.blocks(array(&c[2])));
+                return;
+            }
+            other => {
+                debug!("{}", t!("pandoc.ignored", element = other));
+                return;
+            }
+        };
+        out.push(token);
+    }
+
+    fn items(&mut self, items: &Value) -> Vec<Token> {
+        array(items)
+            .iter()
+            .map(|item| Token::Item(self.blocks(array(item))))
+            .collect()
+    }
+
+    fn table(&mut self, c: &Value) -> Token {
+        self.features.table = true;
+        let n = array(&c[2]).len() as i32;
+        let mut rows = vec![];
+        for row in array(&c[3][1]) {
+            rows.push(Token::TableHead(self.cells(row)));
+        }
+        for body in array(&c[4]) {
+            for row in array(&body[2]).iter().chain(array(&body[3])) {
+                rows.push(Token::TableRow(self.cells(row)));
+            }
+        }
+        Token::Table(n, rows)
+    }
+
+    fn cells(&mut self, row: &Value) -> Vec<Token> {
+        array(&row[1])
+            .iter()
+            .map(|cell| {
+                let mut content = vec![];
+                for block in array(&cell[4]) {
+                    content.append(&mut self.inlines(&block["c"]));
+                }
+                Token::TableCell(content)
+            })
+            .collect()
+    }
+
+    fn inlines(&mut self, inlines: &Value) -> Vec<Token> {
+        let mut res = vec![];
+        for inline in array(inlines) {
+            self.inline(inline, &mut res);
+        }
+        res
+    }
+
+    fn inline(&mut self, inline: &Value, out: &mut Vec<Token>) {
+        let c = &inline["c"];
+        let token = match inline["t"].as_str().unwrap_or("") {
+            "Str" => Token::Str(c.as_str().unwrap_or("").to_owned()),
+            "Space" => Token::Str(String::from(" ")),
+            "SoftBreak" => Token::SoftBreak,
+            "LineBreak" => Token::HardBreak,
+            "Emph" => Token::Emphasis(self.inlines(c)),
+            "Strong" => Token::Strong(self.inlines(c)),
+            "Strikeout" => {
+                self.features.strikethrough = true;
+                Token::Strikethrough(self.inlines(c))
+            }
+            "Superscript" => {
+                self.features.superscript = true;
+                Token::Superscript(self.inlines(c))
+            }
+            "Subscript" => {
+                self.features.subscript = true;
+                Token::Subscript(self.inlines(c))
+            }
+            "Code" => Token::Code(c[1].as_str().unwrap_or("").to_owned()),
+            "RawInline" => Token::Raw(
+                c[0].as_str().unwrap_or("").to_owned(),
+                c[1].as_str().unwrap_or("").to_owned(),
+            ),
+            "Math" => Token::Str(c[1].as_str().unwrap_or("").to_owned()),
+            "Quoted" => {
+                let quote = if c[0]["t"] == "SingleQuote" { "'" } else { "\"" };
+                out.push(Token::Str(quote.to_owned()));
+                out.append(&mut self.inlines(&c[1]));
+                Token::Str(quote.to_owned())
+            }
+            "Link" => {
+                self.features.url = true;
+                Token::Link(
+                    c[2][0].as_str().unwrap_or("").to_owned(),
+                    c[2][1].as_str().unwrap_or("").to_owned(),
+                    self.inlines(&c[1]),
+                )
+            }
+            "Image" => {
+                self.features.image = true;
+                Token::Image(
+                    c[2][0].as_str().unwrap_or("").to_owned(),
+                    c[2][1].as_str().unwrap_or("").to_owned(),
+                    self.inlines(&c[1]),
+                )
+            }
+            "Note" => {
+                self.features.footnote = true;
+                let reference = format!("{}", self.notes.len() + 1);
+                let content = self.blocks(array(c));
+                self.notes
+                    .push(Token::FootnoteDefinition(reference.clone(), content));
+                Token::FootnoteReference(reference)
+            }
+            "Span" | "Cite" => {
+                // An empty span with an id is an anchor
+                let id = c[0][0].as_str().unwrap_or("");
+                if !id.is_empty() && array(&c[1]).is_empty() {
+                    out.push(Token::Anchor(id.to_owned()));
+                }
+                out.append(&mut self.inlines(&c[1]));
+                return;
+            }
+            "SmallCaps" | "Underline" => {
+                out.append(&mut self.inlines(c));
+                return;
+            }
+            other => {
+                debug!("{}", t!("pandoc.ignored", element = other));
+                return;
+            }
+        };
+        // Merge consecutive strings
+        if let (Some(Token::Str(ref mut prev)), Token::Str(ref s)) = (out.last_mut(), &token) {
+            prev.push_str(s);
+            return;
+        }
+        out.push(token);
+    }
+}
+
+/// Returns the value as a slice, or an empty one if it is not an array
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(|v| v.as_slice()).unwrap_or(&[])
+}
@@ -17,18 +17,24 @@
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::book::Book;
+use crate::code_block::CodeInfo;
 use crate::error::{Error, Result, Source};
+use crate::link_refs::{self, LinkRefs};
+use crate::misc;
+use crate::org;
+use crate::pandoc;
 use crate::token::Token;
 
+use std::borrow::Cow;
 use std::convert::AsRef;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 use std::mem;
 use std::ops::BitOr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use comrak::nodes::{AstNode, ListType, NodeValue};
-use comrak::{parse_document, Arena, ComrakOptions};
+use comrak::{parse_document_with_broken_link_callback, Arena, ComrakOptions};
 use rust_i18n::t;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -48,6 +54,8 @@ pub struct Features {
     pub superscript: bool,
     pub strikethrough: bool,
     pub taskitem: bool,
+    pub letter: bool,
+    pub play: bool,
 }
 
 impl Features {
@@ -65,6 +73,8 @@ impl Features {
             superscript: false,
             strikethrough: false,
             taskitem: false,
+            letter: false,
+            play: false,
         }
     }
 }
@@ -91,6 +101,8 @@ impl BitOr for Features {
             superscript: self.superscript | rhs.superscript,
             strikethrough: self.strikethrough | rhs.strikethrough,
             taskitem: self.taskitem | rhs.taskitem,
+            letter: self.letter | rhs.letter,
+            play: self.play | rhs.play,
         }
     }
 }
@@ -126,8 +138,25 @@ pub struct Parser {
     ignore_paragraphs: bool,
 
     html_as_text: bool,
+    /// Whether only CommonMark is parsed, without crowbook's and GFM's extensions
+    commonmark: bool,
     superscript: bool,
     parse_frontmatter: bool,
+    raw: bool,
+    /// Directory that the paths of chapters are relative to
+    root: PathBuf,
+    sandbox: bool,
+    /// Whether the code blocks are collected for `check.code`
+    check_code: bool,
+    code_blocks: Vec<(u32, String, String)>,
+    /// Link definitions shared by all chapters, and the labels of the ones that were used
+    link_refs: LinkRefs,
+    used_link_refs: Vec<String>,
+    /// Depth of the node being parsed, and its maximum set by `limits.nesting`
+    depth: i32,
+    max_depth: Option<i32>,
+    /// Maximal size of included files, and its value as written, set by `limits.chapter_size`
+    max_size: Option<(u64, String)>,
 }
 
 impl Parser {
@@ -138,8 +167,19 @@ impl Parser {
             features: Features::new(),
             ignore_paragraphs: false,
             html_as_text: true,
+            commonmark: false,
             superscript: false,
             parse_frontmatter: false,
+            raw: false,
+            root: PathBuf::new(),
+            sandbox: false,
+            check_code: false,
+            code_blocks: vec![],
+            link_refs: LinkRefs::default(),
+            used_link_refs: vec![],
+            depth: 0,
+            max_depth: None,
+            max_size: None,
         }
     }
 
@@ -147,11 +187,24 @@ impl Parser {
     pub fn from(book: &Book) -> Parser {
         let mut parser = Parser::new();
         parser.html_as_text = book.options.get_bool("crowbook.html_as_text").unwrap();
+        parser.commonmark = book.options.get_str("input.markdown").unwrap() == "commonmark";
         parser.parse_frontmatter = book.options.get_bool("input.yaml_blocks").unwrap();
         parser.superscript = book
             .options
             .get_bool("crowbook.markdown.superscript")
             .unwrap();
+        parser.raw = book.options.get_bool("input.raw").unwrap();
+        parser.root = book.root.clone();
+        parser.sandbox = book.options.is_sandboxed();
+        parser.check_code = book.options.get_bool("check.code").unwrap();
+        parser.max_depth = book.options.get_i32("limits.nesting").ok();
+        if let Ok(max) = book.options.get_size("limits.chapter_size") {
+            let limit = book.options.get_str("limits.chapter_size").unwrap();
+            parser.max_size = Some((max, limit.to_owned()));
+        }
+        if let Some(ref link_refs) = book.link_refs {
+            parser.link_refs = link_refs.clone();
+        }
         parser
     }
 
@@ -160,6 +213,37 @@ impl Parser {
         self.html_as_text = b;
     }
 
+    /// Enable/disable CommonMark compliance
+    ///
+    /// When enabled, extensions (tables, footnotes, strikethrough, `:::` blocks...) are
+    /// disabled and HTML is kept as raw HTML, as the CommonMark specification requires.
+    pub fn commonmark(&mut self, b: bool) {
+        self.commonmark = b;
+    }
+
+    /// Enable/disable raw spans and blocks, e.g. `` `\LaTeX`{=latex} ``
+    pub fn raw(&mut self, b: bool) {
+        self.raw = b;
+    }
+
+    /// Enable/disable YAML front matter at the top of a document
+    pub fn frontmatter(&mut self, b: bool) {
+        self.parse_frontmatter = b;
+    }
+
+    /// Returns the labels of the shared link definitions used by the documents parsed so far
+    #[doc(hidden)]
+    pub fn used_link_refs(&self) -> &[String] {
+        &self.used_link_refs
+    }
+
+    /// Returns the line, info string and content of the code blocks parsed so far,
+    /// if `check.code` is set
+    #[doc(hidden)]
+    pub fn code_blocks(&self) -> &[(u32, String, String)] {
+        &self.code_blocks
+    }
+
     /// Sets a parser's source file
     pub fn set_source_file(&mut self, s: &str) {
         self.source = Source::new(s);
@@ -197,25 +281,100 @@ impl Parser {
         let mut options = ComrakOptions::default();
         options.render.hardbreaks = false;
         options.parse.smart = false;
-        options.extension.strikethrough = true;
-        options.extension.table = true;
-        options.extension.autolink = true;
-        options.extension.tasklist = true;
-        options.extension.superscript = self.superscript;
-        options.extension.footnotes = true;
-        options.extension.description_lists = true;
+        let extended = !self.commonmark;
+        options.extension.strikethrough = extended;
+        options.extension.table = extended;
+        options.extension.autolink = extended;
+        options.extension.tasklist = extended;
+        options.extension.superscript = extended && self.superscript;
+        options.extension.footnotes = extended;
+        options.extension.description_lists = extended;
         if self.parse_frontmatter {
             options.extension.front_matter_delimiter = Some("---".to_owned());
         }
 
-        let root = parse_document(&arena, s, &options);
+        let s = if self.commonmark {
+            Cow::Borrowed(s)
+        } else {
+            mark_blocks(s)
+        };
+
+        if let Some(max) = self.max_depth {
+            if let Some(line) = too_deep(&s, usize::try_from(max).unwrap_or(0)) {
+                let mut source = self.source.clone();
+                source.set_line(line as u32);
+                return Err(Error::parser(source, t!("limits.nesting", limit = max)));
+            }
+        }
 
+        // References that aren't defined in the document are looked for in the shared
+        // definitions
+        let mut used = vec![];
+        let mut undefined = vec![];
+        let root = {
+            let link_refs = &self.link_refs;
+            let mut callback = |label: &str| {
+                if label.starts_with('^') {
+                    // Footnote reference
+                    return None;
+                }
+                let definition = link_refs.get(label);
+                if definition.is_some() {
+                    used.push(label.to_owned());
+                } else {
+                    undefined.push(label.to_owned());
+                }
+                definition
+            };
+            parse_document_with_broken_link_callback(&arena, &s, &options, Some(&mut callback))
+        };
+        if !undefined.is_empty() {
+            let explicit = link_refs::explicit_references(&s);
+            undefined.dedup();
+            for label in undefined.iter().filter(|label| explicit.contains(label)) {
+                warn!(
+                    "{}",
+                    Error::parser(&self.source, t!("parser.undefined_link", label = label))
+                );
+            }
+        }
+        self.used_link_refs.append(&mut used);
+
+        self.depth = 0;
         let mut res = self.parse_node(root, &mut yaml)?;
 
         collapse(&mut res);
 
         find_standalone(&mut res);
+        if !self.commonmark {
+            find_descriptions(&mut res);
+        }
+
+        if self.raw {
+            find_raw(&mut res);
+        }
+
+        Ok(res)
+    }
+
+    /// Parse a pandoc JSON document and returns an AST or an Error.
+    /// If yaml is set to some string, fill it with the document's metadata
+    pub fn parse_pandoc_json(&mut self, s: &str, yaml: Option<&mut String>) -> Result<Vec<Token>> {
+        let yaml = if self.parse_frontmatter { yaml } else { None };
+        let mut res = pandoc::from_pandoc_json(&self.source, s, yaml, &mut self.features)?;
+        if !self.raw {
+            remove_raw(&mut res);
+        }
+        collapse(&mut res);
+        Ok(res)
+    }
 
+    /// Parse an org-mode document and returns an AST or an Error.
+    /// If yaml is set to some string, fill it with the document's in-buffer settings
+    pub fn parse_org(&mut self, s: &str, yaml: Option<&mut String>) -> Result<Vec<Token>> {
+        let yaml = if self.parse_frontmatter { yaml } else { None };
+        let mut res = org::parse_org(s, yaml, &mut self.features);
+        collapse(&mut res);
         Ok(res)
     }
 
@@ -245,6 +404,162 @@ impl Parser {
         self.features
     }
 
+    /// Parses a line of a `:::` block as inline content
+    fn parse_line(&mut self, line: &str) -> Result<Vec<Token>> {
+        if line.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut inner = self.parse(line, None)?;
+        if !matches!(inner.as_slice(), [Token::Paragraph(_)]) {
+            // The line was parsed as a block, e.g. a list item, so escape its marker
+            let i = line.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+            inner = self.parse(&format!("{}\\{}", &line[..i], &line[i..]), None)?;
+        }
+        Ok(match inner.pop() {
+            Some(Token::Paragraph(inner)) => inner,
+            Some(token) => vec![token],
+            None => vec![],
+        })
+    }
+
+    /// Reads the code included by an empty code block with a `file` attribute
+    ///
+    /// The path of the file is relative to the chapter's file.
+    fn include_code(&self, info: &str, file: &str) -> Result<String> {
+        let dir = match self.source.file {
+            Some(ref chapter) => Path::new(chapter).parent().unwrap_or_else(|| Path::new("")),
+            None => Path::new(""),
+        };
+        let path = self.root.join(dir).join(file);
+        // Checked before opening the file, so whether files outside exist isn't revealed
+        if self.sandbox && !misc::is_inside(&path, &self.root) {
+            return Err(Error::parser(
+                &self.source,
+                t!("parser.include_sandbox", file = file),
+            ));
+        }
+        let not_found = |err| {
+            Error::file_not_found(&self.source, t!("format.code_file"), path.display().to_string())
+                .with_cause(err)
+        };
+        // Larger files aren't read to their end, so they (or e.g. /dev/zero) can't fill the
+        // memory
+        let read_limit = self
+            .max_size
+            .as_ref()
+            .map_or(u64::MAX, |(max, _)| max.saturating_add(1));
+        let mut bytes = vec![];
+        File::open(&path)
+            .and_then(|f| f.take(read_limit).read_to_end(&mut bytes))
+            .map_err(not_found)?;
+        if let Some((max, ref limit)) = self.max_size {
+            if bytes.len() as u64 > max {
+                return Err(Error::parser(
+                    &self.source,
+                    t!("parser.include_size", file = file, limit = limit),
+                ));
+            }
+        }
+        let content = String::from_utf8(bytes)
+            .map_err(|err| not_found(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+        CodeInfo::new(info).select_lines(&content).ok_or_else(|| {
+            Error::parser(&self.source, t!("parser.include_lines", file = file))
+        })
+    }
+
+    /// Parses the content of a `::: verse`, `::: letter` or `::: play` block
+    fn parse_block(&mut self, kind: &str, s: &str) -> Result<Vec<Token>> {
+        match kind {
+            "verse" => self.parse_verse(s),
+            "letter" => self.parse_letter(s),
+            _ => self.parse_play(s),
+        }
+    }
+
+    /// Parses the content of a `::: verse` block
+    ///
+    /// A line ending with `{#label}` gets an anchor, so it can be linked to with `#label`.
+    fn parse_verse(&mut self, s: &str) -> Result<Vec<Token>> {
+        let mut lines = vec![];
+        for line in s.lines() {
+            let (line, label) = line_label(line);
+            let indent = line
+                .chars()
+                .take_while(|c| c.is_whitespace())
+                .map(|c| if c == '\t' { 4 } else { 1 })
+                .sum();
+            let mut inner = self.parse_line(line.trim())?;
+            if let Some(label) = label {
+                inner.insert(0, Token::Anchor(label.to_owned()));
+            }
+            lines.push(Token::VerseLine(indent, inner));
+        }
+        Ok(vec![Token::verse(lines)])
+    }
+
+    /// Parses the content of a `::: letter` block: `from:` and `date:` lines at its beginning,
+    /// its body, and a `signature:` line at its end
+    fn parse_letter(&mut self, s: &str) -> Result<Vec<Token>> {
+        self.features.letter = true;
+        let mut lines: Vec<&str> = s.trim().lines().collect();
+        let mut inner = vec![];
+        while let Some((key, value)) = lines.first().and_then(|line| letter_field(line)) {
+            if key == "signature" {
+                break;
+            }
+            inner.push(Token::LetterField(key.to_owned(), self.parse_line(value)?));
+            lines.remove(0);
+        }
+        let signature = match lines.last().and_then(|line| letter_field(line)) {
+            Some(("signature", value)) => {
+                lines.pop();
+                Some(Token::LetterField(String::from("signature"), self.parse_line(value)?))
+            }
+            _ => None,
+        };
+        inner.append(&mut self.parse(&lines.join("\n"), None)?);
+        inner.extend(signature);
+        Ok(vec![Token::Letter(inner)])
+    }
+
+    /// Parses the content of a `::: play` block: speeches start with `SPEAKER: `, and lines
+    /// that aren't part of a speech are stage directions
+    fn parse_play(&mut self, s: &str) -> Result<Vec<Token>> {
+        self.features.play = true;
+        let mut inner = vec![];
+        let mut speech: Option<(String, Vec<Token>)> = None;
+        for line in s.lines() {
+            if line.trim().is_empty() {
+                if let Some((speaker, content)) = speech.take() {
+                    inner.push(Token::Speech(speaker, content));
+                }
+                continue;
+            }
+            let trimmed = line.trim();
+            let direction = (trimmed.starts_with('(') && trimmed.ends_with(')'))
+                || (trimmed.starts_with('[') && trimmed.ends_with(']'));
+            if let Some((speaker, text)) = speaker(line) {
+                if let Some((speaker, content)) = speech.take() {
+                    inner.push(Token::Speech(speaker, content));
+                }
+                speech = Some((speaker.to_owned(), self.parse_line(text)?));
+            } else if let (Some((_, ref mut content)), false) = (speech.as_mut(), direction) {
+                // Keep line breaks, since plays are often written in verse
+                content.push(Token::HardBreak);
+                content.append(&mut self.parse_line(trimmed)?);
+            } else {
+                if let Some((speaker, content)) = speech.take() {
+                    inner.push(Token::Speech(speaker, content));
+                }
+                inner.push(Token::StageDirection(self.parse_line(trimmed)?));
+            }
+        }
+        if let Some((speaker, content)) = speech {
+            inner.push(Token::Speech(speaker, content));
+        }
+        Ok(vec![Token::Play(inner)])
+    }
+
     fn parse_node<'a>(&mut self, node: &'a AstNode<'a>, yaml_block: &mut Option<&mut String>) -> Result<Vec<Token>> {
         let mut inner = vec![];
 
@@ -252,16 +567,32 @@ impl Parser {
         if let NodeValue::DescriptionTerm = node.data.borrow().value {
             self.ignore_paragraphs = true;
         }
+        self.depth += 1;
+        if let Some(max) = self.max_depth.filter(|&max| self.depth > max) {
+            let mut source = self.source.clone();
+            source.set_line(node.data.borrow().sourcepos.start.line as u32);
+            return Err(Error::parser(source, t!("limits.nesting", limit = max)));
+        }
         for c in node.children() {
             let mut v = self.parse_node(c, yaml_block)?;
             inner.append(&mut v);
         }
+        self.depth -= 1;
         // Reset state after special cases shenanigans
         if let NodeValue::DescriptionTerm = node.data.borrow().value {
             // There should be no paragraphs inside description terms
             self.ignore_paragraphs = false;
         }
 
+        // Errors in code blocks (e.g. included files or interactive fiction) point to them
+        let is_code_block = matches!(node.data.borrow().value, NodeValue::CodeBlock(_));
+        if is_code_block {
+            let start = node.data.borrow().sourcepos.start;
+            self.source
+                .set_line(start.line as u32)
+                .set_column(start.column as u32);
+        }
+
         inner = match node.data.borrow().value {
             NodeValue::Document => inner,
             NodeValue::BlockQuote => {
@@ -287,15 +618,37 @@ impl Parser {
             NodeValue::DescriptionItem(_) => vec![Token::DescriptionItem(inner)],
             NodeValue::DescriptionTerm => vec![Token::DescriptionTerm(inner)],
             NodeValue::DescriptionDetails => vec![Token::DescriptionDetails(inner)],
+            NodeValue::CodeBlock(ref block) if !self.commonmark && block.info.starts_with(BLOCK) => {
+                self.parse_block(&block.info[BLOCK.len_utf8()..], &block.literal)?
+            }
             NodeValue::CodeBlock(ref block) => {
                 let info = block.info.clone();
-                let code = block.literal.clone();
+                if raw_format(&info).is_none() && !self.commonmark {
+                    if let Err(attribute) = CodeInfo::parse(&info) {
+                        warn!(
+                            "{}",
+                            Error::parser(&self.source, t!("parser.code_attribute", attribute = attribute))
+                        );
+                    }
+                }
+                let mut code = block.literal.clone();
+                if code.trim().is_empty() && !self.commonmark {
+                    if let Some(file) = CodeInfo::new(&info).file {
+                        code = self.include_code(&info, &file)?;
+                    }
+                }
                 self.features.codeblock = true;
+                if self.check_code {
+                    let line = node.data.borrow().sourcepos.start.line as u32;
+                    self.code_blocks.push((line, info.clone(), code.clone()));
+                }
                 vec![Token::CodeBlock(info, code)]
             }
             NodeValue::HtmlBlock(ref block) => {
                 let text = block.literal.clone();
-                if self.html_as_text {
+                if self.commonmark {
+                    vec![Token::Raw(String::from("html"), text)]
+                } else if self.html_as_text {
                     vec![Token::Str(text)]
                 } else {
                     debug!("{}", t!("parser.ignore_html", block = text));
@@ -304,7 +657,9 @@ impl Parser {
             }
             NodeValue::HtmlInline(ref html) => {
                 let text = html.clone();
-                if self.html_as_text {
+                if self.commonmark {
+                    vec![Token::Raw(String::from("html"), text)]
+                } else if self.html_as_text {
                     vec![Token::Str(text)]
                 } else {
                     debug!("{}", t!("parser.ignore_html", block = text));
@@ -318,7 +673,12 @@ impl Parser {
                     inner
                 }
             }
-            NodeValue::Heading(ref heading) => vec![Token::Header(heading.level as i32, inner)],
+            NodeValue::Heading(ref heading) => {
+                if !self.commonmark {
+                    header_attributes(&mut inner);
+                }
+                vec![Token::Header(heading.level as i32, inner)]
+            }
             NodeValue::ThematicBreak => vec![Token::Rule],
             NodeValue::FootnoteDefinition(ref def) => {
                 let reference = def.clone();
@@ -337,7 +697,7 @@ impl Parser {
             NodeValue::Emph => vec![Token::Emphasis(inner)],
             NodeValue::TaskItem(c) => {
                 self.features.taskitem = true;
-                let checked = if c.is_some() { true } else { false };
+                let checked = c.is_some();
                 vec![Token::TaskItem(checked, inner)]
             }
             NodeValue::Strong => vec![Token::Strong(inner)],
@@ -377,6 +737,9 @@ impl Parser {
                 vec![Token::Table(aligns.len() as i32, inner)]
             }
         };
+        if is_code_block {
+            self.source.unset_line();
+        }
         Ok(inner)
     }
 }
@@ -387,6 +750,61 @@ impl Default for Parser {
     }
 }
 
+/// Returns the format of a raw attribute, e.g. `latex` for `{=latex}`
+fn raw_format(attribute: &str) -> Option<&str> {
+    let format = attribute.trim().strip_prefix("{=")?.strip_suffix('}')?;
+    if !format.is_empty() && format.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(format)
+    } else {
+        None
+    }
+}
+
+/// Converts code spans and code blocks with a raw attribute to `Token::Raw`
+fn find_raw(ast: &mut Vec<Token>) {
+    let mut i = 0;
+    while i < ast.len() {
+        let raw = match (&ast[i], ast.get(i + 1)) {
+            (Token::CodeBlock(ref info, ref code), _) => {
+                raw_format(info).map(|format| (format.to_owned(), code.clone(), 0))
+            }
+            (Token::Code(ref code), Some(Token::Str(ref next))) if next.starts_with("{=") => next
+                .find('}')
+                .and_then(|end| raw_format(&next[..=end]).map(|format| (format.to_owned(), code.clone(), end + 1))),
+            _ => None,
+        };
+        match raw {
+            Some((format, content, len)) => {
+                ast[i] = Token::Raw(format, content);
+                if len > 0 {
+                    if let Token::Str(ref mut next) = ast[i + 1] {
+                        next.replace_range(..len, "");
+                        if next.is_empty() {
+                            ast.remove(i + 1);
+                        }
+                    }
+                }
+            }
+            None => {
+                if let Some(inner) = ast[i].inner_mut() {
+                    find_raw(inner);
+                }
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Removes raw content from the AST
+fn remove_raw(ast: &mut Vec<Token>) {
+    ast.retain(|token| !matches!(*token, Token::Raw(..)));
+    for token in ast.iter_mut() {
+        if let Some(inner) = token.inner_mut() {
+            remove_raw(inner);
+        }
+    }
+}
+
 /// Replace consecutives Strs by a Str of both, collapse soft breaks to previous std and so on
 fn collapse(ast: &mut Vec<Token>) {
     let mut i = 0;
@@ -423,6 +841,324 @@ fn collapse(ast: &mut Vec<Token>) {
     }
 }
 
+/// Prefix of the info string of the code blocks `:::` blocks are replaced with
+const BLOCK: char = '\u{E001}';
+
+/// Kinds of `:::` blocks
+const BLOCKS: &[&str] = &["verse", "letter", "play"];
+
+/// Replaces `::: verse`, `::: letter` and `::: play` blocks by code blocks, so the parser
+/// keeps their lines as they are
+fn mark_blocks(s: &str) -> Cow<str> {
+    if !s.contains(":::") {
+        return Cow::Borrowed(s);
+    }
+    let mut res = String::with_capacity(s.len());
+    let mut fence: Option<&str> = None;
+    // Number of nested `:::` blocks, which are parsed with the content of the outer one
+    let mut depth = 0;
+    for line in s.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let kind = trimmed
+            .strip_prefix(":::")
+            .map(str::trim)
+            .filter(|kind| BLOCKS.contains(kind));
+        if depth > 0 {
+            if kind.is_some() {
+                depth += 1;
+            } else if trimmed == ":::" {
+                depth -= 1;
+                if depth == 0 {
+                    res.push_str("~~~~~~~~~~\n");
+                    continue;
+                }
+            }
+        } else if let Some(f) = fence {
+            if trimmed.starts_with(f) {
+                fence = None;
+            }
+        } else if trimmed.starts_with("```") {
+            fence = Some("```");
+        } else if trimmed.starts_with("~~~") {
+            fence = Some("~~~");
+        } else if let Some(kind) = kind {
+            res.push_str(&format!("~~~~~~~~~~ {BLOCK}{kind}\n"));
+            depth = 1;
+            continue;
+        }
+        res.push_str(line);
+    }
+    Cow::Owned(res)
+}
+
+/// Returns the (1-based) first line of a document nesting more than `max` elements
+///
+/// Comrak builds and walks its tree recursively, so a document with thousands of nested
+/// elements can overflow the stack before the tree can be checked. This only counts the
+/// markers opening elements (block quotes, list items and indentation, emphasis and link
+/// brackets), so it overestimates the depth rather than missing nested elements.
+fn too_deep(s: &str, max: usize) -> Option<usize> {
+    // Fence of the code block the line is in, whose content isn't parsed
+    let mut fence: Option<(u8, usize)> = None;
+    // Emphasis and brackets opened in the current paragraph
+    let mut inline = 0;
+    for (i, line) in s.lines().enumerate() {
+        let line = line.as_bytes();
+        let mut block = 0;
+        let mut indent = 0;
+        let mut pos = 0;
+        loop {
+            while pos < line.len() && (line[pos] == b' ' || line[pos] == b'\t') {
+                indent += if line[pos] == b'\t' { 4 } else { 1 };
+                pos += 1;
+            }
+            let digits = line[pos..].iter().take_while(|c| c.is_ascii_digit()).count();
+            let marker = match line.get(pos) {
+                Some(b'>') => 1,
+                Some(b'-' | b'*' | b'+') => 1,
+                Some(_) if digits > 0 && matches!(line.get(pos + digits), Some(b'.' | b')')) => {
+                    digits + 1
+                }
+                _ => 0,
+            };
+            let is_open = marker > 0
+                && (line[pos] == b'>'
+                    || line.get(pos + marker).map_or(true, u8::is_ascii_whitespace));
+            if !is_open {
+                break;
+            }
+            block += 1;
+            pos += marker;
+        }
+        let depth = block + indent / 2;
+        let rest = &line[pos..];
+        let run = |c: u8, from: usize| rest[from..].iter().take_while(|&&x| x == c).count();
+        if let Some((c, len)) = fence {
+            if run(c, 0) >= len {
+                fence = None;
+            }
+            if depth > max {
+                return Some(i + 1);
+            }
+            continue;
+        }
+        if let Some(&c @ (b'`' | b'~')) = rest.first() {
+            if run(c, 0) >= 3 {
+                fence = Some((c, run(c, 0)));
+                inline = 0;
+                continue;
+            }
+        }
+        if rest.iter().all(u8::is_ascii_whitespace) {
+            inline = 0;
+        }
+        let mut j = 0;
+        while j < rest.len() {
+            match rest[j] {
+                b'\\' => j += 1,
+                b'[' => inline += 1,
+                b']' => inline = usize::saturating_sub(inline, 1),
+                b'`' => {
+                    // Code spans end with the same number of backticks
+                    let len = run(b'`', j);
+                    let end = (j + len..rest.len())
+                        .find(|&k| run(b'`', k) == len && rest[k - 1] != b'`');
+                    j = end.map_or(j + len, |k| k + len) - 1;
+                }
+                c @ (b'*' | b'_' | b'~') => {
+                    let len = run(c, j);
+                    let before = j > 0 && !rest[j - 1].is_ascii_whitespace();
+                    let after = rest.get(j + len).map_or(false, |c| !c.is_ascii_whitespace());
+                    if after && !before {
+                        inline += len;
+                    } else if before && !after {
+                        inline = usize::saturating_sub(inline, len);
+                    }
+                    j += len - 1;
+                }
+                _ => (),
+            }
+            if depth + inline > max {
+                return Some(i + 1);
+            }
+            j += 1;
+        }
+        if depth + inline > max {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+/// Splits a line ending with `{#label}` into its content and its label
+pub fn line_label(line: &str) -> (&str, Option<&str>) {
+    let trimmed = line.trim_end();
+    let label = trimmed
+        .strip_suffix('}')
+        .and_then(|s| s.rsplit_once("{#"))
+        .filter(|(before, label)| {
+            (before.is_empty() || before.ends_with(char::is_whitespace))
+                && !label.is_empty()
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        });
+    match label {
+        Some((before, label)) => (before.trim_end(), Some(label)),
+        None => (line, None),
+    }
+}
+
+/// Removes the `{#label}` markers at the end of the lines of a code block, returning the
+/// code and the label of each line
+pub fn code_labels(code: &str) -> (String, Vec<Option<String>>) {
+    let mut res = String::with_capacity(code.len());
+    let mut labels = vec![];
+    for line in code.lines() {
+        let (line, label) = line_label(line);
+        res.push_str(line);
+        res.push('\n');
+        labels.push(label.map(|l| l.to_owned()));
+    }
+    (res, labels)
+}
+
+/// Moves the `{#id .class}` attributes at the end of the title of a header to a
+/// `HeaderAttributes` token at its beginning
+///
+/// Text between braces that isn't only made of ids and classes is left as is.
+fn header_attributes(inner: &mut Vec<Token>) {
+    let text = match inner.last_mut() {
+        Some(Token::Str(ref mut text)) => text,
+        _ => return,
+    };
+    let trimmed = text.trim_end();
+    let start = match trimmed.strip_suffix('}').and_then(|s| s.rfind('{')) {
+        Some(start) => start,
+        None => return,
+    };
+    let mut id = String::new();
+    let mut classes = vec![];
+    for attribute in trimmed[start + 1..trimmed.len() - 1].split_whitespace() {
+        if let Some(s) = attribute.strip_prefix('#').filter(|s| !s.is_empty()) {
+            id = s.to_owned();
+        } else if let Some(s) = attribute.strip_prefix('.').filter(|s| !s.is_empty()) {
+            classes.push(s.to_owned());
+        } else {
+            return;
+        }
+    }
+    if id.is_empty() && classes.is_empty() {
+        return;
+    }
+    let len = text[..start].trim_end().len();
+    text.truncate(len);
+    if text.is_empty() {
+        inner.pop();
+    }
+    inner.insert(0, Token::HeaderAttributes(id, classes));
+}
+
+/// Returns the labels of the anchors of some tokens, including the labels of code lines
+/// if `code` is true
+pub fn labels(tokens: &[Token], code: bool) -> Vec<String> {
+    let mut res = vec![];
+    for token in tokens {
+        match *token {
+            Token::Anchor(ref label) => res.push(label.clone()),
+            Token::HeaderAttributes(ref id, _) if !id.is_empty() => res.push(id.clone()),
+            Token::CodeBlock(_, ref s) if code => {
+                res.extend(code_labels(s).1.into_iter().flatten());
+            }
+            _ => {
+                if let Some(inner) = token.inner() {
+                    res.extend(labels(inner, code));
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Returns the key and value of a `from:`, `date:` or `signature:` line of a letter
+fn letter_field(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    ["from", "date", "signature"]
+        .iter()
+        .find(|k| key.trim().eq_ignore_ascii_case(k))
+        .map(|k| (*k, value.trim()))
+}
+
+/// Returns the speaker and the text of a line of a play starting with `SPEAKER: `
+fn speaker(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let (speaker, text) = line.split_once(':')?;
+    let speaker = speaker.trim();
+    let valid = !speaker.is_empty()
+        && speaker.chars().count() <= 40
+        && !speaker.contains(|c| "*_[]()`<>".contains(c))
+        && (text.is_empty() || text.starts_with(char::is_whitespace));
+    if valid {
+        Some((speaker, text.trim()))
+    } else {
+        None
+    }
+}
+
+/// Marker of a block quote containing the long description of the previous image
+const DESCRIPTION_MARKER: &str = "[!description]";
+
+/// Replace standalone images followed by a block quote starting with `[!description]`
+/// by described images
+fn find_descriptions(ast: &mut Vec<Token>) {
+    let mut i = 0;
+    while i + 1 < ast.len() {
+        if !matches!(ast[i], Token::StandaloneImage(..)) || !is_description(&ast[i + 1]) {
+            i += 1;
+            continue;
+        }
+        let mut description = match ast.remove(i + 1) {
+            Token::BlockQuote(inner) => inner,
+            _ => unreachable!(),
+        };
+        if let Some(Token::Paragraph(ref mut inner)) = description.first_mut() {
+            if let Some(Token::Str(ref mut s)) = inner.first_mut() {
+                *s = s.trim_start()[DESCRIPTION_MARKER.len()..].trim_start().to_owned();
+                if s.is_empty() {
+                    inner.remove(0);
+                }
+            }
+            while matches!(inner.first(), Some(Token::SoftBreak) | Some(Token::HardBreak)) {
+                inner.remove(0);
+            }
+            if inner.is_empty() {
+                description.remove(0);
+            }
+        }
+        let image = mem::replace(&mut ast[i], Token::Rule);
+        description.insert(0, image);
+        ast[i] = Token::DescribedImage(description);
+        i += 1;
+    }
+}
+
+/// Returns true if a token is a block quote starting with `[!description]`
+fn is_description(token: &Token) -> bool {
+    match *token {
+        Token::BlockQuote(ref inner) => match inner.first() {
+            Some(Token::Paragraph(ref inner)) => match inner.first() {
+                Some(Token::Str(ref s)) => s
+                    .trim_start()
+                    .get(..DESCRIPTION_MARKER.len())
+                    .map_or(false, |start| start.eq_ignore_ascii_case(DESCRIPTION_MARKER)),
+                _ => false,
+            },
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
 /// Replace images which are alone in a paragraph by standalone images
 fn find_standalone(ast: &mut Vec<Token>) {
     for token in ast {
@@ -0,0 +1,157 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Checks that the fonts of a PDF file are embedded, which PDF/A requires.
+//!
+//! This doesn't fully parse the PDF: it looks for font dictionaries in the file and in its
+//! compressed streams (where object streams put them), which is enough for the files LaTeX
+//! generates.
+
+use std::borrow::Cow;
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+/// Returns the position of all occurrences of `needle` in `haystack`
+fn find_all<'h>(haystack: &'h [u8], needle: &'h [u8]) -> impl Iterator<Item = usize> + 'h {
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(move |(_, w)| *w == needle)
+        .map(|(i, _)| i)
+}
+
+/// Returns the objects of a PDF file: its content outside of streams, then the content of
+/// its compressed object streams
+fn sections(pdf: &[u8]) -> Vec<Cow<'_, [u8]>> {
+    let mut outside = vec![];
+    let mut sections = vec![];
+    let mut previous_end = 0;
+    for pos in find_all(pdf, b"stream") {
+        if pos < previous_end || pdf[..pos].ends_with(b"end") {
+            continue;
+        }
+        outside.extend_from_slice(&pdf[previous_end..pos]);
+        let dict = &pdf[previous_end..pos];
+        let dict = match dict.windows(3).rposition(|w| w == b"obj") {
+            Some(start) => &dict[start..],
+            None => dict,
+        };
+        let mut start = pos + b"stream".len();
+        if pdf[start..].starts_with(b"\r") {
+            start += 1;
+        }
+        if pdf[start..].starts_with(b"\n") {
+            start += 1;
+        }
+        let end = match find_all(&pdf[start..], b"endstream").next() {
+            Some(len) => start + len,
+            None => break,
+        };
+        previous_end = end;
+        // Other streams (pages, images, font programs...) don't contain objects
+        if find_all(dict, b"/ObjStm").next().is_none()
+            || find_all(dict, b"/FlateDecode").next().is_none()
+        {
+            continue;
+        }
+        // A truncated or corrupted stream still gives the objects before the error
+        let mut content = vec![];
+        let _ = ZlibDecoder::new(&pdf[start..end]).read_to_end(&mut content);
+        sections.push(Cow::Owned(content));
+    }
+    outside.extend_from_slice(&pdf[previous_end.min(pdf.len())..]);
+    sections.insert(0, Cow::Owned(outside));
+    sections
+}
+
+/// Returns the dictionary starting at `start` (with `<<`), up to its matching `>>`
+fn dictionary(content: &[u8], start: usize) -> &[u8] {
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < content.len() {
+        match &content[i..i + 2] {
+            b"<<" => {
+                depth += 1;
+                i += 2;
+            }
+            b">>" => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return &content[start..i];
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    &content[start..]
+}
+
+/// Returns the value of a name entry (e.g. `/FontName`) of a dictionary, without its slash
+fn name(dict: &[u8], key: &[u8]) -> Option<String> {
+    let pos = find_all(dict, key).next()? + key.len();
+    let value = dict[pos..].iter().skip_while(|b| b.is_ascii_whitespace());
+    let value: Vec<u8> = value
+        .skip(1)
+        .take_while(|b| !b.is_ascii_whitespace() && !b"/<>[]()".contains(b))
+        .copied()
+        .collect();
+    Some(String::from_utf8_lossy(&value).into_owned())
+}
+
+/// Returns the names of the fonts that aren't embedded in a PDF file
+pub fn unembedded(pdf: &[u8]) -> Vec<String> {
+    let mut fonts = vec![];
+    for content in sections(pdf) {
+        let mut dicts = vec![];
+        for pos in find_all(&content, b"<<") {
+            let dict = dictionary(&content, pos);
+            // Only keep innermost dictionaries whose type is a font or a font descriptor
+            if find_all(&dict[2..], b"<<").next().is_none()
+                && find_all(dict, b"/Font").next().is_some()
+            {
+                dicts.push(dict);
+            }
+        }
+        for dict in dicts {
+            let missing = if find_all(dict, b"/FontDescriptor").next().is_some()
+                && find_all(dict, b"/FontName").next().is_some()
+            {
+                // A font descriptor must point to the font program
+                find_all(dict, b"/FontFile").next().is_none()
+            } else if let Some(subtype) = name(dict, b"/Subtype") {
+                // Simple fonts without a descriptor are the standard ones, which are never
+                // embedded; Type0 and Type3 fonts don't need one
+                (subtype == "Type1" || subtype == "TrueType" || subtype == "MMType1")
+                    && find_all(dict, b"/FontDescriptor").next().is_none()
+                    && find_all(dict, b"/BaseFont").next().is_some()
+            } else {
+                false
+            };
+            if missing {
+                let font = name(dict, b"/FontName")
+                    .or_else(|| name(dict, b"/BaseFont"))
+                    .unwrap_or_default();
+                if !fonts.contains(&font) {
+                    fonts.push(font);
+                }
+            }
+        }
+    }
+    fonts
+}
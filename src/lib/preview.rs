@@ -0,0 +1,87 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rendering of a book given as JSON to HTML, entirely in memory, for a live preview
+//! (e.g. in a browser, with the `wasm` feature).
+
+use crate::book::Book;
+use crate::error::{Error, Result, Source};
+use crate::number::Number;
+
+use rust_i18n::t;
+use serde_json::{Map, Value};
+use yaml_rust::Yaml;
+
+/// Converts a JSON value to the YAML value it would be in a book configuration file
+fn json_to_yaml(value: Value) -> Yaml {
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => Yaml::Integer(n),
+            None => Yaml::Real(n.to_string()),
+        },
+        Value::String(s) => Yaml::String(s),
+        Value::Array(values) => Yaml::Array(values.into_iter().map(json_to_yaml).collect()),
+        Value::Object(map) => Yaml::Hash(
+            map.into_iter()
+                .map(|(key, value)| (Yaml::String(key), json_to_yaml(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Renders a book to standalone HTML, without reading or writing any file
+///
+/// # Arguments
+/// * `config_json`: the book options, as a JSON object, e.g. `{"title": "Foo", "lang": "fr"}`,
+///   where `null` keeps the default value of an option;
+/// * `chapters_json`: the chapters, as a JSON array of Markdown strings.
+///
+/// # Example
+///
+/// ```
+/// let html = crowbook::render_html(r#"{"title": "Preview"}"#, r#"["# Chapter\n\nText"]"#).unwrap();
+/// assert!(html.contains("<title>Preview</title>"));
+/// ```
+pub fn render_html(config_json: &str, chapters_json: &str) -> Result<String> {
+    let options: Map<String, Value> = serde_json::from_str(config_json).map_err(|err| {
+        Error::config_parser(Source::empty(), t!("preview.config", error = err))
+    })?;
+    let chapters: Vec<String> = serde_json::from_str(chapters_json).map_err(|err| {
+        Error::config_parser(Source::empty(), t!("preview.chapters", error = err))
+    })?;
+
+    let mut book = Book::new();
+    book.options.set("crowbook.in_memory", "true")?;
+    for (key, value) in options {
+        if value.is_null() {
+            continue;
+        }
+        book.options.set_yaml(Yaml::String(key), json_to_yaml(value))?;
+    }
+    book.update_cleaner();
+    for (i, chapter) in chapters.iter().enumerate() {
+        book.add_chapter_from_named_source(
+            Number::Default,
+            &format!("chapter_{}.md", i + 1),
+            chapter.as_bytes(),
+            true,
+        )?;
+    }
+    book.render_format_to_string("html")
+}
@@ -0,0 +1,69 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Actions run after a format is rendered to a file, set by `publish.*` options,
+//! e.g. checking an ebook with Kindle Previewer and sending it to a Kindle.
+
+use crate::book::Book;
+use crate::error::{Error, Result, Source};
+use crate::hooks;
+
+use std::fs;
+use std::path::Path;
+use rust_i18n::t;
+
+/// Formats published to a Kindle if `publish.kindle.formats` isn't set
+const KINDLE_FORMATS: &[&str] = &["epub", "azw3"];
+
+/// Publishes a rendered file to a Kindle if `publish.kindle` is set and its format is one
+/// of `publish.kindle.formats`
+///
+/// The file is checked with the `publish.kindle.previewer` command, copied to the
+/// `publish.kindle.device` directory, then sent with the `publish.kindle.email` command,
+/// for each of these options that is set.
+pub fn kindle(book: &Book, format: &str, path: &Path) -> Result<()> {
+    if !book.options.get_bool("publish.kindle").unwrap() {
+        return Ok(());
+    }
+    let published = match book.options.get_str_vec("publish.kindle.formats") {
+        Ok(formats) => formats.iter().any(|f| f == format),
+        Err(_) => KINDLE_FORMATS.contains(&format),
+    };
+    if !published {
+        return Ok(());
+    }
+
+    hooks::run(book, "publish.kindle.previewer", Some((format, path)))?;
+    if let Ok(device) = book.options.get_path("publish.kindle.device") {
+        let device = Path::new(&device);
+        if !device.is_dir() {
+            return Err(Error::render(
+                Source::empty(),
+                t!("publish.no_device", path = device.display()),
+            ));
+        }
+        let dest = device.join(path.file_name().unwrap_or_default());
+        fs::copy(path, &dest).map_err(|err| {
+            Error::render(
+                Source::empty(),
+                t!("publish.copy_error", path = dest.display(), error = err),
+            )
+        })?;
+        info!("{}", t!("publish.copied", path = dest.display()));
+    }
+    hooks::run(book, "publish.kindle.email", Some((format, path)))
+}
@@ -0,0 +1,120 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Option values referencing other options, e.g. `output.epub: "{{title|slug}}.epub"`, which
+//! are evaluated once the book configuration is read.
+
+use crate::bookoption::BookOption;
+use crate::slug::Slugifier;
+
+use rust_i18n::t;
+
+/// A part of a value
+enum Part<'a> {
+    /// Text written as it is
+    Text(&'a str),
+    /// The key of an option, and the filters applied to its value
+    Reference(&'a str, Vec<&'a str>),
+}
+
+/// Returns true if a value references other options
+pub fn is_template(value: &str) -> bool {
+    value.contains("{{")
+}
+
+/// Splits a value into text and references
+fn parse(value: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = vec![];
+    let mut rest = value;
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            parts.push(Part::Text(&rest[..start]));
+        }
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| t!("references.unclosed", value = value))?;
+        let mut filters = rest[start + 2..start + end].split('|').map(str::trim);
+        // `split` always returns at least one item
+        let key = filters.next().unwrap();
+        if key.is_empty() {
+            return Err(t!("references.empty", value = value));
+        }
+        parts.push(Part::Reference(key, filters.collect()));
+        rest = &rest[start + end + 2..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    Ok(parts)
+}
+
+/// Returns the keys of the options a value references
+pub fn references(value: &str) -> Result<Vec<&str>, String> {
+    Ok(parse(value)?
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Reference(key, _) => Some(key),
+            Part::Text(_) => None,
+        })
+        .collect())
+}
+
+/// Returns the value of an option as it is inserted in another one
+fn to_text(option: &BookOption) -> String {
+    match option {
+        BookOption::String(s) | BookOption::Path(s) => s.clone(),
+        BookOption::Bool(b) => b.to_string(),
+        BookOption::Char(c) => c.to_string(),
+        BookOption::Int(i) => i.to_string(),
+        BookOption::Float(f) => f.to_string(),
+        BookOption::StringVec(v) => v.join(", "),
+    }
+}
+
+/// Applies a filter to a value
+fn filter(name: &str, value: String, slugifier: &Slugifier) -> Result<String, String> {
+    match name {
+        "slug" => Ok(slugifier.slug(&value)),
+        "lower" => Ok(value.to_lowercase()),
+        "upper" => Ok(value.to_uppercase()),
+        "trim" => Ok(value.trim().to_owned()),
+        _ => Err(t!("references.unknown_filter", filter = name)),
+    }
+}
+
+/// Evaluates a value, replacing its references by the value of the options returned by
+/// `get`, the `slug` filter using `slugifier`
+pub fn evaluate<'o, F>(value: &str, slugifier: &Slugifier, get: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<&'o BookOption>,
+{
+    let mut result = String::new();
+    for part in parse(value)? {
+        match part {
+            Part::Text(text) => result.push_str(text),
+            Part::Reference(key, filters) => {
+                let option = get(key).ok_or_else(|| t!("references.not_set", key = key))?;
+                let mut value = to_text(option);
+                for name in filters {
+                    value = filter(name, value, slugifier)?;
+                }
+                result.push_str(&value);
+            }
+        }
+    }
+    Ok(result)
+}
@@ -1,4 +1,5 @@
-use crate::error::{Error, Result, Source};
+use crate::book::Book;
+use crate::error::{self, Error, Result, Source};
 use crate::token::Token;
 use crate::misc;
 
@@ -22,6 +23,8 @@ pub struct ResourceHandler {
     links: HashMap<String, String>,
     map_images: bool,
     base64: bool,
+    sandbox: Option<PathBuf>,
+    strict: bool,
 
     /// Maps an original (local) file name to a new file name. Allows to
     /// make sure all image files will be included in e.g. the Epub document.
@@ -37,6 +40,8 @@ impl ResourceHandler {
             images: HashMap::new(),
             map_images: false,
             base64: false,
+            sandbox: None,
+            strict: false,
         }
     }
 
@@ -56,6 +61,27 @@ impl ResourceHandler {
         self.base64 = b;
     }
 
+    /// Returns true if `map_image` includes images as base64
+    pub fn is_base64(&self) -> bool {
+        self.base64
+    }
+
+    /// Sets sandbox mode
+    ///
+    /// If set to some directory, only images located inside this directory
+    /// can be included, and remote images are refused.
+    pub fn set_sandbox(&mut self, root: Option<PathBuf>) {
+        self.sandbox = root;
+    }
+
+    /// Sets strict mode
+    ///
+    /// If set to true, `map_link` returns an error for links that don't match any
+    /// file of the book instead of a warning.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
     /// Add a local image file and get the resulting transformed
     /// file name
     pub fn map_image<'a, S: Into<Cow<'a, str>>>(
@@ -66,6 +92,12 @@ impl ResourceHandler {
         // If image is not local, do nothing much
         let file = file.into();
         if !Self::is_local(file.as_ref()) {
+            if self.sandbox.is_some() {
+                return Err(Error::render(
+                    source,
+                    t!("resources.sandbox_remote", file = file),
+                ));
+            }
             warn!(
                 "{}",
                 t!("resources.non_local",
@@ -75,6 +107,17 @@ impl ResourceHandler {
             return Ok(file);
         }
 
+        // In sandbox mode, check that the image is inside the book's directory, before
+        // checking its existence so it isn't revealed for files outside
+        if let Some(ref root) = self.sandbox {
+            if !misc::is_inside(file.as_ref(), root) {
+                return Err(Error::render(
+                    source,
+                    t!("resources.sandbox_outside", file = file),
+                ));
+            }
+        }
+
         // Check exisence of the file
         if fs::metadata(file.as_ref()).is_err() {
             return Err(Error::file_not_found(
@@ -156,36 +199,77 @@ impl ResourceHandler {
         &self.images
     }
 
+    /// Adds an image to the images mapping, e.g. when restoring a cached state
+    #[doc(hidden)]
+    pub fn add_image_mapping(&mut self, source: String, dest: String) {
+        self.images.insert(source, dest);
+    }
+
     /// Add a match between an original file and a dest file
     pub fn add_link<S1: Into<String>, S2: Into<String>>(&mut self, from: S1, to: S2) {
         self.links.insert(from.into(), to.into());
     }
 
+    /// Maps the links to the files listed in `resources.files` to their path in the output
+    /// (i.e. in `resources.out_path`), for formats that copy these files
+    pub fn add_resources_links(&mut self, book: &Book) -> Result<()> {
+        if let Ok(list) = book.options.get_str_vec("resources.files") {
+            let base = book.options.get_path("resources.base_path.files").unwrap();
+            let out_path = book.options.get_relative_path("resources.out_path")?;
+            for path in get_files(list, &base)? {
+                let from = Path::new(&base).join(&path);
+                let from = from.strip_prefix(&book.root).unwrap_or(&from);
+                self.add_link(
+                    misc::clean_path(from),
+                    misc::clean_path(Path::new(&out_path).join(&path)),
+                );
+            }
+        }
+        Ok(())
+    }
+
     /// Get a destination link from an original link
     pub fn get_link<'a>(&'a self, from: &'a str) -> &'a str {
+        match self.find_link(from) {
+            Ok(link) => link,
+            Err(problem) => {
+                warn!("{problem}");
+                from
+            }
+        }
+    }
+
+    /// Get a destination link from an original link, like `get_link`, except that a link
+    /// without match is an error in strict mode
+    pub fn map_link<'a>(&'a self, source: &Source, from: &'a str) -> Result<&'a str> {
+        match self.find_link(from) {
+            Ok(link) => Ok(link),
+            Err(problem) => error::lossy(self.strict, source, problem).map(|_| from),
+        }
+    }
+
+    /// Finds the destination of a link, or returns a message explaining there is none
+    fn find_link<'a>(&'a self, from: &str) -> std::result::Result<&'a str, String> {
         if let Some(link) = self.links.get(from) {
-            link
+            Ok(link)
+        } else if let Some(link) = self.links.get(&misc::clean_path(from)) {
+            Ok(link)
         } else {
             // Try to get a link by changing the extension
             let new_from = format!("{}", Path::new(from).with_extension("md").display()).replace("\\", "/");
-            if let Some(link) = self.links.get(&new_from) {
-                link
-            } else {
-                warn!(
-                    "{}",
-                    t!("resources.no_match",
-                        file = from,
-                        new_from = new_from
-                    )
-                );
-                from
+            match self.links.get(&new_from) {
+                Some(link) => Ok(link),
+                None => Err(t!("resources.no_match",
+                    file = from,
+                    new_from = new_from
+                )),
             }
         }
     }
 
     /// Tell whether a file name is a local resource or net
     pub fn contains_link(&self, from: &str) -> bool {
-        if self.links.contains_key(from) {
+        if self.links.contains_key(from) || self.links.contains_key(&misc::clean_path(from)) {
             true
         } else {
             // Try to get a link by changing the extension and rewriting backlashes
@@ -194,8 +278,19 @@ impl ResourceHandler {
         }
     }
 
+    /// Returns false if path is an URL with a scheme, e.g. `https:`, `mailto:` or `data:`
     pub fn is_local(path: &str) -> bool {
-        !path.contains("://") // todo: use better algorithm
+        match path.find(':') {
+            // Single letters are Windows drive names, not schemes
+            Some(i) if i > 1 => {
+                let scheme = &path[..i];
+                !(scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')))
+            }
+            _ => true,
+        }
     }
 
     /// Add a path offset to all linked urls and images src
@@ -207,17 +302,18 @@ impl ResourceHandler {
         for token in ast {
             match *token {
                 Token::Link(ref mut url, _, ref mut v) => {
-                    if ResourceHandler::is_local(url) {
-                        let new_url = format!("{}", link_offset.join(&url).display());
-                        *url = new_url;
+                    if ResourceHandler::is_local(url)
+                        && !url.starts_with('#')
+                        && !misc::is_absolute_path(url)
+                    {
+                        *url = misc::path_to_url(link_offset.join(url.as_str()));
                     }
                     Self::add_offset(link_offset, image_offset, v);
                 }
                 Token::Image(ref mut url, _, ref mut v)
                 | Token::StandaloneImage(ref mut url, _, ref mut v) => {
-                    if ResourceHandler::is_local(url) {
-                        let new_url = format!("{}", image_offset.join(&url).display());
-                        *url = new_url;
+                    if ResourceHandler::is_local(url) && !misc::is_absolute_path(url) {
+                        *url = misc::path_to_url(image_offset.join(url.as_str()));
                     }
                     Self::add_offset(link_offset, image_offset, v);
                 }
@@ -0,0 +1,117 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Full-text search index, used by the multiple files HTML renderer so
+//! readers can search the book offline.
+
+use crate::token::Token;
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// A document of the index, i.e. a chapter
+struct Document {
+    url: String,
+    title: String,
+    body: String,
+}
+
+/// Full-text search index of a book
+///
+/// The index maps each word to the documents containing it, along with
+/// the number of occurrences, so it can be used by a script without any
+/// further processing.
+#[derive(Default)]
+pub struct SearchIndex {
+    documents: Vec<Document>,
+    index: BTreeMap<String, Vec<(usize, usize)>>,
+}
+
+impl SearchIndex {
+    /// Creates a new, empty index
+    pub fn new() -> SearchIndex {
+        SearchIndex::default()
+    }
+
+    /// Adds a chapter to the index
+    ///
+    /// * `url`: the link to the chapter
+    /// * `title`: the title of the chapter, as raw text
+    /// * `tokens`: the content of the chapter
+    pub fn add(&mut self, url: &str, title: &str, tokens: &[Token]) {
+        let mut body = String::new();
+        collect_text(tokens, &mut body);
+        let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for word in words(&body) {
+            *counts.entry(word).or_insert(0) += 1;
+        }
+        let id = self.documents.len();
+        for (word, count) in counts {
+            self.index.entry(word).or_default().push((id, count));
+        }
+        self.documents.push(Document {
+            url: url.to_owned(),
+            title: title.to_owned(),
+            body,
+        });
+    }
+
+    /// Returns the JSON representation of the index
+    pub fn to_json(&self) -> Value {
+        let docs: Vec<Value> = self
+            .documents
+            .iter()
+            .map(|doc| json!({"url": doc.url, "title": doc.title, "body": doc.body}))
+            .collect();
+        json!({"docs": docs, "index": self.index})
+    }
+}
+
+/// Splits a text in lowercase words, the same way the search script does
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+}
+
+/// Appends the text content of tokens to a string, separating blocks with spaces
+fn collect_text(tokens: &[Token], text: &mut String) {
+    for token in tokens {
+        match *token {
+            Token::Str(ref s) | Token::Code(ref s) | Token::CodeBlock(_, ref s) => {
+                text.push_str(s)
+            }
+            Token::SoftBreak | Token::HardBreak | Token::Rule => text.push(' '),
+            Token::Raw(..) | Token::FootnoteReference(_) => {}
+            Token::Emphasis(ref v)
+            | Token::Strong(ref v)
+            | Token::Strikethrough(ref v)
+            | Token::Superscript(ref v)
+            | Token::Subscript(ref v)
+            | Token::Annotation(_, ref v)
+            | Token::Link(_, _, ref v) => collect_text(v, text),
+            _ => {
+                if let Some(v) = token.inner() {
+                    collect_text(v, text);
+                }
+                text.push(' ');
+            }
+        }
+    }
+}
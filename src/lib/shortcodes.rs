@@ -0,0 +1,222 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Shortcodes, e.g. `{{% youtube id="..." %}}`, which are expanded in the text of a
+//! document using templates set by the `shortcodes.{name}.{format}` options.
+
+use crate::bookoptions::BookOptions;
+use crate::error::{Error, Result, Source};
+use crate::parser::Parser;
+use crate::token::Token;
+
+use std::collections::HashMap;
+use rust_i18n::t;
+
+/// Templates of the shortcodes of a book
+#[derive(Debug, Clone, Default)]
+pub struct Shortcodes {
+    /// Templates for each shortcode, as `(format, template)`
+    templates: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Shortcodes {
+    /// Gets the shortcodes set by `shortcodes.{name}.{format}` options
+    pub fn from(options: &BookOptions) -> Shortcodes {
+        let mut templates: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for key in options.get_shortcodes() {
+            let (name, format) = key["shortcodes.".len()..].rsplit_once('.').unwrap();
+            templates
+                .entry(name.to_owned())
+                .or_default()
+                .push((format.to_owned(), options.get_str(key).unwrap().to_owned()));
+        }
+        for v in templates.values_mut() {
+            v.sort();
+        }
+        Shortcodes { templates }
+    }
+
+    /// Returns true if there are no shortcodes
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Expands the shortcodes in the text of a document, leaving code untouched
+    ///
+    /// Each shortcode is replaced by a `Token::Shortcode`, containing its HTML and LaTeX
+    /// templates as raw tokens and its Markdown template, parsed by `parser`, which is
+    /// only used by formats that don't have their own template. A shortcode alone in
+    /// a paragraph replaces this paragraph, so its templates can contain blocks.
+    pub fn expand(&self, source: &Source, parser: &mut Parser, ast: &mut Vec<Token>) -> Result<()> {
+        let mut i = 0;
+        while i < ast.len() {
+            let standalone = match ast[i] {
+                Token::Paragraph(ref inner) => match inner.as_slice() {
+                    [Token::Str(ref s)] => standalone(s),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(content) = standalone {
+                if let Some(token) = self.shortcode(source, parser, &content, true)? {
+                    ast[i] = token;
+                    i += 1;
+                    continue;
+                }
+            }
+            match ast[i] {
+                Token::Code(_) | Token::CodeBlock(..) | Token::Raw(..) | Token::Shortcode(..) => i += 1,
+                Token::Str(ref s) if s.contains("{{%") => {
+                    let tokens = self.expand_str(source, parser, s)?;
+                    let len = tokens.len();
+                    ast.splice(i..=i, tokens);
+                    i += len;
+                }
+                _ => {
+                    if let Some(inner) = ast[i].inner_mut() {
+                        self.expand(source, parser, inner)?;
+                    }
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands the shortcodes of some text, returning the text around them as `Str` tokens
+    fn expand_str(&self, source: &Source, parser: &mut Parser, s: &str) -> Result<Vec<Token>> {
+        let mut res = vec![];
+        let mut text = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{%") {
+            let end = rest[start..].find("%}}").map(|end| start + end).ok_or_else(|| {
+                let len = rest[start..].find('\n').unwrap_or(rest.len() - start);
+                Error::parser(
+                    source,
+                    t!("shortcode.unclosed", start = &rest[start..start + len]),
+                )
+            })?;
+            text.push_str(&rest[..start]);
+            match self.shortcode(source, parser, &rest[start + 3..end], false)? {
+                Some(token) => {
+                    if !text.is_empty() {
+                        res.push(Token::Str(std::mem::take(&mut text)));
+                    }
+                    res.push(token);
+                }
+                None => text.push_str(&rest[start..end + 3]),
+            }
+            rest = &rest[end + 3..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            res.push(Token::Str(text));
+        }
+        Ok(res)
+    }
+
+    /// Expands a shortcode, given its content between `{{%` and `%}}`, or returns None if
+    /// it isn't defined
+    fn shortcode(
+        &self,
+        source: &Source,
+        parser: &mut Parser,
+        content: &str,
+        block: bool,
+    ) -> Result<Option<Token>> {
+        let (name, args) = parse_shortcode(source, content)?;
+        let templates = match self.templates.get(name) {
+            Some(templates) => templates,
+            None => {
+                warn!("{}", t!("shortcode.unknown", name = name));
+                return Ok(None);
+            }
+        };
+        let mut raws = vec![];
+        let mut markdown = vec![];
+        for (format, template) in templates {
+            let engine = upon::Engine::new();
+            let content = engine
+                .compile(template)
+                .and_then(|template| template.render(&args).to_string())
+                .map_err(|err| {
+                    Error::template(
+                        source,
+                        t!(
+                            "error.compile_template",
+                            template = format!("shortcodes.{name}.{format}"),
+                            error = format!("{err:#}")
+                        ),
+                    )
+                })?;
+            if format != "markdown" {
+                raws.push(Token::Raw(format.clone(), content));
+            } else if block {
+                markdown = parser.parse(&content, None)?;
+            } else {
+                markdown = parser.parse_inline(&content)?;
+            }
+        }
+        Ok(Some(Token::Shortcode(raws, markdown)))
+    }
+}
+
+/// Returns the content of a shortcode if it is the only content of some text
+fn standalone(s: &str) -> Option<String> {
+    let content = s.trim().strip_prefix("{{%")?.strip_suffix("%}}")?;
+    if content.contains("%}}") {
+        None
+    } else {
+        Some(content.to_owned())
+    }
+}
+
+/// Parses the content of a shortcode, e.g. `figure src="foo.png" width=50%`
+fn parse_shortcode<'s>(source: &Source, s: &'s str) -> Result<(&'s str, HashMap<&'s str, &'s str>)> {
+    let s = s.trim();
+    let (name, mut rest) = s.split_at(s.find(char::is_whitespace).unwrap_or(s.len()));
+    let mut args = HashMap::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let invalid = || {
+            let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            Error::parser(
+                source,
+                t!("shortcode.invalid_argument", argument = &rest[..len], name = name),
+            )
+        };
+        let equal = rest.find('=').ok_or_else(invalid)?;
+        let key = &rest[..equal];
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        let value = &rest[equal + 1..];
+        let (value, len) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"').ok_or_else(invalid)?;
+            (&quoted[..end], end + 2)
+        } else {
+            let end = value.find(char::is_whitespace).unwrap_or(value.len());
+            (&value[..end], end)
+        };
+        args.insert(key, value);
+        rest = &rest[equal + 1 + len..];
+    }
+    Ok((name, args))
+}
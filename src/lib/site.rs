@@ -0,0 +1,225 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Static website of the book: a landing page with its cover, a blurb and links to download
+//! other formats, next to the multiple files HTML version, that can be published as is
+//! (e.g. with GitHub Pages).
+
+use crate::book::Book;
+use crate::book_renderer::BookRenderer;
+use crate::error::{Error, Result, Source};
+use crate::html::{self, HtmlRenderer};
+use crate::html_dir::HtmlDir;
+use crate::misc;
+use crate::parser::Parser;
+use crate::renderer::Renderer;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crowbook_text_processing::escape;
+use rust_i18n::t;
+
+/// Formats rendered for download if `site.downloads` isn't set
+const DOWNLOADS: &[&str] = &["epub"];
+
+/// Renders the book as a static website in a directory
+pub struct Site {}
+
+impl Site {
+    /// Returns the formats set by `site.downloads`
+    fn downloads(book: &Book) -> Result<Vec<String>> {
+        let downloads = match book.options.get_str_vec("site.downloads") {
+            Ok(formats) => formats.to_vec(),
+            Err(_) => DOWNLOADS.iter().map(|f| f.to_string()).collect(),
+        };
+        if downloads.iter().any(|f| f == "site") {
+            return Err(Error::book_option(
+                &book.options.source,
+                t!("site.recursive_download"),
+            ));
+        }
+        Ok(downloads)
+    }
+
+    /// Returns the subdirectory set by `site.reader`, or None if it is empty
+    fn reader<'a>(book: &'a Book) -> Result<Option<&'a str>> {
+        let reader = book.options.get_str("site.reader").unwrap();
+        if reader.is_empty() {
+            return Ok(None);
+        }
+        if book.options.is_sandboxed() && !misc::is_contained(reader) {
+            return Err(Error::book_option(
+                &book.options.source,
+                t!("opt.sandbox_path", key = "site.reader", value = reader),
+            ));
+        }
+        Ok(Some(reader))
+    }
+
+    /// Renders the landing page
+    fn render_index(
+        book: &Book,
+        reader: Option<&str>,
+        downloads: Vec<upon::Value>,
+        cover: Option<String>,
+    ) -> Result<String> {
+        let mut html = HtmlRenderer::new(
+            book,
+            book.options
+                .get_str("html.highlight.theme")
+                .unwrap_or_else(|_| book.options.get_str("rendering.highlight.theme").unwrap()),
+        )?;
+        let mut data = html.get_metadata()?;
+        let blurb = match book.options.get_str("site.blurb") {
+            Ok(blurb) => html.render_vec(&Parser::from(book).parse(blurb, None)?)?,
+            Err(_) => match data.get("description") {
+                Some(upon::Value::String(s)) if !s.is_empty() => format!("<p>{s}</p>"),
+                _ => String::new(),
+            },
+        };
+        data.insert("blurb".into(), blurb.into());
+        data.insert("cover".into(), cover.unwrap_or_default().into());
+        data.insert(
+            "reader".into(),
+            reader.map_or_else(String::new, |r| format!("{}/index.html", r.trim_end_matches('/'))).into(),
+        );
+        data.insert("downloads".into(), upon::Value::List(downloads));
+        data.insert("theme".into(), html.get_css_theme("html").into());
+
+        let template_src = book.get_template("site.template")?;
+        let template = book.compile_str(template_src.as_ref(), &book.source, "site.template")?;
+        html::escape_raw(&mut data);
+        book.template_vars.record("site.template", &data);
+        Ok(template.render(&data).to_string()?)
+    }
+
+    /// Copies the cover next to the landing page, returning its file name if there is one
+    fn copy_cover(book: &Book, dest: &Path) -> Result<Option<String>> {
+        let cover = match book.options.get_path("cover") {
+            Ok(cover) => cover,
+            Err(_) => return Ok(None),
+        };
+        let file = match Path::new(&cover).extension() {
+            Some(ext) => format!("cover.{}", ext.to_string_lossy()),
+            None => String::from("cover"),
+        };
+        fs::copy(&cover, dest.join(&file)).map_err(|err| {
+            Error::file_not_found(&book.source, t!("epub.cover"), cover.clone()).with_cause(err)
+        })?;
+        Ok(Some(file))
+    }
+
+    /// Writes a file of the site
+    fn write_file(book: &Book, path: &Path, content: &[u8]) -> Result<()> {
+        fs::write(path, content).map_err(|e| {
+            Error::render(
+                &book.source,
+                t!("html.write_file_error", file = path.display(), error = e),
+            )
+        })
+    }
+}
+
+impl BookRenderer for Site {
+    fn auto_path(&self, _: &str) -> Result<String> {
+        Ok(String::from("site"))
+    }
+
+    fn required_commands(&self, book: &Book) -> Result<Vec<String>> {
+        let mut commands = vec![];
+        for format in Site::downloads(book)? {
+            commands.extend(book.renderer(&format)?.1.required_commands(book)?);
+        }
+        Ok(commands)
+    }
+
+    fn render(&self, _: &Book, _: &mut dyn io::Write) -> Result<()> {
+        Err(Error::render(Source::empty(), t!("site.to_stream_error")))
+    }
+
+    fn render_to_file(&self, book: &Book, path: &Path) -> Result<()> {
+        let downloads = Site::downloads(book)?;
+        let reader = Site::reader(book)?;
+
+        if let Ok(metadata) = fs::metadata(path) {
+            if metadata.is_file() {
+                return Err(Error::render(
+                    &book.source,
+                    t!("html.exist_not_dir", path = path.display()),
+                ));
+            }
+            debug!("{}", t!("html.delete_dir", path = path.display()));
+            fs::remove_dir_all(path).map_err(|e| {
+                Error::render(
+                    &book.source,
+                    t!("html.delete_dir_error", path = path.display(), error = e),
+                )
+            })?;
+        }
+        fs::DirBuilder::new()
+            .recursive(true)
+            .create(path)
+            .map_err(|e| {
+                Error::render(
+                    &book.source,
+                    t!("html.create_dir_error", path = path.display(), error = e),
+                )
+            })?;
+
+        if let Some(reader) = reader {
+            HtmlDir {}.render_to_file(book, &path.join(reader))?;
+        }
+
+        let name = book.file_stem().unwrap_or_else(|| String::from("book"));
+        let mut links = Vec::with_capacity(downloads.len());
+        for format in &downloads {
+            let (description, renderer) = book.renderer(format)?;
+            let file = renderer.auto_path(&name)?;
+            book.timings().time(&format!("render: {format}"), || {
+                renderer.render_to_file(book, &path.join(&file))
+            })?;
+            let mut link = BTreeMap::new();
+            link.insert(String::from("format"), upon::Value::from(format.as_str()));
+            link.insert(String::from("name"), upon::Value::from(description));
+            link.insert(String::from("url"), upon::Value::from(escape::html(file).into_owned()));
+            links.push(upon::Value::Map(link));
+        }
+
+        let cover = Site::copy_cover(book, path)?;
+        let index = Site::render_index(book, reader, links, cover)?;
+        Site::write_file(book, &path.join("index.html"), index.as_bytes())?;
+        if book.options.get_bool("site.nojekyll").unwrap() {
+            // Otherwise GitHub Pages ignores files starting with an underscore
+            Site::write_file(book, &path.join(".nojekyll"), b"")?;
+        }
+        Ok(())
+    }
+
+    fn dry_run(&self, book: &Book) -> Result<()> {
+        let downloads = Site::downloads(book)?;
+        if Site::reader(book)?.is_some() {
+            HtmlDir {}.dry_run(book)?;
+        }
+        for format in &downloads {
+            book.renderer(format)?.1.dry_run(book)?;
+        }
+        Ok(())
+    }
+}
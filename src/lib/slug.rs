@@ -0,0 +1,191 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Slugs of texts, usable in file names and URLs, set by the `slug.*` options, and the
+//! policies naming files and anchors after them.
+
+use crate::bookoptions::BookOptions;
+
+use std::collections::HashSet;
+
+use rust_i18n::t;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Transliterations that differ from the usual ones in some languages, e.g. `ü` is
+/// written `ue` in German but `u` elsewhere
+fn locale_replacements(lang: &str) -> &'static [(char, &'static str)] {
+    let language = lang.split(|c| c == '-' || c == '_').next().unwrap_or_default();
+    match language.to_ascii_lowercase().as_str() {
+        "de" => &[
+            ('ä', "ae"),
+            ('ö', "oe"),
+            ('ü', "ue"),
+            ('Ä', "Ae"),
+            ('Ö', "Oe"),
+            ('Ü', "Ue"),
+            ('ß', "ss"),
+        ],
+        "da" | "nb" | "nn" | "no" => &[
+            ('æ', "ae"),
+            ('ø', "oe"),
+            ('å', "aa"),
+            ('Æ', "Ae"),
+            ('Ø', "Oe"),
+            ('Å', "Aa"),
+        ],
+        _ => &[],
+    }
+}
+
+/// Returns true if a text can separate the words of slugs: slugs are used in file names,
+/// so it can't contain path separators, dots or control characters
+pub fn is_valid_separator(separator: &str) -> bool {
+    !separator
+        .chars()
+        .any(|c| matches!(c, '/' | '\\' | '.') || c.is_control())
+}
+
+/// Converts texts to slugs, following the `slug.separator`, `slug.lowercase` and
+/// `slug.transliterate` options and the language of the book
+///
+/// # Example
+///
+/// ```
+/// use crowbook::{BookOptions, Slugifier};
+///
+/// let mut options = BookOptions::new();
+/// options.set("lang", "de").unwrap();
+/// let slugifier = Slugifier::new(&options);
+/// assert_eq!(slugifier.slug("Über die Brücke"), "ueber-die-bruecke");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Slugifier {
+    separator: String,
+    lowercase: bool,
+    transliterate: bool,
+    lang: String,
+}
+
+impl Slugifier {
+    /// Creates a slugifier from the options of a book
+    pub fn new(options: &BookOptions) -> Slugifier {
+        Slugifier {
+            separator: options
+                .get_str("slug.separator")
+                .ok()
+                .filter(|separator| is_valid_separator(separator))
+                .unwrap_or("-")
+                .to_owned(),
+            lowercase: options.get_bool("slug.lowercase").unwrap(),
+            transliterate: options.get_bool("slug.transliterate").unwrap(),
+            lang: options.get_str("lang").unwrap().to_owned(),
+        }
+    }
+
+    /// Returns the slug of a text: its letters and digits, words being separated by
+    /// the separator
+    ///
+    /// With transliteration, only ASCII letters and digits are kept, accented letters
+    /// losing their accents and other scripts (e.g. Chinese) being written with latin
+    /// letters. Without it, letters of all scripts are kept as they are.
+    pub fn slug(&self, s: &str) -> String {
+        let s: String = s.nfc().collect();
+        let s = if self.transliterate {
+            let replacements = locale_replacements(&self.lang);
+            let mut replaced = String::with_capacity(s.len());
+            for c in s.chars() {
+                match replacements.iter().find(|(from, _)| *from == c) {
+                    Some((_, to)) => replaced.push_str(to),
+                    None => replaced.push(c),
+                }
+            }
+            // Characters without transliteration separate words
+            deunicode::deunicode_with_tofu(&replaced, " ")
+        } else {
+            s
+        };
+        let mut slug = String::with_capacity(s.len());
+        let mut separate = false;
+        for c in s.chars() {
+            if c.is_alphanumeric() {
+                if separate && !slug.is_empty() {
+                    slug.push_str(&self.separator);
+                }
+                separate = false;
+                if self.lowercase {
+                    slug.extend(c.to_lowercase());
+                } else {
+                    slug.push(c);
+                }
+            } else if !self.transliterate && is_combining_mark(c) {
+                // Combining marks belong to the letter they follow
+                slug.push(c);
+            } else {
+                separate = true;
+            }
+        }
+        slug
+    }
+
+    /// Returns the slug of a text that isn't in `used`, adding the separator and a number
+    /// if needed, and adds it to `used`
+    ///
+    /// **Returns** `None` if the slug of the text is empty.
+    pub fn unique_slug(&self, s: &str, used: &mut HashSet<String>) -> Option<String> {
+        let slug = self.slug(s);
+        if slug.is_empty() {
+            return None;
+        }
+        let mut unique = slug.clone();
+        let mut n = 2;
+        while used.contains(&unique) {
+            unique = format!("{slug}{}{n}", self.separator);
+            n += 1;
+        }
+        used.insert(unique.clone());
+        Some(unique)
+    }
+}
+
+/// How files or anchors are named, set by `html.anchors` and `html.dir.filenames`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Naming {
+    /// After their number, e.g. `link-3` or `chapter_002.html`
+    Number,
+    /// After the slug of their title
+    Slug,
+}
+
+impl Naming {
+    /// Gets the naming set by an option
+    ///
+    /// If the option isn't valid, an error is displayed and names use numbers.
+    pub fn from(options: &BookOptions, key: &str) -> Naming {
+        match options.get_str(key).unwrap() {
+            "number" => Naming::Number,
+            "slug" => Naming::Slug,
+            value => {
+                error!(
+                    "{}",
+                    t!("error.naming", key = key, value = value, values = "number, slug")
+                );
+                Naming::Number
+            }
+        }
+    }
+}
@@ -30,6 +30,8 @@ use punkt::{SentenceTokenizer, TrainingData};
 use std::f64;
 use std::fmt;
 use rust_i18n::t;
+use unicode_normalization::char::is_combining_mark;
+use unicode_segmentation::UnicodeSegmentation;
 
 /* Only collected on nightly */
 struct AdvancedStats {
@@ -111,11 +113,19 @@ impl Stats {
         }
 
         for c in &book.chapters {
-            let name = c.filename.clone();
+            let name = match c.status {
+                Some(ref status) => format!("{} [{status}]", c.filename),
+                None => c.filename.clone(),
+            };
             let text = view_as_text(&c.content);
-            let wc = text.split_whitespace().count();
-            // Note: Don't count the bytes with `len()` count the actual (multibyte-)characters
-            let cc = text.chars().count();
+            // Combining marks following a space don't make a word on their own
+            let wc = text
+                .split_whitespace()
+                .filter(|w| !w.chars().all(is_combining_mark))
+                .count();
+            // Note: Don't count the bytes with `len()` nor the chars, but the graphemes, so a letter
+            // followed by combining marks counts as a single character
+            let cc = text.graphemes(true).count();
 
             let mut chapter_stats = ChapterStats {
                 name,
@@ -0,0 +1,67 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Structured subjects of the book: BISAC and Thema subject codes, and keywords.
+
+use crate::bookoptions::BookOptions;
+
+/// Returns true if `code` looks like a BISAC subject code, i.e. three letters followed by
+/// six digits, e.g. `FIC009000`
+pub fn is_bisac(code: &str) -> bool {
+    let bytes = code.as_bytes();
+    bytes.len() == 9
+        && bytes[..3].iter().all(u8::is_ascii_uppercase)
+        && bytes[3..].iter().all(u8::is_ascii_digit)
+}
+
+/// Returns true if `code` looks like a Thema code: a subject category starting with a letter
+/// (e.g. `FBA`) or a qualifier starting with a digit from 1 to 6 (e.g. `1DDF`)
+pub fn is_thema(code: &str) -> bool {
+    let bytes = code.as_bytes();
+    !bytes.is_empty()
+        && bytes.len() <= 9
+        && (bytes[0].is_ascii_uppercase() || (b'1'..=b'6').contains(&bytes[0]))
+        && bytes[1..]
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || *b == b'-')
+}
+
+/// Returns the scheme and the first code of `codes` that isn't valid, if `key` is an option
+/// listing subject codes
+pub fn invalid_code<'a>(key: &str, codes: &'a [String]) -> Option<(&'static str, &'a str)> {
+    let (scheme, is_valid): (_, fn(&str) -> bool) = match key {
+        "subject.bisac" => ("BISAC", is_bisac),
+        "subject.thema" => ("Thema", is_thema),
+        _ => return None,
+    };
+    codes
+        .iter()
+        .find(|code| !is_valid(code))
+        .map(|code| (scheme, code.as_str()))
+}
+
+/// Returns the keywords of the book: `subject`, if it is set, then `subject.keywords`
+pub fn keywords(options: &BookOptions) -> Vec<&str> {
+    let mut keywords = vec![];
+    if let Ok(subject) = options.get_str("subject") {
+        keywords.push(subject);
+    }
+    if let Ok(list) = options.get_str_vec("subject.keywords") {
+        keywords.extend(list.iter().map(String::as_str));
+    }
+    keywords
+}
@@ -20,8 +20,6 @@ use crate::error::Result;
 use crowbook_text_processing::escape;
 use rust_i18n::t;
 
-#[cfg(feature = "syntect")]
-
 /// Wrapper around syntect, so it can be more easily optionally compiled.
 #[cfg(feature = "syntect")]
 pub struct Syntax {
@@ -67,7 +65,9 @@ impl Syntax {
     }
 
     /// Convert a string containing code to HTML
-    pub fn to_html(&self, code: &str, language: &str) -> Result<String> {
+    ///
+    /// Each line is passed to `format_line` with its index, e.g. to highlight it.
+    pub fn to_html(&self, code: &str, language: &str, format_line: &dyn Fn(usize, String) -> String) -> Result<String> {
         let language = strip_language(language);
         let syntax = self
             .syntax_set
@@ -75,19 +75,22 @@ impl Syntax {
             .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
         let mut h = syntect::easy::HighlightLines::new(syntax, &self.theme);
         let mut formatted_code = String::new();
-        for line in code.split('\n') {
+        for (i, line) in code.split('\n').enumerate() {
             let regions = h.highlight_line(line, &self.syntax_set)?;
             let bg = syntect::html::IncludeBackground::No;
             let res: String = syntect::html::styled_line_to_highlighted_html(&regions[..], bg)?;
-            formatted_code.push_str(&res);
-            formatted_code.push_str("\n");
+            formatted_code.push_str(&format_line(i, res));
+            formatted_code.push('\n');
         }
         Ok(format!("<pre>{formatted_code}</pre>"))
     }
 
-    pub fn to_tex(&self, code: &str, language: &str) -> Result<String> {
+    /// Convert a string containing code to LaTeX
+    ///
+    /// Each line is passed to `format_line` with its index, e.g. to number it.
+    pub fn to_tex(&self, code: &str, language: &str, format_line: &dyn Fn(usize, String) -> String) -> Result<String> {
         let language = strip_language(language);
-        use crate::latex::insert_breaks;
+        use crate::latex_escape::insert_breaks;
         use syntect::highlighting::{Color, FontStyle};
         let syntax = self
             .syntax_set
@@ -96,8 +99,9 @@ impl Syntax {
         let mut h = syntect::easy::HighlightLines::new(syntax, &self.theme);
 
         let mut formatted_code = String::new();
-        for line in code.split('\n') {
+        for (i, line) in code.split('\n').enumerate() {
             let regions = h.highlight_line(line, &self.syntax_set)?;
+            let mut formatted_line = String::new();
             for (style, text) in regions {
                 let mut content = escape::tex(text).into_owned();
                 content = insert_breaks(&content);
@@ -119,9 +123,10 @@ impl Syntax {
                 if style.font_style.contains(FontStyle::UNDERLINE) {
                     content = format!("\\underline{{{content}}}");
                 }
-                formatted_code.push_str(&content);
+                formatted_line.push_str(&content);
             }
-	    formatted_code.push_str("\\\\{}\n");
+            formatted_code.push_str(&format_line(i, formatted_line));
+            formatted_code.push_str("\\\\{}\n");
         }
         Ok(format!("{{\\sloppy {formatted_code}}}"))
     }
@@ -129,6 +134,7 @@ impl Syntax {
 
 /// Strip language name of possible other infos, e.g. "rust,ignore" -> "rust"
 /// Currently only ',' is done
+#[cfg(feature = "syntect")]
 fn strip_language(language: &str) -> &str {
     let splits: Vec<_> = language.split(|c: char| matches!(c, ',')).collect();
     splits[0].trim()
@@ -141,15 +147,20 @@ impl Syntax {
         Syntax {}
     }
 
-    pub fn to_html(&self, code: &str, language: &str) -> Result<String> {
+    pub fn to_html(&self, code: &str, language: &str, format_line: &dyn Fn(usize, String) -> String) -> Result<String> {
+        let lines: Vec<String> = escape::html(code)
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| format_line(i, line.to_owned()))
+            .collect();
         Ok(format!(
             "<pre><code class = \"language-{lang}\">{code}</code></pre>",
-            code = escape::html(code),
+            code = lines.join("\n"),
             lang = language
         ))
     }
 
-    pub fn to_tex(&self, code: &str, _: &str) -> Result<String> {
+    pub fn to_tex(&self, code: &str, _: &str, _: &dyn Fn(usize, String) -> String) -> Result<String> {
         Ok(format!(
             "\\begin{{spverbatim}}{}\\end{{spverbatim}}\n",
             code
@@ -0,0 +1,69 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Caribon is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Temporary directories, used e.g. to run LaTeX or zip commands.
+
+use crate::error::{Error, Result};
+
+use std::fs::{self, DirBuilder};
+use std::path::{Path, PathBuf};
+use rust_i18n::t;
+
+/// A temporary directory, deleted when it is dropped unless it must be kept
+///
+/// Each directory has a unique name (`crowbook-{uuid}`), so concurrent builds, even of the
+/// same book, never use the same one.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempDir {
+    /// Creates a new temporary directory in `parent`
+    ///
+    /// If `keep` is set, the directory is not deleted, so its files (e.g. LaTeX logs) can
+    /// be inspected.
+    pub fn new<P: AsRef<Path>>(parent: P, keep: bool) -> Result<TempDir> {
+        let parent = parent.as_ref();
+        let uuid = uuid::Uuid::new_v4();
+        let path = parent.join(format!("crowbook-{}", uuid.as_simple()));
+        DirBuilder::new()
+            .recursive(true)
+            .create(&path)
+            .map_err(|_| Error::zipper(t!("zipper.tmp_dir", path = parent.display())))?;
+        Ok(TempDir { path, keep })
+    }
+
+    /// Returns the path of the directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.keep {
+            info!("{}", t!("zipper.kept", path = self.path.display()));
+        } else if let Err(err) = fs::remove_dir_all(&self.path) {
+            warn!(
+                "{}",
+                t!("zipper.delete_error", path = self.path.display(), error = err)
+            );
+        }
+    }
+}
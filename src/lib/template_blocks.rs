@@ -0,0 +1,96 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Named blocks of templates, delimited by `{# block name #}` and `{# endblock #}` comments,
+//! that a template starting with `{# extends #}` can override, keeping the rest of the
+//! default template.
+
+use rust_i18n::t;
+
+/// Comment that must start a template that only overrides blocks of the default one
+pub const EXTENDS: &str = "{# extends #}";
+
+const END: &str = "{# endblock #}";
+
+/// A block of a template
+struct Block<'a> {
+    name: &'a str,
+    /// Position of its content in the template
+    start: usize,
+    end: usize,
+}
+
+/// Returns the blocks of a template, in order
+fn blocks(template: &str) -> std::result::Result<Vec<Block<'_>>, String> {
+    let mut res = vec![];
+    let mut pos = 0;
+    while let Some(i) = template[pos..].find("{# block ") {
+        let open = pos + i;
+        let name_start = open + "{# block ".len();
+        let name_end = match template[name_start..].find("#}") {
+            Some(i) => name_start + i,
+            None => return Err(t!("template.unclosed_block", name = "")),
+        };
+        let name = template[name_start..name_end].trim();
+        let start = name_end + "#}".len();
+        let end = match template[start..].find(END) {
+            Some(i) => start + i,
+            None => return Err(t!("template.unclosed_block", name = name)),
+        };
+        if template[start..end].contains("{# block ") {
+            return Err(t!("template.nested_block", name = name));
+        }
+        res.push(Block { name, start, end });
+        pos = end + END.len();
+    }
+    Ok(res)
+}
+
+/// Replaces the blocks of `base` by the blocks of the same names in `child`
+///
+/// Returns an error message if `child` contains a block that doesn't exist in `base`.
+pub fn extend(base: &str, child: &str) -> std::result::Result<String, String> {
+    let base_blocks = blocks(base)?;
+    let mut overrides = vec![];
+    for block in blocks(child)? {
+        if !base_blocks.iter().any(|b| b.name == block.name) {
+            let available = base_blocks
+                .iter()
+                .map(|b| b.name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(t!(
+                "template.unknown_block",
+                name = block.name,
+                available = available
+            ));
+        }
+        overrides.push((block.name, &child[block.start..block.end]));
+    }
+    let mut res = String::with_capacity(base.len());
+    let mut pos = 0;
+    for block in &base_blocks {
+        // The last override of a block wins
+        if let Some((_, content)) = overrides.iter().rev().find(|(name, _)| *name == block.name) {
+            res.push_str(&base[pos..block.start]);
+            res.push_str(content);
+            pos = block.end;
+        }
+    }
+    res.push_str(&base[pos..]);
+    Ok(res)
+}
@@ -0,0 +1,103 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Introspection of the variables that renderers pass to templates, recorded while a book
+//! is rendered, so the list can't get out of sync with the renderers.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Mutex;
+
+/// A variable that some templates receive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateVar {
+    /// Name of the variable
+    pub name: String,
+    /// Type of its value: `string`, `bool`, `integer`, `float`, `list`, `map` or `none`
+    pub kind: &'static str,
+    /// Names of the templates that receive it, e.g. `html.standalone.template`
+    pub templates: Vec<String>,
+}
+
+impl fmt::Display for TemplateVar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.name, self.kind, self.templates.join(", "))
+    }
+}
+
+/// Type of each recorded variable, and the templates that receive it
+type Entries = BTreeMap<String, (&'static str, BTreeSet<String>)>;
+
+/// Records the variables passed to templates, when recording is enabled
+#[derive(Debug, Default)]
+pub struct TemplateVars {
+    /// Recorded variables, or None if not recording
+    entries: Mutex<Option<Entries>>,
+}
+
+impl TemplateVars {
+    /// Starts recording, forgetting previously recorded variables
+    pub fn start(&self) {
+        *self.entries.lock().unwrap() = Some(BTreeMap::new());
+    }
+
+    /// Stops recording and returns the recorded variables, sorted by name
+    pub fn stop(&self) -> Vec<TemplateVar> {
+        self.entries
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, (kind, templates))| TemplateVar {
+                name,
+                kind,
+                templates: templates.into_iter().collect(),
+            })
+            .collect()
+    }
+
+    /// Records the data passed to a template, if recording is enabled
+    pub fn record(&self, template: &str, data: &BTreeMap<String, upon::Value>) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(ref mut entries) = *entries {
+            for (name, value) in data {
+                let entry = entries
+                    .entry(name.clone())
+                    .or_insert_with(|| (kind(value), BTreeSet::new()));
+                // A variable that is sometimes unset has the type of its set values
+                if entry.0 == "none" {
+                    entry.0 = kind(value);
+                }
+                entry.1.insert(template.to_owned());
+            }
+        }
+    }
+}
+
+/// Returns the name of the type of a value
+fn kind(value: &upon::Value) -> &'static str {
+    match value {
+        upon::Value::None => "none",
+        upon::Value::Bool(_) => "bool",
+        upon::Value::Integer(_) => "integer",
+        upon::Value::Float(_) => "float",
+        upon::Value::String(_) => "string",
+        upon::Value::List(_) => "list",
+        upon::Value::Map(_) => "map",
+    }
+}
@@ -39,10 +39,13 @@ pub mod img {
 pub mod html_single {
     pub static HTML: &str = include_str!("../../templates/html_single/template.html");
     pub static JS: &str = include_str!("../../templates/html_single/script.js");
+    pub static INTERACTIVE_CSS: &str = include_str!("../../templates/html_single/interactive.css");
+    pub static INTERACTIVE_JS: &str = include_str!("../../templates/html_single/interactive.js");
 }
 
 pub mod html_dir {
     pub static TEMPLATE: &str = include_str!("../../templates/html_dir/template.html");
+    pub static SEARCH_JS: &str = include_str!("../../templates/html_dir/search.js");
 }
 
 pub mod html_if {
@@ -52,6 +55,10 @@ pub mod html_if {
 
 pub mod latex {
     pub static TEMPLATE: &str = include_str!("../../templates/latex/template.tex");
+    pub static PREAMBLE: &str = include_str!("../../templates/latex/preamble.tex");
+    pub static TITLEPAGE: &str = include_str!("../../templates/latex/titlepage.tex");
+    pub static HEADERS: &str = include_str!("../../templates/latex/headers.tex");
+    pub static CHAPTER_HEADING: &str = include_str!("../../templates/latex/chapter_heading.tex");
 }
 
 pub mod epub {
@@ -67,6 +74,14 @@ pub mod epub3 {
     pub static TITLE: &str = include_str!("../../templates/epub3/titlepage.xhtml");
 }
 
+pub mod sample {
+    pub static PAGE: &str = include_str!("../../templates/sample/page.md");
+}
+
+pub mod site {
+    pub static TEMPLATE: &str = include_str!("../../templates/site/index.html");
+}
+
 #[cfg(feature = "odt")]
 pub mod odt {
     pub static CONTENT: &str = include_str!("../../templates/odt/content.xml");
@@ -1,5 +1,18 @@
 use super::test_eq;
 use crate::book::Book;
+use crate::filter::ExternalFilter;
+use crate::html::HtmlRenderer;
+use crate::latex::LatexRenderer;
+use crate::misc;
+use crate::number::Number;
+use crate::parser::Parser;
+use crate::renderer::Renderer;
+use crate::resource_handler::ResourceHandler;
+use crate::token::Token;
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use yaml_rust::Yaml;
 
 #[test]
 fn load_config() {
@@ -21,3 +34,2420 @@ epub.version: 3";
     );
     assert_eq!(book.options.get_i32("epub.version").unwrap(), 3);
 }
+
+#[test]
+fn sandbox() {
+    let mut book = Book::new();
+    book.options.set("crowbook.sandbox", "true").unwrap();
+    assert!(book.options.set("tex.command", "rm -rf").is_err());
+    assert!(book.options.set("crowbook.zip.command", "rm -rf").is_err());
+    assert!(book.options.set("manifest.sign.command", "rm -rf").is_err());
+    assert!(book.options.set("cover", "/etc/passwd").is_err());
+    assert!(book.options.set("cover", "../cover.png").is_err());
+    assert!(book.options.set("cover", "images/cover.png").is_ok());
+    assert!(book.options.set("crowbook.sandbox", "false").is_err());
+    // Options inserting raw content in the output all follow the same rule
+    for key in ["html.chapter_footer_snippet", "html.css.add", "html.js.add", "tex.template.add"] {
+        let err = book.options.set(key, "x").unwrap_err();
+        assert!(format!("{err}").contains("raw content"), "{key}");
+    }
+    // Paths can't go through symbolic links leading outside of the book's directory,
+    // but don't have to exist
+    #[cfg(unix)]
+    {
+        let root = std::env::temp_dir().join(format!("crowbook-sandbox-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::os::unix::fs::symlink("/etc", root.join("link")).unwrap();
+        let mut book = Book::new();
+        book.options.root = root.clone();
+        book.options.set("crowbook.sandbox", "true").unwrap();
+        assert!(book.options.set("cover", "link/passwd").is_err());
+        assert!(book.options.set("output.html", "link/new/book.html").is_err());
+        assert!(book.options.set("output.html", "new/book.html").is_ok());
+        assert!(book.options.set("resources.files", "[link]").is_err());
+        assert!(book.options.set("resources.files", "[fonts]").is_ok());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    // README.md exists, but is outside of the book's directory
+    book.root = PathBuf::from("src");
+    let err = book.read_config("author: Foo\n\n- ../README.md\n".as_bytes()).unwrap_err();
+    assert!(err.is_default());
+    // Images outside of it are refused whether they exist or not
+    let mut handler = ResourceHandler::new();
+    handler.set_sandbox(Some(PathBuf::from("src")));
+    for image in ["README.md", "missing.png"] {
+        let err = handler.map_image(&crate::error::Source::empty(), image).unwrap_err();
+        assert!(err.is_render());
+    }
+}
+
+#[test]
+fn html_sanitize() {
+    let doc = "[click](javascript:alert(1)) `<script>x</script>`{=html} \
+               ![an \"image\"](data:text/html,foo \"t\\\"\")\n";
+    let mut book = Book::new();
+    book.options.set("html.sanitize", "true").unwrap();
+    book.options.set("input.raw", "true").unwrap();
+    book.add_chapter_from_source(Number::Default, doc.as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(!html.contains("javascript:"));
+    assert!(!html.contains("<script>x"));
+    assert!(html.contains("&lt;script&gt;x"));
+    assert!(!html.contains("data:text/html"));
+}
+
+#[test]
+fn html_escaped_metadata() {
+    let mut book = Book::new();
+    book.options.set("html.sanitize", "true").unwrap();
+    book.options.set("title", "</title><script>alert(1)</script>").unwrap();
+    book.options.set("author", "x\" onmouseover=\"alert(2)").unwrap();
+    book.add_chapter_from_source(Number::Default, "# Chapter\n".as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let dir = std::env::temp_dir().join(format!("crowbook-escaped-{}", std::process::id()));
+    book.render_format_to_file("html.dir", &dir).unwrap();
+    let index = std::fs::read_to_string(dir.join("index.html")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    for html in [String::from_utf8(html).unwrap(), index] {
+        assert!(!html.contains("</title><script>"));
+        assert!(!html.contains("\"x\" onmouseover"));
+        assert!(html.contains("<meta name=\"author\" content=\"x&quot; onmouseover=&quot;alert(2)\">"));
+    }
+}
+
+#[test]
+fn html_meta_tags() {
+    let mut book = Book::new();
+    book.options.set("title", "Tom & Jerry").unwrap();
+    book.options.set("description", "'`</script>`'").unwrap();
+    book.options.set("html.base_url", "https://example.org/book.html").unwrap();
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<meta property = \"og:title\" content = \"Tom &amp; Jerry\">"));
+    assert!(html.contains("<link rel = \"canonical\" href = \"https://example.org/book.html\">"));
+    assert!(html.contains(r#""about":"<\/script>""#));
+}
+
+#[test]
+fn chapter_date() {
+    let mut book = Book::new();
+    book.options.set("input.yaml_blocks", "true").unwrap();
+    book.options.set("date", "2023-12-01").unwrap();
+    book.add_chapter_from_source(
+        Number::Default,
+        "---\nchapter.date: 2024-01-15\n---\n\n# Title\n".as_bytes(),
+        false,
+    )
+    .unwrap();
+    assert_eq!(book.chapters[0].date.as_deref(), Some("2024-01-15"));
+    test_eq(book.options.get_str("date").unwrap(), "2023-12-01");
+}
+
+#[test]
+fn html_standalone_css() {
+    let mut book = Book::new();
+    book.options.set("html.standalone", "true").unwrap();
+    book.options
+        .set("html.css.add", "'.a { background: url(\"guide/colors.css\") }'")
+        .unwrap();
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains(".a { background: url(\"data:text/css;base64,"));
+}
+
+#[test]
+fn html_interactive() {
+    let render = |book: &Book| {
+        let mut html = vec![];
+        book.render_format_to("html", &mut html).unwrap();
+        String::from_utf8(html).unwrap()
+    };
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "# One\n\nText\n".as_bytes(), false)
+        .unwrap();
+    assert!(!book.options.get_bool("html.interactive").unwrap());
+    let html = render(&book);
+    assert!(!html.contains("reader-toolbar"));
+    assert!(!html.contains("crowbookStorage"));
+
+    book.options.set("html.interactive", "true").unwrap();
+    let html = render(&book);
+    assert!(html.contains("<div id = \"reader-toolbar\">"));
+    assert!(html.contains("onclick = \"changeFontSize(-1)\""));
+    assert!(html.contains("onclick = \"toggleTheme()\""));
+    assert!(html.contains("/* Reader interface for standalone HTML */"));
+    assert!(html.contains("function toggleTocItem(event)"));
+    assert_eq!(html.matches("id = \"reader-toolbar\"").count(), 1);
+}
+
+#[test]
+fn html_chapter_footer_snippet() {
+    let temp = std::env::temp_dir().join(format!("crowbook-footer-test-{}", std::process::id()));
+    let mut book = Book::new();
+    book.set_options(&[
+        ("title", "Footer"),
+        (
+            "html.chapter_footer_snippet",
+            "Chapter {{chapter_number}} of {{title}} - {{chapter_title}} ({{chapter_url}})",
+        ),
+    ]);
+    for chapter in &["# One\n\nText\n", "# Two\n\nText\n"] {
+        book.add_chapter_from_source(Number::Default, chapter.as_bytes(), false)
+            .unwrap();
+    }
+    book.render_format_to_file("html.dir", &temp).unwrap();
+    let chapters: Vec<String> = ["chapter_000.html", "chapter_001.html"]
+        .iter()
+        .map(|file| std::fs::read_to_string(temp.join(file)).unwrap())
+        .collect();
+    let index = std::fs::read_to_string(temp.join("index.html")).unwrap();
+    std::fs::remove_dir_all(&temp).unwrap();
+    for (i, (chapter, title)) in chapters.iter().zip(&["One", "Two"]).enumerate() {
+        assert_eq!(chapter.matches("<div class = \"chapter-footer\">").count(), 1);
+        let footer = format!("Chapter {n} of Footer - {n}. {title} (chapter_00{i}.html)", n = i + 1);
+        assert_eq!(chapter.matches(&footer).count(), 1);
+    }
+    assert!(!index.contains("chapter-footer"));
+}
+
+#[test]
+fn html_dir_cache() {
+    let temp = std::env::temp_dir().join(format!("crowbook-cache-test-{}", std::process::id()));
+    let dir = temp.join("book");
+    std::fs::create_dir_all(&temp).unwrap();
+    let mut book = Book::new();
+    book.options.set("html.dir.cache", "true").unwrap();
+    book.options.set("crowbook.temp_dir", temp.to_str().unwrap()).unwrap();
+    book.add_chapter_from_source(Number::Default, "# Chapter\n\nText\n".as_bytes(), false)
+        .unwrap();
+    // output.html.dir isn't set, the destination is only given to the renderer
+    for _ in 0..2 {
+        book.render_format_to_file("html.dir", &dir).unwrap();
+        let chapter = std::fs::read_to_string(dir.join("chapter_000.html")).unwrap();
+        assert!(chapter.contains("Text"));
+    }
+    let caches = std::fs::read_dir(&temp)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with("crowbook-cache-"))
+        .count();
+    std::fs::remove_dir_all(&temp).unwrap();
+    assert_eq!(caches, 1);
+}
+
+#[test]
+fn html_state_round_trip() {
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    let tokens = Parser::new()
+        .parse("Text[^1] and a [link](https://example.org).\n\n[^1]: Note\n", None)
+        .unwrap();
+    let mut renderer = HtmlRenderer::new(&book, "html").unwrap();
+    renderer.chapter_config(0, Number::Default, String::from("chapter.html"));
+    HtmlRenderer::render_html(&mut renderer, &tokens, true).unwrap();
+    let state = renderer.get_state();
+
+    let mut other = HtmlRenderer::new(&book, "html").unwrap();
+    other.set_state(&state).unwrap();
+    assert_eq!(other.get_state(), state);
+    assert!(other.set_state(&serde_json::json!({})).is_none());
+}
+
+#[test]
+fn progress_callback() {
+    let reported = Arc::new(Mutex::new(vec![]));
+    let mut book = Book::new();
+    let r = reported.clone();
+    book.set_progress_callback(move |progress| r.lock().unwrap().push(format!("{progress:?}")));
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    book.render_format_to("html", &mut vec![]).unwrap();
+    assert_eq!(
+        *reported.lock().unwrap(),
+        vec![r#"Rendering("html")"#, r#"Rendered("html")"#]
+    );
+}
+
+#[test]
+fn timings() {
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "# One\n\nText\n".as_bytes(), false)
+        .unwrap();
+    assert!(book.timings().entries().iter().any(|(phase, _)| phase == "parse"));
+    book.render_format_to("html", &mut vec![]).unwrap();
+    book.render_format_to("html", &mut vec![]).unwrap();
+    let phases: Vec<String> = book.timings().entries().into_iter().map(|(p, _)| p).collect();
+    // Renders of the same format are added up
+    assert_eq!(phases.iter().filter(|p| *p == "render: html").count(), 1);
+
+    let report = book.timings().to_string();
+    assert!(report.lines().any(|line| line.starts_with("render: html") && line.ends_with(" s")));
+    let json = book.timings().to_json();
+    assert!(json["timings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|t| t["phase"] == "render: html" && t["seconds"].is_f64()));
+}
+
+#[test]
+fn missing_command() {
+    let mut book = Book::new();
+    book.options.set("tex.command", "crowbook-no-such-latex").unwrap();
+    let formats = book.available_renderers();
+    assert!(formats.contains(&"html"));
+    assert!(formats.contains(&"tex"));
+    assert!(!formats.contains(&"pdf"));
+    let err = book.render_format_to("pdf", &mut vec![]).unwrap_err();
+    assert!(format!("{err}").contains("crowbook-no-such-latex"));
+}
+
+#[test]
+fn html_pdf() {
+    let command = |book: &Book, backend| crate::html_pdf::command(book, backend).unwrap();
+    let mut book = Book::new();
+    assert_eq!(
+        command(&book, "browser"),
+        (
+            String::from("chromium"),
+            vec![
+                "--headless",
+                "--disable-gpu",
+                "--no-pdf-header-footer",
+                "--print-to-pdf=result.pdf",
+                "result.html"
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect()
+        )
+    );
+    assert_eq!(command(&book, "weasyprint").1, vec!["result.html", "result.pdf"]);
+    assert!(crate::html_pdf::command(&book, "prince").is_err());
+    book.options
+        .set("pdf.command", "'prince \"{input}\" -o {output}'")
+        .unwrap();
+    assert_eq!(
+        command(&book, "weasyprint"),
+        (
+            String::from("prince"),
+            vec![String::from("result.html"), String::from("-o"), String::from("result.pdf")]
+        )
+    );
+
+    // The program is checked before anything is rendered
+    book.set_options(&[
+        ("pdf.backend", "weasyprint"),
+        ("pdf.command", "crowbook-no-such-weasyprint {input} {output}"),
+    ]);
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    assert!(!book.available_renderers().contains(&"pdf"));
+    let err = book.render_format_to("pdf", &mut vec![]).unwrap_err();
+    assert!(format!("{err}").contains("crowbook-no-such-weasyprint"));
+
+    // In sandbox mode, the backend can be chosen but not the command it runs
+    let mut book = Book::new();
+    book.options.set("crowbook.sandbox", "true").unwrap();
+    assert!(book.options.set("pdf.backend", "browser").is_ok());
+    let err = book.options.set("pdf.command", "sh -c 'rm -rf ~'").unwrap_err();
+    assert!(format!("{err}").contains("pdf.command"));
+    assert!(book
+        .read_config("pdf.backend: weasyprint\npdf.command: evil {input} {output}\n".as_bytes())
+        .is_ok());
+    assert_eq!(command(&book, "weasyprint").0, "weasyprint");
+}
+
+#[test]
+fn dry_run() {
+    let dir = std::env::temp_dir().join("crowbook-dry-run-test");
+    let mut book = Book::new();
+    book.options
+        .set("output.html.dir", dir.to_str().unwrap())
+        .unwrap();
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    let results = book.dry_run_all();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, "html.dir");
+    assert_eq!(results[0].1.as_ref().unwrap(), &dir);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn reproducible_epub() {
+    let render = || {
+        let mut book = Book::new();
+        book.read_markdown_config("---\ncrowbook.reproducible: true\n---\n# Title\n".as_bytes())
+            .unwrap();
+        let mut epub = vec![];
+        book.render_format_to("epub", &mut epub).unwrap();
+        epub
+    };
+    let first = render();
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    assert!(first == render());
+}
+
+#[test]
+fn render_hooks() {
+    let path = std::env::temp_dir().join("crowbook-hooks-test.html");
+    let reported = Arc::new(Mutex::new(vec![]));
+    let mut book = Book::new();
+    let r = reported.clone();
+    book.add_pre_render_hook(move |_| {
+        r.lock().unwrap().push(String::from("pre"));
+        Ok(())
+    });
+    let r = reported.clone();
+    book.add_post_render_hook(move |_, format, path| {
+        r.lock().unwrap().push(format!("{format} {}", path.display()));
+        Ok(())
+    });
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    book.render_format_to_file("html", &path).unwrap();
+    assert_eq!(
+        *reported.lock().unwrap(),
+        vec![String::from("pre"), format!("html {}", path.display())]
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn filters() {
+    let mut book = Book::new();
+    book.add_filter(|_: &Book, _: &str, tokens: Vec<Token>| {
+        Ok(tokens
+            .into_iter()
+            .map(|token| match token {
+                Token::Paragraph(inner) => Token::BlockQuote(vec![Token::Paragraph(inner)]),
+                token => token,
+            })
+            .collect())
+    });
+    if cfg!(unix) {
+        book.add_filter(ExternalFilter::new("cat"));
+    }
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    assert_eq!(
+        book.chapters[0].content,
+        vec![Token::BlockQuote(vec![Token::Paragraph(vec![Token::Str(
+            String::from("Text")
+        )])])]
+    );
+    assert!(book.features.blockquote);
+}
+
+#[test]
+fn chapter_front_matter() {
+    let mut book = Book::new();
+    book.add_chapter_from_source(
+        Number::Default,
+        "---
+chapter.title: New *title*
+chapter.status: draft
+chapter.synopsis: Some synopsis
+author: Someone
+---
+
+# Old title
+
+Text
+"
+        .as_bytes(),
+        false,
+    )
+    .unwrap();
+    let chapter = &book.chapters[0];
+    assert_eq!(chapter.status.as_deref(), Some("draft"));
+    assert_eq!(chapter.synopsis.as_deref(), Some("Some synopsis"));
+    assert_eq!(
+        chapter.content[0],
+        Token::Header(
+            1,
+            vec![
+                Token::Str(String::from("New ")),
+                Token::Emphasis(vec![Token::Str(String::from("title"))])
+            ]
+        )
+    );
+    // Other keys are ignored since input.yaml_blocks isn't set
+    assert!(book.options.get_str("author").unwrap() != "Someone");
+}
+
+#[test]
+fn profiles() {
+    let config = "
+title: Some title
+output: [html, epub, pdf]
+profiles.draft.output: [html]
+profiles.draft.html.header: Work in progress
+";
+    let mut book = Book::new();
+    book.set_profile("draft");
+    book.read_config(config.as_bytes()).unwrap();
+    assert_eq!(book.options.get_str_vec("output").unwrap(), &["html"]);
+    assert_eq!(book.options.get_str("html.header").unwrap(), "Work in progress");
+
+    let mut book = Book::new();
+    book.set_profile("release");
+    book.read_config(config.as_bytes()).unwrap();
+    assert_eq!(book.options.get_str_vec("output").unwrap().len(), 3);
+    book.add_chapter_from_source(
+        Number::Default,
+        "---\nchapter.status: draft\n---\n\nText\n".as_bytes(),
+        false,
+    )
+    .unwrap();
+    assert!(book.chapters.is_empty());
+
+    let mut book = Book::new();
+    book.set_profile("foo");
+    assert!(book.read_config(config.as_bytes()).is_err());
+}
+
+#[test]
+fn select_chapters() {
+    let load = |renumber: &str| {
+        let mut book = Book::new();
+        book.options.set("input.tags.renumber", renumber).unwrap();
+        for source in ["# One\n", "---\nchapter.tags: appendix\n---\n\n# Two\n", "# Three\n"] {
+            book.add_chapter_from_source(Number::Default, source.as_bytes(), false)
+                .unwrap();
+        }
+        book
+    };
+    let mut book = load("false");
+    book.select_chapters(&[], &["appendix"]);
+    assert_eq!(book.chapters.len(), 2);
+    assert_eq!(book.chapters[1].number, Number::Specified(3));
+
+    let mut book = load("true");
+    book.select_chapters(&[], &["appendix"]);
+    assert_eq!(book.chapters[1].number, Number::Default);
+
+    let mut book = load("false");
+    book.select_chapters(&["appendix"], &[]);
+    assert_eq!(book.chapters.len(), 1);
+    assert_eq!(book.chapters[0].number, Number::Specified(2));
+}
+
+#[test]
+fn make_sample() {
+    let mut book = Book::new();
+    book.options.set("sample.url", "https://example.com").unwrap();
+    book.add_chapter_from_source(Number::DefaultPart, "# Part\n".as_bytes(), false)
+        .unwrap();
+    for source in ["# One\n", "# Two\n", "# Three\n"] {
+        book.add_chapter_from_source(Number::Default, source.as_bytes(), false)
+            .unwrap();
+    }
+    book.make_sample(2).unwrap();
+    assert_eq!(book.chapters.len(), 4);
+    let page = &book.chapters[3];
+    assert_eq!(page.number, Number::Unnumbered);
+    assert!(page.content.iter().any(|token| match *token {
+        Token::Paragraph(ref inner) => inner
+            .iter()
+            .any(|t| matches!(*t, Token::Link(ref url, _, _) if url == "https://example.com")),
+        _ => false,
+    }));
+}
+
+#[test]
+fn diff_books() {
+    let load = |sources: &[&str]| {
+        let mut book = Book::new();
+        for (i, source) in sources.iter().enumerate() {
+            book.add_chapter_from_named_source(Number::Default, &format!("{i}.md"), source.as_bytes(), false)
+                .unwrap();
+        }
+        book
+    };
+    let old = load(&["# One\n\nThe quick brown fox.\n", "# Two\n\nSame.\n"]);
+    let new = load(&["# One\n\nThe quick red fox.\n", "# Two\n\nSame.\n"]);
+    let html = crate::diff::diff_books(&old, &new);
+    assert!(html.contains("The quick <del>brown </del><ins>red </ins>fox."));
+    assert!(!html.contains("Same."));
+}
+
+#[test]
+fn git_info() {
+    let mut book = Book::new();
+    book.options.set("git.metadata", "true").unwrap();
+    book.read_git_info().unwrap();
+    // Nothing to check if the sources aren't in a git repository
+    if let Some(info) = book.git_info() {
+        assert_eq!(info.hash.len(), 40);
+        let data = book.get_metadata(|s| Ok(s.to_owned())).unwrap();
+        assert!(data.contains_key("git_hash"));
+        assert_eq!(book.options.get_str("version").unwrap(), info.version);
+    }
+}
+
+#[test]
+fn resources_links() {
+    let mut book = Book::new();
+    book.options
+        .set_yaml(
+            Yaml::String("resources.files".into()),
+            Yaml::Array(vec![Yaml::String("Cargo.toml".into())]),
+        )
+        .unwrap();
+    let mut handler = ResourceHandler::new();
+    handler.add_resources_links(&book).unwrap();
+    assert_eq!(handler.get_link("chapters/../Cargo.toml"), "data/Cargo.toml");
+}
+
+#[test]
+fn media() {
+    let book = Book::new();
+    let tokens = Parser::new()
+        .parse("![A talk](https://example.org/talk.mp4)\n", None)
+        .unwrap();
+    let mut renderer = HtmlRenderer::new(&book, "html").unwrap();
+    let html = HtmlRenderer::render_html(&mut renderer, &tokens, true).unwrap();
+    assert!(html.contains(
+        "<video controls = \"controls\" src = \"https://example.org/talk.mp4\" title = \"\">A talk</video>"
+    ));
+}
+
+#[test]
+fn tex_figures() {
+    let mut book = Book::new();
+    book.set_options(&[("tex.figure_caption", "true")]);
+    let tokens = Parser::new()
+        .parse("![A *logo*](img/crowbook-small.png)\n", None)
+        .unwrap();
+    let tex = LatexRenderer::new(&book).render_vec(&tokens).unwrap();
+    assert!(tex.contains("\\mdcaptionedimage{"));
+    assert!(tex.contains("}{A \\emph{logo}}"));
+}
+
+#[test]
+fn tex_layouts() {
+    let mut book = Book::new();
+    book.set_options(&[("tex.landscape_tables", "3")]);
+    let table = "|a|b|c|\n|-|-|-|\n|1|2|3|\n";
+    book.add_chapter_from_source(Number::Default, format!("# One\n\n{table}").as_bytes(), false)
+        .unwrap();
+    book.add_chapter_from_source(
+        Number::Default,
+        format!("---\nchapter.layout: two_columns\n---\n\n# Two\n\n{table}").as_bytes(),
+        false,
+    )
+    .unwrap();
+    assert_eq!(book.chapters[1].layout.as_deref(), Some("two_columns"));
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("\\usepackage{pdflscape}"));
+    assert!(tex.contains("\\usepackage{multicol}"));
+    // The table of the chapter in two columns isn't rotated
+    assert_eq!(tex.matches("\\begin{landscape}").count(), 1);
+}
+
+#[test]
+fn line_numbers() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("rendering.code.line_numbers", "true"),
+        ("rendering.verse.line_numbers", "2"),
+        ("rendering.highlight", "none"),
+    ]);
+    let doc = "```\nlet x = 1;\nx + 1 {#sum}\n```\n\nSee [line 2](#sum).\n\n::: verse\nOne\nTwo\n\nThree\n:::\n";
+    book.add_chapter_from_source(Number::Default, doc.as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("1\n<span id = \"sum\">2</span></pre>"));
+    assert!(html.contains("<pre><code>let x = 1;\nx + 1\n</code></pre>"));
+    assert!(html.contains("<a href = \"#sum\">line 2</a>"));
+    assert!(html.contains("<span class = \"verse-number\">2</span>"));
+    assert_eq!(html.matches("<p class = \"verse\">").count(), 2);
+
+    let mut book = Book::new();
+    book.set_options(&[("rendering.highlight", "none")]);
+    let doc = "```rust {file=main.rs highlight=2 start=5}\na\nb\n```\n\n```rust {start=continue}\nc\n```\n";
+    book.add_chapter_from_source(Number::Default, doc.as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<p class = \"code-caption\"><span class = \"file-name\">main.rs</span></p>"));
+    assert!(html.contains("a\n<span class = \"highlighted-line\">b</span>"));
+    assert!(html.contains("aria-hidden = \"true\">5\n6</pre>"));
+    assert!(html.contains("aria-hidden = \"true\">7</pre>"));
+}
+
+#[test]
+fn include_code() {
+    let mut book = Book::new();
+    book.root = PathBuf::from("src/lib");
+    book.add_chapter_from_source(Number::Default, "```rust {file=code_block.rs lines=18-18}\n```\n".as_bytes(), false)
+        .unwrap();
+    match book.chapters[0].content[0] {
+        Token::CodeBlock(_, ref code) => assert_eq!(code, "//! Attributes of fenced code blocks, e.g.\n"),
+        ref token => panic!("unexpected token {token:?}"),
+    }
+    assert!(book
+        .add_chapter_from_source(Number::Default, "```{file=code_block.rs lines=10000-}\n```\n".as_bytes(), false)
+        .is_err());
+    // Included files are limited by limits.chapter_size, and not read to their end
+    if cfg!(unix) {
+        let err = book
+            .add_chapter_from_source(Number::Default, "```{file=/dev/zero}\n```\n".as_bytes(), false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("limits.chapter_size (16MB)"));
+    }
+    book.options.set("limits.chapter_size", "40B").unwrap();
+    let err = book
+        .add_chapter_from_source(Number::Default, "```{file=code_block.rs}\n```\n".as_bytes(), false)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("limits.chapter_size (40B)"));
+    book.options.set("limits.chapter_size", "16MB").unwrap();
+    // In sandbox mode, files outside the book's directory are refused whether they exist or not
+    book.options.set("crowbook.sandbox", "true").unwrap();
+    for file in ["../../Cargo.toml", "../../missing.toml"] {
+        let err = book
+            .add_chapter_from_source(Number::Default, format!("```{{file={file}}}\n```\n").as_bytes(), false)
+            .err()
+            .unwrap();
+        assert!(err.is_parser());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn check_code() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("check.code", "true"),
+        ("check.fail", "true"),
+        ("check.code.sh", "sh -n \"$CROWBOOK_CODE_FILE\""),
+    ]);
+    book.add_chapter_from_source(Number::Default, "```sh\necho ok\n```\n\n```sh,ignore\nif (\n```\n".as_bytes(), false)
+        .unwrap();
+    let err = match book.add_chapter_from_source(Number::Default, "Text\n\n```sh\nif (\n```\n".as_bytes(), false) {
+        Err(err) => err,
+        Ok(_) => panic!("invalid code passed its check"),
+    };
+    assert!(err.is_default());
+    assert!(err.to_string().starts_with(":3:"), "{err}");
+}
+
+#[test]
+fn unicode_normalize() {
+    let mut book = Book::new();
+    book.set_options(&[("lang", "fr"), ("input.normalize", "true")]);
+    book.add_chapter_from_source(Number::Default, "Cafe\u{301} !\n".as_bytes(), false)
+        .unwrap();
+    assert_eq!(
+        book.chapters[0].content,
+        vec![Token::Paragraph(vec![Token::Str("Caf\u{e9} !".to_owned())])]
+    );
+    // The non-breaking space isn't inserted between a character and its accent
+    assert_eq!(book.clean("\u{ab} \u{301}a"), "\u{ab}\u{301}\u{a0}a");
+}
+
+#[test]
+fn encodings() {
+    let utf16 = |bom: &[u8]| {
+        let mut bytes = bom.to_vec();
+        bytes.extend("Café".encode_utf16().flat_map(|c| c.to_le_bytes()));
+        bytes
+    };
+    let sources: Vec<Vec<u8>> = vec![
+        b"\xef\xbb\xbfCaf\xc3\xa9".to_vec(),
+        b"Caf\xe9".to_vec(),
+        utf16(b"\xff\xfe"),
+        utf16(b""),
+    ];
+    let mut book = Book::new();
+    for source in sources {
+        book.add_chapter_from_source(Number::Default, &source[..], false)
+            .unwrap();
+    }
+    for chapter in &book.chapters {
+        assert_eq!(
+            chapter.content,
+            vec![Token::Paragraph(vec![Token::Str("Café".to_owned())])]
+        );
+    }
+
+    book.options.set("input.encoding", "latin1").unwrap();
+    book.add_chapter_from_source(Number::Default, &b"caf\xe9"[..], false)
+        .unwrap();
+    book.options.set("input.encoding", "utf-8").unwrap();
+    assert!(book
+        .add_chapter_from_source(Number::Default, &b"caf\xe9"[..], false)
+        .is_err());
+    book.options.set("input.encoding", "klingon").unwrap();
+    assert!(book
+        .add_chapter_from_source(Number::Default, &b"cafe"[..], false)
+        .is_err());
+}
+
+#[test]
+fn typography() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("lang", "fr"),
+        ("input.clean.ordinals", "true"),
+        ("input.clean.fractions", "true"),
+        ("input.clean.symbols", "[\"(c)=©\", \"!==≠\"]"),
+    ]);
+    assert_eq!(
+        book.clean("Le 1er et la 2de, au XIXe siècle. Vers midi."),
+        "Le 1\u{1D49}\u{2B3} et la 2\u{1D48}\u{1D49}, au XIX\u{1D49} siècle. Vers midi."
+    );
+    assert_eq!(book.clean("(c) a != b..."), "© a ≠ b…");
+
+    book.set_options(&[("lang", "en"), ("input.clean.symbols", "[]")]);
+    assert_eq!(
+        book.clean("The 1st, 2nd and 23rd... x1st"),
+        "The 1\u{2E2}\u{1D57}, 2\u{207F}\u{1D48} and 23\u{2B3}\u{1D48}... x1st"
+    );
+    assert_eq!(book.clean("1/2, 3/4. 11/2 1/2.5 1/2/2023"), "½, ¾. 11/2 1/2.5 1/2/2023");
+    book.set_options(&[("input.clean.ellipsis", "true")]);
+    assert_eq!(book.clean("Well..."), "Well…");
+}
+
+#[test]
+fn windows_paths() {
+    assert_eq!(
+        misc::split_command(r#""C:\Program Files\Chrome\chrome.exe" --print-to-pdf={output} 'a b' "say \"hi\"""#),
+        vec![r"C:\Program Files\Chrome\chrome.exe", "--print-to-pdf={output}", "a b", "say \"hi\""]
+    );
+    assert!(misc::is_absolute_path(r"C:\images\cover.png"));
+    assert!(misc::is_absolute_path(r"\\server\share\cover.png"));
+    assert!(!misc::is_absolute_path(r"images\cover.png"));
+
+    let mut ast = vec![
+        Token::Image(r"images\cover.png".to_owned(), String::new(), vec![]),
+        Token::Image(r"\\server\share\cover.png".to_owned(), String::new(), vec![]),
+    ];
+    ResourceHandler::add_offset(Path::new("chapters"), Path::new("chapters"), &mut ast);
+    assert_eq!(ast[0], Token::Image("chapters/images/cover.png".to_owned(), String::new(), vec![]));
+    assert_eq!(ast[1], Token::Image(r"\\server\share\cover.png".to_owned(), String::new(), vec![]));
+}
+
+#[test]
+fn temp_dir() {
+    let mut book = Book::new();
+    let (first, second) = (book.temp_dir().unwrap(), book.temp_dir().unwrap());
+    assert_ne!(first.path(), second.path());
+    let path = first.path().to_owned();
+    assert!(path.is_dir());
+    drop(first);
+    assert!(!path.exists());
+
+    book.options.set("crowbook.temp_dir.keep", "true").unwrap();
+    let path = book.temp_dir().unwrap().path().to_owned();
+    assert!(path.is_dir());
+    std::fs::remove_dir(path).unwrap();
+}
+
+#[test]
+fn concurrent_rendering() {
+    use rayon::prelude::*;
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+    let mut book = Book::new();
+    book.set_options(&[("rendering.highlight", "none")]);
+    book.add_chapter_from_source(Number::Default, "# Title\n\nSome text[^1]\n\n[^1]: A note\n".as_bytes(), false)
+        .unwrap();
+    assert_send_sync(&book);
+
+    let book = &book;
+    let outputs: Vec<String> = ["html", "tex", "html", "tex"]
+        .par_iter()
+        .map(|format| {
+            let mut output = vec![];
+            book.render_format_to(format, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        })
+        .collect();
+    assert_eq!(outputs[0], outputs[2]);
+    assert_eq!(outputs[1], outputs[3]);
+}
+
+#[test]
+fn shared_link_refs() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("links.refs.rust", "https://www.rust-lang.org \"Rust\""),
+        ("links.refs.unused", "https://example.com"),
+    ]);
+    book.add_chapter_from_source(
+        Number::Default,
+        "[The language][Rust] and [a note][^1]\n\n[^1]: Note\n".as_bytes(),
+        false,
+    )
+    .unwrap();
+    match book.chapters[0].content[0] {
+        Token::Paragraph(ref inner) => assert_eq!(
+            inner[0],
+            Token::Link(
+                String::from("https://www.rust-lang.org"),
+                String::from("Rust"),
+                vec![Token::Str(String::from("The language"))]
+            )
+        ),
+        ref token => panic!("unexpected token {token:?}"),
+    }
+    let link_refs = book.link_refs.as_ref().unwrap();
+    assert_eq!(link_refs.unused().collect::<Vec<_>>(), vec!["unused"]);
+    assert_eq!(
+        crate::link_refs::explicit_references("[a][Foo  Bar], [baz][] and [not a reference]"),
+        vec!["foo bar", "baz"]
+    );
+}
+
+#[test]
+fn links_style() {
+    let render = |options: &[(&str, &str)], format| {
+        let mut book = Book::new();
+        book.set_options(options);
+        book.add_chapter_from_source(
+            Number::Default,
+            "[Rust](https://www.rust-lang.org), [again](https://www.rust-lang.org) and \
+             <https://crates.io>\n"
+                .as_bytes(),
+            false,
+        )
+        .unwrap();
+        let mut output = vec![];
+        book.render_format_to(format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let tex = render(&[], "tex");
+    assert!(tex.contains("{Rust}\\protect\\footnote{\\url{https://www.rust-lang.org}}"));
+    let tex = render(&[("tex.links_as_footnotes", "false")], "tex");
+    assert!(tex.contains("\\href{https://www.rust-lang.org}{Rust},"));
+    let tex = render(&[("links.style.tex", "endnote")], "tex");
+    assert!(tex.contains("{Rust}\\textsuperscript{[1]}"));
+    assert!(tex.contains("{again}\\textsuperscript{[1]}"));
+    assert!(tex.contains("\\item[{[1]}] \\url{https://www.rust-lang.org}"));
+    assert!(!tex.contains("\\item[{[2]}]"));
+    let tex = render(&[("links.style.tex", "inline")], "tex");
+    assert!(tex.contains("{Rust} (\\url{https://www.rust-lang.org})"));
+
+    let html = render(&[("links.style.html", "inline")], "html");
+    assert!(html.contains("Rust</a> (<span class = \"link-url\">https://www.rust-lang.org</span>)"));
+    assert!(!html.contains("(<span class = \"link-url\">https://crates.io"));
+}
+
+#[test]
+fn side_notes() {
+    let render = |options: &[(&str, &str)], format| {
+        let mut book = Book::new();
+        book.set_options(options);
+        book.add_chapter_from_source(
+            Number::Default,
+            "Some text[^a] and more[^b].\n\n[^a]: First note\n\n[^b]: Second note\n".as_bytes(),
+            false,
+        )
+        .unwrap();
+        let mut output = vec![];
+        book.render_format_to(format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let html = render(&[("html.side_notes", "true")], "html");
+    assert!(html.contains("<span class = \"sidenote\"><span class = \"note-number\">1</span> First note</span>"));
+    assert!(html.contains("class = \"notes side-notes-fallback\""));
+    let html = render(
+        &[("html.side_notes", "true"), ("rendering.notes.numbering", "roman")],
+        "html",
+    );
+    assert!(html.contains("<sup>[ii]</sup></a><span class = \"sidenote\"><span class = \"note-number\">ii</span> Second note"));
+    assert!(html.contains("\">[ii]</a>"));
+
+    let tex = render(&[("tex.side_notes", "true"), ("rendering.notes.numbering", "arabic")], "tex");
+    assert!(tex.contains("\\usepackage{marginnote}"));
+    assert!(tex.contains("text\\textsuperscript{1}\\marginnote{\\textsuperscript{1}~First note}"));
+    assert!(!tex.contains("\\footnotetext"));
+}
+
+#[test]
+fn note_backlinks() {
+    let mut book = Book::new();
+    book.set_options(&[("rendering.notes.numbering", "symbols")]);
+    let tokens = Parser::new()
+        .parse("One[^a], two[^b] and one again[^a].\n\n[^a]: First\n\n[^b]: Second\n", None)
+        .unwrap();
+    let mut renderer = HtmlRenderer::new(&book, "html").unwrap();
+    renderer.epub_version = Some(3);
+    let html = HtmlRenderer::render_html(&mut renderer, &tokens, true).unwrap();
+    assert!(html.contains(
+        "<a class = \"footnote_reference\" epub:type = \"noteref\" href = \"#note-dest-0-1\" \
+         id = \"note-source-0-1\"><sup>[*]</sup></a>"
+    ));
+    assert!(html.contains("id = \"note-source-0-1-2\"><sup>[*]</sup></a>"));
+    assert!(html.contains("<sup>[†]</sup>"));
+    assert!(html.contains(
+        "href = \"#note-source-0-1\">[*]</a> <a class = \"backlink\" href = \"#note-source-0-1-2\">↩</a>"
+    ));
+    assert!(html.contains("<aside class = \"footnote\" epub:type = \"footnote\" id = \"note-dest-0-1\">"));
+
+    let numbering = crate::notes::NoteNumbering::Symbols;
+    assert_eq!(numbering.format("8"), "††");
+    assert_eq!(crate::notes::NoteNumbering::Alpha.format("28"), "ab");
+}
+
+#[test]
+fn header_attributes() {
+    let render = |format| {
+        let mut book = Book::new();
+        book.add_chapter_from_source(
+            Number::Default,
+            "# Chapter\n\n## Intro {#intro .unnumbered .fancy}\n\n## Hidden {.unlisted}\n".as_bytes(),
+            false,
+        )
+        .unwrap();
+        let mut output = vec![];
+        book.render_format_to(format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let html = render("html");
+    assert!(html.contains(
+        "<h2 id = \"link-2\" class = \"unnumbered fancy\"><span id = \"intro\"></span>Intro</h2>"
+    ));
+    assert!(html.contains("<h2 id = \"link-3\" class = \"unlisted\">Hidden</h2>"));
+
+    let tex = render("tex");
+    assert!(tex.contains(
+        "\\section*{Intro}\n\\addcontentsline{toc}{section}{Intro}\n\\phantomsection\\label{intro}"
+    ));
+    assert!(tex.contains(
+        "\\addtocontents{toc}{\\protect\\setcounter{tocdepth}{-10}}\n\\section{Hidden}\n"
+    ));
+}
+
+#[test]
+fn unlisted_chapters() {
+    let dir = std::env::temp_dir().join("crowbook-unlisted-test");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("foreword.md"), "# Foreword\n\n## Thanks\n").unwrap();
+    std::fs::write(dir.join("chapter.md"), "# Beginning\n").unwrap();
+    let mut book = Book::new();
+    book.root = dir.clone();
+    book.options.set("rendering.inline_toc", "true").unwrap();
+    book.read_config("title: Test\n~- foreword.md\n+ chapter.md\n".as_bytes())
+        .unwrap();
+    assert!(book.chapters[0].unlisted);
+    assert!(!book.chapters[1].unlisted);
+
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("Thanks</h2>"));
+    assert!(html.contains("<a href=\"#link-3\">1. Beginning</a>"));
+    assert!(!html.contains("Foreword</a>"));
+    assert!(!html.contains("Thanks</a>"));
+
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains(
+        "\\addtocontents{toc}{\\protect\\setcounter{tocdepth}{-10}}\n\\chapter*{Foreword}\n"
+    ));
+    assert!(tex.contains("\\section*{Thanks}\n"));
+    assert!(!tex.contains("\\addcontentsline{toc}{section}{Thanks}"));
+    assert!(tex.contains("\\addtocontents{toc}{\\protect\\setcounter{tocdepth}{0}}\n"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn numbering_templates() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("rendering.num_depth", "2"),
+        ("rendering.inline_toc", "true"),
+        (
+            "rendering.chapter.template",
+            "'{% if chapter_number | eq: \"1\" %}Chapitre premier{% else %}Chapitre {{ chapter_roman }}{% endif %} – {{ chapter_title }}'",
+        ),
+        ("rendering.section.template", "'{{ chapter_number }}.{{ section_padded }} – {{ title }}'"),
+    ]);
+    book.add_chapter_from_source(Number::Default, "# Start\n\n## Sub\n".as_bytes(), false)
+        .unwrap();
+    book.add_chapter_from_source(Number::Default, "# Next\n\n## Other\n".as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("Chapitre premier – Start</a>"));
+    assert!(html.contains("Chapitre II – Next</a>"));
+    assert!(html.contains(">1.01 – Sub</h2>"));
+    assert!(html.contains(">2.01 – Other</h2>"));
+}
+
+#[test]
+fn tex_template_fragments() {
+    let render = |book: &Book| {
+        let mut tex = vec![];
+        book.render_format_to("tex", &mut tex).unwrap();
+        String::from_utf8(tex).unwrap()
+    };
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "# Title\n".as_bytes(), false)
+        .unwrap();
+    let tex = render(&book);
+    assert!(tex.contains("\\fancyhead[CO,CE]{\\thepage}"));
+    assert!(tex.contains("\\usepackage{fancyhdr}"));
+
+    let path = std::env::temp_dir().join("crowbook-headers-test.tex");
+    std::fs::write(&path, "\\pagestyle{fancy}\n\\fancyhead[LE]{<<title>>}\n").unwrap();
+    book.options
+        .set("tex.template.headers", path.to_str().unwrap())
+        .unwrap();
+    book.options.set("title", "My book").unwrap();
+    let tex = render(&book);
+    assert!(tex.contains("\\fancyhead[LE]{My book}"));
+    assert!(!tex.contains("\\fancyhead[CO,CE]{\\thepage}"));
+    // Other fragments are still the default ones
+    assert!(tex.contains("\\usepackage{fancyhdr}"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn template_blocks() {
+    let path = std::env::temp_dir().join("crowbook-blocks-test.html");
+    let mut book = Book::new();
+    book.options
+        .set("html.standalone.template", path.to_str().unwrap())
+        .unwrap();
+
+    std::fs::write(
+        &path,
+        "{# extends #}\n{# block footer #}<footer>{{ title }}</footer>{# endblock #}\n",
+    )
+    .unwrap();
+    let template = book.get_template("html.standalone.template").unwrap();
+    assert!(template.contains("<footer>{{ title }}</footer>"));
+    assert!(!template.contains("{{footer}}"));
+    assert!(template.contains("{{content}}"));
+
+    std::fs::write(&path, "{# extends #}\n{# block sidebar #}{# endblock #}\n").unwrap();
+    let err = book.get_template("html.standalone.template").unwrap_err();
+    assert!(format!("{err}").contains("sidebar"));
+    assert!(format!("{err}").contains("head, toc, header, chapter, footer"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn content_placeholder() {
+    // Chapters are written where the template displays the content, which must be once
+    let dir = std::env::temp_dir().join(format!("crowbook-content-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let html = dir.join("template.html");
+    let tex = dir.join("template.tex");
+    let mut book = Book::new();
+    book.options.set("html.standalone.template", html.to_str().unwrap()).unwrap();
+    book.options.set("tex.template", tex.to_str().unwrap()).unwrap();
+    book.add_chapter_from_source(Number::Default, "# One\n\nText".as_bytes(), true)
+        .unwrap();
+
+    std::fs::write(&html, "<main>{{content}}</main>").unwrap();
+    let rendered = book.render_format_to_string("html").unwrap();
+    assert!(rendered.starts_with("<main>") && rendered.contains("Text"));
+    for template in ["{{content}}<hr>{{content}}", "{{title}}"] {
+        std::fs::write(&html, template).unwrap();
+        let err = book.render_format_to_string("html").unwrap_err();
+        assert!(format!("{err}").contains("html.standalone.template"));
+    }
+
+    // A LaTeX template that doesn't display the content doesn't drop the chapters silently
+    std::fs::write(&tex, "\\documentclass{article}\\begin{document}\\end{document}").unwrap();
+    let err = book.render_format_to_string("tex").unwrap_err();
+    assert!(format!("{err}").contains("tex.template"));
+    std::fs::write(&tex, "\\begin{document}<<content>><<content>>\\end{document}").unwrap();
+    assert!(book.render_format_to_string("tex").is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn template_variables() {
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "# Title\n\nText\n".as_bytes(), false)
+        .unwrap();
+    let find = |vars: &[crate::TemplateVar], name: &str| {
+        vars.iter().find(|var| var.name == name).cloned().unwrap()
+    };
+
+    let vars = book.template_variables("html").unwrap();
+    let var = find(&vars, "has_toc");
+    assert_eq!(var.kind, "bool");
+    assert_eq!(var.templates, ["html.standalone.template"]);
+    assert!(find(&vars, "chapter_title")
+        .templates
+        .contains(&String::from("rendering.chapter.template")));
+
+    let vars = book.template_variables("pdf").unwrap();
+    assert_eq!(find(&vars, "use_side_notes").templates, ["tex.template"]);
+
+    let vars = book.template_variables("html.dir").unwrap();
+    assert!(find(&vars, "prev_chapter")
+        .templates
+        .contains(&String::from("html.dir.template")));
+}
+
+#[test]
+fn css_theme() {
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("--measure: 33em;"));
+    assert!(html.contains("max-width: var(--measure);"));
+    assert!(!html.contains("--measure: 40em;"));
+
+    book.set_options(&[
+        ("html.css.accent", "\"#0066cc\""),
+        ("html.css.measure", "40em"),
+        ("html.css.font", "Georgia, serif"),
+    ]);
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains(":root {\n    --accent-color: #0066cc;\n    --font-body: Georgia, serif;\n    --measure: 40em;\n}"));
+    let (_, rest) = html.split_once("--measure: 33em;").unwrap();
+    assert!(rest.contains("--measure: 40em;"));
+}
+
+#[test]
+fn additional_code() {
+    let dir = std::env::temp_dir().join("crowbook-additional-code-test");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("extra.css"), ".extra { color: red; }").unwrap();
+    std::fs::write(dir.join("chapter.md"), "Text\n").unwrap();
+    std::fs::write(
+        dir.join("book.book"),
+        "title: Test
+html.css.add: [extra.css, \".inline { color: blue; }\"]
+html.js.add: \"console.log('added');\"
+epub.css.add: extra.css
+- chapter.md
+",
+    )
+    .unwrap();
+    let mut book = Book::new();
+    book.load_file(dir.join("book.book")).unwrap();
+    assert_eq!(book.options.get_str_vec("epub.css.add").unwrap(), ["extra.css"]);
+
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains(".extra { color: red; }\n.inline { color: blue; }"));
+    assert!(html.contains("<script>\nconsole.log('added');\n    </script>"));
+
+    book.options.set("html.css.add", "missing.css").unwrap();
+    let mut html = vec![];
+    assert!(book.render_format_to("html", &mut html).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn epub_pages() {
+    let dir = std::env::temp_dir().join("crowbook-epub-pages-test");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("chapter.xhtml"),
+        "<html><body>{{chapter_index}}/{{chapters_count}} {{chapter_filename}}{% if is_part %} part{% endif %}{{content}}</body></html>",
+    )
+    .unwrap();
+    std::fs::write(dir.join("one.md"), "# One\n").unwrap();
+    std::fs::write(dir.join("two.md"), "# Two\n").unwrap();
+    std::fs::write(
+        dir.join("book.book"),
+        "title: Test
+epub.version: 3
+epub.chapter.xhtml: chapter.xhtml
+epub.pages: [toc, chapters, titlepage]
+@+ one.md
+- two.md
+",
+    )
+    .unwrap();
+    let mut book = Book::new();
+    book.load_file(dir.join("book.book")).unwrap();
+    let mut epub = vec![];
+    book.render_format_to("epub", &mut epub).unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+    let mut read = |name: &str| {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name(name).unwrap(), &mut content)
+            .unwrap();
+        content
+    };
+    let opf = read("OEBPS/content.opf");
+    let toc = opf.find("idref=\"toc.xhtml\"").unwrap();
+    let chapter = opf.find("idref=\"chapter_000.xhtml\"").unwrap();
+    let title = opf.find("idref=\"title_page.xhtml\"").unwrap();
+    assert!(toc < chapter && chapter < title);
+    assert!(read("OEBPS/chapter_000.xhtml").contains("1/2 chapter_000.xhtml part"));
+    assert!(read("OEBPS/chapter_001.xhtml").contains("2/2 chapter_001.xhtml<"));
+
+    book.options.set("epub.pages", "[titlepage]").unwrap();
+    assert!(book.render_format_to("epub", &mut vec![]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn calibre_opf() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("title", "Fish & Chips"),
+        ("author", "Someone"),
+        ("lang", "fr"),
+    ]);
+    let mut opf = vec![];
+    book.render_format_to("opf", &mut opf).unwrap();
+    let opf = String::from_utf8(opf).unwrap();
+    assert!(opf.contains(
+        "<dc:title>Fish &amp; Chips</dc:title>\n    <dc:creator opf:role=\"aut\">Someone</dc:creator>\n    <dc:language>fr</dc:language>"
+    ));
+    assert!(!opf.contains("dc:rights"));
+}
+
+#[cfg(unix)]
+#[test]
+fn calibre_ebook_convert() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("calibre.command", "sh -c 'cat \"$0\" \"$3\" > \"$1\"'"),
+        ("calibre.azw3.args", "--no-inline-toc"),
+    ]);
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    let mut azw3 = vec![];
+    book.render_format_to("azw3", &mut azw3).unwrap();
+    // The fake command concatenates the EPUB file and the metadata
+    assert!(azw3.starts_with(b"PK"));
+    assert!(String::from_utf8_lossy(&azw3).ends_with("</package>\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn publish_kindle() {
+    let dir = std::env::temp_dir().join("crowbook-publish-test");
+    let device = dir.join("device");
+    std::fs::create_dir_all(&device).unwrap();
+    let path = dir.join("book.html");
+    let mut book = Book::new();
+    book.set_options(&[
+        ("publish.kindle", "true"),
+        ("publish.kindle.formats", "html"),
+        ("publish.kindle.previewer", "test -s \"$CROWBOOK_OUTPUT\""),
+        ("publish.kindle.device", device.to_str().unwrap()),
+        ("publish.kindle.email", "echo \"$CROWBOOK_FORMAT\" > sent"),
+    ]);
+    book.root = dir.clone();
+    book.add_chapter_from_source(Number::Default, "Text\n".as_bytes(), false)
+        .unwrap();
+    book.render_format_to_file("html", &path).unwrap();
+    assert!(device.join("book.html").exists());
+    assert_eq!(std::fs::read_to_string(dir.join("sent")).unwrap(), "html\n");
+
+    book.options.set("publish.kindle.device", "'/nonexistent/device'").unwrap();
+    assert!(book.render_format_to_file("html", &path).is_err());
+    book.options.set("publish.kindle.formats", "epub").unwrap();
+    assert!(book.render_format_to_file("html", &path).is_ok());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn site() {
+    let dir = std::env::temp_dir().join("crowbook-site-test");
+    let path = dir.join("site");
+    let mut book = Book::new();
+    book.set_options(&[
+        ("title", "Some title"),
+        ("description", "A *short* description"),
+        ("site.downloads", "[epub, opf]"),
+    ]);
+    book.add_chapter_from_source(Number::Default, "# Chapter\n\nText\n".as_bytes(), false)
+        .unwrap();
+    book.render_format_to_file("site", &path).unwrap();
+    let index = std::fs::read_to_string(path.join("index.html")).unwrap();
+    assert!(index.contains("<p>A <em>short</em> description</p>"));
+    assert!(index.contains("href=\"read/index.html\""));
+    assert!(index.contains("href=\"book.epub\""));
+    assert!(index.contains("href=\"book.opf\""));
+    assert!(path.join("read/chapter_000.html").exists());
+    assert!(path.join("book.epub").exists());
+    assert!(path.join(".nojekyll").exists());
+
+    book.set_options(&[("site.reader", "''"), ("site.downloads", "[]")]);
+    book.render_format_to_file("site", &path).unwrap();
+    let index = std::fs::read_to_string(path.join("index.html")).unwrap();
+    assert!(!index.contains("read/index.html"));
+    assert!(!index.contains("book.epub"));
+    assert!(!path.join("read").exists());
+
+    book.set_options(&[("site.downloads", "site")]);
+    assert!(book.render_format_to_file("site", &path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn html_dir_sitemap() {
+    let dir = std::env::temp_dir().join("crowbook-sitemap-test");
+    let mut book = Book::new();
+    book.set_options(&[("html.base_url", "https://example.org/book/"), ("date", "Spring 2024")]);
+    book.add_chapter_from_source(
+        Number::Default,
+        "---\nchapter.date: 2024-01-15\n---\n# One\n".as_bytes(),
+        false,
+    )
+    .unwrap();
+    book.render_format_to_file("html.dir", &dir).unwrap();
+    let sitemap = std::fs::read_to_string(dir.join("sitemap.xml")).unwrap();
+    assert!(sitemap.contains("<loc>https://example.org/book/</loc>\n  </url>"));
+    assert!(sitemap.contains(
+        "<loc>https://example.org/book/chapter_000.html</loc>\n    <lastmod>2024-01-15</lastmod>"
+    ));
+    let robots = std::fs::read_to_string(dir.join("robots.txt")).unwrap();
+    assert!(robots.ends_with("Sitemap: https://example.org/book/sitemap.xml\n"));
+    let chapter = std::fs::read_to_string(dir.join("chapter_000.html")).unwrap();
+    assert!(chapter.contains("<link rel = \"canonical\" href = \"https://example.org/book/chapter_000.html\">"));
+
+    book.set_options(&[("html.sitemap", "false")]);
+    book.render_format_to_file("html.dir", &dir).unwrap();
+    assert!(!dir.join("sitemap.xml").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn opds() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("title", "Fish & Chips"),
+        ("author", "Someone"),
+        ("output.pdf", "out/fish.pdf"),
+        ("opds.formats", "[epub, pdf]"),
+        ("opds.base_url", "https://example.org/books/"),
+        ("crowbook.reproducible", "true"),
+    ]);
+    let mut opds = vec![];
+    book.render_format_to("opds", &mut opds).unwrap();
+    let opds = String::from_utf8(opds).unwrap();
+    assert!(opds.contains("<title>Fish &amp; Chips</title>"));
+    assert!(opds.contains("<updated>1980-01-01T00:00:00Z</updated>"));
+    assert!(opds.contains(&format!("<id>urn:uuid:{}</id>", book.stable_uuid("crowbook"))));
+    assert!(opds.contains(
+        "<link rel=\"http://opds-spec.org/acquisition\" type=\"application/epub+zip\" href=\"https://example.org/books/book.epub\"/>"
+    ));
+    assert!(opds.contains(
+        "<link rel=\"http://opds-spec.org/acquisition\" type=\"application/pdf\" href=\"https://example.org/books/fish.pdf\"/>"
+    ));
+    assert!(!opds.contains("opds-spec.org/image"));
+}
+
+#[test]
+fn onix() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("title", "Fish & Chips"),
+        ("author", "Someone"),
+        ("lang", "fr"),
+        ("date", "2024-01-15"),
+        ("onix.isbn", "978-2-1234-5680-3"),
+        ("onix.contributors", "[\"translator: Someone Else\", \"A12: An Illustrator\"]"),
+        ("onix.prices", "[9.99 EUR, 10.99 USD]"),
+        ("subject.bisac", "[FIC009000, FIC028000]"),
+    ]);
+    let mut onix = vec![];
+    book.render_format_to("onix", &mut onix).unwrap();
+    let onix = String::from_utf8(onix).unwrap();
+    assert!(onix.contains("<IDValue>9782123456803</IDValue>"));
+    assert!(onix.contains("<TitleText>Fish &amp; Chips</TitleText>"));
+    assert!(onix.contains("<SequenceNumber>2</SequenceNumber>\n          <ContributorRole>B06</ContributorRole>"));
+    assert!(onix.contains("<ContributorRole>A12</ContributorRole>\n          <PersonName>An Illustrator</PersonName>"));
+    assert!(onix.contains("<LanguageCode>fre</LanguageCode>"));
+    assert!(onix.contains("<MainSubject/>\n        <SubjectSchemeIdentifier>10</SubjectSchemeIdentifier>\n        <SubjectCode>FIC009000</SubjectCode>"));
+    assert!(onix.contains("<Date>20240115</Date>"));
+    assert!(onix.contains("<PriceType>02</PriceType>\n          <PriceAmount>10.99</PriceAmount>\n          <CurrencyCode>USD</CurrencyCode>"));
+
+    book.set_options(&[("onix.prices", "['9.99']")]);
+    assert!(book.render_format_to("onix", &mut vec![]).is_err());
+    book.set_options(&[("onix.prices", "[]"), ("onix.contributors", "[\"ghost: Someone\"]")]);
+    assert!(book.render_format_to("onix", &mut vec![]).is_err());
+}
+
+#[test]
+fn subject_codes() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("subject", "Fiction"),
+        ("subject.keywords", "[sea, fish & chips]"),
+        ("subject.bisac", "FIC009000"),
+        ("subject.thema", "[FBA, 1DDF]"),
+    ]);
+    assert!(book.options.set("subject.bisac", "[FIC9000]").is_err());
+    assert!(book.options.set("subject.thema", "[fba]").is_err());
+
+    let mut onix = vec![];
+    book.render_format_to("onix", &mut onix).unwrap();
+    let onix = String::from_utf8(onix).unwrap();
+    assert!(onix.contains("<MainSubject/>\n        <SubjectSchemeIdentifier>93</SubjectSchemeIdentifier>\n        <SubjectCode>FBA</SubjectCode>"));
+    assert!(onix.contains("<SubjectSchemeIdentifier>94</SubjectSchemeIdentifier>\n        <SubjectCode>1DDF</SubjectCode>"));
+    assert!(onix.contains("<SubjectHeadingText>Fiction; sea; fish &amp; chips</SubjectHeadingText>"));
+
+    let mut html = HtmlRenderer::new(&book, "InspiredGitHub").unwrap();
+    let tags = html.get_meta_tags("").unwrap();
+    assert!(tags.contains("<meta name = \"keywords\" content = \"Fiction, sea, fish &amp; chips\">"));
+}
+
+#[test]
+fn identifiers() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("isbn", "978-0-306-40615-7"),
+        ("isbn.epub", "978-3-16-148410-0"),
+        ("doi", "10.1000/182"),
+        ("uuid", "67e55044-10b1-426f-9247-bb680e5fe0c8"),
+        ("epub.version", "3"),
+    ]);
+    assert!(book.options.set("isbn", "978-0-306-40615-8").is_err());
+    assert!(book.options.set("isbn.print", "0-306-40615-2").is_err());
+    assert!(book.options.set("doi", "11.1000/182").is_err());
+    assert!(book.options.set("uuid", "not-an-uuid").is_err());
+    book.add_chapter_from_source(Number::Default, "# One\n\nText".as_bytes(), false)
+        .unwrap();
+
+    let mut epub = vec![];
+    book.render_format_to("epub", &mut epub).unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+    let mut opf = String::new();
+    std::io::Read::read_to_string(&mut archive.by_name("OEBPS/content.opf").unwrap(), &mut opf)
+        .unwrap();
+    assert!(opf.contains(
+        "<dc:identifier id=\"epub-id-1\">urn:uuid:67e55044-10b1-426f-9247-bb680e5fe0c8</dc:identifier>
+    <dc:identifier>urn:isbn:9783161484100</dc:identifier>
+    <dc:identifier>urn:doi:10.1000/182</dc:identifier>"
+    ));
+
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    assert!(String::from_utf8(tex).unwrap().contains("ISBN 978-0-306-40615-7"));
+
+    let mut onix = vec![];
+    book.render_format_to("onix", &mut onix).unwrap();
+    let onix = String::from_utf8(onix).unwrap();
+    assert!(onix.contains("<ProductIDType>15</ProductIDType>\n      <IDValue>9783161484100</IDValue>"));
+    assert!(onix.contains("<ProductIDType>06</ProductIDType>\n      <IDValue>10.1000/182</IDValue>"));
+}
+
+#[test]
+fn imprint() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("imprint", "Some Imprint"),
+        ("edition", "'2'"),
+        ("printing", "'3'"),
+        ("revisions", "['2023-01-01: First edition', '2024-06-01: Fixed *typos*']"),
+        ("revisions.table", "true"),
+    ]);
+    assert!(book.options.set("revisions", "['no date']").is_err());
+    book.add_chapter_from_source(Number::Default, "# One\n\nText".as_bytes(), false)
+        .unwrap();
+
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("{Some Imprint\\\\[5mm]}"));
+    assert!(tex.contains("{Edition~2\\\\[5mm]}"));
+    assert!(tex.contains("{Printing~3\\\\[5mm]}"));
+    assert!(tex.contains("2024-06-01 & Fixed \\emph{typos} \\\\"));
+
+    let mut epub = vec![];
+    book.render_format_to("epub", &mut epub).unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+    let mut read = |name: &str| {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name(name).unwrap(), &mut content)
+            .unwrap();
+        content
+    };
+    let opf = read("OEBPS/content.opf");
+    assert!(opf.contains("<dc:publisher>Some Imprint</dc:publisher>"));
+    assert!(opf.contains("<meta name=\"schema:bookEdition\" content=\"2\" />"));
+    let titlepage = read("OEBPS/title_page.xhtml");
+    assert!(titlepage.contains("<p class=\"imprint\">Edition 2</p>"));
+    assert!(titlepage.contains("<tr><td>2024-06-01</td><td>Fixed <em>typos</em></td></tr>"));
+}
+
+#[cfg(unix)]
+#[test]
+fn manifest() {
+    let dir = std::env::temp_dir().join("crowbook-manifest-test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut book = Book::new();
+    book.set_options(&[
+        ("title", "Some title"),
+        ("version", "'1.2'"),
+        ("manifest.file", &dir.join("manifest.json").to_string_lossy()),
+    ]);
+    book.add_chapter_from_source(Number::Default, "# Chapter\n\nText\n".as_bytes(), false)
+        .unwrap();
+    book.render_format_to_file("html.dir", dir.join("html")).unwrap();
+    let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(manifest["title"], "Some title");
+    assert_eq!(manifest["version"], "1.2");
+    let files = manifest["files"].as_array().unwrap();
+    let index = files.iter().find(|f| f["path"] == "html/index.html").unwrap();
+    let content = std::fs::read(dir.join("html/index.html")).unwrap();
+    assert_eq!(index["format"], "html.dir");
+    assert_eq!(index["size"], content.len());
+    assert_eq!(index["sha256"], crate::manifest::sha256(&content));
+    assert_eq!(
+        crate::manifest::sha256(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+
+    // The fake command writes the key to the signature file
+    book.set_options(&[
+        ("manifest.sign.key", "someone@example.com"),
+        ("manifest.sign.command", "sh -c 'echo \"$3\" > \"$7\"'"),
+    ]);
+    book.render_format_to_file("epub", dir.join("book.epub")).unwrap();
+    let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+    assert!(manifest.contains("\"path\": \"book.epub\""));
+    assert!(!manifest.contains("html/index.html"));
+    let signature = std::fs::read_to_string(dir.join("manifest.json.asc")).unwrap();
+    assert_eq!(signature, "someone@example.com\n");
+
+    book.set_options(&[("manifest.sign.command", "'false'")]);
+    assert!(book.render_format_to_file("epub", dir.join("book.epub")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn output_base_path() {
+    let dir = std::env::temp_dir().join("crowbook-output-test");
+    let _ = std::fs::remove_dir_all(&dir);
+    let base = dir.join("build").to_string_lossy().into_owned();
+    let mut book = Book::new();
+    book.set_options(&[
+        ("output.base_path", base.as_str()),
+        ("output.epub", "ebooks/book.epub"),
+        ("output.html", "book.html"),
+    ]);
+    book.add_chapter_from_source(Number::Default, "# Chapter\n\nText\n".as_bytes(), false)
+        .unwrap();
+    assert_eq!(
+        book.options.get_path("output.epub").unwrap(),
+        dir.join("build/ebooks/book.epub").to_string_lossy()
+    );
+    book.render_all();
+    assert!(dir.join("build/ebooks/book.epub").exists());
+    assert!(dir.join("build/book.html").exists());
+
+    // Directories outside of output.base_path aren't created
+    let outside = dir.join("outside/book.html");
+    assert!(book.render_format_to_file("html", &outside).is_err());
+    assert!(!dir.join("outside").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn output_variants() {
+    let dir = std::env::temp_dir().join("crowbook-variants-test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = format!(
+        "
+title: Some title
+output.html: {dir}/book.html
+output.html.print: {dir}/print.html
+output.html.screen: {dir}/screen.html
+profiles.print.html.header: For print
+profiles.screen.html.header: For screen
+",
+        dir = dir.display()
+    );
+    let mut book = Book::new();
+    book.read_config(config.as_bytes()).unwrap();
+    book.add_chapter_from_source(Number::Default, "# Chapter\n\nText\n".as_bytes(), false)
+        .unwrap();
+    book.render_all();
+    let html = std::fs::read_to_string(dir.join("book.html")).unwrap();
+    let print = std::fs::read_to_string(dir.join("print.html")).unwrap();
+    let screen = std::fs::read_to_string(dir.join("screen.html")).unwrap();
+    assert!(!html.contains("For print") && !html.contains("For screen"));
+    assert!(print.contains("For print"));
+    assert!(screen.contains("For screen"));
+    // The book's own options aren't changed by the variants
+    assert!(book.options.get_str("html.header").is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // A variant must match a profile
+    let mut book = Book::new();
+    book.read_config("output.html.foo: foo.html\n".as_bytes()).unwrap();
+    assert!(book.variant("foo").is_err());
+}
+
+#[test]
+fn print_options() {
+    let render = |book: &Book| {
+        let mut tex = vec![];
+        book.render_format_to("tex", &mut tex).map(|_| String::from_utf8(tex).unwrap())
+    };
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "# Chapter\n\nText\n".as_bytes(), false)
+        .unwrap();
+    let tex = render(&book).unwrap();
+    assert!(!tex.contains("showcrop"));
+    assert!(!tex.contains("{pdfx}"));
+    assert!(tex.contains("\\usepackage[colorlinks=true,breaklinks=true,hypertexnames=false]{hyperref}"));
+
+    book.set_options(&[
+        ("pdf.bleed", "3mm"),
+        ("pdf.crop_marks", "true"),
+        ("pdf.grayscale", "true"),
+        ("pdf.pdfx", "true"),
+    ]);
+    let tex = render(&book).unwrap();
+    assert!(tex.contains("\\setlength\\mdbleed{3mm}"));
+    assert!(tex.contains("showcrop"));
+    assert!(tex.contains("/TrimBox"));
+    assert!(tex.contains("\\PassOptionsToPackage{gray}{xcolor}"));
+    assert!(tex.contains("\\usepackage[x-1a]{pdfx}"));
+    assert!(tex.contains("\\begin{filecontents*}[overwrite]{\\jobname.xmpdata}"));
+    assert!(!tex.contains("{hyperref}"));
+
+    // The ICC profile must exist
+    book.set_options(&[("pdf.icc_profile", "missing.icc")]);
+    assert!(render(&book).is_err());
+
+    let mut book = Book::new();
+    book.set_options(&[("pdf.bleed", "3 apples")]);
+    assert!(render(&book).is_err());
+}
+
+#[test]
+fn pdfa() {
+    let render = |book: &Book| {
+        let mut tex = vec![];
+        book.render_format_to("tex", &mut tex).map(|_| String::from_utf8(tex).unwrap())
+    };
+    let mut book = Book::new();
+    book.set_options(&[("pdf.pdfa", "true"), ("description", "A description")]);
+    let tex = render(&book).unwrap();
+    assert!(tex.contains("\\usepackage[a-2b]{pdfx}"));
+    assert!(tex.contains("\\Subject{A description}"));
+
+    // PDF/A and PDF/X are exclusive
+    book.set_options(&[("pdf.pdfx", "true")]);
+    assert!(render(&book).is_err());
+}
+
+#[test]
+fn pdf_metadata() {
+    let render = |book: &Book| {
+        let mut tex = vec![];
+        book.render_format_to("tex", &mut tex).unwrap();
+        String::from_utf8(tex).unwrap()
+    };
+    let mut book = Book::new();
+    book.set_options(&[
+        ("subject.keywords", "[fantasy, dragons]"),
+        ("rendering.num_depth", "2"),
+        ("lang", "fr"),
+    ]);
+    let tex = render(&book);
+    assert!(tex.contains("pdfkeywords={fantasy, dragons}"));
+    assert!(tex.contains("pdflang={fr}"));
+    assert!(tex.contains("bookmarksdepth=1"));
+    assert!(tex.contains("colorlinks=true"));
+
+    book.set_options(&[("pdf.link_colors", "false"), ("pdf.pdfa", "true")]);
+    let tex = render(&book);
+    assert!(tex.contains("\\Keywords{fantasy\\sep dragons}"));
+    assert!(tex.contains("hidelinks"));
+    assert!(!tex.contains("colorlinks=true"));
+}
+
+#[test]
+fn widows_orphans() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("rendering.widows", "2"),
+        ("rendering.orphans", "3"),
+        ("tex.microtype", "true"),
+        ("tex.line_breaking", "sloppy"),
+        ("tex.hyphenation.exceptions", "[crow-book, ma-nu-script]"),
+    ]);
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("\\widowpenalty=10000"));
+    assert!(tex.contains("\\clubpenalty=10000"));
+    assert!(tex.contains("\\usepackage{microtype}"));
+    assert!(tex.contains("\\sloppy"));
+    assert!(tex.contains("\\hyphenation{crow-book ma-nu-script}"));
+
+    let html = HtmlRenderer::new(&book, "html").unwrap();
+    assert!(html.get_css_theme("epub").contains("p {\n    widows: 2;\n    orphans: 3;\n}"));
+
+    book.set_options(&[("tex.line_breaking", "loose")]);
+    assert!(book.render_format_to("tex", &mut vec![]).is_err());
+}
+
+#[test]
+fn chapter_layout() {
+    let render = |book: &Book| {
+        let mut tex = vec![];
+        book.render_format_to("tex", &mut tex).map(|_| String::from_utf8(tex).unwrap())
+    };
+    let mut book = Book::new();
+    let tex = render(&book).unwrap();
+    assert!(tex.contains("\\documentclass{book}"));
+    assert!(tex.contains("\\usepackage{emptypage}"));
+    assert!(!tex.contains("\\usepackage{titlesec}"));
+
+    book.set_options(&[
+        ("tex.chapter.recto", "false"),
+        ("tex.chapter.drop", "5cm"),
+        ("tex.chapter.style", "rule"),
+        ("tex.font.size", "11"),
+    ]);
+    let tex = render(&book).unwrap();
+    assert!(tex.contains("\\documentclass[11pt,openany]{book}"));
+    assert!(!tex.contains("\\usepackage{emptypage}"));
+    assert!(tex.contains("\\titlerule"));
+    assert!(tex.contains("\\titlespacing*{\\chapter}{0pt}{5cm}{40pt}"));
+
+    book.set_options(&[("tex.chapter.style", "fancy")]);
+    assert!(render(&book).is_err());
+}
+
+#[test]
+fn in_memory() {
+    let temp_dir = std::env::temp_dir().join("crowbook-in-memory-test");
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    std::fs::create_dir_all(&temp_dir).unwrap();
+    let mut book = Book::new();
+    book.set_options(&[
+        ("crowbook.in_memory", "true"),
+        ("crowbook.temp_dir", temp_dir.to_str().unwrap()),
+    ]);
+    book.add_chapter_from_source(Number::Default, "# Chapter\n\nText\n".as_bytes(), false)
+        .unwrap();
+    let epub = book.render_format_to_vec("epub").unwrap();
+    assert!(epub.starts_with(b"PK"));
+    let tex = book.render_format_to_string("tex").unwrap();
+    assert!(tex.contains("\\chapter{Chapter}"));
+    let html = book.render_format_to_string("html").unwrap();
+    assert!(html.contains("<h1"));
+    assert!(book.render_format_to_string("epub").is_err());
+
+    // Running LaTeX needs temporary files
+    assert!(book.render_format_to_vec("pdf").is_err());
+    assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&temp_dir).unwrap();
+}
+
+#[test]
+fn preview_render_html() {
+    let html = crate::preview::render_html(
+        r#"{"title": "Preview", "lang": "fr", "rendering.num_depth": 2, "html.header": null}"#,
+        r##"["# Premier\n\nDu texte : oui", "# Second\n\n*Fin*"]"##,
+    )
+    .unwrap();
+    assert!(html.contains("<title>Preview</title>"));
+    assert!(html.contains("lang=\"fr\""));
+    assert!(html.contains("<em>Fin</em>"));
+    // French typography and translations apply
+    assert!(html.contains("texte\u{a0}: oui"));
+    assert!(html.contains("Chapitre 1"));
+
+    assert!(crate::preview::render_html("[]", "[]").is_err());
+    assert!(crate::preview::render_html("{}", r#"[1]"#).is_err());
+    assert!(crate::preview::render_html(r#"{"rendering.num_depth": "two"}"#, "[]").is_err());
+}
+
+#[test]
+#[cfg(feature = "capi")]
+fn capi() {
+    use crate::capi::*;
+    use std::ffi::{CStr, CString};
+
+    unsafe {
+        let config = CString::new("title: C book\nlang: en").unwrap();
+        let book = crowbook_book_new(config.as_ptr());
+        assert!(!book.is_null());
+        let chapter = CString::new("# Chapter\n\nText").unwrap();
+        assert_eq!(crowbook_book_add_chapter(book, chapter.as_ptr()), 0);
+
+        let format = CString::new("html").unwrap();
+        let mut len = 0;
+        let buffer = crowbook_book_render(book, format.as_ptr(), &mut len);
+        assert!(!buffer.is_null());
+        let html = std::str::from_utf8(std::slice::from_raw_parts(buffer, len)).unwrap();
+        assert!(html.contains("<title>C book</title>"));
+        crowbook_buffer_free(buffer, len);
+
+        let format = CString::new("foo").unwrap();
+        assert!(crowbook_book_render(book, format.as_ptr(), &mut len).is_null());
+        assert!(!crowbook_last_error().is_null());
+        assert_eq!(crowbook_book_add_chapter(book, std::ptr::null()), -1);
+        let error = CStr::from_ptr(crowbook_last_error()).to_str().unwrap();
+        assert!(error.contains("content"));
+        crowbook_book_free(book);
+
+        let config = CString::new("title: Foo\n\n+ missing_chapter.md").unwrap();
+        assert!(crowbook_book_new(config.as_ptr()).is_null());
+    }
+}
+
+#[test]
+fn config_file_editing() {
+    use crate::config_file::ConfigFile;
+
+    let content = "\
+# My book
+author: Me # the author
+title: Old
+description: >-
+  A long
+  description
+
+# Formats
+output.html: foo.html
+
+## Chapters
++ a.md
+- b.md
+";
+    let mut config = ConfigFile::parse(content);
+    assert_eq!(config.keys(), ["author", "title", "description", "output.html"]);
+    assert_eq!(config.get("description"), Some(Yaml::String("A long description".into())));
+    assert_eq!(config.get("lang"), None);
+
+    config.set("description", Yaml::String("Short".into()));
+    config.set("output", Yaml::Array(vec![Yaml::String("epub".into())]));
+    assert!(config.remove("output.html"));
+    assert!(!config.remove("output.html"));
+    assert_eq!(
+        config.to_string(),
+        "\
+# My book
+author: Me # the author
+title: Old
+description: Short
+
+# Formats
+output:
+  - epub
+
+## Chapters
++ a.md
+- b.md
+"
+    );
+    let mut book = Book::new();
+    book.read_config(config.to_string().as_bytes())
+        .unwrap_err(); // a.md doesn't exist, but the options are read
+    assert_eq!(book.options.get_str("description").unwrap(), "Short");
+}
+
+#[test]
+fn save_options() {
+    let dir = std::env::temp_dir().join(format!("crowbook-save-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("book.book");
+    std::fs::write(dir.join("chapter.md"), "# Chapter\n\nText\n").unwrap();
+    std::fs::write(
+        &file,
+        "# My book\nauthor: Me # the author\ntitle: Old\noutput: [epub]\n\n## Chapters\n+ chapter.md\n",
+    )
+    .unwrap();
+    let mut book = Book::new();
+    book.load_file(&file).unwrap();
+    book.options.set("title", "New").unwrap();
+    book.options.set("tex.chapter.style", "rule").unwrap();
+    book.options.set("epub.version", "3").unwrap();
+    // Default values aren't written
+    book.options.set("rendering.num_depth", "1").unwrap();
+    book.options.save(&file).unwrap();
+    let content = std::fs::read_to_string(&file).unwrap();
+    test_eq(
+        &content,
+        "# My book\nauthor: Me # the author\ntitle: New\noutput: [epub]\nepub.version: 3\n\
+         tex.chapter.style: rule\n\n## Chapters\n+ chapter.md\n",
+    );
+
+    // The saved file gives the same options
+    let mut saved = Book::new();
+    saved.load_file(&file).unwrap();
+    assert_eq!(saved.options.get_str("title").unwrap(), "New");
+    assert_eq!(saved.options.get_i32("epub.version").unwrap(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn migrate_config() {
+    use crate::config_file::ConfigFile;
+
+    let content = "\
+title: Foo
+numbering: 2 # chapter depth
+html.highlight_code: true
+output: [html_dir]
+tex.short: true
+
+- a.md
+";
+    let mut config = ConfigFile::parse(content);
+    let changes = crate::migrate::migrate(&mut config).unwrap();
+    assert_eq!(changes.len(), 4);
+    assert_eq!(
+        config.to_string(),
+        "\
+title: Foo
+rendering.num_depth: 2 # chapter depth
+rendering.highlight: highlight.js
+output:
+  - html.dir
+# tex.short: true
+
+- a.md
+"
+    );
+
+    // Migrated files are read without errors
+    let mut book = Book::new();
+    book.read_config("numbering: 2\nhtml.highlight_code: false\n".as_bytes()).unwrap();
+    assert_eq!(book.options.get_i32("rendering.num_depth").unwrap(), 2);
+    assert_eq!(book.options.get_str("rendering.highlight").unwrap(), "none");
+
+    let err = book.read_config("crowbook.version: 3\n".as_bytes()).unwrap_err();
+    assert!(err.to_string().contains("newer version"));
+}
+
+#[test]
+fn date_options() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("lang", "fr"),
+        ("date", "2024-03-03"),
+        ("publication_date", "2023-11-20T10:30:00+01:00"),
+        ("epub.version", "3"),
+    ]);
+    assert!(book.options.set("date", "2024-02-30").is_err());
+    assert!(book.options.set("date", "2024-13-01").is_err());
+    assert_eq!(book.options.get_date("date").unwrap().to_string(), "2024-03-03");
+
+    let data = book.get_metadata(|s| Ok(s.to_owned())).unwrap();
+    assert_eq!(data["date"], upon::Value::from("3 mars 2024"));
+    assert_eq!(data["date_iso"], upon::Value::from("2024-03-03"));
+    assert_eq!(data["publication_date"], upon::Value::from("20 novembre 2023"));
+
+    book.add_chapter_from_source(Number::Default, "# One\n\nText".as_bytes(), false)
+        .unwrap();
+    let mut epub = vec![];
+    book.render_format_to("epub", &mut epub).unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+    let mut opf = String::new();
+    std::io::Read::read_to_string(&mut archive.by_name("OEBPS/content.opf").unwrap(), &mut opf)
+        .unwrap();
+    assert!(opf.contains("<dc:date>2023-11-20T09:30:00Z</dc:date>"));
+    assert!(opf.contains("<meta property=\"dcterms:modified\">2024-03-03T00:00:00Z</meta>"));
+}
+
+#[test]
+fn option_references() {
+    let mut book = Book::new();
+    book.read_config(
+        "title: L'Été indien\noutput.name: \"{{title|slug}}\"\noutput.epub: \"{{ output.name }}.epub\"\n"
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(book.options.get("output.name").unwrap().as_path().unwrap(), "l-ete-indien");
+    assert_eq!(
+        book.options.get("output.epub").unwrap().as_path().unwrap(),
+        "l-ete-indien.epub"
+    );
+    assert_eq!(book.file_stem().as_deref(), Some("l-ete-indien"));
+
+    // References are evaluated again after options change, unless they are set again
+    book.options.set("title", "Autumn").unwrap();
+    book.options.set("output.name", "book").unwrap();
+    book.options.evaluate_references().unwrap();
+    assert_eq!(book.options.get("output.epub").unwrap().as_path().unwrap(), "book.epub");
+
+    let mut book = Book::new();
+    let err = book
+        .read_config("output.html: \"{{output.tex}}\"\noutput.tex: \"{{output.html}}\"\n".as_bytes())
+        .unwrap_err();
+    assert!(err.to_string().contains("output.html -> output.tex -> output.html"));
+    let err = book.read_config("output.html: \"{{title|foo}}\"\n".as_bytes()).unwrap_err();
+    assert!(err.to_string().contains("'foo'"));
+
+    // In sandbox mode, evaluated paths must stay inside the book's directory
+    let mut book = Book::new();
+    book.options.set("crowbook.sandbox", "true").unwrap();
+    let err = book
+        .read_config("title: \"../escaped\"\noutput.html: \"{{title}}.html\"\n".as_bytes())
+        .unwrap_err();
+    assert!(err.to_string().contains("../escaped.html"));
+}
+
+#[test]
+fn slugs() {
+    let mut options = crate::BookOptions::new();
+    options.set("lang", "de").unwrap();
+    let slugifier = crate::Slugifier::new(&options);
+    assert_eq!(slugifier.slug("  Über die Brücke! "), "ueber-die-bruecke");
+    options.set("lang", "zh").unwrap();
+    assert_eq!(crate::Slugifier::new(&options).slug("北京"), "bei-jing");
+    options.set("slug.transliterate", "false").unwrap();
+    options.set("slug.separator", "_").unwrap();
+    options.set("slug.lowercase", "false").unwrap();
+    assert_eq!(crate::Slugifier::new(&options).slug("L'Été à 北京"), "L_Été_à_北京");
+    // Separators are used in file names, so they can't leave the output directory
+    for separator in ["/../../../", "\\", ".", "a\tb"] {
+        let err = options.set("slug.separator", separator).unwrap_err();
+        assert!(err.to_string().contains("can't contain"));
+    }
+    assert!(options.set("slug.separator", "--").is_ok());
+
+    let dir = std::env::temp_dir().join("crowbook-slugs-test");
+    let mut book = Book::new();
+    book.set_options(&[
+        ("title", "L'Été indien"),
+        ("output.filenames", "slug"),
+        ("html.anchors", "slug"),
+        ("html.dir.filenames", "slug"),
+    ]);
+    for source in ["# Index\n\n## Intro {#intro}\n\n## Intro\n", "# Index\n\n## Intro\n"] {
+        book.add_chapter_from_source(Number::Default, source.as_bytes(), false)
+            .unwrap();
+    }
+    assert_eq!(book.file_stem().as_deref(), Some("l-ete-indien"));
+    book.render_format_to_file("html.dir", &dir).unwrap();
+    let chapter = std::fs::read_to_string(dir.join("index-2.html")).unwrap();
+    assert!(chapter.contains("<h1 id = 'index'>"));
+    assert!(chapter.contains("<h2 id = \"intro-2\"><span id = \"intro\"></span>Intro</h2>"));
+    assert!(chapter.contains("<h2 id = \"intro-3\">Intro</h2>"));
+    let chapter = std::fs::read_to_string(dir.join("index-3.html")).unwrap();
+    assert!(chapter.contains("<h2 id = \"intro-4\">Intro</h2>"));
+    let index = std::fs::read_to_string(dir.join("index.html")).unwrap();
+    assert!(index.contains("index-3.html#index-2"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn downgrades() {
+    use crate::capabilities::{Fallback, Feature};
+
+    let mut book = Book::new();
+    book.add_chapter_from_source(
+        Number::Default,
+        "# One\n\n![A song](song.ogg)\n\n![](https://example.org/a.png)\n\n![Clip](clip.mp4)\n"
+            .as_bytes(),
+        false,
+    )
+    .unwrap();
+    let downgrades = book.downgrades("tex").unwrap();
+    let features: Vec<_> = downgrades.iter().map(|d| (d.feature, d.fallback)).collect();
+    assert_eq!(
+        features,
+        [
+            (Feature::Audio, Fallback::Link),
+            (Feature::Video, Fallback::Link),
+            (Feature::RemoteImage, Fallback::Omitted)
+        ]
+    );
+    assert_eq!(
+        downgrades[1].to_string(),
+        "tex doesn't support video (in chapter 1): replaced by a link"
+    );
+    assert!(book.downgrades("html").unwrap().is_empty());
+    book.options.set("epub.version", "2").unwrap();
+    assert_eq!(book.downgrades("epub").unwrap().len(), 2);
+    book.options.set("epub.version", "3").unwrap();
+    assert!(book.downgrades("epub").unwrap().is_empty());
+}
+
+#[test]
+fn strict() {
+    let mut book = Book::new();
+    book.add_chapter_from_source(
+        Number::Default,
+        "# One\n\n![Clip](https://example.org/clip.mp4)\n\n[Two](two.md)\n".as_bytes(),
+        false,
+    )
+    .unwrap();
+    assert!(book.render_format_to_string("tex").is_ok());
+    assert!(book.render_format_to_string("html").is_ok());
+    book.options.set("rendering.strict", "true").unwrap();
+    let err = book.render_format_to_string("tex").unwrap_err();
+    assert!(err.to_string().contains("video"));
+    let err = book.render_format_to_string("html").unwrap_err();
+    assert!(err.to_string().contains("two.md"));
+}
+
+#[test]
+fn char_options() {
+    use crate::bookoptions::parse_char;
+
+    assert_eq!(parse_char("x"), Ok('x'));
+    assert_eq!(parse_char("'x'"), Ok('x'));
+    assert_eq!(parse_char("'"), Ok('\''));
+    assert_eq!(parse_char("e\u{301}"), Ok('é'));
+    assert_eq!(parse_char("U+00A0"), Ok('\u{A0}'));
+    assert_eq!(parse_char("u+202f"), Ok('\u{202F}'));
+    assert_eq!(parse_char("nbsp"), Ok('\u{A0}'));
+    assert_eq!(parse_char("narrow-nbsp"), Ok('\u{202F}'));
+    assert_eq!(parse_char("thin-space"), Ok('\u{2009}'));
+    assert!(parse_char("U+D800").unwrap_err().contains("U+D800"));
+    assert!(parse_char("U++41").is_err());
+    assert!(parse_char("xy").unwrap_err().contains("narrow-nbsp"));
+    assert!(parse_char("").is_err());
+}
+
+#[test]
+fn sizes_and_durations() {
+    use crate::units::{parse_duration, parse_size};
+    use std::time::Duration;
+
+    assert_eq!(parse_size("2MB"), Some(2_000_000));
+    assert_eq!(parse_size("2 MiB"), Some(2 * 1024 * 1024));
+    assert_eq!(parse_size("1.5kb"), Some(1500));
+    assert_eq!(parse_size("512"), Some(512));
+    assert_eq!(parse_size("2MB 3kB"), None);
+    assert_eq!(parse_size("2 parsecs"), None);
+    assert_eq!(parse_size("-1"), None);
+    assert_eq!(parse_duration("10s"), Some(Duration::from_secs(10)));
+    assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+    assert_eq!(parse_duration("1h 30min"), Some(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+    assert_eq!(parse_duration("1m 30"), None);
+    assert_eq!(parse_duration(""), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn command_timeout_and_cancellation() {
+    use std::time::{Duration, Instant};
+
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "# One\n".as_bytes(), false)
+        .unwrap();
+    book.options.set("hooks.pre_render", "sleep 10").unwrap();
+    book.options.set("crowbook.command.timeout", "200ms").unwrap();
+    let start = Instant::now();
+    let err = book.render_format_to_string("html").unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(err.to_string().contains("hooks.pre_render"));
+    assert!(!err.is_cancelled());
+
+    book.options.set("crowbook.command.timeout", "1h").unwrap();
+    let token = book.cancellation_token();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        token.cancel();
+    });
+    let start = Instant::now();
+    let err = book.render_format_to_string("html").unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(err.is_cancelled());
+    assert_eq!(err.code(), "cancelled");
+}
+
+#[test]
+fn variant_cancellation() {
+    let dir = std::env::temp_dir().join(format!("crowbook-variant-cancel-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = format!(
+        "output.html.print: {}\nprofiles.print.html.header: For print\n",
+        dir.join("print.html").display()
+    );
+    let mut book = Book::new();
+    book.read_config(config.as_bytes()).unwrap();
+    book.add_chapter_from_source(Number::Default, "# One\n".as_bytes(), false)
+        .unwrap();
+    let rendering = Arc::new(Mutex::new(vec![]));
+    let progress = Arc::clone(&rendering);
+    book.set_progress_callback(move |p| {
+        if let crate::Progress::Rendering(format) = p {
+            progress.lock().unwrap().push(format.to_owned());
+        }
+    });
+
+    // Variants report their progress and timings with the book
+    let variant = book.variant("print").unwrap();
+    variant.render_format_to_string("html").unwrap();
+    assert_eq!(*rendering.lock().unwrap(), ["html"]);
+    assert!(book.timings().entries().iter().any(|(phase, _)| phase == "render: html"));
+
+    // and are cancelled with it, even once they are created
+    book.cancellation_token().cancel();
+    let err = variant.render_format_to_string("html").unwrap_err();
+    assert!(err.is_cancelled());
+    book.render_variant_with_bar("html", "print", 0);
+    assert!(!dir.join("print.html").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn limits() {
+    let mut book = Book::new();
+    book.options.set("limits.chapter_size", "20B").unwrap();
+    book.options.set("limits.nesting", "10").unwrap();
+    book.options.set("limits.images", "2").unwrap();
+    let err = book
+        .add_chapter_from_source(
+            Number::Default,
+            "# A chapter that is too long\n".as_bytes(),
+            false,
+        )
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("limits.chapter_size (20B)"));
+    book.options.set("limits.chapter_size", "1kB").unwrap();
+    let err = book
+        .add_chapter_from_source(
+            Number::Default,
+            "> > > > > > > > > > > deep\n".as_bytes(),
+            false,
+        )
+        .err()
+        .unwrap();
+    assert!(err.is_parser());
+    // Inputs deep enough to overflow the stack while parsing are refused before
+    let mut deep = Book::new();
+    for source in [format!("{} hi\n", ">".repeat(20000)), format!("{}a\n", "*".repeat(20000))] {
+        let err = deep
+            .add_chapter_from_source(Number::Default, source.as_bytes(), false)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("limits.nesting (100)"));
+    }
+    book.add_chapter_from_source(Number::Default, "![](a.png) ![](b.png)\n".as_bytes(), false)
+        .unwrap();
+    let err = book
+        .add_chapter_from_source(Number::Default, "![](c.png)\n".as_bytes(), false)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("limits.images"));
+
+    // A configuration file importing itself stops at the limit
+    let name = format!("crowbook-import-{}.book", std::process::id());
+    let path = std::env::temp_dir().join(&name);
+    std::fs::write(&path, format!("import: {name}\n")).unwrap();
+    let loaded = Book::new().load_file(&path);
+    let mut book = Book::new();
+    book.options.set("limits.include_depth", "0").unwrap();
+    let err = book.options.set("import", path.to_str().unwrap()).err().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(loaded.is_ok());
+    assert!(err.to_string().contains("limits.include_depth (0)"));
+
+    // Limits can only be lowered in sandbox mode
+    let mut book = Book::new();
+    book.options.set("crowbook.sandbox", "true").unwrap();
+    assert!(book.options.set("limits.chapter_size", "1MB").is_ok());
+    assert!(book.options.set("limits.chapter_size", "2MB").is_err());
+    assert!(book.options.set("limits.nesting", "1000").is_err());
+    assert!(book.options.set("limits.images", "1000").is_ok());
+}
+
+#[test]
+fn legacy_date() {
+    // Older versions took any text as date
+    let mut book = Book::new();
+    book.read_config(
+        "title: Legacy
+date: Summer 2020
+html.base_url: https://example.org/book/
+"
+        .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(book.options.get_str("date").unwrap(), "Summer 2020");
+    assert!(book.options.get_date("date").is_err());
+
+    let data = book.get_metadata(|s| Ok(s.to_owned())).unwrap();
+    assert_eq!(data["date"], upon::Value::from("Summer 2020"));
+    assert_eq!(data["has_date"], upon::Value::from(true));
+    assert_eq!(data["date_iso"], upon::Value::from(""));
+
+    book.add_chapter_from_source(Number::Default, "# One\n\nText".as_bytes(), false)
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    assert!(String::from_utf8(html).unwrap().contains("Summer 2020"));
+    let mut epub = vec![];
+    book.render_format_to("epub", &mut epub).unwrap();
+}
+
+#[test]
+fn import_epub_paths() {
+    use std::io::Write;
+
+    let dir = std::env::temp_dir().join(format!("crowbook-import-paths-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let epub = dir.join("book.epub");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&epub).unwrap());
+    let files = [
+        (
+            "META-INF/container.xml",
+            r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#,
+        ),
+        (
+            "OEBPS/content.opf",
+            r#"<package><metadata><title>Title</title><meta name="cover" content="cover"/></metadata>
+<manifest><item id="cover" href="img\my%20cover%3A%201.png" media-type="image/png"/>
+<item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="one"/></spine></package>"#,
+        ),
+        (
+            "OEBPS/text/one.xhtml",
+            r#"<html><body><p>Text</p><p><img src="../img/my%20cover%3A%201.png" alt="Cover"/></p></body></html>"#,
+        ),
+        ("OEBPS/img/my cover: 1.png", "png"),
+    ];
+    for (name, content) in files {
+        zip.start_file(name, zip::write::FileOptions::default()).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let project = dir.join("project");
+    let file = crate::import_epub(&epub, &project).unwrap();
+    assert!(project.join("images/my_cover__1.png").exists());
+    let config = std::fs::read_to_string(file).unwrap();
+    assert!(config.contains("cover: \"images/my_cover__1.png\"\n"));
+    let chapter = std::fs::read_to_string(project.join("chapter_01.md")).unwrap();
+    assert!(chapter.contains("![Cover](images/my_cover__1.png)"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,131 @@
+use super::test_eq;
+use crate::book::Book;
+use crate::latex;
+use crate::latex_escape;
+use crate::latex_log;
+use crate::pdf_fonts;
+
+use std::io::Write;
+
+#[test]
+fn escape_text() {
+    test_eq(
+        &latex_escape::text("_ & % # ~ ^ \\ { } $"),
+        "\\_ \\& \\% \\# \\textasciitilde{} \\textasciicircum{} \\textbackslash{} \\{ \\} \\$",
+    );
+}
+
+#[test]
+fn escape_code() {
+    test_eq(
+        &latex_escape::code("a_b{}"),
+        "a\\_\\allowbreak{}b\\{\\}",
+    );
+}
+
+#[test]
+fn escape_url() {
+    test_eq(
+        &latex_escape::url("http://foo.bar/~me/a_b%20c?x={y}#top"),
+        "http://foo.bar/~me/a_b\\%20c?x=%7By%7D\\#top",
+    );
+    test_eq(&latex_escape::url("a\\b^c d"), "a%5Cb%5Ec%20d");
+}
+
+#[test]
+fn escape_label() {
+    test_eq(&latex_escape::label("chapter-1"), "chapter-1");
+    test_eq(&latex_escape::label("a_b#c{}"), "a-b-c--");
+}
+
+#[test]
+fn parse_log() {
+    let log = "(./result.tex
+! LaTeX Error: File `lettrine.sty' not found.
+
+Type X to quit or <RETURN> to proceed,
+or enter new name. (Default extension: sty)
+
+Enter file name: 
+! Emergency stop.
+<read *> 
+         
+l.12 \\usepackage
+                 {lettrine}^^M
+! Undefined control sequence.
+l.42 Some \\foo
+               
+Missing character: There is no ☃ (U+2603) in font Foo!
+Missing character: There is no ☃ (U+2603) in font Foo!
+";
+    let errors = latex_log::parse_log(log);
+    assert_eq!(errors.len(), 3);
+    assert!(errors[0].message.contains("lettrine.sty"));
+    assert_eq!(errors[0].line, Some(12));
+    assert!(errors[1].message.contains("\\foo"));
+    assert_eq!(errors[1].line, Some(42));
+    assert!(errors[2].message.contains("☃"));
+    assert_eq!(errors[2].line, None);
+
+    let tex = "\\begin{document}\n\\label{chapter-0}\nfoo\n\\label{chapter-1}\nbar\n";
+    assert_eq!(latex_log::chapter_of_line(tex, 1), None);
+    assert_eq!(latex_log::chapter_of_line(tex, 3), Some(0));
+    assert_eq!(latex_log::chapter_of_line(tex, 5), Some(1));
+}
+
+#[test]
+fn pdf_unembedded_fonts() {
+    let mut pdf = b"%PDF-1.5
+4 0 obj
+<< /Type /FontDescriptor /FontName /ABCDEF+LMRoman10-Regular /Flags 4 /FontFile 5 0 R >>
+endobj
+5 0 obj
+<< /Length 3 >>
+stream
+<<<
+endstream
+endobj
+6 0 obj
+<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>
+endobj
+7 0 obj
+<< /Type /Font /Subtype /Type0 /BaseFont /Foo /DescendantFonts [8 0 R] >>
+endobj
+"
+    .to_vec();
+    assert_eq!(pdf_fonts::unembedded(&pdf), ["Helvetica"]);
+
+    // Font descriptors can also be in compressed object streams
+    let mut encoder =
+        flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+    encoder
+        .write_all(b"9 0 << /Type /FontDescriptor /FontName /Times-Roman /Flags 6 >>")
+        .unwrap();
+    let stream = encoder.finish().unwrap();
+    write!(
+        pdf,
+        "10 0 obj\n<< /Type /ObjStm /N 1 /First 4 /Filter /FlateDecode /Length {} >>\nstream\n",
+        stream.len()
+    )
+    .unwrap();
+    pdf.extend_from_slice(&stream);
+    pdf.extend_from_slice(b"\nendstream\nendobj\n");
+    assert_eq!(pdf_fonts::unembedded(&pdf), ["Helvetica", "Times-Roman"]);
+}
+
+#[test]
+fn pdf_command() {
+    let mut book = Book::new();
+    assert_eq!(latex::pdf_command(&book), ("xelatex", &[][..], 3));
+    book.options.set("tex.runs", "1").unwrap();
+    book.options.set("tex.command", "pdflatex").unwrap();
+    assert_eq!(latex::pdf_command(&book), ("pdflatex", &[][..], 1));
+    // Tectonic runs its passes by itself, and keeps its log for explaining errors
+    book.options.set("tex.runs", "3").unwrap();
+    book.options.set("tex.command", "/opt/bin/tectonic").unwrap();
+    assert_eq!(latex::pdf_command(&book), ("/opt/bin/tectonic", &["--keep-logs"][..], 1));
+    // It uses XeTeX, wherever it is installed
+    book.add_chapter_from_source(crate::number::Number::Default, "# One".as_bytes(), true).unwrap();
+    let tex = book.render_format_to_string("tex").unwrap();
+    assert!(tex.contains("fontspec"));
+}
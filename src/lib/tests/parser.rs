@@ -1,6 +1,9 @@
 use super::test_eq;
 use crate::book::Book;
+use crate::code_block::CodeInfo;
+use crate::number::Number;
 use crate::parser::Parser;
+use crate::search_index::SearchIndex;
 use crate::token::Token;
 
 fn parse_from_str(doc: &str) -> Vec<Token> {
@@ -177,3 +180,397 @@ fn table_simple() {
     let result = format!("{:?}", parse_from_str(doc));
     test_eq(&result, expected);
 }
+
+#[test]
+fn pandoc_json() {
+    let doc = r#"{"pandoc-api-version":[1,23,1],"meta":{},"blocks":[
+{"t":"Header","c":[1,["",[],[]],[{"t":"Str","c":"Title"}]]},
+{"t":"Para","c":[{"t":"Str","c":"some"},{"t":"Space"},{"t":"Emph","c":[{"t":"Str","c":"text"}]},
+  {"t":"Note","c":[{"t":"Para","c":[{"t":"Str","c":"note"}]}]}]}]}"#;
+    let mut parser = Parser::new();
+    let res = parser.parse_pandoc_json(doc, None).unwrap();
+    let expected = vec![
+        Token::Header(1, vec![Token::Str(String::from("Title"))]),
+        Token::Paragraph(vec![
+            Token::Str(String::from("some ")),
+            Token::Emphasis(vec![Token::Str(String::from("text"))]),
+            Token::FootnoteReference(String::from("1")),
+        ]),
+        Token::FootnoteDefinition(
+            String::from("1"),
+            vec![Token::Paragraph(vec![Token::Str(String::from("note"))])],
+        ),
+    ];
+    assert_eq!(res, expected);
+
+    for (level, expected) in [("-1", 1), ("0", 1), ("7", 6), ("1000000000000", 6)] {
+        let doc = format!(
+            r#"{{"pandoc-api-version":[1,23,1],"meta":{{}},"blocks":[
+{{"t":"Header","c":[{level},["",[],[]],[{{"t":"Str","c":"Title"}}]]}}]}}"#
+        );
+        let res = Parser::new().parse_pandoc_json(&doc, None).unwrap();
+        assert_eq!(res, vec![Token::Header(expected, vec![Token::Str(String::from("Title"))])]);
+    }
+}
+
+#[test]
+fn org_mode() {
+    let doc = "
+* Title
+Some /emphasis/ and a [[http://foo.bar][link]][fn:1]
+
+- item
+
+[fn:1] A note
+";
+    let mut parser = Parser::new();
+    let res = parser.parse_org(doc, None).unwrap();
+    let expected = vec![
+        Token::Header(1, vec![Token::Str(String::from("Title"))]),
+        Token::Paragraph(vec![
+            Token::Str(String::from("Some ")),
+            Token::Emphasis(vec![Token::Str(String::from("emphasis"))]),
+            Token::Str(String::from(" and a ")),
+            Token::Link(
+                String::from("http://foo.bar"),
+                String::new(),
+                vec![Token::Str(String::from("link"))],
+            ),
+            Token::FootnoteReference(String::from("1")),
+        ]),
+        Token::List(vec![Token::Item(vec![Token::Paragraph(vec![
+            Token::Str(String::from("item")),
+        ])])]),
+        Token::FootnoteDefinition(
+            String::from("1"),
+            vec![Token::Paragraph(vec![Token::Str(String::from("A note"))])],
+        ),
+    ];
+    assert_eq!(res, expected);
+}
+
+#[test]
+fn raw_spans() {
+    let doc = "A `\\LaTeX`{=latex} span
+
+```{=html}
+<div>raw</div>
+```
+";
+    let mut parser = Parser::new();
+    parser.raw(true);
+    let res = parser.parse(doc, None).unwrap();
+    assert_eq!(
+        res,
+        vec![
+            Token::Paragraph(vec![
+                Token::Str(String::from("A ")),
+                Token::Raw(String::from("latex"), String::from("\\LaTeX")),
+                Token::Str(String::from(" span")),
+            ]),
+            Token::Raw(String::from("html"), String::from("<div>raw</div>\n")),
+        ]
+    );
+
+    // Raw attributes are only recognized when enabled
+    let res = parse_from_str(doc);
+    assert_eq!(
+        res[0],
+        Token::Paragraph(vec![
+            Token::Str(String::from("A ")),
+            Token::Code(String::from("\\LaTeX")),
+            Token::Str(String::from("{=latex} span")),
+        ])
+    );
+}
+
+#[test]
+fn shortcodes() {
+    let mut book = Book::new();
+    book.options
+        .set("shortcodes.youtube.html", "'<iframe src=\"https://youtube.com/embed/{{ id }}\"></iframe>'")
+        .unwrap();
+    book.options
+        .set("shortcodes.youtube.markdown", "'[{{ title }}](https://youtu.be/{{ id }})'")
+        .unwrap();
+    book.options
+        .set("shortcodes.kbd.tex", "'\\texttt{ {{- key -}} }'")
+        .unwrap();
+    assert!(book.options.set("shortcodes.kbd", "'foo'").is_err());
+    book.add_chapter_from_source(
+        Number::Default,
+        "Press {{% kbd key=Enter %}}.
+
+{{% youtube id=abc title=\"A video\" %}}
+"
+        .as_bytes(),
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        book.chapters[0].content,
+        vec![
+            Token::Paragraph(vec![
+                Token::Str(String::from("Press ")),
+                Token::Shortcode(
+                    vec![Token::Raw(String::from("tex"), String::from("\\texttt{Enter}"))],
+                    vec![]
+                ),
+                Token::Str(String::from(".")),
+            ]),
+            Token::Shortcode(
+                vec![Token::Raw(
+                    String::from("html"),
+                    String::from("<iframe src=\"https://youtube.com/embed/abc\"></iframe>")
+                )],
+                vec![Token::Paragraph(vec![Token::Link(
+                    String::from("https://youtu.be/abc"),
+                    String::new(),
+                    vec![Token::Str(String::from("A video"))]
+                )])]
+            ),
+        ]
+    );
+    assert!(book
+        .add_chapter_from_source(Number::Default, "{{% youtube id=\"abc %}}".as_bytes(), false)
+        .is_err());
+}
+
+#[test]
+fn shortcodes_in_code() {
+    let mut book = Book::new();
+    book.options.set("shortcodes.kbd.markdown", "'**{{ key }}**'").unwrap();
+    book.options.set("shortcodes.kbd.html", "'<kbd>{{ key }}</kbd>'").unwrap();
+    let doc = "Press `{{% kbd key=Enter %}}` or {{% kbd key=Tab %}}.
+
+```
+{{% kbd key=Esc %}}
+```
+";
+    book.add_chapter_from_source(Number::Default, doc.as_bytes(), false)
+        .unwrap();
+    let content = &book.chapters[0].content;
+    assert_eq!(content[0].inner().unwrap()[1], Token::Code(String::from("{{% kbd key=Enter %}}")));
+    assert_eq!(
+        content[1],
+        Token::CodeBlock(String::new(), String::from("{{% kbd key=Esc %}}\n"))
+    );
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<code>{{% kbd key=Enter %}}</code>"));
+    assert!(html.contains("{{% kbd key=Esc %}}"));
+    assert!(html.contains("<kbd>Tab</kbd>"));
+    assert!(!html.contains('\u{E000}'));
+}
+
+#[test]
+fn shortcodes_templates() {
+    let mut book = Book::new();
+    book.options.set("shortcodes.b.markdown", "'*{{ t }}*'").unwrap();
+    book.options.set("shortcodes.b.html", "'<b class = \"sc\">{{ t }}</b>'").unwrap();
+    book.add_chapter_from_source(Number::Default, "A {{% b t=abc %}} B\n".as_bytes(), false)
+        .unwrap();
+    // The Markdown template is only used by formats that don't have their own
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert_eq!(html.matches("abc").count(), 1);
+    assert!(html.contains("A <b class = \"sc\">abc</b> B"));
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("A \\emph{abc} B"));
+    assert!(!tex.contains("sc\">"));
+
+    // Shortcodes can be defined in the front matter of a single file
+    let mut book = Book::new();
+    book.read_markdown_config(
+        "---
+shortcodes.b.markdown: '*{{ t }}*'
+---
+
+A {{% b t=abc %}} B
+"
+        .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(
+        book.chapters[0].content,
+        vec![Token::Paragraph(vec![
+            Token::Str(String::from("A ")),
+            Token::Shortcode(vec![], vec![Token::Emphasis(vec![Token::Str(String::from("abc"))])]),
+            Token::Str(String::from(" B")),
+        ])]
+    );
+}
+
+#[test]
+fn search_index() {
+    let doc = "# Title\n\nSome *emphasised*\ntext.\n\n* An item\n\nText again\n";
+    let mut index = SearchIndex::new();
+    index.add("chapter_000.html", "Title", &parse_from_str(doc));
+    let json = index.to_json();
+    test_eq(
+        json["docs"][0]["body"].as_str().unwrap(),
+        "Title Some emphasised text. An item Text again",
+    );
+    assert_eq!(json["index"]["text"], serde_json::json!([[0, 2]]));
+    assert_eq!(json["index"]["emphasised"], serde_json::json!([[0, 1]]));
+}
+
+#[test]
+fn described_image() {
+    let doc = "![A chart](chart.png)\n\n> [!description] Sales doubled\n> in 2020.\n>\n> Then they stalled.\n";
+    let expected = vec![Token::DescribedImage(vec![
+        Token::StandaloneImage(
+            String::from("chart.png"),
+            String::new(),
+            vec![Token::Str(String::from("A chart"))],
+        ),
+        Token::Paragraph(vec![Token::Str(String::from("Sales doubled in 2020."))]),
+        Token::Paragraph(vec![Token::Str(String::from("Then they stalled."))]),
+    ])];
+    assert_eq!(parse_from_str(doc), expected);
+    let doc = "![A chart](chart.png)\n\n> Just a quote\n";
+    assert!(matches!(parse_from_str(doc)[0], Token::StandaloneImage(..)));
+}
+
+#[test]
+fn verse() {
+    let doc = "::: verse\nSome *verse*\n  1. indented\n\n\nNew stanza {#refrain}\n:::\n";
+    let expected = vec![Token::Verse(vec![
+        Token::VerseLine(
+            0,
+            vec![
+                Token::Str(String::from("Some ")),
+                Token::Emphasis(vec![Token::Str(String::from("verse"))]),
+            ],
+        ),
+        Token::VerseLine(2, vec![Token::Str(String::from("1. indented"))]),
+        Token::VerseLine(0, vec![]),
+        Token::VerseLine(
+            0,
+            vec![
+                Token::Anchor(String::from("refrain")),
+                Token::Str(String::from("New stanza")),
+            ],
+        ),
+    ])];
+    assert_eq!(parse_from_str(doc), expected);
+}
+
+#[test]
+fn letter_and_play() {
+    let doc = "::: letter\nFrom: Jane\n\nDear Cassandra,\n\nSignature: Jane\n:::\n";
+    let expected = vec![Token::Letter(vec![
+        Token::LetterField(String::from("from"), vec![Token::Str(String::from("Jane"))]),
+        Token::Paragraph(vec![Token::Str(String::from("Dear Cassandra,"))]),
+        Token::LetterField(String::from("signature"), vec![Token::Str(String::from("Jane"))]),
+    ])];
+    assert_eq!(parse_from_str(doc), expected);
+
+    let doc = "::: play\nHAMLET: To be,\n  or not to be.\n(Exit.)\n:::\n";
+    let expected = vec![Token::Play(vec![
+        Token::Speech(
+            String::from("HAMLET"),
+            vec![
+                Token::Str(String::from("To be,")),
+                Token::HardBreak,
+                Token::Str(String::from("or not to be.")),
+            ],
+        ),
+        Token::StageDirection(vec![Token::Str(String::from("(Exit.)"))]),
+    ])];
+    assert_eq!(parse_from_str(doc), expected);
+}
+
+#[test]
+fn code_info() {
+    let info = CodeInfo::parse("rust {caption=\"Hello, world\" file=main.rs highlight=1,3-4 start=continue}").unwrap();
+    assert_eq!(info.language, "rust");
+    assert_eq!(info.caption.as_deref(), Some("Hello, world"));
+    assert_eq!(info.file.as_deref(), Some("main.rs"));
+    assert!(info.is_highlighted(3) && !info.is_highlighted(2));
+    assert_eq!(info.first_line(12), 13);
+    assert_eq!(CodeInfo::parse(&info.to_info()).unwrap(), info);
+    assert_eq!(CodeInfo::parse("{.python start=10}").unwrap().language, "python");
+    assert_eq!(CodeInfo::parse("rust {size=3}").unwrap_err(), "size=3");
+}
+
+#[test]
+fn error_location() {
+    use std::error::Error as _;
+
+    let mut parser = Parser::new();
+    parser.set_source_file("chapter.md");
+    let err = parser
+        .parse("Text\n\n  ```rust {file=missing.rs}\n  ```\n", None)
+        .unwrap_err();
+    assert_eq!(err.code(), "file-not-found");
+    assert_eq!(err.location().file(), Some("chapter.md"));
+    assert_eq!(err.location().line(), Some(3));
+    assert_eq!(err.location().column(), Some(3));
+    assert!(err.to_string().starts_with("chapter.md:3:3: "));
+    assert!(err.source().unwrap().is::<std::io::Error>());
+}
+
+#[test]
+fn collapse_text() {
+    let doc = "A *line*\nbroken in `three`\nparts, <b>with</b> HTML\nand text";
+    let expected = vec![Token::Paragraph(vec![
+        Token::Str(String::from("A ")),
+        Token::Emphasis(vec![Token::Str(String::from("line"))]),
+        Token::SoftBreak,
+        Token::Str(String::from("broken in ")),
+        Token::Code(String::from("three")),
+        Token::SoftBreak,
+        Token::Str(String::from("parts, <b>with</b> HTML and text")),
+    ])];
+    assert_eq!(parse_from_str(doc), expected);
+}
+
+#[test]
+fn commonmark() {
+    let doc = "~~not struck~~ <span>html</span>\n\n| a | b |\n|---|---|\n";
+    let mut parser = Parser::new();
+    parser.commonmark(true);
+    let expected = vec![
+        Token::Paragraph(vec![
+            Token::Str(String::from("~~not struck~~ ")),
+            Token::Raw(String::from("html"), String::from("<span>")),
+            Token::Str(String::from("html")),
+            Token::Raw(String::from("html"), String::from("</span>")),
+        ]),
+        Token::Paragraph(vec![Token::Str(String::from("| a | b | |---|---|"))]),
+    ];
+    assert_eq!(parser.parse(doc, None).unwrap(), expected);
+    assert!(parser.parse("Reference[^1]", None).is_ok());
+
+    let mut book = Book::new();
+    book.options.set("input.markdown", "gfm").unwrap();
+    assert!(book
+        .add_chapter_from_str(crate::Number::Default, "x.md", "Text")
+        .is_err());
+}
+
+#[test]
+fn header_attributes() {
+    let doc = "# Title {#my-id .unnumbered .fancy}\n\n## Sub {not attributes}";
+    let expected = vec![
+        Token::Header(
+            1,
+            vec![
+                Token::HeaderAttributes(
+                    String::from("my-id"),
+                    vec![String::from("unnumbered"), String::from("fancy")],
+                ),
+                Token::Str(String::from("Title")),
+            ],
+        ),
+        Token::Header(2, vec![Token::Str(String::from("Sub {not attributes}"))]),
+    ];
+    assert_eq!(parse_from_str(doc), expected);
+    assert!(expected[0].has_class("unnumbered"));
+    assert!(!expected[1].has_class("unnumbered"));
+}
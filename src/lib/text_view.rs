@@ -36,11 +36,15 @@ where
         | Token::StandaloneImage(..)
         | Token::FootnoteDefinition(..)
         | Token::FootnoteReference(..)
+        | Token::Anchor(..)
+        | Token::HeaderAttributes(..)
         | Token::Table(..)
         | Token::TableHead(..)
         | Token::TableRow(..)
         | Token::TableCell(..) => f(""),
 
+        Token::VerseLine(_, ref v) => add(traverse_vec(v, f, add), f("\n")),
+
         _ => traverse_vec(token.inner().unwrap(), f, add),
     }
 }
@@ -0,0 +1,92 @@
+// Copyright (C) 2016-2023 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Measure of the time spent in each phase of the generation of a book,
+//! to help understand why a build is slow.
+
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Time spent in each phase of the generation of a book
+///
+/// Phases are named e.g. `parse: chapter_01.md`, `render: pdf` or `command: latexmk`.
+/// Since formats are rendered in parallel, the total can be longer than the actual build.
+#[derive(Debug, Default)]
+pub struct Timings {
+    entries: Mutex<Vec<(String, Duration)>>,
+}
+
+impl Timings {
+    /// Creates a new, empty, set of timings
+    pub fn new() -> Timings {
+        Timings::default()
+    }
+
+    /// Adds some time spent in a phase, adding it to the previous time if this phase was
+    /// already measured
+    pub fn add(&self, phase: &str, duration: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.iter_mut().find(|(p, _)| p == phase) {
+            Some(entry) => entry.1 += duration,
+            None => entries.push((phase.to_owned(), duration)),
+        }
+    }
+
+    /// Runs a function, measuring the time it took
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn time<T, F: FnOnce() -> T>(&self, phase: &str, f: F) -> T {
+        let start = std::time::Instant::now();
+        let result = f();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    /// Runs a function; in a browser, where there is no clock to measure the time, nothing
+    /// is recorded
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    pub fn time<T, F: FnOnce() -> T>(&self, _phase: &str, f: F) -> T {
+        f()
+    }
+
+    /// Returns the phases and the time spent in them, in the order they were first measured
+    pub fn entries(&self) -> Vec<(String, Duration)> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Returns the timings as JSON, in seconds
+    pub fn to_json(&self) -> Value {
+        let phases: Vec<Value> = self
+            .entries()
+            .into_iter()
+            .map(|(phase, duration)| json!({"phase": phase, "seconds": duration.as_secs_f64()}))
+            .collect();
+        json!({ "timings": phases })
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entries = self.entries();
+        let width = entries.iter().map(|(p, _)| p.chars().count()).max().unwrap_or(0);
+        for (phase, duration) in entries {
+            writeln!(f, "{phase:<width$}  {:>9.3} s", duration.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
@@ -53,6 +53,12 @@ pub enum Token {
     BlockQuote(Vec<Token>),
     /// Code block with language and content
     CodeBlock(String, String),
+    /// Raw content with its output format, e.g. `` `\LaTeX`{=latex} ``, that is
+    /// inserted as is in this format and ignored by other renderers
+    Raw(String, String),
+    /// An expanded shortcode, with the `Raw` tokens of its templates for some output formats
+    /// and its Markdown template, which is used by the formats that don't have one
+    Shortcode(Vec<Token>, Vec<Token>),
 
     /// Superscript, indicated with ^...^
     Superscript(Vec<Token>),
@@ -105,6 +111,28 @@ pub enum Token {
     Image(String, String, Vec<Token>),
     /// Similar to previous, but when image is in a standalone paragraph
     StandaloneImage(String, String, Vec<Token>),
+    /// A standalone image (the first token) followed by its long description
+    DescribedImage(Vec<Token>),
+
+    /// A poem, containing `VerseLine`s, stanzas being separated by empty ones
+    Verse(Vec<Token>),
+    /// A line of verse, with its indentation (in number of spaces)
+    VerseLine(u32, Vec<Token>),
+    /// An anchor that `#label` links point to
+    Anchor(String),
+    /// The id (possibly empty) and classes of the header containing it, set with
+    /// `{#id .class}` at the end of the header, e.g. `.unnumbered` or `.unlisted`
+    HeaderAttributes(String, Vec<String>),
+    /// A letter, containing blocks and `LetterField`s
+    Letter(Vec<Token>),
+    /// The sender (`from`), date (`date`) or signature (`signature`) of a letter
+    LetterField(String, Vec<Token>),
+    /// A play script, containing `Speech`es and `StageDirection`s
+    Play(Vec<Token>),
+    /// A line of dialogue, with its speaker
+    Speech(String, Vec<Token>),
+    /// A stage direction of a play
+    StageDirection(Vec<Token>),
 
     /// An annotation inserted by crowbook for e.g. grammar checking
     Annotation(Data, Vec<Token>),
@@ -113,6 +141,26 @@ pub enum Token {
 use Token::*;
 
 impl Token {
+    /// Creates a `Verse` from its lines, removing leading, trailing and repeated empty lines
+    pub fn verse(lines: Vec<Token>) -> Token {
+        let mut res: Vec<Token> = Vec::with_capacity(lines.len());
+        for line in lines {
+            let empty = matches!(line, VerseLine(_, ref v) if v.is_empty());
+            let after_empty = match res.last() {
+                None => true,
+                Some(VerseLine(_, v)) => v.is_empty(),
+                Some(_) => false,
+            };
+            if !(empty && after_empty) {
+                res.push(line);
+            }
+        }
+        if matches!(res.last(), Some(VerseLine(_, v)) if v.is_empty()) {
+            res.pop();
+        }
+        Verse(res)
+    }
+
     /// Returns the inner list of tokens contained in this token (if any)
     pub fn inner(&self) -> Option<&[Token]> {
         match *self {
@@ -121,7 +169,10 @@ impl Token {
             | HardBreak
             | Str(_)
             | CodeBlock(_, _)
+            | Raw(_, _)
             | Code(_)
+            | Anchor(_)
+            | HeaderAttributes(..)
             | FootnoteReference(_) => None,
 
             Paragraph(ref v)
@@ -146,8 +197,17 @@ impl Token {
             | Link(_, _, ref v)
             | Image(_, _, ref v)
             | StandaloneImage(_, _, ref v)
+            | DescribedImage(ref v)
+            | Verse(ref v)
+            | VerseLine(_, ref v)
+            | Letter(ref v)
+            | LetterField(_, ref v)
+            | Play(ref v)
+            | Speech(_, ref v)
+            | StageDirection(ref v)
             | Strikethrough(ref v)
             | TaskItem(_, ref v)
+            | Shortcode(_, ref v)
             | Annotation(_, ref v) => Some(v),
         }
     }
@@ -160,7 +220,10 @@ impl Token {
             | HardBreak
             | Str(_)
             | CodeBlock(_, _)
+            | Raw(_, _)
             | Code(_)
+            | Anchor(_)
+            | HeaderAttributes(..)
             | FootnoteReference(_) => None,
 
             Paragraph(ref mut v)
@@ -187,6 +250,15 @@ impl Token {
             | Image(_, _, ref mut v)
             | Strikethrough(ref mut v)
             | TaskItem(_, ref mut v)
+            | DescribedImage(ref mut v)
+            | Verse(ref mut v)
+            | VerseLine(_, ref mut v)
+            | Letter(ref mut v)
+            | LetterField(_, ref mut v)
+            | Play(ref mut v)
+            | Speech(_, ref mut v)
+            | StageDirection(ref mut v)
+            | Shortcode(_, ref mut v)
             | StandaloneImage(_, _, ref mut v) => Some(v),
         }
     }
@@ -211,6 +283,25 @@ impl Token {
         matches!(*self, Token::Header(..))
     }
 
+    /// Returns the id (empty if it isn't set) and the classes of a header, set with
+    /// `{#id .class}`
+    ///
+    /// **Returns** an empty id and no class if token is not a header.
+    pub fn header_attributes(&self) -> (&str, &[String]) {
+        match *self {
+            Header(_, ref v) => match v.first() {
+                Some(HeaderAttributes(ref id, ref classes)) => (id, classes),
+                _ => ("", &[]),
+            },
+            _ => ("", &[]),
+        }
+    }
+
+    /// Returns true if token is a header with this class
+    pub fn has_class(&self, class: &str) -> bool {
+        self.header_attributes().1.iter().any(|c| c == class)
+    }
+
     /// Returns true if token is code or code block
     pub fn is_code(&self) -> bool {
         matches!(*self, Token::CodeBlock(..) | Token::Code(..))
@@ -0,0 +1,96 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sizes and durations of the `size` and `duration` options, written with their unit
+//! (e.g. `2MB` or `10s`).
+
+use std::time::Duration;
+
+/// Splits a value such as `1h 30min` into numbers and their (lowercase) units, returning
+/// None if it doesn't have this form
+fn quantities(s: &str) -> Option<Vec<(f64, String)>> {
+    let mut quantities = vec![];
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number = rest[..end].parse().ok()?;
+        rest = rest[end..].trim_start();
+        let end = rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len());
+        quantities.push((number, rest[..end].to_lowercase()));
+        rest = rest[end..].trim_start();
+    }
+    Some(quantities)
+}
+
+/// Parses a size in bytes, e.g. `2MB`, returning None if it isn't valid
+///
+/// Units are `B` (the default), `kB`, `MB` and `GB` (powers of 1000), or `KiB`, `MiB` and
+/// `GiB` (powers of 1024), in any case.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let quantities = quantities(s)?;
+    let (number, unit) = match quantities.as_slice() {
+        [quantity] => quantity,
+        _ => return None,
+    };
+    let factor = match unit.as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    let bytes = (number * factor).round();
+    // `as` saturates, so values that don't fit are refused instead
+    if bytes < u64::MAX as f64 {
+        Some(bytes as u64)
+    } else {
+        None
+    }
+}
+
+/// Parses a duration, e.g. `10s`, `500ms` or `1h 30min`, returning None if it isn't valid
+///
+/// Units are `ms`, `s` (the default, for a number alone), `min` (or `m`) and `h`.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let quantities = quantities(s)?;
+    let mut seconds = 0.0;
+    for (number, unit) in &quantities {
+        let factor = match unit.as_str() {
+            "" if quantities.len() == 1 => 1.0,
+            "ms" => 1e-3,
+            "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" => 3600.0,
+            _ => return None,
+        };
+        seconds += number * factor;
+    }
+    // `from_secs_f64` panics on values that don't fit
+    if seconds < u64::MAX as f64 {
+        Some(Duration::from_secs_f64(seconds))
+    } else {
+        None
+    }
+}
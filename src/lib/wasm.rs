@@ -0,0 +1,28 @@
+// Copyright (C) 2016-2024 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! JavaScript bindings, to preview a book in a browser (with the `wasm` feature).
+
+use wasm_bindgen::prelude::*;
+
+/// Renders a book to standalone HTML (see `crowbook::render_html`), throwing the error
+/// message if it fails
+#[wasm_bindgen]
+pub fn render_html(config_json: &str, chapters_json: &str) -> Result<String, JsValue> {
+    crate::preview::render_html(config_json, chapters_json)
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
@@ -15,50 +15,60 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::cancellation::CommandLimits;
 use crate::error::{Error, Result};
+use crate::misc;
+use crate::temp_dir::TempDir;
 
+use chrono::{DateTime, Datelike, Timelike};
 use std::fs::{self, DirBuilder, File};
 use std::io;
-use std::io::Write;
-use std::ops::Drop;
-use std::path::{Path, PathBuf};
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
 use std::process::Command;
 use rust_i18n::t;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
 /// Struct used to create zip (using filesystem and zip command)
 pub struct Zipper {
     args: Vec<String>,
-    path: PathBuf,
+    dir: TempDir,
+    limits: CommandLimits,
 }
 
 impl Zipper {
-    /// Creates new zipper
-    ///
-    /// # Arguments
-    /// * `path`: the path to a temporary directory
-    /// (zipper will create a random dir in it and clean it later)
-    pub fn new(path: &str) -> Result<Zipper> {
-        let uuid = uuid::Uuid::new_v4();
-        let zipper_path = Path::new(path).join(uuid.as_simple().to_string());
-
-        DirBuilder::new()
-            .recursive(true)
-            .create(&zipper_path)
-            .map_err(|_| {
-                Error::zipper(t!(
-                    "zipper.tmp_dir",
-                    path = path
-                ))
-            })?;
-
-        Ok(Zipper {
+    /// Creates new zipper, writing its files in a temporary directory (which is deleted
+    /// when the zipper is dropped, unless it must be kept), and running commands within
+    /// `limits`
+    pub fn new(dir: TempDir, limits: CommandLimits) -> Zipper {
+        Zipper {
             args: vec![],
-            path: zipper_path,
-        })
+            dir,
+            limits,
+        }
     }
 
     /// writes a content to a temporary file
     pub fn write<P: AsRef<Path>>(&mut self, path: P, content: &[u8], add_args: bool) -> Result<()> {
+        let path = path.as_ref();
+        let file = format!("{}", path.display());
+        let mut f = self.create(path)?;
+        if f.write_all(content).is_ok() {
+            if add_args {
+                self.args.push(file);
+            }
+            Ok(())
+        } else {
+            Err(Error::zipper(t!(
+                "zipper.write_error",
+                file = file
+            )))
+        }
+    }
+
+    /// Creates a temporary file, so its content can be written progressively
+    pub fn create<P: AsRef<Path>>(&self, path: P) -> Result<File> {
         let path = path.as_ref();
         let file = format!("{}", path.display());
         if path.starts_with("..") || path.is_absolute() {
@@ -66,7 +76,7 @@ impl Zipper {
                 file = file
             )));
         }
-        let dest_file = self.path.join(path);
+        let dest_file = self.dir.path().join(path);
         let dest_dir = dest_file.parent().unwrap();
         if fs::metadata(dest_dir).is_err() {
             // dir does not exist, create it
@@ -75,30 +85,18 @@ impl Zipper {
                 .create(dest_dir)
                 .map_err(|_| {
                     Error::zipper(t!(
-                        "zipper.tpm_dir",
+                        "zipper.tmp_dir",
                         path = dest_dir.display()
                     ))
                 })?;
         }
 
-        if let Ok(mut f) = File::create(&dest_file) {
-            if f.write_all(content).is_ok() {
-                if add_args {
-                    self.args.push(file);
-                }
-                Ok(())
-            } else {
-                Err(Error::zipper(t!(
-                    "zipper.write_error",
-                    file = file
-                )))
-            }
-        } else {
-            Err(Error::zipper(t!(
+        File::create(&dest_file).map_err(|_| {
+            Error::zipper(t!(
                 "zipper.create_error",
                 file = file
-            )))
-        }
+            ))
+        })
     }
 
     /// run command and copy content of file output (supposed to result from the command) to current dir
@@ -109,7 +107,7 @@ impl Zipper {
         in_file: &str,
         out: &mut dyn Write,
     ) -> Result<String> {
-        let res_output = command.output().map_err(|e| {
+        let res_output = self.limits.output(&mut command, command_name)?.map_err(|e| {
             debug!(
                 "{}",
                 t!("zipper.command_output",
@@ -124,7 +122,7 @@ impl Zipper {
         });
         let output = res_output?;
         if output.status.success() {
-            let mut file = File::open(self.path.join(in_file)).map_err(|_| {
+            let mut file = File::open(self.dir.path().join(in_file)).map_err(|_| {
                 debug!(
                     "{}",
                     t!("zipper.command_result_error",
@@ -162,7 +160,7 @@ impl Zipper {
     #[cfg(feature = "odt")]
     pub fn generate_odt(&mut self, command_name: &str, odt_file: &mut dyn Write) -> Result<String> {
         let mut command = Command::new(command_name);
-        command.current_dir(&self.path);
+        command.current_dir(self.dir.path());
         command.arg("-r");
         command.arg("result.odt");
         command.arg(".");
@@ -170,36 +168,116 @@ impl Zipper {
     }
 
     /// generate a pdf file into given file name
+    ///
+    /// The command is run `runs` times, so the table of contents and references are correct;
+    /// `on_run` is called before each run with its number.
     pub fn generate_pdf(
         &mut self,
         command_name: &str,
+        args: &[&str],
         tex_file: &str,
+        runs: u32,
+        on_run: &dyn Fn(u32),
         pdf_file: &mut dyn Write,
     ) -> Result<String> {
-        // first pass
-        let mut command = Command::new(command_name);
-        command.current_dir(&self.path).arg(tex_file);
-        let _ = command.output();
+        let mut command = misc::command(command_name);
+        command.current_dir(self.dir.path()).args(args).arg(tex_file);
+        for i in 1..runs {
+            on_run(i);
+            let _ = self.limits.output(&mut command, command_name)?;
+        }
+        on_run(runs.max(1));
+        self.run_command(command, command_name, "result.pdf", pdf_file)
+    }
 
-        // second pass
-        let _ = command.output();
+    /// Returns the path of the temporary directory
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
 
-        // third pass
-        // let mut command = Command::new(command_name);
-        // command.current_dir(&self.path);
-        // command.arg(tex_file);
-        self.run_command(command, command_name, "result.pdf", pdf_file)
+    /// Returns the content of a temporary file, e.g. a log file, if it exists
+    pub fn read_to_string<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        fs::read(self.dir.path().join(path))
+            .ok()
+            .map(|content| String::from_utf8_lossy(&content).into_owned())
     }
 }
 
-impl Drop for Zipper {
-    fn drop(&mut self) {
-        if let Err(err) = fs::remove_dir_all(&self.path) {
-            println!(
-                "Error in zipper: could not delete temporary directory {}, error: {}",
-                self.path.to_string_lossy(),
-                err
-            );
+/// Rewrites a ZIP file so that it is byte-identical when its content is: files are sorted
+/// (except `mimetype`, which must stay first in EPUB files), directory entries are removed,
+/// and the modification time of all files is set to `timestamp`.
+pub fn normalize_zip(content: &[u8], timestamp: i64) -> Result<Vec<u8>> {
+    let error = |err: zip::result::ZipError| {
+        Error::zipper(t!("zipper.normalize_error", error = err))
+    };
+    let time = DateTime::from_timestamp(timestamp, 0)
+        .and_then(|t| {
+            zip::DateTime::from_date_and_time(
+                t.year().clamp(1980, 2107) as u16,
+                t.month() as u8,
+                t.day() as u8,
+                t.hour() as u8,
+                t.minute() as u8,
+                t.second() as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default();
+
+    let mut archive = ZipArchive::new(Cursor::new(content)).map_err(error)?;
+    let mut names: Vec<String> = archive.file_names().map(|name| name.to_owned()).collect();
+    names.sort_by(|a, b| (a != "mimetype", a).cmp(&(b != "mimetype", b)));
+
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+    for name in names {
+        let mut file = archive.by_name(&name).map_err(error)?;
+        if file.is_dir() {
+            continue;
+        }
+        let method = if name == "mimetype" {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        let options = FileOptions::default()
+            .compression_method(method)
+            .last_modified_time(time)
+            .unix_permissions(0o644);
+        writer.start_file(name.as_str(), options).map_err(error)?;
+        io::copy(&mut file, &mut writer)
+            .map_err(|err| error(zip::result::ZipError::Io(err)))?;
+    }
+    Ok(writer.finish().map_err(error)?.into_inner())
+}
+
+/// Rewrites a ZIP file, editing the content of one of its files with `edit` and copying the
+/// other ones as they are
+pub fn edit_zip_file<F: FnOnce(&mut String)>(content: &[u8], name: &str, edit: F) -> Result<Vec<u8>> {
+    let error = |err: zip::result::ZipError| {
+        Error::zipper(t!("zipper.normalize_error", error = err))
+    };
+    let mut archive = ZipArchive::new(Cursor::new(content)).map_err(error)?;
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+    let mut edit = Some(edit);
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(error)?;
+        if file.name() != name {
+            writer.raw_copy_file(file).map_err(error)?;
+            continue;
+        }
+        let mut text = String::new();
+        file.read_to_string(&mut text)
+            .map_err(|err| error(zip::result::ZipError::Io(err)))?;
+        if let Some(edit) = edit.take() {
+            edit(&mut text);
         }
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(file.last_modified());
+        writer.start_file(name, options).map_err(error)?;
+        writer
+            .write_all(text.as_bytes())
+            .map_err(|err| error(zip::result::ZipError::Io(err)))?;
     }
+    Ok(writer.finish().map_err(error)?.into_inner())
 }
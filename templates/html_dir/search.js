@@ -0,0 +1,82 @@
+/* Full-text search, using the index in search_index.js */
+
+function searchWords(text) {
+    return text.toLowerCase().split(/[^\p{L}\p{N}]+/u).filter(function(w) { return w.length > 0; });
+}
+
+function searchSnippet(body, word) {
+    var i = body.toLowerCase().indexOf(word);
+    if (i < 0) {
+        i = 0;
+    }
+    var start = Math.max(0, i - 60);
+    var end = Math.min(body.length, i + 100);
+    return (start > 0 ? "…" : "") + body.substring(start, end) + (end < body.length ? "…" : "");
+}
+
+function search(query) {
+    var index = crowbookSearchIndex.index;
+    var scores = null;
+    var words = searchWords(query);
+    for (var i = 0; i < words.length; i++) {
+        // Each word of the query can be the beginning of a word of the text
+        var found = {};
+        for (var term in index) {
+            if (term.lastIndexOf(words[i], 0) === 0) {
+                var postings = index[term];
+                for (var j = 0; j < postings.length; j++) {
+                    var doc = postings[j][0];
+                    found[doc] = (found[doc] || 0) + postings[j][1];
+                }
+            }
+        }
+        if (scores === null) {
+            scores = found;
+        } else {
+            for (var doc in scores) {
+                if (found[doc] === undefined) {
+                    delete scores[doc];
+                } else {
+                    scores[doc] += found[doc];
+                }
+            }
+        }
+    }
+    if (scores === null) {
+        return [];
+    }
+    var results = Object.keys(scores);
+    results.sort(function(a, b) { return scores[b] - scores[a]; });
+    return results.map(function(doc) {
+        var d = crowbookSearchIndex.docs[doc];
+        return {url: d.url, title: d.title, snippet: searchSnippet(d.body, words[0])};
+    });
+}
+
+function displaySearchResults() {
+    var query = document.getElementById("search").value;
+    var list = document.getElementById("search-results");
+    while (list.firstChild) {
+        list.removeChild(list.firstChild);
+    }
+    var results = search(query);
+    for (var i = 0; i < results.length; i++) {
+        var item = document.createElement("li");
+        var link = document.createElement("a");
+        link.href = results[i].url;
+        link.textContent = results[i].title;
+        var snippet = document.createElement("p");
+        snippet.className = "search-snippet";
+        snippet.textContent = results[i].snippet;
+        item.appendChild(link);
+        item.appendChild(snippet);
+        list.appendChild(item);
+    }
+}
+
+document.addEventListener('DOMContentLoaded', function() {
+    var input = document.getElementById("search");
+    if (input) {
+        input.addEventListener("input", displaySearchResults);
+    }
+});
@@ -0,0 +1,66 @@
+/* Reader interface for standalone HTML: theme, font size and collapsible table of contents */
+
+var crowbookStorage = {
+    get: function(key) {
+        try {
+            return window.localStorage.getItem("crowbook-" + key);
+        } catch (e) {
+            return null;
+        }
+    },
+    set: function(key, value) {
+        try {
+            window.localStorage.setItem("crowbook-" + key, value);
+        } catch (e) {
+        }
+    }
+};
+
+var fontSize = parseInt(crowbookStorage.get("font-size")) || 100;
+
+function applyReaderSettings() {
+    var root = document.documentElement;
+    if (crowbookStorage.get("theme") == "dark") {
+        root.classList.add("dark");
+    } else {
+        root.classList.remove("dark");
+    }
+    root.style.fontSize = fontSize + "%";
+}
+
+function toggleTheme() {
+    var dark = !document.documentElement.classList.contains("dark");
+    crowbookStorage.set("theme", dark ? "dark" : "light");
+    applyReaderSettings();
+}
+
+function changeFontSize(step) {
+    fontSize = Math.min(200, Math.max(50, fontSize + step * 10));
+    crowbookStorage.set("font-size", fontSize);
+    applyReaderSettings();
+}
+
+function toggleTocItem(event) {
+    var item = event.target.parentElement;
+    item.classList.toggle("collapsed");
+    event.target.setAttribute("aria-expanded", !item.classList.contains("collapsed"));
+}
+
+// Apply settings as soon as possible, to avoid flashing the light theme
+applyReaderSettings();
+
+document.addEventListener('DOMContentLoaded', function() {
+    var items = document.querySelectorAll('#nav li');
+    for (var i = 0; i < items.length; i++) {
+        var item = items[i];
+        if (item.querySelector('ul')) {
+            var button = document.createElement('span');
+            button.className = 'toc-toggle';
+            button.setAttribute('role', 'button');
+            button.setAttribute('aria-expanded', 'false');
+            button.addEventListener('click', toggleTocItem);
+            item.insertBefore(button, item.firstChild);
+            item.classList.add('collapsed');
+        }
+    }
+});
@@ -1,4 +1,4 @@
-use crowbook::Book;
+use crowbook::{Book, BookBuilder, Number};
 use std::io;
 
 #[test]
@@ -22,3 +22,231 @@ fn book_example() {
     book.render_format_to("html", &mut io::sink()).unwrap();
     book.render_format_to("tex", &mut io::sink()).unwrap();
 }
+
+#[test]
+fn import_epub() {
+    let dir = std::env::temp_dir().join(format!("crowbook-import-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let epub = dir.join("test.epub");
+
+    let mut book = Book::new();
+    book.load_file(&format!(
+        "{}/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        "tests/test.book"
+    ))
+    .unwrap();
+    book.render_format_to_file("epub", &epub).unwrap();
+
+    let file = crowbook::import_epub(&epub, dir.join("project")).unwrap();
+    let mut imported = Book::new();
+    imported.load_file(&file).unwrap();
+    assert_eq!(
+        imported.options.get_str("title").unwrap(),
+        book.options.get_str("title").unwrap()
+    );
+    imported.render_format_to("html", &mut io::sink()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn book_builder() {
+    let book = BookBuilder::new()
+        .title("1984")
+        .author("Joan Doe")
+        .lang("en")
+        .option("rendering.num_depth", "2")
+        .add_chapter_from_str("# The beginning\n\nBla, bla, bla")
+        .add_numbered_chapter_from_str(Number::Unnumbered, "# Epilogue\n\nThe end")
+        .build()
+        .unwrap();
+    assert_eq!(book.options.get_str("title").unwrap(), "1984");
+    assert_eq!(book.chapters.len(), 2);
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("Bla, bla, bla"));
+    book.render_format_to("epub", &mut io::sink()).unwrap();
+
+    assert!(BookBuilder::new()
+        .option("rendering.num_depth", "two")
+        .add_chapter_from_str("Text")
+        .build()
+        .is_err());
+}
+
+#[test]
+fn chapter_from_reader() {
+    let mut book = Book::new();
+    book.add_chapter_from_str(Number::Default, "intro.md", "# Introduction\n\nHello")
+        .unwrap();
+    book.add_chapter_from_reader(Number::Unnumbered, "notes/end.md", &b"The end"[..])
+        .unwrap();
+    assert_eq!(book.chapters[1].filename, "notes/end.md");
+
+    book.options.set("input.encoding", "utf-8").unwrap();
+    match book.add_chapter_from_reader(Number::Default, "broken.md", &b"\xe9t\xe9"[..]) {
+        Ok(_) => panic!("invalid UTF-8 should be an error"),
+        Err(err) => assert!(err.to_string().starts_with("broken.md: ")),
+    }
+}
+
+#[test]
+fn streamed_rendering() {
+    let book = BookBuilder::new()
+        .title("Streams")
+        .option("rendering.inline_toc", "true")
+        .option("html.standalone.one_chapter", "true")
+        .add_chapter_from_str("# First\n\nAlpha")
+        .add_chapter_from_str("# Second\n\nBeta")
+        .add_chapter_from_str("# Third\n\nGamma")
+        .build()
+        .unwrap();
+    for format in ["html", "tex"] {
+        let mut content = vec![];
+        book.render_format_to(format, &mut content).unwrap();
+        let content = String::from_utf8(content).unwrap();
+        let positions: Vec<_> = ["Alpha", "Beta", "Gamma"]
+            .iter()
+            .map(|text| content.find(text).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+
+        let dir = std::env::temp_dir()
+            .join(format!("crowbook-stream-{}-{format}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(format!("book.{format}"));
+        book.render_format_to_file(format, &file).unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), content);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.find("id = \"toc\"").unwrap() < html.find("Alpha").unwrap());
+    assert!(html.contains("Third »"));
+}
+
+#[test]
+fn daemon() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::{Command, Stdio};
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_crowbook"))
+        .args(["--quiet", "--daemon"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let book = format!("{}/tests/test.book", env!("CARGO_MANIFEST_DIR"));
+    let requests = [
+        serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "load", "params": {"path": book}}),
+        serde_json::json!({"jsonrpc": "2.0", "method": "set_option",
+                           "params": {"book": book, "key": "title", "value": "Daemon"}}),
+        serde_json::json!({"jsonrpc": "2.0", "id": 2, "method": "set_option",
+                           "params": {"book": book, "key": "rendering.num_depth", "value": 2}}),
+        serde_json::json!({"jsonrpc": "2.0", "id": 3, "method": "render",
+                           "params": {"book": book, "format": "tex"}}),
+        serde_json::json!({"jsonrpc": "2.0", "id": 4, "method": "stats", "params": {"book": book}}),
+        serde_json::json!({"jsonrpc": "2.0", "id": 5, "method": "diagnostics",
+                           "params": {"book": book}}),
+        serde_json::json!({"jsonrpc": "2.0", "id": 6, "method": "render",
+                           "params": {"book": "other.book", "format": "tex"}}),
+        serde_json::json!({"jsonrpc": "2.0", "id": 7, "method": "foo"}),
+    ];
+    let mut stdin = daemon.stdin.take().unwrap();
+    for request in &requests {
+        writeln!(stdin, "{request}").unwrap();
+    }
+    writeln!(stdin, "not json").unwrap();
+    drop(stdin);
+
+    let responses: Vec<serde_json::Value> = BufReader::new(daemon.stdout.take().unwrap())
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert!(daemon.wait().unwrap().success());
+    // The notification (without id) has no response
+    assert_eq!(responses.len(), 8);
+    assert_eq!(responses[0]["result"]["chapters"], 1);
+    assert_eq!(responses[1]["id"], 2);
+    assert!(responses[1]["error"].is_null());
+    let tex = responses[2]["result"]["content"].as_str().unwrap();
+    assert!(tex.contains("\\title{Daemon}"));
+    assert!(responses[3]["result"]["text"].as_str().is_some());
+    assert!(responses[4]["result"]["diagnostics"].is_array());
+    assert_eq!(responses[5]["error"]["code"], -32602);
+    assert_eq!(responses[6]["error"]["code"], -32601);
+    assert_eq!(responses[7]["error"]["code"], -32700);
+}
+
+#[test]
+fn timings_report() {
+    use std::process::Command;
+
+    let book = format!("{}/tests/test.book", env!("CARGO_MANIFEST_DIR"));
+    let output = std::env::temp_dir().join(format!("crowbook-timings-{}.html", std::process::id()));
+    let result = Command::new(env!("CARGO_BIN_EXE_crowbook"))
+        .args(["--quiet", "--timings=json", "--to", "html", "--output"])
+        .arg(&output)
+        .arg(&book)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&output).ok();
+    assert!(result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    let json: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    let phases: Vec<&str> = json["timings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["phase"].as_str().unwrap())
+        .collect();
+    assert!(phases.iter().any(|p| p.starts_with("parse")));
+    assert!(phases.contains(&"render: html"));
+}
+
+#[test]
+fn french_nb_spaces() {
+    use std::process::Command;
+
+    let dir = std::env::temp_dir().join(format!("crowbook-nb-spaces-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("fr.md");
+    std::fs::write(&source, "---\nlang: fr\n---\n\n# Un\n\nBonjour : oui ? Ah !\n").unwrap();
+    let render = |set: &[&str]| {
+        let output = dir.join("fr.html");
+        let result = Command::new(env!("CARGO_BIN_EXE_crowbook"))
+            .args(["--single", "--to", "html", "--output"])
+            .arg(&output)
+            .arg(&source)
+            .arg("--set")
+            .args(set)
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+        let html = std::fs::read_to_string(&output).unwrap_or_default();
+        (html, String::from_utf8(result.stderr).unwrap())
+    };
+
+    let (html, _) = render(&["rendering.num_depth", "1"]);
+    // Narrow non-breaking spaces are escaped by default in HTML
+    assert!(html.contains("Bonjour\u{A0}: <span class = \"nnbsp\">oui"));
+    let (html, _) = render(&[
+        "input.clean.french.nb_space",
+        "U+2009",
+        "input.clean.french.nb_space_narrow",
+        "thin-space",
+    ]);
+    assert!(html.contains("Bonjour\u{2009}: oui\u{2009}? Ah\u{2009}!"));
+    let (html, _) = render(&["input.clean.french.nb_space_narrow", "'e\u{301}'"]);
+    assert!(html.contains("oui\u{E9}?"));
+    let (_, stderr) = render(&["input.clean.french.nb_space", "xy"]);
+    assert!(stderr.contains("narrow-nbsp"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}